@@ -14,33 +14,34 @@ pub async fn init(shared_config: SharedConfig) -> Result<(), AppError> {
         let mut health_check = HealthCheck::from_shared_config(cloned_config);
         health_check.start_health_check_loop().await;
     });
-    let mut app_config = shared_config.shared_data.lock()?;
-    for (_, item) in app_config.api_service_config.iter_mut() {
-        let port = item.listen_port;
-        let server_type = item.server_type.clone();
-        let mapping_key = format!("{}-{}", port, server_type);
-        let (sender, receiver) = mpsc::channel::<()>(1000);
-        item.sender = sender;
-        let cloned_config = shared_config.clone();
-        let cert_str = item.cert_str.clone();
-        let key_str = item.key_str.clone();
-        tokio::task::spawn(async move {
-            if let Err(err) = start_proxy(
-                cloned_config,
-                port,
-                receiver,
-                server_type,
-                mapping_key,
-                cert_str,
-                key_str,
-            )
-            .await
-            {
-                error!("{}", err);
-            }
-        });
-    }
-    Ok(())
+    shared_config.update(|app_config| {
+        for (_, item) in app_config.api_service_config.iter_mut() {
+            let port = item.listen_port;
+            let server_type = item.server_type.clone();
+            let mapping_key = format!("{}-{}", port, server_type);
+            let (sender, receiver) = mpsc::channel::<()>(1000);
+            item.sender = sender;
+            let cloned_config = shared_config.clone();
+            let cert_str = item.cert_str.clone();
+            let key_str = item.key_str.clone();
+            tokio::task::spawn(async move {
+                if let Err(err) = start_proxy(
+                    cloned_config,
+                    port,
+                    receiver,
+                    server_type,
+                    mapping_key,
+                    cert_str,
+                    key_str,
+                )
+                .await
+                {
+                    error!("{}", err);
+                }
+            });
+        }
+        Ok(())
+    })
 }
 
 pub async fn start_proxy(
@@ -335,7 +336,7 @@ peIJpwo+Kuf964DexDVglw==
         let init_result = init(shared_config.clone()).await;
         assert!(init_result.is_ok());
         {
-            let app_config_guard = shared_config.shared_data.lock().unwrap();
+            let app_config_guard = shared_config.load();
             for (_, port, service_conf) in &services_to_init {
                 let api_service = app_config_guard
                     .api_service_config
@@ -3,14 +3,40 @@ pub const DENY_RESPONSE: &str = r#"{
     "response_code": -1,
     "response_object": "The request has been blocked by the Spire!"
 }"#;
+pub const RATE_LIMITED_RESPONSE: &str = r#"{
+    "response_code": -1,
+    "response_object": "The request has been rate limited by the Spire!"
+}"#;
+pub const UNAUTHENTICATED_RESPONSE: &str = r#"{
+    "response_code": -1,
+    "response_object": "The request could not be authenticated by the Spire!"
+}"#;
+pub const FORBIDDEN_RESPONSE: &str = r#"{
+    "response_code": -1,
+    "response_object": "The request is not permitted by the Spire!"
+}"#;
+pub const AUTH_SERVICE_UNAVAILABLE: &str = r#"{
+    "response_code": -1,
+    "response_object": "The authorization service could not be reached by the Spire!"
+}"#;
 pub const NOT_FOUND: &str = r#"{
     "response_code": -1,
     "response_object": "The route could not be found in the Proxy!"
 }"#;
+pub const NO_HEALTHY_UPSTREAM: &str = r#"{
+    "response_code": -1,
+    "response_object": "There are not enough healthy upstream endpoints!"
+}"#;
+pub const ALL_CIRCUITS_OPEN: &str = r#"{
+    "response_code": -1,
+    "response_object": "The circuits of all upstream endpoints are open!"
+}"#;
 pub const DEFAULT_FIXEDWINDOW_MAP_SIZE: i32 = 3;
 
 pub const TIMER_WAIT_SECONDS: u64 = 5;
 pub const DEFAULT_HTTP_TIMEOUT: u64 = 10;
+pub const DEFAULT_REDIS_PORT: u16 = 6379;
+pub const DEFAULT_MYSQL_PORT: u16 = 3306;
 pub const DEFAULT_TEMPORARY_DIR: &str = "temporary";
 pub const GRPC_STATUS_HEADER: &str = "grpc-status";
 pub const GRPC_STATUS_OK: &str = "0";
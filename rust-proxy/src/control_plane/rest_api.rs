@@ -29,7 +29,7 @@ use tower_http::cors::CorsLayer;
 async fn get_app_config(
     State(shared_config): State<SharedConfig>,
 ) -> Result<impl axum::response::IntoResponse, AppError> {
-    let app_config = shared_config.shared_data.lock()?.clone();
+    let app_config = AppConfig::clone(&shared_config.load());
     Ok(Json(BaseResponse {
         response_code: 0,
         response_object: app_config,
@@ -66,24 +66,30 @@ async fn post_app_config_with_error(
     if current_type == ServiceType::Https || current_type == ServiceType::Http2Tls {
         validate_tls_config(api_service.cert_str.clone(), api_service.key_str.clone())?;
     }
-    let mut rw_global_lock = shared_config.shared_data.lock()?;
-    match rw_global_lock
-        .api_service_config
-        .iter_mut()
-        .find(|(_, item)| item.listen_port == api_service.listen_port)
-    {
-        Some((_, data)) => data.route_configs.push(
-            api_service
-                .route_configs
-                .first()
-                .ok_or(AppError::from("The route is empty!"))?
-                .clone(),
-        ),
-        None => {
-            rw_global_lock.api_service_config.insert(port, api_service);
-        }
-    };
-    let app_config = rw_global_lock.clone();
+    let app_config = shared_config.update(|app_config| {
+        match app_config
+            .api_service_config
+            .iter_mut()
+            .find(|(_, item)| item.listen_port == api_service.listen_port)
+        {
+            Some((_, data)) => data.route_configs.push(
+                api_service
+                    .route_configs
+                    .first()
+                    .ok_or(AppError::from("The route is empty!"))?
+                    .clone(),
+            ),
+            None => {
+                app_config.api_service_config.insert(port, api_service);
+            }
+        };
+        app_config
+            .api_service_config
+            .values()
+            .filter(|item| item.listen_port == port)
+            .try_for_each(|item| item.check_routes())?;
+        Ok(app_config.clone())
+    })?;
     tokio::spawn(async {
         if let Err(err) = save_config_to_file(app_config).await {
             error!("Save file error,the error is {}!", err);
@@ -113,19 +119,19 @@ async fn delete_route_with_error(
     shared_config: SharedConfig,
     route_id: String,
 ) -> Result<String, AppError> {
-    let mut rw_global_lock = shared_config.shared_data.lock()?;
-    let mut api_services = HashMap::new();
-    for (port, mut api_service) in rw_global_lock.clone().api_service_config {
-        api_service
-            .route_configs
-            .retain(|route| route.route_id != route_id);
-        if !api_service.route_configs.is_empty() {
-            api_services.insert(port, api_service);
+    let app_config = shared_config.update(|app_config| {
+        let mut api_services = HashMap::new();
+        for (port, mut api_service) in app_config.api_service_config.drain() {
+            api_service
+                .route_configs
+                .retain(|route| route.route_id != route_id);
+            if !api_service.route_configs.is_empty() {
+                api_services.insert(port, api_service);
+            }
         }
-    }
-    rw_global_lock.api_service_config = api_services;
-
-    let app_config = rw_global_lock.clone();
+        app_config.api_service_config = api_services;
+        Ok(app_config.clone())
+    })?;
     tokio::spawn(async {
         if let Err(err) = save_config_to_file(app_config).await {
             error!("Save file error,the error is {}!", err);
@@ -152,18 +158,62 @@ async fn put_route_with_error(
     let (_, body) = req.into_parts();
     let bytes = axum::body::to_bytes(body, usize::MAX).await?;
     let route: RouteConfig = serde_yaml::from_slice(&bytes)?;
-    let mut rw_global_lock = shared_config.shared_data.lock()?;
-
-    let old_route = rw_global_lock
-        .api_service_config
-        .iter_mut()
-        .flat_map(|(_, item)| item.route_configs.iter_mut())
-        .find(|r| r.route_id == route.route_id)
-        .ok_or(AppError::from("Can not find the route by route id!"))?;
-
-    *old_route = route;
-
-    let app_config = rw_global_lock.clone();
+    let app_config = shared_config.update(|app_config| {
+        let api_service = app_config
+            .api_service_config
+            .values_mut()
+            .find(|item| {
+                item.route_configs
+                    .iter()
+                    .any(|r| r.route_id == route.route_id)
+            })
+            .ok_or(AppError::from("Can not find the route by route id!"))?;
+        let old_route = api_service
+            .route_configs
+            .iter_mut()
+            .find(|r| r.route_id == route.route_id)
+            .ok_or(AppError::from("Can not find the route by route id!"))?;
+        *old_route = route;
+        api_service.check_routes()?;
+        Ok(app_config.clone())
+    })?;
+    tokio::spawn(async {
+        if let Err(err) = save_config_to_file(app_config).await {
+            error!("Save file error,the error is {}!", err);
+        }
+    });
+    let data = BaseResponse {
+        response_code: 0,
+        response_object: 0,
+    };
+    Ok(serde_yaml::to_string(&data)?)
+}
+async fn put_canary_weights(
+    State(shared_config): State<SharedConfig>,
+    axum::extract::Path(route_id): axum::extract::Path<String>,
+    req: Request,
+) -> Result<impl axum::response::IntoResponse, AppError> {
+    put_canary_weights_with_error(shared_config, route_id, req).await
+}
+/// Changes the weights of the canary variants, the body maps the variant names to their weights.
+async fn put_canary_weights_with_error(
+    shared_config: SharedConfig,
+    route_id: String,
+    req: Request,
+) -> Result<String, AppError> {
+    let (_, body) = req.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await?;
+    let weights: HashMap<String, u32> = serde_yaml::from_slice(&bytes)?;
+    let app_config = shared_config.update(|app_config| {
+        let route = app_config
+            .api_service_config
+            .values_mut()
+            .flat_map(|api_service| api_service.route_configs.iter_mut())
+            .find(|r| r.route_id == route_id)
+            .ok_or(AppError::from("Can not find the route by route id!"))?;
+        route.router.set_canary_weights(&weights)?;
+        Ok(app_config.clone())
+    })?;
     tokio::spawn(async {
         if let Err(err) = save_config_to_file(app_config).await {
             error!("Save file error,the error is {}!", err);
@@ -246,6 +296,7 @@ pub fn get_router(shared_config: SharedConfig) -> Router {
         .route("/metrics", get(get_prometheus_metrics))
         .route("/route/{id}", delete(delete_route))
         .route("/route", put(put_routex))
+        .route("/route/{id}/canary", put(put_canary_weights))
         .route("/letsEncryptCertificate", post(lets_encrypt_certificate))
         .layer(axum::middleware::from_fn(print_request_response))
         .layer(CorsLayer::permissive())
@@ -328,7 +379,7 @@ mod tests {
         println!("{}", String::from_utf8_lossy(&body));
         let body_response: BaseResponse<AppConfig> = serde_yaml::from_slice(&body).unwrap();
 
-        let expected_config = shared_config.shared_data.lock().unwrap().clone();
+        let expected_config = AppConfig::clone(&shared_config.load());
         assert_eq!(body_response.response_object, expected_config);
 
         cleanup();
@@ -365,7 +416,7 @@ mod tests {
         println!("{}", String::from_utf8_lossy(&responsexx));
         // assert_eq!(response.status(), StatusCode::OK);
 
-        let locked_config = shared_config.shared_data.lock().unwrap();
+        let locked_config = shared_config.load();
         assert_eq!(locked_config.api_service_config.len(), 2);
         assert!(locked_config.api_service_config.contains_key(&9090));
         assert_eq!(
@@ -407,7 +458,7 @@ mod tests {
         let response = router.oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        let locked_config = shared_config.shared_data.lock().unwrap();
+        let locked_config = shared_config.load();
         let service_8080 = locked_config.api_service_config.get(&8080).unwrap();
         assert_eq!(service_8080.route_configs.len(), 2);
         assert!(service_8080
@@ -443,7 +494,7 @@ mod tests {
         let response = router.oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        let locked_config = shared_config.shared_data.lock().unwrap();
+        let locked_config = shared_config.load();
         let service_8080 = locked_config.api_service_config.get(&8080).unwrap();
         let _route = service_8080.route_configs.first().unwrap();
 
@@ -493,12 +544,62 @@ mod tests {
         let response = router.oneshot(request).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        let locked_config = shared_config.shared_data.lock().unwrap();
+        let locked_config = shared_config.load();
         assert!(locked_config.api_service_config.is_empty());
 
         cleanup();
     }
 
+    #[tokio::test]
+    async fn test_put_canary_weights() {
+        let (router, shared_config) = setup();
+        let canary_router = serde_yaml::from_str(
+            r#"
+kind: canary
+hash_on:
+  kind: client_ip
+targets:
+  - name: stable
+    weight: 95
+    forward_to: http://127.0.0.1:9001
+  - name: v2
+    weight: 5
+    forward_to: http://127.0.0.1:9002
+"#,
+        )
+        .unwrap();
+        shared_config
+            .update(|app_config| {
+                let api_service = app_config.api_service_config.get_mut(&8080).unwrap();
+                api_service.route_configs[0].router = canary_router;
+                Ok(())
+            })
+            .unwrap();
+
+        let request = Request::builder()
+            .uri("/route/route1/canary")
+            .method("PUT")
+            .body(Body::from("v2: 20\nstable: 80"))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let locked_config = shared_config.load();
+        let route = &locked_config.api_service_config[&8080].route_configs[0];
+        let router_yaml = serde_yaml::to_string(&route.router).unwrap();
+        assert!(router_yaml.contains("weight: 80"));
+        assert!(router_yaml.contains("weight: 20"));
+
+        let request = Request::builder()
+            .uri("/route/route1/canary")
+            .method("PUT")
+            .body(Body::from("v3: 20"))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        cleanup();
+    }
+
     #[tokio::test]
     async fn test_get_prometheus_metrics_success() {
         let (router, _) = setup();
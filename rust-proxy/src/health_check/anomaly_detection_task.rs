@@ -0,0 +1,202 @@
+use crate::vojo::app_config::AppConfig;
+use crate::vojo::app_config::RouteConfig;
+use crate::vojo::app_error::AppError;
+use crate::SharedConfig;
+use std::time::Duration;
+
+fn find_route<'a>(app_config: &'a AppConfig, route_id: &str) -> Option<&'a RouteConfig> {
+    app_config
+        .api_service_config
+        .values()
+        .flat_map(|item| &item.route_configs)
+        .find(|item| item.route_id == route_id)
+}
+
+/// Records the result of an upstream request and ejects the endpoint once it reaches
+/// `consecutive_5xx` failures in a row. The endpoint is re-admitted after `ejection_second`.
+/// The ejection is kept apart from the health check verdict, which it never overrides.
+pub fn report_upstream_result(
+    shared_config: SharedConfig,
+    route_id: String,
+    endpoint: String,
+    is_failure: bool,
+) -> Result<(), AppError> {
+    let app_config = shared_config.load();
+    let route = match find_route(&app_config, &route_id) {
+        Some(route) => route,
+        None => return Ok(()),
+    };
+    if !route.record_upstream_result(&endpoint, is_failure) {
+        return Ok(());
+    }
+    let ejection_second = match &route.anomaly_detection {
+        Some(anomaly_detection) => anomaly_detection.get_ejection_second(),
+        None => return Ok(()),
+    };
+    warn!(
+        "Eject the endpoint {} of route {} for {} seconds.",
+        endpoint, route_id, ejection_second
+    );
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(ejection_second)).await;
+        readmit_endpoint(shared_config, &route_id, &endpoint);
+    });
+    Ok(())
+}
+
+fn readmit_endpoint(shared_config: SharedConfig, route_id: &str, endpoint: &str) {
+    let app_config = shared_config.load();
+    if let Some(route) = find_route(&app_config, route_id) {
+        route.readmit_endpoint(endpoint);
+        info!("Readmit the endpoint {} of route {}.", endpoint, route_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::proxy_trait::RouterDestination;
+    use crate::vojo::anomaly_detection::{
+        AnomalyDetectionType, BaseAnomalyDetectionParam, HttpAnomalyDetectionParam,
+    };
+    use crate::vojo::app_config::{ApiService, AppConfig};
+    use crate::vojo::router::{BaseRoute, PollRoute, Router, SelectionContext};
+    use http::HeaderMap;
+    use std::collections::HashMap;
+
+    fn create_shared_config(ejection_second: u64) -> SharedConfig {
+        let route = RouteConfig {
+            route_id: "route1".to_string(),
+            anomaly_detection: Some(AnomalyDetectionType::Http(HttpAnomalyDetectionParam {
+                consecutive_5xx: 2,
+                base_anomaly_detection_param: BaseAnomalyDetectionParam { ejection_second },
+            })),
+            router: Router::Poll(PollRoute {
+                current_index: Default::default(),
+                routes: vec![
+                    BaseRoute {
+                        endpoint: "http://127.0.0.1:9001".to_string(),
+                        is_alive: None,
+                    },
+                    BaseRoute {
+                        endpoint: "http://127.0.0.1:9002".to_string(),
+                        is_alive: None,
+                    },
+                ],
+            }),
+            ..Default::default()
+        };
+        let mut api_service = ApiService::default();
+        api_service.route_configs.push(route);
+        let mut config_map = HashMap::new();
+        config_map.insert(8080, api_service);
+        SharedConfig::from_app_config(AppConfig {
+            api_service_config: config_map,
+            ..Default::default()
+        })
+    }
+
+    async fn get_alive_map(shared_config: &SharedConfig) -> HashMap<String, Option<bool>> {
+        let mut router = {
+            let app_config = shared_config.load();
+            app_config.api_service_config[&8080].route_configs[0]
+                .router
+                .clone()
+        };
+        router
+            .get_all_route()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.endpoint, item.is_alive))
+            .collect()
+    }
+    fn is_ejected(shared_config: &SharedConfig, endpoint: &str) -> bool {
+        let app_config = shared_config.load();
+        app_config.api_service_config[&8080].route_configs[0]
+            .ejected_endpoints()
+            .iter()
+            .any(|item| item == endpoint)
+    }
+    fn report(shared_config: &SharedConfig, endpoint: &str, is_failure: bool) {
+        report_upstream_result(
+            shared_config.clone(),
+            "route1".into(),
+            endpoint.to_string(),
+            is_failure,
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_eject_after_consecutive_failures() {
+        let shared_config = create_shared_config(60);
+        let endpoint = "http://127.0.0.1:9001";
+        report(&shared_config, endpoint, true);
+        assert!(!is_ejected(&shared_config, endpoint));
+
+        report(&shared_config, endpoint, true);
+        assert!(is_ejected(&shared_config, endpoint));
+        assert!(!is_ejected(&shared_config, "http://127.0.0.1:9002"));
+        let app_config = shared_config.load();
+        let route = &app_config.api_service_config[&8080].route_configs[0];
+        for _ in 0..4 {
+            let router_destination = route
+                .get_route(&HeaderMap::new(), SelectionContext::default())
+                .unwrap();
+            let RouterDestination::Http(base_route) = router_destination else {
+                panic!("{:?}", router_destination);
+            };
+            assert_eq!(base_route.endpoint, "http://127.0.0.1:9002");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_success_resets_counter() {
+        let shared_config = create_shared_config(60);
+        let endpoint = "http://127.0.0.1:9001";
+        for is_failure in [true, false, true] {
+            report(&shared_config, endpoint, is_failure);
+        }
+        assert!(!is_ejected(&shared_config, endpoint));
+    }
+
+    #[tokio::test]
+    async fn test_readmit_after_ejection() {
+        let shared_config = create_shared_config(1);
+        let endpoint = "http://127.0.0.1:9001";
+        shared_config
+            .update(|app_config| {
+                app_config
+                    .api_service_config
+                    .get_mut(&8080)
+                    .unwrap()
+                    .route_configs[0]
+                    .router
+                    .update_route_alive(
+                        BaseRoute {
+                            endpoint: endpoint.to_string(),
+                            is_alive: None,
+                        },
+                        false,
+                    )
+            })
+            .unwrap();
+        for _ in 0..2 {
+            report(&shared_config, endpoint, true);
+        }
+        assert!(is_ejected(&shared_config, endpoint));
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!is_ejected(&shared_config, endpoint));
+        // The health check verdict is not overridden by the readmission.
+        assert_eq!(get_alive_map(&shared_config).await[endpoint], Some(false));
+        let app_config = shared_config.load();
+        let status = app_config.api_service_config[&8080].route_configs[0]
+            .anomaly_detection_status
+            .get(endpoint)
+            .unwrap()
+            .clone();
+        assert_eq!(status.consecutive_5xx, 0);
+    }
+}
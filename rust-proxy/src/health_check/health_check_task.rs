@@ -1,24 +1,40 @@
+use crate::constants::common_constants::DEFAULT_MYSQL_PORT;
+use crate::constants::common_constants::DEFAULT_REDIS_PORT;
+use crate::constants::common_constants::GRPC_STATUS_HEADER;
+use crate::constants::common_constants::GRPC_STATUS_OK;
 use crate::constants::common_constants::TIMER_WAIT_SECONDS;
 use crate::proxy::http1::http_client::HttpClients;
+use crate::proxy::http2::grpc_proxy::connect_outbound;
+use crate::utils::mysql_client::MysqlConnection;
+use crate::utils::redis_client::RedisConnection;
 use crate::vojo::app_config::RouteConfig;
 use crate::vojo::app_error::AppError;
 use crate::vojo::cli::SharedConfig;
+use crate::vojo::health_check::GrpcHealthCheckParam;
 use crate::vojo::health_check::HealthCheckType;
 use crate::vojo::health_check::HttpHealthCheckParam;
+use crate::vojo::health_check::MysqlHealthCheckParam;
+use crate::vojo::health_check::RedisHealthCheckParam;
+use crate::vojo::router::BaseRoute;
 use async_trait::async_trait;
 use bytes::Bytes;
 use delay_timer::prelude::*;
 use futures;
 use futures::FutureExt;
+use http::Method;
 use http::Request;
 use http::StatusCode;
+use http::Version;
 use http_body_util::combinators::BoxBody;
 use http_body_util::BodyExt;
 use http_body_util::Full;
 use hyper::Response;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio::task::JoinSet;
 use tokio::time::sleep;
 
@@ -119,8 +135,10 @@ impl HealthCheck {
         }
     }
 
+    // The task keys hold the compiled body regex, which hashes by its immutable pattern.
+    #[allow(clippy::mutable_key_type)]
     async fn do_health_check(&mut self) -> Result<(), AppError> {
-        let app_config = self.shared_config.shared_data.lock()?.clone();
+        let app_config = self.shared_config.load();
         let mut route_list = HashMap::new();
         for (_, service_config) in app_config.api_service_config.iter() {
             for route in &service_config.route_configs {
@@ -197,56 +215,217 @@ async fn do_http_health_check<HC: HttpClientTrait + Send + Sync + 'static>(
     let mut set = JoinSet::new();
     for item in route_list {
         let http_client_shared = http_health_check_client.clone();
-        let host_option = Url::parse(item.endpoint.as_str());
-        if host_option.is_err() {
-            error!("Parse host error,the error is {}", host_option.unwrap_err());
-            continue;
-        }
+        let host = match Url::parse(item.endpoint.as_str()) {
+            Ok(host) => host,
+            Err(e) => {
+                error!("Parse host error,the error is {}", e);
+                continue;
+            }
+        };
 
-        let join_option = host_option?.join(http_health_check_param.path.clone().as_str());
-        if join_option.is_err() {
-            error!("Parse host error,the error is {}", join_option.unwrap_err());
-            continue;
-        }
+        let join_option = match host.join(http_health_check_param.path.clone().as_str()) {
+            Ok(join_option) => join_option,
+            Err(e) => {
+                error!("Parse host error,the error is {}", e);
+                continue;
+            }
+        };
 
-        let req = Request::builder()
-            .uri(join_option?.to_string())
-            .method("GET")
-            .body(Full::new(Bytes::new()).map_err(AppError::from).boxed())?;
-        let cloned_route = route.clone();
+        let mut builder = Request::builder()
+            .uri(join_option.to_string())
+            .method(http_health_check_param.method.clone());
+        for (key, value) in http_health_check_param.headers.iter() {
+            builder = builder.header(key, value);
+        }
+        let req = builder.body(Full::new(Bytes::new()).map_err(AppError::from).boxed())?;
+        let cloned_param = http_health_check_param.clone();
         set.spawn(async move {
             let res = http_client_shared
                 .request_http(req, timeout_number as u64)
                 .await;
-            (res, cloned_route, item)
+            let check_result = match res {
+                Ok(response) => check_http_response(response, &cloned_param).await,
+                Err(e) => Err(e),
+            };
+            (check_result, item)
         });
     }
     while let Some(response_result1) = set.join_next().await {
         match response_result1 {
-            Ok((res, route, base_route)) => {
-                let mut lock = shared_config.shared_data.lock()?;
-                let shared_route = lock
-                    .api_service_config
-                    .iter_mut()
-                    .flat_map(|(_, item)| &mut item.route_configs)
-                    .find(|item| item.route_id == route.route_id);
-                let new_route = match shared_route {
-                    Some(route) => route,
-                    None => {
-                        continue;
+            Ok((check_result, base_route)) => {
+                if let Err(e) = &check_result {
+                    error!(
+                        "Http health check error, the url is {},the error is {}",
+                        base_route.endpoint, e
+                    );
+                }
+                record_probe_result(
+                    &shared_config,
+                    &route.route_id,
+                    base_route,
+                    check_result.is_ok(),
+                    http_health_check_param.healthy_threshold,
+                    http_health_check_param.unhealthy_threshold,
+                )?;
+            }
+            Err(e) => {
+                error!("set join error,the error is {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+async fn check_http_response(
+    response: Response<BoxBody<Bytes, AppError>>,
+    http_health_check_param: &HttpHealthCheckParam,
+) -> Result<(), AppError> {
+    let body_regex = http_health_check_param
+        .body_regex
+        .as_ref()
+        .map(|body_regex| &body_regex.0);
+    let status = response.status();
+    if !http_health_check_param.is_expected_status(status.as_u16()) {
+        return Err(AppError(format!("Unexpected status code {}", status)));
+    }
+    if http_health_check_param.body_contains.is_none() && body_regex.is_none() {
+        return Ok(());
+    }
+    let body_bytes = response.into_body().collect().await?.to_bytes();
+    let body = String::from_utf8_lossy(&body_bytes);
+    if let Some(body_contains) = &http_health_check_param.body_contains {
+        if !body.contains(body_contains.as_str()) {
+            return Err(AppError(format!(
+                "The body does not contain {}",
+                body_contains
+            )));
+        }
+    }
+    if let Some(body_regex) = body_regex {
+        if !body_regex.is_match(&body) {
+            return Err(AppError(format!("The body does not match {}", body_regex)));
+        }
+    }
+    Ok(())
+}
+/// Records the probe result in the runtime status of the endpoint. A new snapshot is only
+/// published when the liveness of the endpoint changes, not on every probe.
+fn record_probe_result(
+    shared_config: &SharedConfig,
+    route_id: &str,
+    base_route: BaseRoute,
+    is_healthy: bool,
+    healthy_threshold: u32,
+    unhealthy_threshold: u32,
+) -> Result<(), AppError> {
+    let is_alive = {
+        let app_config = shared_config.load();
+        let shared_route = app_config
+            .api_service_config
+            .values()
+            .flat_map(|item| &item.route_configs)
+            .find(|item| item.route_id == route_id);
+        match shared_route {
+            Some(route) => route.record_health_check_result(
+                &base_route.endpoint,
+                is_healthy,
+                healthy_threshold,
+                unhealthy_threshold,
+            ),
+            None => None,
+        }
+    };
+    match is_alive {
+        Some(is_alive) => update_endpoint_alive(shared_config, route_id, base_route, is_alive),
+        None => Ok(()),
+    }
+}
+fn update_endpoint_alive(
+    shared_config: &SharedConfig,
+    route_id: &str,
+    base_route: BaseRoute,
+    is_alive: bool,
+) -> Result<(), AppError> {
+    shared_config.update(|app_config| {
+        let shared_route = app_config
+            .api_service_config
+            .iter_mut()
+            .flat_map(|(_, item)| &mut item.route_configs)
+            .find(|item| item.route_id == route_id);
+        if let Some(route) = shared_route {
+            let _ = route.router.update_route_alive(base_route, is_alive);
+        }
+        Ok(())
+    })
+}
+/// Converts endpoints like `redis://127.0.0.1:6379` or `127.0.0.1` to a socket address.
+pub fn get_socket_address(endpoint: &str, default_port: Option<u16>) -> Result<String, AppError> {
+    let no_port_error = || AppError(format!("The endpoint {} has no port", endpoint));
+    if endpoint.contains("://") {
+        let url = Url::parse(endpoint).map_err(|e| AppError(e.to_string()))?;
+        let host = url.host_str().ok_or("The endpoint has no host")?;
+        let port = url
+            .port()
+            .or(default_port)
+            .or(url.port_or_known_default())
+            .ok_or_else(no_port_error)?;
+        return Ok(format!("{}:{}", host, port));
+    }
+    let endpoint = endpoint.trim_end_matches('/');
+    let has_port = match endpoint.rsplit_once(':') {
+        Some((host, port)) => {
+            port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+        }
+        None => false,
+    };
+    if has_port {
+        Ok(endpoint.to_string())
+    } else {
+        let port = default_port.ok_or_else(no_port_error)?;
+        Ok(format!("{}:{}", endpoint, port))
+    }
+}
+async fn do_protocol_health_check<F, Fut>(
+    mut route: RouteConfig,
+    timeout_number: i32,
+    shared_config: SharedConfig,
+    probe: F,
+) -> Result<(), AppError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(), AppError>> + Send + 'static,
+{
+    let route_list = route.router.get_all_route().await?;
+    let mut set = JoinSet::new();
+    for item in route_list {
+        let probe_future = probe(item.endpoint.clone());
+        set.spawn(async move {
+            let res =
+                tokio::time::timeout(Duration::from_secs(timeout_number as u64), probe_future)
+                    .await;
+            (res, item)
+        });
+    }
+    while let Some(response_result) = set.join_next().await {
+        match response_result {
+            Ok((res, base_route)) => {
+                let is_alive = match res {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        error!(
+                            "Health check error, the endpoint is {},the error is {}",
+                            base_route.endpoint, e
+                        );
+                        false
                     }
-                };
-
-                if let Ok(res) = res {
-                    if res.status() == StatusCode::OK {
-                        let _ = new_route
-                            .router
-                            .update_route_alive(base_route.clone(), true);
+                    Err(_) => {
+                        error!(
+                            "Health check time out, the endpoint is {}",
+                            base_route.endpoint
+                        );
+                        false
                     }
-                } else {
-                    error!("Request time out, the url is {}", base_route.endpoint);
-                    let _ = new_route.router.update_route_alive(base_route, false);
-                }
+                };
+                record_probe_result(&shared_config, &route.route_id, base_route, is_alive, 1, 1)?;
             }
             Err(e) => {
                 error!("set join error,the error is {}", e);
@@ -255,6 +434,179 @@ async fn do_http_health_check<HC: HttpClientTrait + Send + Sync + 'static>(
     }
     Ok(())
 }
+async fn redis_probe(endpoint: String, param: RedisHealthCheckParam) -> Result<(), AppError> {
+    let address = get_socket_address(&endpoint, Some(DEFAULT_REDIS_PORT))?;
+    let mut connection = RedisConnection::connect(&address).await?;
+    if let Some(password) = &param.password {
+        connection
+            .auth(param.username.as_deref(), password.as_str())
+            .await?;
+    }
+    if let Some(database) = param.database {
+        connection.select(database).await?;
+    }
+    connection.ping().await
+}
+async fn mysql_probe(endpoint: String, param: MysqlHealthCheckParam) -> Result<(), AppError> {
+    let address = get_socket_address(&endpoint, Some(DEFAULT_MYSQL_PORT))?;
+    let mut connection = MysqlConnection::connect(
+        &address,
+        param.user.as_str(),
+        param.password.as_deref().unwrap_or_default(),
+        param.database.as_deref(),
+    )
+    .await?;
+    // Reaching the full authentication of caching_sha2_password proves the server is alive.
+    if !connection.is_authenticated() {
+        return Ok(());
+    }
+    connection.ping().await
+}
+async fn tcp_probe(endpoint: String) -> Result<(), AppError> {
+    let address = get_socket_address(&endpoint, None)?;
+    TcpStream::connect(address).await?;
+    Ok(())
+}
+/// Encodes a `grpc.health.v1.HealthCheckRequest` into a length-prefixed gRPC message.
+fn encode_grpc_health_check_request(service: &str) -> Bytes {
+    let mut message = vec![];
+    if !service.is_empty() {
+        message.push(0x0a);
+        let mut len = service.len();
+        while len >= 0x80 {
+            message.push((len as u8 & 0x7f) | 0x80);
+            len >>= 7;
+        }
+        message.push(len as u8);
+        message.extend_from_slice(service.as_bytes());
+    }
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    Bytes::from(frame)
+}
+/// Returns true if the gRPC message holds a `HealthCheckResponse` with the SERVING status.
+fn is_grpc_serving(frame: &[u8]) -> bool {
+    let message = match frame.get(5..) {
+        Some(message) => message,
+        None => return false,
+    };
+    let mut cursor = 0;
+    let mut serving = false;
+    while let Some(tag) = message.get(cursor) {
+        cursor += 1;
+        let mut value = 0u64;
+        let mut shift = 0;
+        while let Some(byte) = message.get(cursor) {
+            cursor += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        match tag {
+            0x08 => serving = value == 1,
+            tag if tag & 0x07 == 2 => cursor += value as usize,
+            _ => {}
+        }
+    }
+    serving
+}
+async fn grpc_probe(endpoint: String, param: GrpcHealthCheckParam) -> Result<(), AppError> {
+    let url = Url::parse(&endpoint)
+        .and_then(|url| url.join("/grpc.health.v1.Health/Check"))
+        .map_err(|e| AppError(e.to_string()))?;
+    let mut send_request = connect_outbound(&url).await?.ready().await?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .version(Version::HTTP_2)
+        .uri(url.to_string())
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(())?;
+    let (response, mut send_stream) = send_request.send_request(request, false)?;
+    send_stream.send_data(
+        encode_grpc_health_check_request(param.service.as_deref().unwrap_or_default()),
+        true,
+    )?;
+    let (head, mut body) = response.await?.into_parts();
+    if head.status != StatusCode::OK {
+        return Err(AppError(format!(
+            "The grpc health check status code is {}",
+            head.status
+        )));
+    }
+    let mut grpc_status = head.headers.get(GRPC_STATUS_HEADER).cloned();
+    let mut frame = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        body.flow_control().release_capacity(chunk.len())?;
+        frame.extend_from_slice(&chunk);
+    }
+    if let Some(trailers) = body.trailers().await? {
+        if let Some(status) = trailers.get(GRPC_STATUS_HEADER) {
+            grpc_status = Some(status.clone());
+        }
+    }
+    let grpc_status = grpc_status
+        .map(|item| item.to_str().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    if grpc_status != GRPC_STATUS_OK {
+        return Err(AppError(format!(
+            "The grpc health check grpc-status is {}",
+            grpc_status
+        )));
+    }
+    if !is_grpc_serving(&frame) {
+        return Err(AppError::from("The grpc service is not serving"));
+    }
+    Ok(())
+}
+async fn do_tcp_health_check(
+    route: RouteConfig,
+    timeout_number: i32,
+    shared_config: SharedConfig,
+) -> Result<(), AppError> {
+    info!("Do tcp health check,the route is {:?}!", route);
+    do_protocol_health_check(route, timeout_number, shared_config, tcp_probe).await
+}
+async fn do_grpc_health_check(
+    grpc_health_check_param: GrpcHealthCheckParam,
+    route: RouteConfig,
+    timeout_number: i32,
+    shared_config: SharedConfig,
+) -> Result<(), AppError> {
+    info!("Do grpc health check,the route is {:?}!", route);
+    do_protocol_health_check(route, timeout_number, shared_config, |endpoint| {
+        grpc_probe(endpoint, grpc_health_check_param.clone())
+    })
+    .await
+}
+async fn do_redis_health_check(
+    redis_health_check_param: RedisHealthCheckParam,
+    route: RouteConfig,
+    timeout_number: i32,
+    shared_config: SharedConfig,
+) -> Result<(), AppError> {
+    info!("Do redis health check,the route is {:?}!", route);
+    do_protocol_health_check(route, timeout_number, shared_config, |endpoint| {
+        redis_probe(endpoint, redis_health_check_param.clone())
+    })
+    .await
+}
+async fn do_mysql_health_check(
+    mysql_health_check_param: MysqlHealthCheckParam,
+    route: RouteConfig,
+    timeout_number: i32,
+    shared_config: SharedConfig,
+) -> Result<(), AppError> {
+    info!("Do mysql health check,the route is {:?}!", route);
+    do_protocol_health_check(route, timeout_number, shared_config, |endpoint| {
+        mysql_probe(endpoint, mysql_health_check_param.clone())
+    })
+    .await
+}
 fn submit_task(
     task_id: u64,
     route: RouteConfig,
@@ -284,8 +636,36 @@ fn submit_task(
                         )
                         .await
                     }
-                    HealthCheckType::Mysql(_) => Ok(()),
-                    HealthCheckType::Redis(_) => Ok(()),
+                    HealthCheckType::Mysql(mysql_health_check_param) => {
+                        do_mysql_health_check(
+                            mysql_health_check_param,
+                            route_share,
+                            timeout_share,
+                            cloned_shared_config,
+                        )
+                        .await
+                    }
+                    HealthCheckType::Redis(redis_health_check_param) => {
+                        do_redis_health_check(
+                            redis_health_check_param,
+                            route_share,
+                            timeout_share,
+                            cloned_shared_config,
+                        )
+                        .await
+                    }
+                    HealthCheckType::Tcp(_) => {
+                        do_tcp_health_check(route_share, timeout_share, cloned_shared_config).await
+                    }
+                    HealthCheckType::Grpc(grpc_health_check_param) => {
+                        do_grpc_health_check(
+                            grpc_health_check_param,
+                            route_share,
+                            timeout_share,
+                            cloned_shared_config,
+                        )
+                        .await
+                    }
                 }
             }
         };
@@ -309,9 +689,11 @@ mod tests {
     use crate::vojo::app_config::AppConfig;
     use crate::vojo::app_config::LivenessConfig;
     use crate::vojo::health_check::BaseHealthCheckParam;
+    use crate::vojo::health_check::BodyRegex;
     use crate::vojo::router::RandomRoute;
     use mockall::mock;
     use mockall::predicate::*;
+    use regex::Regex;
 
     mock! {
             pub HttpClient {
@@ -357,6 +739,7 @@ mod tests {
                 timeout: 1000,
             },
             path: "/health".to_string(),
+            ..Default::default()
         };
         let shared_config = create_test_shared_config(vec![RouteConfig {
             route_id: "config1".to_string(),
@@ -398,6 +781,7 @@ mod tests {
                 timeout: 1000,
             },
             path: "/health".to_string(),
+            ..Default::default()
         };
         let shared_config = create_test_shared_config(vec![RouteConfig {
             route_id: "config1".to_string(),
@@ -440,6 +824,7 @@ mod tests {
                 timeout: 1,
             },
             path: "/health".to_string(),
+            ..Default::default()
         };
         let task_id = 101;
         let route_config = RouteConfig {
@@ -479,6 +864,7 @@ mod tests {
                 timeout: 1000,
             },
             path: "/health".to_string(),
+            ..Default::default()
         };
         let health_check_clients = HealthCheckClient::new();
         let shared_config = create_test_shared_config(vec![RouteConfig {
@@ -504,12 +890,15 @@ mod tests {
             },
 
             path: "/health".to_string(),
+
+            ..Default::default()
         };
         let shared_config = create_test_shared_config(vec![RouteConfig {
             route_id: "config1".to_string(),
             health_check: Some(HealthCheckType::HttpGet(http_health_check_param.clone())),
             liveness_config: Some(LivenessConfig {
                 min_liveness_count: 1,
+                ..Default::default()
             }),
             ..Default::default()
         }]);
@@ -539,4 +928,466 @@ mod tests {
         let res = get_endpoint_list(route).await;
         assert!(res.len() == 1);
     }
+
+    async fn start_mock_redis_server(password: Option<&'static str>) -> String {
+        use crate::utils::redis_client::{parse_value, RespValue};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buffer = bytes::BytesMut::new();
+                    let mut authed = password.is_none();
+                    loop {
+                        while let Some(RespValue::Array(Some(args))) =
+                            parse_value(&mut buffer).unwrap()
+                        {
+                            let args = args
+                                .into_iter()
+                                .map(|item| match item {
+                                    RespValue::BulkString(Some(b)) => String::from_utf8(b).unwrap(),
+                                    _ => String::new(),
+                                })
+                                .collect::<Vec<String>>();
+                            let res: &[u8] = match args[0].as_str() {
+                                "AUTH" if Some(args.last().unwrap().as_str()) == password => {
+                                    authed = true;
+                                    b"+OK\r\n"
+                                }
+                                "AUTH" => b"-WRONGPASS invalid password\r\n",
+                                _ if !authed => b"-NOAUTH Authentication required.\r\n",
+                                "SELECT" => b"+OK\r\n",
+                                "PING" => b"+PONG\r\n",
+                                _ => b"-ERR unknown command\r\n",
+                            };
+                            stream.write_all(res).await.unwrap();
+                        }
+                        if stream.read_buf(&mut buffer).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        address
+    }
+    async fn get_unused_address() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+    async fn get_alive_map(shared_config: &SharedConfig) -> HashMap<String, Option<bool>> {
+        let route = shared_config.load().api_service_config[&8080].route_configs[0].clone();
+        get_endpoint_alive_list(route).await
+    }
+    async fn get_endpoint_alive_list(mut route: RouteConfig) -> HashMap<String, Option<bool>> {
+        route
+            .router
+            .get_all_route()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.endpoint, item.is_alive))
+            .collect()
+    }
+
+    #[test]
+    fn test_get_socket_address() {
+        assert_eq!(
+            get_socket_address("redis://127.0.0.1:6380/0", Some(DEFAULT_REDIS_PORT)).unwrap(),
+            "127.0.0.1:6380"
+        );
+        assert_eq!(
+            get_socket_address("redis://localhost", Some(DEFAULT_REDIS_PORT)).unwrap(),
+            "localhost:6379"
+        );
+        assert_eq!(
+            get_socket_address("127.0.0.1:3307", Some(DEFAULT_MYSQL_PORT)).unwrap(),
+            "127.0.0.1:3307"
+        );
+        assert_eq!(
+            get_socket_address("db.local", Some(DEFAULT_MYSQL_PORT)).unwrap(),
+            "db.local:3306"
+        );
+        assert_eq!(
+            get_socket_address("[::1]:3307", Some(DEFAULT_MYSQL_PORT)).unwrap(),
+            "[::1]:3307"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redis_health_check_update_alive() {
+        let alive_address = start_mock_redis_server(Some("secret")).await;
+        let dead_address = get_unused_address().await;
+        let route = RouteConfig {
+            route_id: "redis".to_string(),
+            router: crate::vojo::router::Router::Random(RandomRoute::new(vec![
+                format!("redis://{}", alive_address),
+                dead_address.clone(),
+            ])),
+            ..Default::default()
+        };
+        let shared_config = create_test_shared_config(vec![route.clone()]);
+        let param = RedisHealthCheckParam {
+            base_health_check_param: BaseHealthCheckParam {
+                interval: 10,
+                timeout: 1,
+            },
+            username: None,
+            password: Some("secret".to_string()),
+            database: Some(1),
+        };
+        do_redis_health_check(param.clone(), route.clone(), 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map[&format!("redis://{}", alive_address)], Some(true));
+        assert_eq!(alive_map[&dead_address], Some(false));
+
+        let wrong_password_param = RedisHealthCheckParam {
+            password: Some("wrong".to_string()),
+            ..param
+        };
+        do_redis_health_check(wrong_password_param, route, 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(
+            alive_map[&format!("redis://{}", alive_address)],
+            Some(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mysql_health_check_update_alive() {
+        let alive_address =
+            crate::utils::mysql_client::tests::start_mock_mysql_server("secret").await;
+        let dead_address = get_unused_address().await;
+        let route = RouteConfig {
+            route_id: "mysql".to_string(),
+            router: crate::vojo::router::Router::Random(RandomRoute::new(vec![
+                alive_address.clone(),
+                dead_address.clone(),
+            ])),
+            ..Default::default()
+        };
+        let shared_config = create_test_shared_config(vec![route.clone()]);
+        let param = MysqlHealthCheckParam {
+            base_health_check_param: BaseHealthCheckParam {
+                interval: 10,
+                timeout: 1,
+            },
+            user: "root".to_string(),
+            password: Some("secret".to_string()),
+            database: Some("test".to_string()),
+        };
+        do_mysql_health_check(param, route, 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map[&alive_address], Some(true));
+        assert_eq!(alive_map[&dead_address], Some(false));
+    }
+
+    /// Starts a stand-in grpc health server, only the `ok` service is serving.
+    async fn start_mock_grpc_health_server() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut connection = h2::server::handshake(stream).await.unwrap();
+                    while let Some(Ok((request, mut respond))) = connection.accept().await {
+                        let path = request.uri().path().to_string();
+                        let mut body = request.into_body();
+                        let mut frame = vec![];
+                        while let Some(Ok(chunk)) = body.data().await {
+                            frame.extend_from_slice(&chunk);
+                        }
+                        let response = Response::builder()
+                            .header("content-type", "application/grpc")
+                            .body(())
+                            .unwrap();
+                        let mut send_stream = respond.send_response(response, false).unwrap();
+                        let status = if frame.ends_with(b"ok") { 1 } else { 2 };
+                        if path == "/grpc.health.v1.Health/Check" {
+                            send_stream
+                                .send_data(Bytes::from(vec![0, 0, 0, 0, 2, 0x08, status]), false)
+                                .unwrap();
+                        }
+                        let mut trailers = http::HeaderMap::new();
+                        trailers.insert(GRPC_STATUS_HEADER, "0".parse().unwrap());
+                        send_stream.send_trailers(trailers).unwrap();
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_encode_grpc_health_check_request() {
+        assert_eq!(
+            encode_grpc_health_check_request("").to_vec(),
+            vec![0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            encode_grpc_health_check_request("ok").to_vec(),
+            vec![0, 0, 0, 0, 4, 0x0a, 2, b'o', b'k']
+        );
+    }
+
+    #[test]
+    fn test_is_grpc_serving() {
+        assert!(is_grpc_serving(&[0, 0, 0, 0, 2, 0x08, 1]));
+        assert!(!is_grpc_serving(&[0, 0, 0, 0, 2, 0x08, 2]));
+        assert!(!is_grpc_serving(&[0, 0, 0, 0, 0]));
+        assert!(!is_grpc_serving(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_health_check_update_alive() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let alive_address = listener.local_addr().unwrap().to_string();
+        let dead_address = get_unused_address().await;
+        let route = RouteConfig {
+            route_id: "tcp".to_string(),
+            router: crate::vojo::router::Router::Random(RandomRoute::new(vec![
+                alive_address.clone(),
+                dead_address.clone(),
+            ])),
+            ..Default::default()
+        };
+        let shared_config = create_test_shared_config(vec![route.clone()]);
+        do_tcp_health_check(route, 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map[&alive_address], Some(true));
+        assert_eq!(alive_map[&dead_address], Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_health_check_update_alive() {
+        let address = start_mock_grpc_health_server().await;
+        let endpoint = format!("http://{}", address);
+        let dead_endpoint = format!("http://{}", get_unused_address().await);
+        let route = RouteConfig {
+            route_id: "grpc".to_string(),
+            router: crate::vojo::router::Router::Random(RandomRoute::new(vec![
+                endpoint.clone(),
+                dead_endpoint.clone(),
+            ])),
+            ..Default::default()
+        };
+        let shared_config = create_test_shared_config(vec![route.clone()]);
+        let param = GrpcHealthCheckParam {
+            base_health_check_param: BaseHealthCheckParam {
+                interval: 10,
+                timeout: 1,
+            },
+            service: Some("ok".to_string()),
+        };
+        do_grpc_health_check(param.clone(), route.clone(), 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map[&endpoint], Some(true));
+        assert_eq!(alive_map[&dead_endpoint], Some(false));
+
+        let not_serving_param = GrpcHealthCheckParam {
+            service: Some("other".to_string()),
+            ..param
+        };
+        do_grpc_health_check(not_serving_param, route, 1, shared_config.clone())
+            .await
+            .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map[&endpoint], Some(false));
+    }
+
+    fn dummy_response_with_body(
+        status_code: StatusCode,
+        body: &'static str,
+    ) -> Response<BoxBody<Bytes, AppError>> {
+        Response::builder()
+            .status(status_code)
+            .body(Full::new(Bytes::from(body)).map_err(AppError::from).boxed())
+            .unwrap()
+    }
+    async fn run_http_health_check(
+        http_health_check_param: HttpHealthCheckParam,
+        shared_config: SharedConfig,
+        status_code: StatusCode,
+        body: &'static str,
+    ) -> HashMap<String, Option<bool>> {
+        let mut mock_http_client = MockHttpClient::new();
+        mock_http_client
+            .expect_request_http()
+            .returning(move |_, _| Ok(dummy_response_with_body(status_code, body)));
+        let route = shared_config.load().api_service_config[&8080].route_configs[0].clone();
+        do_http_health_check(
+            http_health_check_param,
+            route,
+            1,
+            Arc::new(mock_http_client),
+            shared_config.clone(),
+        )
+        .await
+        .unwrap();
+        get_alive_map(&shared_config).await
+    }
+    fn create_http_route() -> RouteConfig {
+        RouteConfig {
+            route_id: "http".to_string(),
+            router: crate::vojo::router::Router::Random(RandomRoute::new(vec![
+                "http://127.0.0.1:8080".to_string(),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_health_check_thresholds() {
+        let param = HttpHealthCheckParam {
+            healthy_threshold: 2,
+            unhealthy_threshold: 2,
+            ..Default::default()
+        };
+        let shared_config = create_test_shared_config(vec![create_http_route()]);
+        let endpoint = "http://127.0.0.1:8080";
+
+        let alive_map = run_http_health_check(
+            param.clone(),
+            shared_config.clone(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "",
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], None);
+        let alive_map = run_http_health_check(
+            param.clone(),
+            shared_config.clone(),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "",
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(false));
+
+        let alive_map =
+            run_http_health_check(param.clone(), shared_config.clone(), StatusCode::OK, "").await;
+        assert_eq!(alive_map[endpoint], Some(false));
+        let alive_map =
+            run_http_health_check(param.clone(), shared_config.clone(), StatusCode::OK, "").await;
+        assert_eq!(alive_map[endpoint], Some(true));
+
+        // Probes which do not change the liveness keep the snapshot.
+        let snapshot = shared_config.load();
+        run_http_health_check(param, shared_config.clone(), StatusCode::OK, "").await;
+        assert!(Arc::ptr_eq(&snapshot, &shared_config.load()));
+    }
+
+    #[tokio::test]
+    async fn test_http_health_check_expected_statuses() {
+        let shared_config = create_test_shared_config(vec![create_http_route()]);
+        let endpoint = "http://127.0.0.1:8080";
+        let alive_map = run_http_health_check(
+            HttpHealthCheckParam::default(),
+            shared_config.clone(),
+            StatusCode::NO_CONTENT,
+            "",
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(true));
+
+        let alive_map = run_http_health_check(
+            HttpHealthCheckParam::default(),
+            shared_config.clone(),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "",
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(false));
+
+        let param: HttpHealthCheckParam = serde_yaml::from_str(
+            r#"
+base_health_check_param:
+  timeout: 1
+  interval: 1
+path: /health
+expected_statuses: [200, "500-503"]
+"#,
+        )
+        .unwrap();
+        let alive_map = run_http_health_check(
+            param,
+            shared_config.clone(),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "",
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_http_health_check_body_match() {
+        let shared_config = create_test_shared_config(vec![create_http_route()]);
+        let endpoint = "http://127.0.0.1:8080";
+        let param = HttpHealthCheckParam {
+            body_contains: Some("UP".to_string()),
+            body_regex: Some(BodyRegex(Regex::new(r#""db":\s*"ok""#).unwrap())),
+            ..Default::default()
+        };
+        let alive_map = run_http_health_check(
+            param.clone(),
+            shared_config.clone(),
+            StatusCode::OK,
+            r#"{"status":"UP","db": "ok"}"#,
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(true));
+
+        let alive_map = run_http_health_check(
+            param,
+            shared_config.clone(),
+            StatusCode::OK,
+            r#"{"status":"UP","db":"down"}"#,
+        )
+        .await;
+        assert_eq!(alive_map[endpoint], Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_http_health_check_method_and_headers() {
+        let shared_config = create_test_shared_config(vec![create_http_route()]);
+        let mut headers = std::collections::BTreeMap::new();
+        headers.insert("x-probe".to_string(), "spire".to_string());
+        let param = HttpHealthCheckParam {
+            method: Method::HEAD,
+            headers,
+            path: "/ready".to_string(),
+            ..Default::default()
+        };
+        let mut mock_http_client = MockHttpClient::new();
+        mock_http_client
+            .expect_request_http()
+            .withf(|req, _| {
+                req.method() == Method::HEAD
+                    && *req.uri() == "http://127.0.0.1:8080/ready"
+                    && req.headers().get("x-probe").unwrap() == "spire"
+            })
+            .times(1)
+            .returning(|_, _| Ok(dummy_response(StatusCode::OK)));
+        do_http_health_check(
+            param,
+            create_http_route(),
+            1,
+            Arc::new(mock_http_client),
+            shared_config.clone(),
+        )
+        .await
+        .unwrap();
+        let alive_map = get_alive_map(&shared_config).await;
+        assert_eq!(alive_map["http://127.0.0.1:8080"], Some(true));
+    }
 }
@@ -1 +1,2 @@
+pub mod anomaly_detection_task;
 pub mod health_check_task;
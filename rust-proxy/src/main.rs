@@ -53,6 +53,9 @@ async fn run_app(reload_handle: Handle<filter::Targets, Registry>) -> Result<(),
 
     reconfigure_logger(&reload_handle, &config);
     info!("Logger reconfigured to level: {}", config.get_log_level());
+    if let Err(e) = config.check_routes() {
+        warn!("{}", e);
+    }
 
     let admin_port = config.admin_port.unwrap_or(DEFAULT_ADMIN_PORT);
     let shared_config = SharedConfig::from_app_config(config);
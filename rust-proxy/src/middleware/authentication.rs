@@ -5,6 +5,10 @@ use http::HeaderValue;
 
 use serde::{Deserialize, Serialize};
 
+use super::jwt_auth::JwtAuth;
+use super::middlewares::Admission;
+use super::middlewares::Denial;
+use super::middlewares::Grant;
 use crate::vojo::app_error::AppError;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -14,17 +18,27 @@ pub enum Authentication {
     Basic(BasicAuth),
     #[serde(rename = "api_key")]
     ApiKey(ApiKeyAuth),
+    #[serde(rename = "jwt")]
+    Jwt(Box<JwtAuth>),
 }
 
 impl Authentication {
-    pub fn check_authentication(
-        &mut self,
-        headers: &HeaderMap<HeaderValue>,
-    ) -> Result<bool, AppError> {
-        match self {
-            Authentication::Basic(auth) => auth.check_authentication(headers),
-            Authentication::ApiKey(auth) => auth.check_authentication(headers),
+    pub async fn admit(&self, headers: &HeaderMap<HeaderValue>) -> Result<Admission, AppError> {
+        let (is_authenticated, www_authenticate) = match self {
+            Authentication::Basic(auth) => (
+                auth.check_authentication(headers)?,
+                Some("Basic realm=\"spire\"".to_string()),
+            ),
+            // API keys have no standard challenge.
+            Authentication::ApiKey(auth) => (auth.check_authentication(headers)?, None),
+            Authentication::Jwt(auth) => return auth.admit(headers).await,
+        };
+        if !is_authenticated {
+            return Ok(Admission::Denied(Denial::Unauthenticated {
+                www_authenticate,
+            }));
         }
+        Ok(Admission::Allowed(Grant::default()))
     }
 }
 
@@ -34,7 +48,7 @@ pub struct BasicAuth {
 }
 
 impl BasicAuth {
-    fn check_authentication(&mut self, headers: &HeaderMap<HeaderValue>) -> Result<bool, AppError> {
+    fn check_authentication(&self, headers: &HeaderMap<HeaderValue>) -> Result<bool, AppError> {
         if headers.is_empty() || !headers.contains_key("Authorization") {
             return Ok(false);
         }
@@ -58,7 +72,7 @@ pub struct ApiKeyAuth {
 }
 
 impl ApiKeyAuth {
-    fn check_authentication(&mut self, headers: &HeaderMap<HeaderValue>) -> Result<bool, AppError> {
+    fn check_authentication(&self, headers: &HeaderMap<HeaderValue>) -> Result<bool, AppError> {
         if headers.is_empty() || !headers.contains_key(&self.key) {
             return Ok(false);
         }
@@ -73,7 +87,7 @@ mod tests {
 
     #[test]
     fn test_basic_auth_success() {
-        let mut auth = BasicAuth {
+        let auth = BasicAuth {
             credentials: "user:pass".to_string(),
         };
         let encoded = general_purpose::STANDARD_NO_PAD.encode("user:pass");
@@ -88,7 +102,7 @@ mod tests {
 
     #[test]
     fn test_basic_auth_missing_header() {
-        let mut auth = BasicAuth {
+        let auth = BasicAuth {
             credentials: "user:pass".to_string(),
         };
         let headers = HeaderMap::new();
@@ -98,7 +112,7 @@ mod tests {
 
     #[test]
     fn test_basic_auth_invalid_format() {
-        let mut auth = BasicAuth {
+        let auth = BasicAuth {
             credentials: "user:pass".to_string(),
         };
         let mut headers = HeaderMap::new();
@@ -109,7 +123,7 @@ mod tests {
 
     #[test]
     fn test_basic_auth_wrong_credentials() {
-        let mut auth = BasicAuth {
+        let auth = BasicAuth {
             credentials: "user:wrong".to_string(),
         };
         let encoded = general_purpose::STANDARD_NO_PAD.encode("user:pass");
@@ -124,7 +138,7 @@ mod tests {
 
     #[test]
     fn test_api_key_auth_success() {
-        let mut auth = ApiKeyAuth {
+        let auth = ApiKeyAuth {
             key: "X-API-KEY".to_string(),
             value: "secret".to_string(),
         };
@@ -136,7 +150,7 @@ mod tests {
 
     #[test]
     fn test_api_key_auth_missing_header() {
-        let mut auth = ApiKeyAuth {
+        let auth = ApiKeyAuth {
             key: "X-API-KEY".to_string(),
             value: "secret".to_string(),
         };
@@ -147,7 +161,7 @@ mod tests {
 
     #[test]
     fn test_api_key_auth_wrong_value() {
-        let mut auth = ApiKeyAuth {
+        let auth = ApiKeyAuth {
             key: "X-API-KEY".to_string(),
             value: "secret".to_string(),
         };
@@ -159,7 +173,7 @@ mod tests {
 
     #[test]
     fn test_api_key_auth_case_sensitive() {
-        let mut auth = ApiKeyAuth {
+        let auth = ApiKeyAuth {
             key: "X-API-KEY".to_string(),
             value: "Secret".to_string(),
         };
@@ -169,9 +183,9 @@ mod tests {
         assert!(!auth.check_authentication(&headers).unwrap());
     }
 
-    #[test]
-    fn test_authentication_enum_basic() {
-        let mut auth = Authentication::Basic(BasicAuth {
+    #[tokio::test]
+    async fn test_authentication_enum_basic() {
+        let auth = Authentication::Basic(BasicAuth {
             credentials: "admin:admin".to_string(),
         });
         let encoded = general_purpose::STANDARD_NO_PAD.encode("admin:admin");
@@ -181,24 +195,24 @@ mod tests {
             HeaderValue::from_str(&format!("Basic {}", encoded)).unwrap(),
         );
 
-        assert!(auth.check_authentication(&headers).unwrap());
+        assert!(auth.admit(&headers).await.unwrap().is_allowed());
     }
 
-    #[test]
-    fn test_authentication_enum_api_key() {
-        let mut auth = Authentication::ApiKey(ApiKeyAuth {
+    #[tokio::test]
+    async fn test_authentication_enum_api_key() {
+        let auth = Authentication::ApiKey(ApiKeyAuth {
             key: "Authorization".to_string(),
             value: "Bearer token".to_string(),
         });
         let mut headers = HeaderMap::new();
         headers.insert("Authorization", HeaderValue::from_static("Bearer token"));
 
-        assert!(auth.check_authentication(&headers).unwrap());
+        assert!(auth.admit(&headers).await.unwrap().is_allowed());
     }
 
     #[test]
     fn test_invalid_header_value() {
-        let mut auth = BasicAuth {
+        let auth = BasicAuth {
             credentials: "user:pass".to_string(),
         };
         let mut headers = HeaderMap::new();
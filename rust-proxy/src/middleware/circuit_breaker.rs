@@ -0,0 +1,409 @@
+use crate::monitor::prometheus_exporter::set_circuit_breaker_state;
+use crate::vojo::runtime_state::RuntimeState;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+const WINDOW_BUCKETS: u32 = 10;
+
+/// Stops sending requests to an endpoint which keeps failing. The circuit of an endpoint opens
+/// when any configured threshold is crossed, stays open for `open_ms`, and then lets
+/// `half_open_requests` trial requests through, which close it again if they all succeed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreaker {
+    /// The length of the rolling window which the rates are computed over.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// The rates are only evaluated once the window holds this many requests.
+    #[serde(default = "default_minimum_requests")]
+    pub minimum_requests: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consecutive_failures: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_rate_percent: Option<f64>,
+    /// Requests slower than `slow_request_ms` count as slow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_request_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slow_rate_percent: Option<f64>,
+    #[serde(default = "default_open_ms")]
+    pub open_ms: u64,
+    #[serde(default = "default_half_open_requests")]
+    pub half_open_requests: u32,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub circuits: RuntimeState<DashMap<String, Circuit>>,
+}
+fn default_window_ms() -> u64 {
+    10_000
+}
+fn default_minimum_requests() -> u64 {
+    10
+}
+fn default_open_ms() -> u64 {
+    30_000
+}
+fn default_half_open_requests() -> u32 {
+    1
+}
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            window_ms: default_window_ms(),
+            minimum_requests: default_minimum_requests(),
+            consecutive_failures: None,
+            error_rate_percent: None,
+            slow_request_ms: None,
+            slow_rate_percent: None,
+            open_ms: default_open_ms(),
+            half_open_requests: default_half_open_requests(),
+            circuits: RuntimeState::default(),
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+impl CircuitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+#[derive(Debug, Default)]
+struct WindowBucket {
+    requests: u64,
+    failures: u64,
+    slow_requests: u64,
+}
+#[derive(Debug)]
+pub struct Circuit {
+    state: CircuitState,
+    opened_at: Instant,
+    consecutive_failures: u32,
+    half_open_in_flight: u32,
+    half_open_successes: u32,
+    /// Bumped on every transition, so a trial slot of an earlier half-open period is not
+    /// released into a later one.
+    generation: u64,
+    bucket_started_at: Instant,
+    buckets: VecDeque<WindowBucket>,
+}
+impl Default for Circuit {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            opened_at: Instant::now(),
+            consecutive_failures: 0,
+            half_open_in_flight: 0,
+            half_open_successes: 0,
+            generation: 0,
+            bucket_started_at: Instant::now(),
+            buckets: VecDeque::from([WindowBucket::default()]),
+        }
+    }
+}
+impl Circuit {
+    fn rotate_buckets(&mut self, bucket_length: Duration) {
+        while self.bucket_started_at.elapsed() >= bucket_length {
+            self.bucket_started_at += bucket_length;
+            self.buckets.push_back(WindowBucket::default());
+            if self.buckets.len() > WINDOW_BUCKETS as usize {
+                self.buckets.pop_front();
+            }
+            if self.buckets.iter().all(|bucket| bucket.requests == 0) {
+                self.bucket_started_at = Instant::now();
+            }
+        }
+    }
+    fn reset_window(&mut self) {
+        self.consecutive_failures = 0;
+        self.bucket_started_at = Instant::now();
+        self.buckets = VecDeque::from([WindowBucket::default()]);
+    }
+}
+impl CircuitBreaker {
+    fn bucket_length(&self) -> Duration {
+        Duration::from_millis((self.window_ms / WINDOW_BUCKETS as u64).max(1))
+    }
+    fn transition(&self, route_id: &str, endpoint: &str, circuit: &mut Circuit, to: CircuitState) {
+        info!(
+            "The circuit of {} in the route {} changes from {} to {}.",
+            endpoint,
+            route_id,
+            circuit.state.as_str(),
+            to.as_str()
+        );
+        circuit.state = to;
+        circuit.half_open_in_flight = 0;
+        circuit.half_open_successes = 0;
+        circuit.generation += 1;
+        match to {
+            CircuitState::Open => circuit.opened_at = Instant::now(),
+            CircuitState::Closed => circuit.reset_window(),
+            CircuitState::HalfOpen => {}
+        }
+        set_circuit_breaker_state(route_id, endpoint, to.as_str());
+    }
+    /// Returns whether a request may be sent to the endpoint. An open circuit turns half-open
+    /// once `open_ms` has passed.
+    pub fn is_available(&self, route_id: &str, endpoint: &str) -> bool {
+        let Some(mut circuit) = self.circuits.get_mut(endpoint) else {
+            return true;
+        };
+        if circuit.state == CircuitState::Open
+            && circuit.opened_at.elapsed() >= Duration::from_millis(self.open_ms)
+        {
+            self.transition(route_id, endpoint, &mut circuit, CircuitState::HalfOpen);
+        }
+        match circuit.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => circuit.half_open_in_flight < self.half_open_requests,
+        }
+    }
+    /// Returns the endpoints among `endpoints` which must not be picked.
+    pub fn unavailable_endpoints(&self, route_id: &str, endpoints: &[String]) -> Vec<String> {
+        endpoints
+            .iter()
+            .filter(|endpoint| !self.is_available(route_id, endpoint))
+            .cloned()
+            .collect()
+    }
+    /// Starts a request to the endpoint right before it is sent. While the circuit is half-open
+    /// the request takes a trial slot, which is given back when its result is recorded or when
+    /// the request is dropped without one.
+    pub fn start_request(&self, endpoint: &str) -> CircuitRequest {
+        let mut trial_generation = None;
+        if let Some(mut circuit) = self.circuits.get_mut(endpoint) {
+            if circuit.state == CircuitState::HalfOpen
+                && circuit.half_open_in_flight < self.half_open_requests
+            {
+                circuit.half_open_in_flight += 1;
+                trial_generation = Some(circuit.generation);
+            }
+        }
+        CircuitRequest {
+            circuits: self.circuits.clone(),
+            endpoint: endpoint.to_string(),
+            trial_generation,
+        }
+    }
+    pub fn record_result(
+        &self,
+        route_id: &str,
+        mut request: CircuitRequest,
+        is_failure: bool,
+        latency: Duration,
+    ) {
+        let trial_generation = request.trial_generation.take();
+        let endpoint = request.endpoint.as_str();
+        let mut circuit = self.circuits.entry(endpoint.to_string()).or_default();
+        match circuit.state {
+            CircuitState::Open => {}
+            CircuitState::HalfOpen => {
+                // Only the trial requests of the current half-open period decide it.
+                if trial_generation != Some(circuit.generation) {
+                    return;
+                }
+                circuit.half_open_in_flight = circuit.half_open_in_flight.saturating_sub(1);
+                if is_failure {
+                    self.transition(route_id, endpoint, &mut circuit, CircuitState::Open);
+                } else {
+                    circuit.half_open_successes += 1;
+                    if circuit.half_open_successes >= self.half_open_requests {
+                        self.transition(route_id, endpoint, &mut circuit, CircuitState::Closed);
+                    }
+                }
+            }
+            CircuitState::Closed => {
+                circuit.rotate_buckets(self.bucket_length());
+                let is_slow = self.slow_request_ms.is_some_and(|slow_request_ms| {
+                    latency >= Duration::from_millis(slow_request_ms)
+                });
+                if let Some(bucket) = circuit.buckets.back_mut() {
+                    bucket.requests += 1;
+                    bucket.failures += is_failure as u64;
+                    bucket.slow_requests += is_slow as u64;
+                }
+                circuit.consecutive_failures = match is_failure {
+                    true => circuit.consecutive_failures + 1,
+                    false => 0,
+                };
+                if self.should_trip(&circuit) {
+                    self.transition(route_id, endpoint, &mut circuit, CircuitState::Open);
+                }
+            }
+        }
+    }
+    fn should_trip(&self, circuit: &Circuit) -> bool {
+        if self
+            .consecutive_failures
+            .is_some_and(|threshold| circuit.consecutive_failures >= threshold)
+        {
+            return true;
+        }
+        let (requests, failures, slow_requests) = circuit.buckets.iter().fold(
+            (0, 0, 0),
+            |(requests, failures, slow_requests), bucket| {
+                (
+                    requests + bucket.requests,
+                    failures + bucket.failures,
+                    slow_requests + bucket.slow_requests,
+                )
+            },
+        );
+        if requests == 0 || requests < self.minimum_requests {
+            return false;
+        }
+        let exceeds = |count: u64, percent: Option<f64>| {
+            percent.is_some_and(|percent| count as f64 * 100.0 / requests as f64 >= percent)
+        };
+        exceeds(failures, self.error_rate_percent) || exceeds(slow_requests, self.slow_rate_percent)
+    }
+}
+/// A request which has been sent to an endpoint guarded by a circuit breaker.
+pub struct CircuitRequest {
+    circuits: RuntimeState<DashMap<String, Circuit>>,
+    endpoint: String,
+    trial_generation: Option<u64>,
+}
+impl Drop for CircuitRequest {
+    fn drop(&mut self) {
+        let Some(trial_generation) = self.trial_generation.take() else {
+            return;
+        };
+        if let Some(mut circuit) = self.circuits.get_mut(&self.endpoint) {
+            if circuit.state == CircuitState::HalfOpen && circuit.generation == trial_generation {
+                circuit.half_open_in_flight = circuit.half_open_in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        circuit_breaker: &CircuitBreaker,
+        endpoint: &str,
+        is_failure: bool,
+        latency: Duration,
+    ) {
+        let request = circuit_breaker.start_request(endpoint);
+        circuit_breaker.record_result("route", request, is_failure, latency);
+    }
+
+    #[test]
+    fn test_consecutive_failures_open_the_circuit() {
+        let circuit_breaker = CircuitBreaker {
+            consecutive_failures: Some(3),
+            open_ms: 20,
+            half_open_requests: 2,
+            ..Default::default()
+        };
+        let latency = Duration::from_millis(1);
+        for is_failure in [true, true, false, true, true] {
+            record(&circuit_breaker, "a", is_failure, latency);
+        }
+        assert!(circuit_breaker.is_available("route", "a"));
+        record(&circuit_breaker, "a", true, latency);
+        assert!(!circuit_breaker.is_available("route", "a"));
+        assert_eq!(
+            circuit_breaker.unavailable_endpoints("route", &["a".to_string(), "b".to_string()]),
+            vec!["a".to_string()]
+        );
+
+        std::thread::sleep(Duration::from_millis(30));
+        let mut trials = Vec::new();
+        for _ in 0..2 {
+            assert!(circuit_breaker.is_available("route", "a"));
+            trials.push(circuit_breaker.start_request("a"));
+        }
+        assert!(!circuit_breaker.is_available("route", "a"));
+        for (trial, is_failure) in trials.into_iter().zip([false, true]) {
+            circuit_breaker.record_result("route", trial, is_failure, latency);
+        }
+        assert!(!circuit_breaker.is_available("route", "a"));
+
+        std::thread::sleep(Duration::from_millis(30));
+        for _ in 0..2 {
+            assert!(circuit_breaker.is_available("route", "a"));
+            record(&circuit_breaker, "a", false, latency);
+        }
+        assert_eq!(
+            circuit_breaker.circuits.get("a").unwrap().state,
+            CircuitState::Closed
+        );
+    }
+
+    #[test]
+    fn test_dropped_trial_requests_release_their_slot() {
+        let circuit_breaker = CircuitBreaker {
+            consecutive_failures: Some(1),
+            open_ms: 20,
+            ..Default::default()
+        };
+        let latency = Duration::from_millis(1);
+        record(&circuit_breaker, "a", true, latency);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(circuit_breaker.is_available("route", "a"));
+        let trial = circuit_breaker.start_request("a");
+        assert!(!circuit_breaker.is_available("route", "a"));
+        drop(trial);
+        assert!(circuit_breaker.is_available("route", "a"));
+
+        let trial = circuit_breaker.start_request("a");
+        record(&circuit_breaker, "a", false, latency);
+        assert_eq!(
+            circuit_breaker.circuits.get("a").unwrap().state,
+            CircuitState::HalfOpen
+        );
+        circuit_breaker.record_result("route", trial, true, latency);
+        assert!(!circuit_breaker.is_available("route", "a"));
+    }
+
+    #[test]
+    fn test_error_and_slow_rates_open_the_circuit() {
+        let circuit_breaker = CircuitBreaker {
+            minimum_requests: 4,
+            error_rate_percent: Some(50.0),
+            slow_request_ms: Some(100),
+            slow_rate_percent: Some(75.0),
+            ..Default::default()
+        };
+        let fast = Duration::from_millis(1);
+        let slow = Duration::from_millis(200);
+        record(&circuit_breaker, "a", true, fast);
+        record(&circuit_breaker, "a", true, fast);
+        assert!(circuit_breaker.is_available("route", "a"));
+        record(&circuit_breaker, "a", false, fast);
+        record(&circuit_breaker, "a", false, fast);
+        assert!(!circuit_breaker.is_available("route", "a"));
+
+        for _ in 0..3 {
+            record(&circuit_breaker, "b", false, slow);
+        }
+        assert!(circuit_breaker.is_available("route", "b"));
+        record(&circuit_breaker, "b", false, slow);
+        assert!(!circuit_breaker.is_available("route", "b"));
+    }
+
+    #[test]
+    fn test_deserialize_circuit_breaker() {
+        let circuit_breaker: CircuitBreaker =
+            serde_yaml::from_str("consecutive_failures: 5\nopen_ms: 1000").unwrap();
+        assert_eq!(circuit_breaker.consecutive_failures, Some(5));
+        assert_eq!(circuit_breaker.open_ms, 1000);
+        assert_eq!(circuit_breaker.window_ms, 10_000);
+        assert_eq!(circuit_breaker.half_open_requests, 1);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::constants::common_constants::GRPC_STATUS_HEADER;
+use crate::proxy::proxy_trait::LocalResponse;
+use crate::vojo::app_error::AppError;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+const FAULT_MESSAGE: &str = "fault injected";
+
+/// Injects faults into the requests of the route for resilience testing. A triggered delay is
+/// applied first, then a triggered reset or abort replaces the upstream response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fault {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay: Option<DelayFault>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abort: Option<AbortFault>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset: Option<ResetFault>,
+}
+/// Decides which requests a fault applies to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaultTrigger {
+    #[serde(default = "default_percent", deserialize_with = "deserialize_percent")]
+    pub percent: f64,
+    /// Only requests which carry this header are affected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+}
+fn default_percent() -> f64 {
+    100.0
+}
+fn deserialize_percent<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let percent = f64::deserialize(deserializer)?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(serde::de::Error::custom(format!(
+            "the fault percent must be between 0 and 100, not {}",
+            percent
+        )));
+    }
+    Ok(percent)
+}
+impl FaultTrigger {
+    fn is_triggered(&self, headers: &HeaderMap<HeaderValue>) -> bool {
+        if let Some(header) = &self.header {
+            if !headers.contains_key(header.as_str()) {
+                return false;
+            }
+        }
+        rand::rng().random_bool((self.percent / 100.0).clamp(0.0, 1.0))
+    }
+}
+/// Delays the request by `duration_ms` plus a random jitter of up to `jitter_ms`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DelayFault {
+    #[serde(flatten)]
+    pub trigger: FaultTrigger,
+    pub duration_ms: u64,
+    pub jitter_ms: u64,
+}
+impl<'de> Deserialize<'de> for DelayFault {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Inner {
+            #[serde(flatten)]
+            trigger: FaultTrigger,
+            duration_ms: u64,
+            #[serde(default)]
+            jitter_ms: u64,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+        if inner.duration_ms.checked_add(inner.jitter_ms).is_none() {
+            return Err(serde::de::Error::custom(
+                "the fault delay and its jitter are too long",
+            ));
+        }
+        Ok(DelayFault {
+            trigger: inner.trigger,
+            duration_ms: inner.duration_ms,
+            jitter_ms: inner.jitter_ms,
+        })
+    }
+}
+impl DelayFault {
+    fn delay(&self) -> Duration {
+        let jitter_ms = rand::rng().random_range(0..=self.jitter_ms);
+        Duration::from_millis(self.duration_ms.saturating_add(jitter_ms))
+    }
+}
+/// Answers with `http_status`, or with a gRPC trailers-only response if `grpc_status` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AbortFault {
+    #[serde(flatten)]
+    pub trigger: FaultTrigger,
+    #[serde(
+        default = "default_http_status",
+        deserialize_with = "deserialize_http_status"
+    )]
+    pub http_status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc_status: Option<u32>,
+}
+fn default_http_status() -> u16 {
+    503
+}
+fn deserialize_http_status<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let http_status = u16::deserialize(deserializer)?;
+    StatusCode::from_u16(http_status).map_err(|_| {
+        serde::de::Error::custom(format!(
+            "the fault status {} is no valid status code",
+            http_status
+        ))
+    })?;
+    Ok(http_status)
+}
+impl AbortFault {
+    fn to_local_response(&self) -> Result<LocalResponse, AppError> {
+        let Some(grpc_status) = self.grpc_status else {
+            let status =
+                StatusCode::from_u16(self.http_status).map_err(|e| AppError(e.to_string()))?;
+            return Ok(LocalResponse::new(status, FAULT_MESSAGE));
+        };
+        let mut local_response = LocalResponse::new(StatusCode::OK, "");
+        let headers = &mut local_response.headers;
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        headers.insert(GRPC_STATUS_HEADER, HeaderValue::from(grpc_status));
+        headers.insert("grpc-message", HeaderValue::from_static(FAULT_MESSAGE));
+        Ok(local_response)
+    }
+}
+/// Closes the connection, or resets the stream for HTTP/2, without answering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResetFault {
+    #[serde(flatten)]
+    pub trigger: FaultTrigger,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultAction {
+    Abort(LocalResponse),
+    Reset,
+}
+/// Marks a response whose connection has to be closed instead of being answered.
+#[derive(Debug, Clone, Copy)]
+pub struct ResetConnection;
+
+impl Fault {
+    /// Waits out a triggered delay and returns the triggered fault which replaces the upstream
+    /// response, if any.
+    pub async fn inject(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Result<Option<FaultAction>, AppError> {
+        if let Some(delay) = self
+            .delay
+            .as_ref()
+            .filter(|d| d.trigger.is_triggered(headers))
+        {
+            tokio::time::sleep(delay.delay()).await;
+        }
+        if self
+            .reset
+            .as_ref()
+            .is_some_and(|reset| reset.trigger.is_triggered(headers))
+        {
+            return Ok(Some(FaultAction::Reset));
+        }
+        match self
+            .abort
+            .as_ref()
+            .filter(|a| a.trigger.is_triggered(headers))
+        {
+            Some(abort) => Ok(Some(FaultAction::Abort(abort.to_local_response()?))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[tokio::test]
+    async fn test_delay_and_abort() {
+        let fault: Fault = serde_yaml::from_str(
+            r#"
+delay:
+  duration_ms: 50
+  jitter_ms: 10
+abort:
+  percent: 100
+  http_status: 418
+"#,
+        )
+        .unwrap();
+        let started_at = Instant::now();
+        let action = fault.inject(&HeaderMap::new()).await.unwrap();
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+        match action {
+            Some(FaultAction::Abort(local_response)) => {
+                assert_eq!(local_response.status, StatusCode::IM_A_TEAPOT);
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_faults_are_rejected() {
+        for invalid in [
+            "abort:\n  percent: .nan",
+            "reset:\n  percent: 101",
+            "abort:\n  http_status: 1000",
+            "delay:\n  duration_ms: 18446744073709551615\n  jitter_ms: 1",
+        ] {
+            assert!(
+                serde_yaml::from_str::<Fault>(invalid).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_gated_faults() {
+        let fault: Fault = serde_yaml::from_str(
+            r#"
+abort:
+  header: x-chaos
+  grpc_status: 14
+reset:
+  percent: 0
+"#,
+        )
+        .unwrap();
+        assert_eq!(fault.inject(&HeaderMap::new()).await.unwrap(), None);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-chaos", HeaderValue::from_static("1"));
+        match fault.inject(&headers).await.unwrap() {
+            Some(FaultAction::Abort(local_response)) => {
+                assert_eq!(local_response.status, StatusCode::OK);
+                assert_eq!(local_response.headers["grpc-status"], "14");
+                assert_eq!(local_response.headers["content-type"], "application/grpc");
+            }
+            other => panic!("unexpected action {:?}", other),
+        }
+
+        let reset = Fault {
+            reset: Some(ResetFault {
+                trigger: FaultTrigger {
+                    percent: 100.0,
+                    header: None,
+                },
+            }),
+            ..fault
+        };
+        assert_eq!(
+            reset.inject(&headers).await.unwrap(),
+            Some(FaultAction::Reset)
+        );
+    }
+}
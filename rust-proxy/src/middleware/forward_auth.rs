@@ -0,0 +1,553 @@
+use crate::constants::common_constants::AUTH_SERVICE_UNAVAILABLE;
+use crate::middleware::middlewares::Admission;
+use crate::middleware::middlewares::Denial;
+use crate::middleware::middlewares::Grant;
+use crate::proxy::http1::http_client::HttpClients;
+use crate::proxy::proxy_trait::LocalResponse;
+use crate::vojo::app_error::AppError;
+use crate::vojo::route_matcher::RequestInfo;
+use crate::vojo::runtime_state::RuntimeState;
+use bytes::Bytes;
+use http::header;
+use http::response::Parts;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::StatusCode;
+use http::Uri;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use lru::LruCache;
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The headers which only concern a single connection, they are neither sent to the
+/// authorization service nor returned from it.
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Caches the allowed decisions by the method, uri and host of the request and the values of the
+/// key headers, so the decision must only depend on them. Denials are never cached, and requests
+/// without any key header are always sent to the authorization service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecisionCache {
+    #[serde(default = "default_key_headers")]
+    pub key_headers: Vec<String>,
+    /// Keys the decisions by the key headers alone, which shares them between all requests of a
+    /// client. Only fits services which decide by the identity and not by the request.
+    #[serde(default)]
+    pub headers_only: bool,
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+fn default_key_headers() -> Vec<String> {
+    vec![String::from("authorization"), String::from("cookie")]
+}
+fn default_ttl_seconds() -> u64 {
+    60
+}
+fn default_max_entries() -> usize {
+    10_000
+}
+impl DecisionCache {
+    fn key(&self, request: &RequestInfo<'_>) -> Option<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        if !self.headers_only {
+            for part in [
+                request.method.as_str(),
+                request.path,
+                request.query.unwrap_or_default(),
+                request.host.unwrap_or_default(),
+            ] {
+                hasher.update(part.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        let mut has_key_header = false;
+        for name in self.key_headers.iter() {
+            for value in request.headers.get_all(name.as_str()) {
+                has_key_header = true;
+                hasher.update(name.to_ascii_lowercase().as_bytes());
+                hasher.update([0]);
+                hasher.update(value.as_bytes());
+                hasher.update([0]);
+            }
+        }
+        has_key_header.then(|| hasher.finalize().into())
+    }
+}
+struct CachedDecision {
+    upstream_headers: Vec<(HeaderName, Option<HeaderValue>)>,
+    expires_at: Instant,
+}
+pub struct ForwardAuthState {
+    decisions: Mutex<LruCache<[u8; 32], CachedDecision>>,
+    client: OnceLock<HttpClients>,
+}
+impl Default for ForwardAuthState {
+    fn default() -> Self {
+        Self {
+            decisions: Mutex::new(LruCache::unbounded()),
+            client: OnceLock::new(),
+        }
+    }
+}
+impl std::fmt::Debug for ForwardAuthState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cached_decisions = self
+            .decisions
+            .lock()
+            .map(|decisions| decisions.len())
+            .unwrap_or_default();
+        f.debug_struct("ForwardAuthState")
+            .field("cached_decisions", &cached_decisions)
+            .finish()
+    }
+}
+/// Asks an external authorization service about every request, like the `auth_request` of
+/// Nginx. The service gets a GET with the selected request headers and the original request in
+/// the `X-Forwarded-Method`, `X-Forwarded-Uri`, `X-Forwarded-Host` and `X-Forwarded-For`
+/// headers. A 2xx answer allows the request, any other answer is returned to the client.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForwardAuth {
+    /// The absolute http or https uri of the service.
+    pub address: String,
+    /// The request headers which are sent to the service, all but the hop-by-hop ones if empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub request_headers: Vec<String>,
+    /// The headers of an allowing answer which are copied onto the upstream request. They are
+    /// removed from the request even if the answer lacks them, so clients can not set them.
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_header_names"
+    )]
+    pub response_headers: Vec<HeaderName>,
+    pub timeout_ms: u64,
+    /// Allows the requests when the service can not be reached, instead of answering a 503.
+    pub fail_open: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<DecisionCache>,
+    #[serde(skip_serializing)]
+    pub state: RuntimeState<ForwardAuthState>,
+}
+fn default_timeout_ms() -> u64 {
+    2000
+}
+fn serialize_header_names<S>(names: &[HeaderName], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut seq = serializer.serialize_seq(Some(names.len()))?;
+    for name in names {
+        seq.serialize_element(name.as_str())?;
+    }
+    seq.end()
+}
+impl<'de> Deserialize<'de> for ForwardAuth {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Inner {
+            address: String,
+            #[serde(default)]
+            request_headers: Vec<String>,
+            #[serde(default)]
+            response_headers: Vec<String>,
+            #[serde(default = "default_timeout_ms")]
+            timeout_ms: u64,
+            #[serde(default)]
+            fail_open: bool,
+            #[serde(default)]
+            cache: Option<DecisionCache>,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+        let is_valid_address = inner.address.parse::<Uri>().is_ok_and(|uri| {
+            matches!(uri.scheme_str(), Some("http") | Some("https")) && uri.authority().is_some()
+        });
+        if !is_valid_address {
+            return Err(serde::de::Error::custom(format!(
+                "the authorization service address {} must be an absolute http or https uri",
+                inner.address
+            )));
+        }
+        let response_headers = inner
+            .response_headers
+            .iter()
+            .map(|name| {
+                HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                    serde::de::Error::custom(format!(
+                        "the response header {} is no valid header name",
+                        name
+                    ))
+                })
+            })
+            .collect::<Result<Vec<HeaderName>, D::Error>>()?;
+        Ok(ForwardAuth {
+            address: inner.address,
+            request_headers: inner.request_headers,
+            response_headers,
+            timeout_ms: inner.timeout_ms,
+            fail_open: inner.fail_open,
+            cache: inner.cache,
+            state: RuntimeState::default(),
+        })
+    }
+}
+impl ForwardAuth {
+    pub async fn admit(&self, request: &RequestInfo<'_>) -> Result<Admission, AppError> {
+        let cache_key = self.cache.as_ref().and_then(|cache| cache.key(request));
+        if let Some(upstream_headers) = cache_key.and_then(|key| self.cached_decision(&key)) {
+            return Ok(Admission::Allowed(Grant {
+                upstream_headers,
+                ..Default::default()
+            }));
+        }
+        let timeout = Duration::from_millis(self.timeout_ms);
+        let (parts, body) = match tokio::time::timeout(timeout, self.ask(request)).await {
+            Ok(Ok(answer)) => answer,
+            Ok(Err(err)) => return self.unavailable(&err.to_string()),
+            Err(_) => return self.unavailable("the request timed out"),
+        };
+        if !parts.status.is_success() {
+            let mut local_response = LocalResponse::new(parts.status, body);
+            local_response.headers = parts.headers;
+            for name in HOP_BY_HOP_HEADERS.iter() {
+                local_response.headers.remove(name);
+            }
+            return Ok(Admission::Denied(Denial::ForwardAuth(local_response)));
+        }
+        let upstream_headers = self.upstream_headers(Some(&parts.headers));
+        if let (Some(cache), Some(key)) = (&self.cache, cache_key) {
+            let mut decisions = self.state.decisions.lock()?;
+            while decisions.len() >= cache.max_entries.max(1) {
+                decisions.pop_lru();
+            }
+            decisions.put(
+                key,
+                CachedDecision {
+                    upstream_headers: upstream_headers.clone(),
+                    expires_at: Instant::now() + Duration::from_secs(cache.ttl_seconds),
+                },
+            );
+        }
+        Ok(Admission::Allowed(Grant {
+            upstream_headers,
+            ..Default::default()
+        }))
+    }
+    fn cached_decision(&self, key: &[u8; 32]) -> Option<Vec<(HeaderName, Option<HeaderValue>)>> {
+        let mut decisions = self.state.decisions.lock().ok()?;
+        let cached_decision = decisions.get(key)?;
+        if cached_decision.expires_at > Instant::now() {
+            return Some(cached_decision.upstream_headers.clone());
+        }
+        decisions.pop(key);
+        None
+    }
+    fn unavailable(&self, reason: &str) -> Result<Admission, AppError> {
+        warn!(
+            "The authorization service {} failed: {}.",
+            self.address, reason
+        );
+        if self.fail_open {
+            return Ok(Admission::Allowed(Grant {
+                upstream_headers: self.upstream_headers(None),
+                ..Default::default()
+            }));
+        }
+        Ok(Admission::Denied(Denial::ForwardAuth(LocalResponse::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            AUTH_SERVICE_UNAVAILABLE,
+        ))))
+    }
+    async fn ask(&self, request: &RequestInfo<'_>) -> Result<(Parts, Bytes), AppError> {
+        let auth_request = self.auth_request(request)?;
+        let client = self.state.client.get_or_init(HttpClients::new);
+        let response = client
+            .request(auth_request, Duration::from_millis(self.timeout_ms))
+            .await??;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        Ok((parts, body))
+    }
+    fn auth_request(
+        &self,
+        request: &RequestInfo<'_>,
+    ) -> Result<Request<BoxBody<Bytes, AppError>>, AppError> {
+        let mut auth_request = Request::get(self.address.as_str())
+            .body(Full::new(Bytes::new()).map_err(AppError::from).boxed())?;
+        let headers = auth_request.headers_mut();
+        for (name, value) in request.headers.iter() {
+            let is_selected = if self.request_headers.is_empty() {
+                !HOP_BY_HOP_HEADERS.contains(name)
+            } else {
+                self.request_headers
+                    .iter()
+                    .any(|item| name.as_str().eq_ignore_ascii_case(item))
+            };
+            if is_selected {
+                headers.append(name, value.clone());
+            }
+        }
+        let uri = match request.query {
+            Some(query) => format!("{}?{}", request.path, query),
+            None => request.path.to_string(),
+        };
+        headers.insert(
+            "x-forwarded-method",
+            HeaderValue::from_str(request.method.as_str())?,
+        );
+        headers.insert("x-forwarded-uri", HeaderValue::from_str(&uri)?);
+        if let Some(host) = request.host {
+            headers.insert("x-forwarded-host", HeaderValue::from_str(host)?);
+        }
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_str(&request.peer_addr.ip().to_string())?,
+        );
+        Ok(auth_request)
+    }
+    /// Takes the configured response headers from the answer, a name without a value removes
+    /// the header from the upstream request.
+    fn upstream_headers(
+        &self,
+        answer_headers: Option<&HeaderMap<HeaderValue>>,
+    ) -> Vec<(HeaderName, Option<HeaderValue>)> {
+        let mut upstream_headers = vec![];
+        for name in self.response_headers.iter() {
+            let values: Vec<HeaderValue> = answer_headers
+                .map(|headers| headers.get_all(name).iter().cloned().collect())
+                .unwrap_or_default();
+            if values.is_empty() {
+                upstream_headers.push((name.clone(), None));
+                continue;
+            }
+            for value in values {
+                upstream_headers.push((name.clone(), Some(value)));
+            }
+        }
+        upstream_headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Method;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Allows the `Bearer good` token and redirects everything else to a login page. Every
+    /// request is reported with its forwarded method, uri and host.
+    async fn start_auth_service() -> (String, mpsc::UnboundedReceiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let header = |name: &str| {
+                            req.headers()
+                                .get(name)
+                                .map(|value| value.to_str().unwrap().to_string())
+                                .unwrap_or_default()
+                        };
+                        let _ = sender.send(vec![
+                            header("x-forwarded-method"),
+                            header("x-forwarded-uri"),
+                            header("x-forwarded-host"),
+                            header("cookie"),
+                        ]);
+                        let response = if header("authorization") == "Bearer good" {
+                            Response::builder()
+                                .header("x-user", "alice")
+                                .header("x-internal", "secret")
+                                .body(Full::new(Bytes::new()))
+                        } else {
+                            Response::builder()
+                                .status(StatusCode::FOUND)
+                                .header("location", "https://sso.example.com/login")
+                                .body(Full::new(Bytes::from("login")))
+                        };
+                        async move { Ok::<_, hyper::Error>(response.unwrap()) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        (format!("http://{}/verify", addr), receiver)
+    }
+    async fn admit(forward_auth: &ForwardAuth, token: &str) -> Admission {
+        admit_request(forward_auth, Method::POST, "/orders?id=7", token).await
+    }
+    async fn admit_request(
+        forward_auth: &ForwardAuth,
+        method: Method,
+        uri: &'static str,
+        token: &str,
+    ) -> Admission {
+        let uri = Uri::from_static(uri);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "api.example.com".parse().unwrap());
+        headers.insert(header::AUTHORIZATION, token.parse().unwrap());
+        headers.insert(header::COOKIE, "session=1".parse().unwrap());
+        let peer_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let request = RequestInfo::new(&method, &uri, &headers, &peer_addr);
+        forward_auth.admit(&request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_forward_auth_allows_and_denies() {
+        let (address, mut receiver) = start_auth_service().await;
+        let forward_auth: ForwardAuth = serde_yaml::from_str(&format!(
+            "address: {}\nrequest_headers: [authorization]\nresponse_headers: [x-user]",
+            address
+        ))
+        .unwrap();
+        let grant = match admit(&forward_auth, "Bearer good").await {
+            Admission::Allowed(grant) => grant,
+            admission => panic!("{:?}", admission),
+        };
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            vec!["POST", "/orders?id=7", "api.example.com", ""]
+        );
+        let mut upstream_headers = HeaderMap::new();
+        upstream_headers.insert("x-user", "mallory".parse().unwrap());
+        grant.apply(&mut upstream_headers);
+        assert_eq!(upstream_headers["x-user"], "alice");
+        assert!(!upstream_headers.contains_key("x-internal"));
+
+        let local_response = match admit(&forward_auth, "Bearer bad").await {
+            Admission::Denied(Denial::ForwardAuth(local_response)) => local_response,
+            admission => panic!("{:?}", admission),
+        };
+        assert_eq!(local_response.status, StatusCode::FOUND);
+        assert_eq!(
+            local_response.headers["location"],
+            "https://sso.example.com/login"
+        );
+        assert_eq!(local_response.body, Bytes::from("login"));
+    }
+
+    #[tokio::test]
+    async fn test_forward_auth_caches_and_fails() {
+        let (address, mut receiver) = start_auth_service().await;
+        let forward_auth: ForwardAuth = serde_yaml::from_str(&format!(
+            "address: {}\nresponse_headers: [x-user]\ncache:\n  key_headers: [authorization]",
+            address
+        ))
+        .unwrap();
+        assert!(admit(&forward_auth, "Bearer good").await.is_allowed());
+        assert!(admit(&forward_auth, "Bearer good").await.is_allowed());
+        assert_eq!(receiver.recv().await.unwrap()[3], "session=1");
+        assert!(receiver.try_recv().is_err());
+        assert!(
+            admit_request(&forward_auth, Method::DELETE, "/admin", "Bearer good")
+                .await
+                .is_allowed()
+        );
+        assert_eq!(receiver.recv().await.unwrap()[..2], ["DELETE", "/admin"]);
+        let headers_only: ForwardAuth = serde_yaml::from_str(&format!(
+            "address: {}\ncache:\n  key_headers: [authorization]\n  headers_only: true",
+            forward_auth.address
+        ))
+        .unwrap();
+        assert!(admit(&headers_only, "Bearer good").await.is_allowed());
+        assert!(receiver.recv().await.is_some());
+        assert!(
+            admit_request(&headers_only, Method::DELETE, "/admin", "Bearer good")
+                .await
+                .is_allowed()
+        );
+        assert!(receiver.try_recv().is_err());
+        assert!(!admit(&forward_auth, "Bearer bad").await.is_allowed());
+        assert!(!admit(&forward_auth, "Bearer bad").await.is_allowed());
+        assert!(receiver.recv().await.is_some());
+        assert!(receiver.recv().await.is_some());
+
+        let unreachable = ForwardAuth {
+            address: String::from("http://127.0.0.1:1/verify"),
+            cache: None,
+            ..forward_auth
+        };
+        match admit(&unreachable, "Bearer good").await {
+            Admission::Denied(Denial::ForwardAuth(local_response)) => {
+                assert_eq!(local_response.status, StatusCode::SERVICE_UNAVAILABLE)
+            }
+            admission => panic!("{:?}", admission),
+        }
+        let fail_open = ForwardAuth {
+            fail_open: true,
+            ..unreachable
+        };
+        assert_eq!(
+            admit(&fail_open, "Bearer good").await,
+            Admission::Allowed(Grant {
+                upstream_headers: vec![(HeaderName::from_static("x-user"), None)],
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_is_checked_on_load() {
+        let forward_auth: ForwardAuth = serde_yaml::from_str(
+            "address: https://auth.internal/verify\nresponse_headers: [X-User]",
+        )
+        .unwrap();
+        assert_eq!(
+            forward_auth.response_headers,
+            vec![HeaderName::from_static("x-user")]
+        );
+        assert_eq!(forward_auth.timeout_ms, 2000);
+        let yaml = serde_yaml::to_string(&forward_auth).unwrap();
+        assert!(yaml.contains("- x-user"));
+        assert_eq!(
+            serde_yaml::from_str::<ForwardAuth>(&yaml).unwrap(),
+            forward_auth
+        );
+
+        for yaml in [
+            "address: /verify",
+            "address: ftp://auth.internal/verify",
+            "address: not a uri",
+            "address: http://auth.internal/verify\nresponse_headers: [bad header]",
+        ] {
+            assert!(
+                serde_yaml::from_str::<ForwardAuth>(yaml).is_err(),
+                "{}",
+                yaml
+            );
+        }
+    }
+}
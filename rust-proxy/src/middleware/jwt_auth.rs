@@ -0,0 +1,742 @@
+use super::middlewares::Admission;
+use super::middlewares::Denial;
+use super::middlewares::Grant;
+use crate::proxy::http1::http_client::HttpClients;
+use crate::vojo::app_error::AppError;
+use crate::vojo::runtime_state::RuntimeState;
+use bytes::Bytes;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use jsonwebtoken::jwk::AlgorithmParameters;
+use jsonwebtoken::jwk::Jwk;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::DecodingKey;
+use jsonwebtoken::Validation;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+
+const BEARER_CHALLENGE: &str = r#"Bearer realm="spire""#;
+
+/// Where the token is read from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum TokenSource {
+    /// A header, whose `Bearer` prefix is stripped if present.
+    #[serde(rename = "header")]
+    Header { name: String },
+    #[serde(rename = "cookie")]
+    Cookie { name: String },
+}
+impl Default for TokenSource {
+    fn default() -> Self {
+        TokenSource::Header {
+            name: http::header::AUTHORIZATION.to_string(),
+        }
+    }
+}
+impl TokenSource {
+    fn get_token<'a>(&self, headers: &'a HeaderMap<HeaderValue>) -> Option<&'a str> {
+        let token = match self {
+            TokenSource::Header { name } => {
+                let value = headers.get(name.as_str())?.to_str().ok()?.trim();
+                match value.split_once(' ') {
+                    Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => token.trim(),
+                    _ => value,
+                }
+            }
+            TokenSource::Cookie { name } => headers
+                .get_all(http::header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(cookie_name, _)| cookie_name == name)
+                .map(|(_, token)| token)?,
+        };
+        (!token.is_empty()).then_some(token)
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "HS256")]
+    Hs256,
+}
+impl JwtAlgorithm {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            JwtAlgorithm::Rs256 => Algorithm::RS256,
+            JwtAlgorithm::Es256 => Algorithm::ES256,
+            JwtAlgorithm::Hs256 => Algorithm::HS256,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum JwksSource {
+    #[serde(rename = "file")]
+    File { path: String },
+    #[serde(rename = "url")]
+    Url { url: String },
+}
+/// The public keys of RS256 and ES256 tokens. The keys are reloaded in the background after
+/// `cache_seconds`, and earlier when a token names an unknown `kid`, which picks up rotated keys.
+/// Reloads happen at most once per `min_refresh_seconds`, and the cached keys stay in use while
+/// the source fails.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Jwks {
+    #[serde(flatten)]
+    pub source: JwksSource,
+    #[serde(default = "default_cache_seconds")]
+    pub cache_seconds: u64,
+    #[serde(default = "default_min_refresh_seconds")]
+    pub min_refresh_seconds: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+fn default_cache_seconds() -> u64 {
+    300
+}
+fn default_min_refresh_seconds() -> u64 {
+    10
+}
+fn default_timeout_ms() -> u64 {
+    3000
+}
+impl Jwks {
+    async fn load(&self, client: &HttpClients) -> Result<JwkSet, AppError> {
+        let body = match &self.source {
+            JwksSource::File { path } => Bytes::from(tokio::fs::read(path).await?),
+            JwksSource::Url { url } => {
+                let req = Request::builder()
+                    .uri(url.as_str())
+                    .header(http::header::ACCEPT, "application/json")
+                    .body(Full::new(Bytes::new()).map_err(AppError::from).boxed())?;
+                let response = client
+                    .request(req, Duration::from_millis(self.timeout_ms))
+                    .await??;
+                if !response.status().is_success() {
+                    return Err(AppError(format!(
+                        "The jwks url {} answered {}",
+                        url,
+                        response.status()
+                    )));
+                }
+                response.into_body().collect().await?.to_bytes()
+            }
+        };
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+#[derive(Default)]
+struct CachedKeys {
+    keys: Option<JwkSet>,
+    loaded_at: Option<Instant>,
+    /// When the keys were last loaded or failed to load.
+    attempted_at: Option<Instant>,
+}
+#[derive(Default)]
+pub struct KeyCache {
+    cached_keys: RwLock<CachedKeys>,
+    reload: tokio::sync::Mutex<()>,
+    /// Whether a background reload of expired keys is running.
+    is_refreshing: AtomicBool,
+    client: OnceLock<HttpClients>,
+}
+impl KeyCache {
+    fn find_key(
+        &self,
+        kid: Option<&str>,
+        algorithm: Algorithm,
+    ) -> Result<Option<DecodingKey>, AppError> {
+        let cached_keys = self.cached_keys.read()?;
+        cached_keys
+            .keys
+            .as_ref()
+            .and_then(|keys| find_key(keys, kid, algorithm))
+            .map(|jwk| DecodingKey::from_jwk(jwk).map_err(|e| AppError(e.to_string())))
+            .transpose()
+    }
+    fn has_keys(&self) -> Result<bool, AppError> {
+        Ok(self.cached_keys.read()?.keys.is_some())
+    }
+    fn is_fresh(&self, jwks: &Jwks) -> Result<bool, AppError> {
+        Ok(self
+            .cached_keys
+            .read()?
+            .loaded_at
+            .is_some_and(|loaded_at| loaded_at.elapsed().as_secs() < jwks.cache_seconds))
+    }
+    /// Reloads the keys unless that has been attempted within `min_refresh_seconds`. A failed
+    /// load keeps the cached keys, and without any the tokens are rejected as invalid. The
+    /// caller holds the reload lock.
+    async fn reload_keys(&self, jwks: &Jwks) -> Result<(), AppError> {
+        let may_reload = self
+            .cached_keys
+            .read()?
+            .attempted_at
+            .is_none_or(|attempted_at| {
+                attempted_at.elapsed().as_secs() >= jwks.min_refresh_seconds
+            });
+        if !may_reload {
+            return Ok(());
+        }
+        let client = self.client.get_or_init(HttpClients::new);
+        let result = jwks.load(client).await;
+        let mut cached_keys = self.cached_keys.write()?;
+        let now = Instant::now();
+        cached_keys.attempted_at = Some(now);
+        match result {
+            Ok(keys) => {
+                cached_keys.keys = Some(keys);
+                cached_keys.loaded_at = Some(now);
+            }
+            Err(e) if cached_keys.keys.is_some() => warn!(
+                "The jwks could not be reloaded, the cached keys stay in use: {}",
+                e
+            ),
+            Err(e) => warn!(
+                "The jwks could not be loaded, the tokens are rejected: {}",
+                e
+            ),
+        }
+        Ok(())
+    }
+}
+impl std::fmt::Debug for KeyCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let loaded_at = self
+            .cached_keys
+            .read()
+            .map(|cached_keys| cached_keys.loaded_at)
+            .unwrap_or_default();
+        f.debug_struct("KeyCache")
+            .field("loaded_at", &loaded_at)
+            .finish()
+    }
+}
+/// Finds the key of the `kid`, or the first key of the algorithm if the token names none.
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>, algorithm: Algorithm) -> Option<&'a Jwk> {
+    let fits_algorithm = |jwk: &&Jwk| match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => algorithm == Algorithm::RS256,
+        AlgorithmParameters::EllipticCurve(_) => algorithm == Algorithm::ES256,
+        _ => false,
+    };
+    match kid {
+        Some(kid) => keys.find(kid).filter(fits_algorithm),
+        None => keys.keys.iter().find(fits_algorithm),
+    }
+}
+/// Authenticates requests by a signed JWT. Valid tokens which lack a required claim or scope
+/// are answered with a 403, with the `insufficient_scope` error for missing scopes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JwtAuth {
+    pub token_source: TokenSource,
+    pub algorithms: Vec<JwtAlgorithm>,
+    /// The shared secret of HS256 tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks: Option<Jwks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// The token has to name one of the audiences.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub audiences: Vec<String>,
+    /// The clock skew which is tolerated for `exp` and `nbf`.
+    pub leeway_seconds: u64,
+    /// Claims which must have the value, or contain it if the claim is an array.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub required_claims: HashMap<String, String>,
+    /// Scopes which must all be in the space separated `scope` claim or the `scp` claim.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub required_scopes: Vec<String>,
+    /// Maps claims to the headers which carry them upstream. The headers are removed from the
+    /// request even if the claim is missing, so clients can not set them.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub forward_claims: HashMap<String, String>,
+    #[serde(skip_serializing)]
+    pub key_cache: RuntimeState<KeyCache>,
+}
+impl<'de> Deserialize<'de> for JwtAuth {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Inner {
+            #[serde(default)]
+            token_source: TokenSource,
+            #[serde(default = "default_algorithms")]
+            algorithms: Vec<JwtAlgorithm>,
+            #[serde(default)]
+            secret: Option<String>,
+            #[serde(default)]
+            jwks: Option<Jwks>,
+            #[serde(default)]
+            issuer: Option<String>,
+            #[serde(default)]
+            audiences: Vec<String>,
+            #[serde(default = "default_leeway_seconds")]
+            leeway_seconds: u64,
+            #[serde(default)]
+            required_claims: HashMap<String, String>,
+            #[serde(default)]
+            required_scopes: Vec<String>,
+            #[serde(default)]
+            forward_claims: HashMap<String, String>,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+        if inner.secret.is_none() && inner.jwks.is_none() {
+            return Err(serde::de::Error::custom(
+                "the jwt authentication needs a secret or jwks",
+            ));
+        }
+        if let Some(header) = inner
+            .forward_claims
+            .values()
+            .find(|header| HeaderName::from_bytes(header.as_bytes()).is_err())
+        {
+            return Err(serde::de::Error::custom(format!(
+                "the claim header {} is no valid header name",
+                header
+            )));
+        }
+        Ok(JwtAuth {
+            token_source: inner.token_source,
+            algorithms: inner.algorithms,
+            secret: inner.secret,
+            jwks: inner.jwks,
+            issuer: inner.issuer,
+            audiences: inner.audiences,
+            leeway_seconds: inner.leeway_seconds,
+            required_claims: inner.required_claims,
+            required_scopes: inner.required_scopes,
+            forward_claims: inner.forward_claims,
+            key_cache: RuntimeState::default(),
+        })
+    }
+}
+fn default_algorithms() -> Vec<JwtAlgorithm> {
+    vec![
+        JwtAlgorithm::Rs256,
+        JwtAlgorithm::Es256,
+        JwtAlgorithm::Hs256,
+    ]
+}
+fn default_leeway_seconds() -> u64 {
+    60
+}
+fn unauthenticated(www_authenticate: String) -> Admission {
+    Admission::Denied(Denial::Unauthenticated {
+        www_authenticate: Some(www_authenticate),
+    })
+}
+fn forbidden(www_authenticate: String) -> Admission {
+    Admission::Denied(Denial::Forbidden {
+        www_authenticate: Some(www_authenticate),
+    })
+}
+fn claim_has_value(claim: &Value, expected: &str) -> bool {
+    match claim {
+        Value::String(value) => value == expected,
+        Value::Array(values) => values.iter().any(|value| claim_has_value(value, expected)),
+        Value::Bool(value) => expected.parse() == Ok(*value),
+        Value::Number(value) => expected
+            .parse::<serde_json::Number>()
+            .is_ok_and(|expected| expected == *value),
+        _ => false,
+    }
+}
+fn get_scopes(claims: &Map<String, Value>) -> Vec<&str> {
+    ["scope", "scp"]
+        .iter()
+        .filter_map(|name| claims.get(*name))
+        .flat_map(|claim| match claim {
+            Value::String(scopes) => scopes.split_whitespace().collect(),
+            Value::Array(scopes) => scopes.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+impl JwtAuth {
+    pub async fn admit(&self, headers: &HeaderMap<HeaderValue>) -> Result<Admission, AppError> {
+        let Some(token) = self.token_source.get_token(headers) else {
+            return Ok(unauthenticated(BEARER_CHALLENGE.to_string()));
+        };
+        let invalid_token = format!(r#"{}, error="invalid_token""#, BEARER_CHALLENGE);
+        let Some(claims) = self.verify(token).await? else {
+            return Ok(unauthenticated(invalid_token));
+        };
+        let has_required_claims = self.required_claims.iter().all(|(name, expected)| {
+            claims
+                .get(name)
+                .is_some_and(|claim| claim_has_value(claim, expected))
+        });
+        if !has_required_claims {
+            debug!("The jwt lacks a required claim.");
+            return Ok(forbidden(BEARER_CHALLENGE.to_string()));
+        }
+        let scopes = get_scopes(&claims);
+        if !self
+            .required_scopes
+            .iter()
+            .all(|scope| scopes.contains(&scope.as_str()))
+        {
+            return Ok(forbidden(format!(
+                r#"{}, error="insufficient_scope", scope="{}""#,
+                BEARER_CHALLENGE,
+                self.required_scopes.join(" ")
+            )));
+        }
+        Ok(Admission::Allowed(Grant {
+            upstream_headers: self.upstream_headers(&claims)?,
+            ..Default::default()
+        }))
+    }
+    fn upstream_headers(
+        &self,
+        claims: &Map<String, Value>,
+    ) -> Result<Vec<(HeaderName, Option<HeaderValue>)>, AppError> {
+        self.forward_claims
+            .iter()
+            .map(|(claim, header)| {
+                let name = HeaderName::from_bytes(header.as_bytes())
+                    .map_err(|e| AppError(e.to_string()))?;
+                let value = claims.get(claim).and_then(|value| match value {
+                    Value::String(value) => HeaderValue::from_str(value).ok(),
+                    value => HeaderValue::from_str(&value.to_string()).ok(),
+                });
+                Ok((name, value))
+            })
+            .collect()
+    }
+    /// Returns the claims of a valid token, or None if the token is rejected.
+    async fn verify(&self, token: &str) -> Result<Option<Map<String, Value>>, AppError> {
+        let header = match jsonwebtoken::decode_header(token) {
+            Ok(header) => header,
+            Err(e) => {
+                debug!("The jwt header is invalid: {}", e);
+                return Ok(None);
+            }
+        };
+        let Some(algorithm) = self
+            .algorithms
+            .iter()
+            .map(|algorithm| algorithm.algorithm())
+            .find(|algorithm| *algorithm == header.alg)
+        else {
+            debug!("The jwt algorithm {:?} is not allowed.", header.alg);
+            return Ok(None);
+        };
+        let key = match (algorithm, &self.secret, &self.jwks) {
+            (Algorithm::HS256, Some(secret), _) => {
+                Some(DecodingKey::from_secret(secret.as_bytes()))
+            }
+            (Algorithm::HS256, None, _) | (_, _, None) => None,
+            (_, _, Some(jwks)) => {
+                self.jwks_key(jwks, header.kid.as_deref(), algorithm)
+                    .await?
+            }
+        };
+        let Some(key) = key else {
+            debug!("There is no key for the jwt of {:?}.", algorithm);
+            return Ok(None);
+        };
+        match jsonwebtoken::decode::<Map<String, Value>>(token, &key, &self.validation(algorithm)) {
+            Ok(token_data) => Ok(Some(token_data.claims)),
+            Err(e) => {
+                debug!("The jwt is invalid: {}", e);
+                Ok(None)
+            }
+        }
+    }
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = self.leeway_seconds;
+        validation.validate_nbf = true;
+        let mut required_claims = vec!["exp"];
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required_claims.push("iss");
+        }
+        if self.audiences.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.audiences);
+            required_claims.push("aud");
+        }
+        validation.set_required_spec_claims(&required_claims);
+        validation
+    }
+    async fn jwks_key(
+        &self,
+        jwks: &Jwks,
+        kid: Option<&str>,
+        algorithm: Algorithm,
+    ) -> Result<Option<DecodingKey>, AppError> {
+        let key_cache = &self.key_cache;
+        if let Some(key) = key_cache.find_key(kid, algorithm)? {
+            if !key_cache.is_fresh(jwks)? && !key_cache.is_refreshing.swap(true, Ordering::AcqRel) {
+                let key_cache = key_cache.clone();
+                let jwks = jwks.clone();
+                tokio::spawn(async move {
+                    let _reload = key_cache.reload.lock().await;
+                    if let Err(e) = key_cache.reload_keys(&jwks).await {
+                        error!("The jwks reload failed: {}", e);
+                    }
+                    key_cache.is_refreshing.store(false, Ordering::Release);
+                });
+            }
+            return Ok(Some(key));
+        }
+        // Without any keys every request has to wait for the first load, while an unknown `kid`
+        // only triggers a reload when none is running.
+        let _reload = match key_cache.has_keys()? {
+            true => match key_cache.reload.try_lock() {
+                Ok(reload) => reload,
+                Err(_) => return Ok(None),
+            },
+            false => key_cache.reload.lock().await,
+        };
+        key_cache.reload_keys(jwks).await?;
+        key_cache.find_key(kid, algorithm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use jsonwebtoken::EncodingKey;
+    use jsonwebtoken::Header;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use tokio::net::TcpListener;
+
+    fn now() -> u64 {
+        jsonwebtoken::get_current_timestamp()
+    }
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+        );
+        headers
+    }
+    fn challenge(admission: Admission) -> String {
+        match admission {
+            Admission::Denied(Denial::Unauthenticated {
+                www_authenticate: Some(challenge),
+            }) => challenge,
+            other => panic!("{:?} is not unauthenticated", other),
+        }
+    }
+    fn forbidden_challenge(admission: Admission) -> String {
+        match admission {
+            Admission::Denied(Denial::Forbidden {
+                www_authenticate: Some(challenge),
+            }) => challenge,
+            other => panic!("{:?} is not forbidden", other),
+        }
+    }
+    /// Returns the signing key and the public JWK of a new P-256 key.
+    fn es256_key(kid: &str) -> (EncodingKey, Value) {
+        let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        let point = key_pair.public_key_raw();
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": kid,
+            "x": general_purpose::URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": general_purpose::URL_SAFE_NO_PAD.encode(&point[33..]),
+        });
+        (EncodingKey::from_ec_der(&key_pair.serialize_der()), jwk)
+    }
+    fn es256_token(key: &EncodingKey, kid: &str) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(kid.to_string());
+        jsonwebtoken::encode(&header, &json!({"sub": "alice", "exp": now() + 60}), key).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hs256_claims_are_checked_and_forwarded() {
+        let auth: JwtAuth = serde_yaml::from_str(
+            "secret: top-secret\nissuer: https://issuer.example.com\naudiences: [api]\nrequired_claims:\n  tenant: acme\nrequired_scopes: [read]\nforward_claims:\n  sub: x-user\n  tenant: x-tenant",
+        )
+        .unwrap();
+        let token = |changes: Value, secret: &str| {
+            let mut claims = json!({
+                "sub": "alice",
+                "iss": "https://issuer.example.com",
+                "aud": "api",
+                "exp": now() + 60,
+                "tenant": "acme",
+                "scope": "read write",
+            });
+            for (name, value) in changes.as_object().unwrap() {
+                claims[name] = value.clone();
+            }
+            let key = EncodingKey::from_secret(secret.as_bytes());
+            jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap()
+        };
+
+        let admission = auth
+            .admit(&bearer(&token(json!({}), "top-secret")))
+            .await
+            .unwrap();
+        let Admission::Allowed(grant) = admission else {
+            panic!("{:?} is not allowed", admission);
+        };
+        let mut upstream_headers = HeaderMap::new();
+        upstream_headers.insert("x-user", HeaderValue::from_static("mallory"));
+        grant.apply(&mut upstream_headers);
+        assert_eq!(upstream_headers["x-user"], "alice");
+        assert_eq!(upstream_headers["x-tenant"], "acme");
+
+        let within_leeway = token(json!({"exp": now() - 30}), "top-secret");
+        assert!(auth
+            .admit(&bearer(&within_leeway))
+            .await
+            .unwrap()
+            .is_allowed());
+
+        let invalid_tokens = [
+            token(json!({"exp": now() - 120}), "top-secret"),
+            token(json!({"nbf": now() + 600}), "top-secret"),
+            token(json!({"aud": "other"}), "top-secret"),
+            token(json!({"iss": "https://other.example.com"}), "top-secret"),
+            token(json!({}), "wrong-secret"),
+        ];
+        for invalid_token in invalid_tokens {
+            let admission = auth.admit(&bearer(&invalid_token)).await.unwrap();
+            assert!(challenge(admission).contains("invalid_token"));
+        }
+        let admission = auth
+            .admit(&bearer(&token(json!({"scope": "write"}), "top-secret")))
+            .await
+            .unwrap();
+        assert!(
+            forbidden_challenge(admission).contains(r#"error="insufficient_scope", scope="read""#)
+        );
+        let admission = auth
+            .admit(&bearer(&token(json!({"tenant": "other"}), "top-secret")))
+            .await
+            .unwrap();
+        assert_eq!(forbidden_challenge(admission), BEARER_CHALLENGE);
+        let admission = auth.admit(&HeaderMap::new()).await.unwrap();
+        assert_eq!(challenge(admission), BEARER_CHALLENGE);
+    }
+
+    #[test]
+    fn test_config_is_checked_on_load() {
+        assert!(serde_yaml::from_str::<JwtAuth>("issuer: https://issuer.example.com").is_err());
+        assert!(serde_yaml::from_str::<JwtAuth>(
+            "secret: top-secret\nforward_claims:\n  sub: bad header"
+        )
+        .is_err());
+        assert!(serde_yaml::from_str::<JwtAuth>("secret: top-secret").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_jwks_keys_are_loaded_and_rotated() {
+        let (first_key, first_jwk) = es256_key("first");
+        let (second_key, second_jwk) = es256_key("second");
+
+        let path = std::env::temp_dir().join("spire_jwt_auth_test_jwks.json");
+        std::fs::write(&path, json!({"keys": [first_jwk]}).to_string()).unwrap();
+        let auth: JwtAuth = serde_yaml::from_str(&format!(
+            "algorithms: [ES256]\njwks:\n  kind: file\n  path: {}\ntoken_source:\n  kind: cookie\n  name: session",
+            path.display()
+        ))
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "cookie",
+            HeaderValue::from_str(&format!(
+                "theme=dark; session={}",
+                es256_token(&first_key, "first")
+            ))
+            .unwrap(),
+        );
+        assert!(auth.admit(&headers).await.unwrap().is_allowed());
+        std::fs::remove_file(&path).unwrap();
+
+        let served_jwks = Arc::new(Mutex::new(json!({"keys": [first_jwk]}).to_string()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let shared_jwks = served_jwks.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let served_jwks = shared_jwks.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |_: Request<hyper::body::Incoming>| {
+                        let body = served_jwks.lock().unwrap().clone();
+                        async move { Ok::<_, AppError>(Response::new(Full::new(Bytes::from(body)))) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        let auth: JwtAuth = serde_yaml::from_str(&format!(
+            "jwks:\n  kind: url\n  url: http://{}/jwks.json\n  min_refresh_seconds: 0",
+            address
+        ))
+        .unwrap();
+        let first_token = bearer(&es256_token(&first_key, "first"));
+        let second_token = bearer(&es256_token(&second_key, "second"));
+        assert!(auth.admit(&first_token).await.unwrap().is_allowed());
+        assert!(!auth.admit(&second_token).await.unwrap().is_allowed());
+
+        *served_jwks.lock().unwrap() = json!({"keys": [second_jwk]}).to_string();
+        assert!(auth.admit(&second_token).await.unwrap().is_allowed());
+        assert!(!auth.admit(&first_token).await.unwrap().is_allowed());
+    }
+
+    #[tokio::test]
+    async fn test_jwks_failures_keep_cached_keys() {
+        let (key, jwk) = es256_key("first");
+        let path = std::env::temp_dir().join("spire_jwt_auth_test_failing_jwks.json");
+        let auth: JwtAuth = serde_yaml::from_str(&format!(
+            "jwks:\n  kind: file\n  path: {}\n  cache_seconds: 0\n  min_refresh_seconds: 0",
+            path.display()
+        ))
+        .unwrap();
+        let token = bearer(&es256_token(&key, "first"));
+        let admission = auth.admit(&token).await.unwrap();
+        assert!(challenge(admission).contains("invalid_token"));
+
+        std::fs::write(&path, json!({"keys": [jwk]}).to_string()).unwrap();
+        assert!(auth.admit(&token).await.unwrap().is_allowed());
+        std::fs::remove_file(&path).unwrap();
+        for _ in 0..3 {
+            assert!(auth.admit(&token).await.unwrap().is_allowed());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
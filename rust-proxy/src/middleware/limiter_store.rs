@@ -0,0 +1,104 @@
+use crate::vojo::app_error::AppError;
+use crate::vojo::consistent_hash::hash;
+use lru::LruCache;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const SHARD_COUNT: usize = 16;
+
+struct StoreEntry<T> {
+    value: T,
+    last_access: Instant,
+}
+/// The per-client state of a limiter. The keys are spread over shards which are locked
+/// independently, each shard holds at most its part of `max_keys` and drops the least recently
+/// used key when full. Keys which have been idle for longer than the idle timeout start over.
+pub struct LimiterStore<T> {
+    shards: Vec<Mutex<LruCache<String, StoreEntry<T>>>>,
+}
+impl<T> Default for LimiterStore<T> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(LruCache::unbounded()))
+                .collect(),
+        }
+    }
+}
+impl<T> std::fmt::Debug for LimiterStore<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LimiterStore")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+impl<T> LimiterStore<T> {
+    /// Runs `f` on the state of the key, which is created by `init` if it is missing or idle.
+    pub fn update<R>(
+        &self,
+        key: &str,
+        max_keys: usize,
+        idle_timeout: Duration,
+        init: impl FnOnce() -> T,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Result<R, AppError> {
+        let shard = &self.shards[(hash(key.as_bytes()) % SHARD_COUNT as u64) as usize];
+        let mut cache = shard.lock()?;
+        let now = Instant::now();
+        let is_idle = |entry: &StoreEntry<T>| now.duration_since(entry.last_access) > idle_timeout;
+        while cache.peek_lru().is_some_and(|(_, entry)| is_idle(entry)) {
+            cache.pop_lru();
+        }
+        if !cache.contains(key) {
+            let capacity = max_keys.div_ceil(SHARD_COUNT).max(1);
+            while cache.len() >= capacity {
+                cache.pop_lru();
+            }
+        }
+        let entry = cache.get_or_insert_mut(key.to_string(), || StoreEntry {
+            value: init(),
+            last_access: now,
+        });
+        entry.last_access = now;
+        Ok(f(&mut entry.value))
+    }
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().map(|cache| cache.len()).unwrap_or_default())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_bounded_and_expire() {
+        let store = LimiterStore::<u32>::default();
+        let increment = |key: &str, idle_timeout: Duration| {
+            store
+                .update(
+                    key,
+                    32,
+                    idle_timeout,
+                    || 0,
+                    |count| {
+                        *count += 1;
+                        *count
+                    },
+                )
+                .unwrap()
+        };
+        for index in 0..1000 {
+            increment(&format!("client-{}", index), Duration::from_secs(60));
+        }
+        assert!(store.len() <= 32, "{} keys", store.len());
+        assert_eq!(increment("client-999", Duration::from_secs(60)), 2);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(increment("client-999", Duration::from_millis(10)), 1);
+    }
+}
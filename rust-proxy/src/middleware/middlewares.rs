@@ -2,11 +2,19 @@ use super::forward_header::ForwardHeader;
 use super::headers::StaticResourceHeaders;
 use crate::middleware::allow_deny_ip::AllowDenyIp;
 use crate::middleware::authentication::Authentication;
+use crate::middleware::circuit_breaker::CircuitBreaker;
 use crate::middleware::cors_config::CorsConfig;
+use crate::middleware::fault::Fault;
+use crate::middleware::forward_auth::ForwardAuth;
+use crate::middleware::mirror::Mirror;
+use crate::middleware::rate_limit::RateLimitStatus;
 use crate::middleware::rate_limit::Ratelimit;
+use crate::proxy::proxy_trait::LocalResponse;
+use crate::vojo::route_matcher::RequestInfo;
 use crate::AppError;
 use bytes::Bytes;
 use http::HeaderMap;
+use http::HeaderName;
 use http::HeaderValue;
 use http::Request;
 use http::Response;
@@ -29,39 +37,105 @@ pub enum MiddleWares {
     Headers(StaticResourceHeaders),
     #[serde(rename = "forward_headers")]
     ForwardHeader(ForwardHeader),
+    #[serde(rename = "circuit_breaker")]
+    CircuitBreaker(CircuitBreaker),
+    #[serde(rename = "mirror")]
+    Mirror(Mirror),
+    #[serde(rename = "fault")]
+    Fault(Fault),
+    #[serde(rename = "forward_auth")]
+    ForwardAuth(ForwardAuth),
+}
+/// Why a middleware denied a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denial {
+    RateLimited(RateLimitStatus),
+    Unauthenticated {
+        www_authenticate: Option<String>,
+    },
+    /// The client is authenticated but lacks a required claim or scope.
+    Forbidden {
+        www_authenticate: Option<String>,
+    },
+    IpDenied,
+    /// The answer of the authorization service, which is returned as it is.
+    ForwardAuth(LocalResponse),
+}
+/// What the middlewares attach to an allowed request.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Grant {
+    /// The status of the rate limit with the least quota left, if one counted the request.
+    pub rate_limit_status: Option<RateLimitStatus>,
+    /// Replaces these headers of the upstream request. A name without a value only removes the
+    /// header, so that clients can not set it themselves.
+    pub upstream_headers: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+impl Grant {
+    pub fn merge(&mut self, other: Grant) {
+        if let Some(status) = other.rate_limit_status {
+            self.rate_limit_status = Some(status.most_restrictive(self.rate_limit_status));
+        }
+        self.upstream_headers.extend(other.upstream_headers);
+    }
+    pub fn apply(&self, headers: &mut HeaderMap<HeaderValue>) {
+        for (name, _) in self.upstream_headers.iter() {
+            headers.remove(name);
+        }
+        for (name, value) in self.upstream_headers.iter() {
+            if let Some(value) = value {
+                headers.append(name, value.clone());
+            }
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Admission {
+    Allowed(Grant),
+    Denied(Denial),
+}
+impl Admission {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Admission::Allowed(_))
+    }
 }
 impl MiddleWares {
-    pub fn is_allowed(
-        &mut self,
+    pub async fn admit(
+        &self,
         peer_addr: &SocketAddr,
-        headers_option: Option<&HeaderMap<HeaderValue>>,
-    ) -> Result<bool, AppError> {
+        request_option: Option<&RequestInfo<'_>>,
+    ) -> Result<Admission, AppError> {
         match self {
             MiddleWares::RateLimit(ratelimit) => {
-                if let Some(header_map) = headers_option {
-                    let is_allowed = !ratelimit.should_limit(header_map, peer_addr)?;
-                    if !is_allowed {
-                        return Ok(is_allowed);
-                    }
+                if let Some(request) = request_option {
+                    return Ok(match ratelimit.check(request.headers, peer_addr).await? {
+                        Some(status) if status.is_limited => {
+                            Admission::Denied(Denial::RateLimited(status))
+                        }
+                        rate_limit_status => Admission::Allowed(Grant {
+                            rate_limit_status,
+                            ..Default::default()
+                        }),
+                    });
                 }
             }
             MiddleWares::Authentication(authentication) => {
-                if let Some(header_map) = headers_option {
-                    let is_allowed = authentication.check_authentication(header_map)?;
-                    if !is_allowed {
-                        return Ok(is_allowed);
-                    }
+                if let Some(request) = request_option {
+                    return authentication.admit(request.headers).await;
                 }
             }
-            MiddleWares::AllowDenyList(allow_deny_list) => {
-                let is_allowed = allow_deny_list.ip_is_allowed(peer_addr)?;
-                if !is_allowed {
-                    return Ok(is_allowed);
+            MiddleWares::ForwardAuth(forward_auth) => {
+                if let Some(request) = request_option {
+                    return forward_auth.admit(request).await;
                 }
             }
+            MiddleWares::AllowDenyList(allow_deny_list)
+                if !allow_deny_list.ip_is_allowed(peer_addr)? =>
+            {
+                return Ok(Admission::Denied(Denial::IpDenied));
+            }
             _ => {}
         }
-        Ok(true)
+        Ok(Admission::Allowed(Grant::default()))
     }
     pub fn handle_before_response(
         &self,
@@ -101,37 +175,51 @@ mod tests {
         allow_deny_ip::AllowDenyItem, authentication::BasicAuth, rate_limit::TokenBucketRateLimit,
     };
     use http::header;
+    use http::Uri;
     use std::net::IpAddr;
     use std::net::Ipv4Addr;
-    #[test]
-    fn test_rate_limit_middleware() {
+    #[tokio::test]
+    async fn test_rate_limit_middleware() {
         let mut headers = HeaderMap::new();
         headers.insert(header::USER_AGENT, "test-agent".parse().unwrap());
         let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let (method, uri) = (http::Method::GET, Uri::from_static("/"));
+        let request = RequestInfo::new(&method, &uri, &headers, &socket);
         println!("a-----------------");
-        let mut middleware =
+        let middleware =
             MiddleWares::RateLimit(Ratelimit::TokenBucket(TokenBucketRateLimit::default()));
 
-        let result = middleware.is_allowed(&socket, Some(&headers));
+        let result = middleware
+            .admit(&socket, Some(&request))
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(result.unwrap());
 
-        let result = middleware.is_allowed(&socket, Some(&headers));
+        let result = middleware
+            .admit(&socket, Some(&request))
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(result.unwrap());
     }
 
-    #[test]
-    fn test_authentication_middleware() {
+    #[tokio::test]
+    async fn test_authentication_middleware() {
         let mut headers = HeaderMap::new();
         headers.insert(header::AUTHORIZATION, "Bearer test-token".parse().unwrap());
         let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
-        let mut middleware = MiddleWares::Authentication(Authentication::Basic(BasicAuth {
+        let middleware = MiddleWares::Authentication(Authentication::Basic(BasicAuth {
             credentials: "test-token".to_string(),
         }));
+        let (method, uri) = (http::Method::GET, Uri::from_static("/"));
 
-        let result = middleware.is_allowed(&socket, Some(&headers));
+        let request = RequestInfo::new(&method, &uri, &headers, &socket);
+        let result = middleware
+            .admit(&socket, Some(&request))
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(!result.unwrap());
 
@@ -139,27 +227,43 @@ mod tests {
             header::AUTHORIZATION,
             "Bearer invalid-token".parse().unwrap(),
         );
-        let result = middleware.is_allowed(&socket, Some(&headers));
+        let request = RequestInfo::new(&method, &uri, &headers, &socket);
+        let result = middleware
+            .admit(&socket, Some(&request))
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(!result.unwrap());
+        assert_eq!(
+            middleware.admit(&socket, Some(&request)).await.unwrap(),
+            Admission::Denied(Denial::Unauthenticated {
+                www_authenticate: Some("Basic realm=\"spire\"".to_string())
+            })
+        );
     }
 
-    #[test]
-    fn test_allow_deny_list_middleware() {
+    #[tokio::test]
+    async fn test_allow_deny_list_middleware() {
         let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
-        let mut middleware = MiddleWares::AllowDenyList(AllowDenyIp {
+        let middleware = MiddleWares::AllowDenyList(AllowDenyIp {
             rules: vec![AllowDenyItem {
                 policy: AllowType::Allow,
                 value: Some("127.0.0.1".to_string()),
             }],
         });
 
-        let result = middleware.is_allowed(&socket, None);
+        let result = middleware
+            .admit(&socket, None)
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(result.unwrap());
 
         let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
-        let result = middleware.is_allowed(&socket, None);
+        let result = middleware
+            .admit(&socket, None)
+            .await
+            .map(|admission| admission.is_allowed());
         assert!(result.is_ok());
         assert!(result.unwrap());
     }
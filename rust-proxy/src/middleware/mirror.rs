@@ -0,0 +1,264 @@
+use crate::proxy::http1::http_client::HttpClients;
+use crate::vojo::app_error::AppError;
+use bytes::Bytes;
+use http::request::Parts;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Request;
+use http::Uri;
+use http_body_util::combinators::BoxBody;
+use http_body_util::BodyExt;
+use http_body_util::Full;
+use hyper::body::Body;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+/// Sends a copy of the sampled requests to a shadow endpoint in the background. The shadow
+/// response is dropped, so it never affects the client. Requests whose body may be larger than
+/// `max_body_bytes` are not mirrored, as the body has to be buffered to be sent twice.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mirror {
+    /// The shadow upstream, the path and query of the upstream request are appended to it.
+    #[serde(deserialize_with = "deserialize_endpoint")]
+    pub endpoint: String,
+    #[serde(default = "default_sample_percent")]
+    pub sample_percent: f64,
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u64,
+    /// The header which marks the copy as shadowed, its value is `true`.
+    #[serde(
+        default = "default_shadow_header",
+        deserialize_with = "deserialize_shadow_header"
+    )]
+    pub shadow_header: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+fn default_sample_percent() -> f64 {
+    100.0
+}
+fn default_max_body_bytes() -> u64 {
+    64 * 1024
+}
+fn default_shadow_header() -> String {
+    String::from("x-spire-shadow")
+}
+fn default_timeout_ms() -> u64 {
+    5000
+}
+fn deserialize_endpoint<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let endpoint = String::deserialize(deserializer)?;
+    let is_valid = endpoint
+        .parse::<Uri>()
+        .is_ok_and(|uri| uri.scheme().is_some() && uri.authority().is_some());
+    if !is_valid {
+        return Err(serde::de::Error::custom(format!(
+            "the mirror endpoint {} must be an absolute uri",
+            endpoint
+        )));
+    }
+    Ok(endpoint)
+}
+fn deserialize_shadow_header<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let shadow_header = String::deserialize(deserializer)?;
+    HeaderName::from_bytes(shadow_header.as_bytes()).map_err(|_| {
+        serde::de::Error::custom(format!(
+            "the shadow header {} is no valid header name",
+            shadow_header
+        ))
+    })?;
+    Ok(shadow_header)
+}
+impl Mirror {
+    fn is_sampled(&self) -> bool {
+        rand::rng().random_bool((self.sample_percent / 100.0).clamp(0.0, 1.0))
+    }
+    fn shadow_uri(&self, request_path: &str) -> Result<Uri, AppError> {
+        let upstream_uri: Uri = request_path.parse()?;
+        let path_and_query = upstream_uri
+            .path_and_query()
+            .map(|item| item.as_str())
+            .unwrap_or("/");
+        Ok(format!("{}{}", self.endpoint.trim_end_matches('/'), path_and_query).parse()?)
+    }
+    fn shadow_request(
+        &self,
+        parts: &Parts,
+        body: Bytes,
+        request_path: &str,
+    ) -> Result<Request<BoxBody<Bytes, AppError>>, AppError> {
+        let shadow_uri = self.shadow_uri(request_path)?;
+        let mut shadow_req = Request::new(Full::new(body).map_err(AppError::from).boxed());
+        *shadow_req.method_mut() = parts.method.clone();
+        *shadow_req.headers_mut() = parts.headers.clone();
+        let host = shadow_uri
+            .authority()
+            .ok_or("The mirror endpoint has no host")?;
+        shadow_req
+            .headers_mut()
+            .insert(http::header::HOST, HeaderValue::from_str(host.as_str())?);
+        shadow_req.headers_mut().insert(
+            HeaderName::from_bytes(self.shadow_header.as_bytes())
+                .map_err(|e| AppError(e.to_string()))?,
+            HeaderValue::from_static("true"),
+        );
+        *shadow_req.uri_mut() = shadow_uri;
+        Ok(shadow_req)
+    }
+    /// Spawns the shadow request when the request is sampled and returns the request to send
+    /// upstream, whose body is buffered if it was mirrored. A shadow request which cannot be
+    /// built is logged and skipped, so it never fails the client request.
+    pub async fn mirror(
+        &self,
+        client: &HttpClients,
+        req: Request<BoxBody<Bytes, AppError>>,
+        request_path: &str,
+    ) -> Result<Request<BoxBody<Bytes, AppError>>, AppError> {
+        let within_limit = req
+            .body()
+            .size_hint()
+            .upper()
+            .is_some_and(|size| size <= self.max_body_bytes);
+        if !within_limit || !self.is_sampled() {
+            return Ok(req);
+        }
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await?.to_bytes();
+        match self.shadow_request(&parts, body.clone(), request_path) {
+            Ok(shadow_req) => {
+                let shadow_future =
+                    client.request(shadow_req, Duration::from_millis(self.timeout_ms));
+                tokio::spawn(async move {
+                    match shadow_future.await {
+                        Ok(Ok(response)) => {
+                            let _ = response.into_body().collect().await;
+                        }
+                        Ok(Err(err)) => debug!("The shadow request failed: {}.", err),
+                        Err(_) => debug!("The shadow request timed out."),
+                    }
+                });
+            }
+            Err(err) => warn!("The shadow request could not be built: {}.", err),
+        }
+        Ok(Request::from_parts(
+            parts,
+            Full::new(body).map_err(AppError::from).boxed(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    async fn start_shadow() -> (String, mpsc::UnboundedReceiver<(String, String, Bytes)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let sender = sender.clone();
+                        async move {
+                            let uri = req.uri().to_string();
+                            let shadow = req
+                                .headers()
+                                .get("x-shadow")
+                                .map(|value| value.to_str().unwrap().to_string())
+                                .unwrap_or_default();
+                            let body = req.into_body().collect().await?.to_bytes();
+                            let _ = sender.send((uri, shadow, body));
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::new())))
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        (format!("http://{}", addr), receiver)
+    }
+    fn create_request(body: &'static str) -> Request<BoxBody<Bytes, AppError>> {
+        Request::post("http://127.0.0.1:1/ignored")
+            .body(Full::new(Bytes::from(body)).map_err(AppError::from).boxed())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mirror_sends_a_shadow_copy() {
+        let (endpoint, mut receiver) = start_shadow().await;
+        let mirror: Mirror = serde_yaml::from_str(&format!(
+            "endpoint: {}\nshadow_header: x-shadow\nmax_body_bytes: 8",
+            endpoint
+        ))
+        .unwrap();
+        let client = HttpClients::new();
+        let req = mirror
+            .mirror(
+                &client,
+                create_request("payload"),
+                "http://10.0.0.1/v1/items?id=7",
+            )
+            .await
+            .unwrap();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("payload"));
+        let (uri, shadow, body) = receiver.recv().await.unwrap();
+        assert_eq!(uri, "/v1/items?id=7");
+        assert_eq!(shadow, "true");
+        assert_eq!(body, Bytes::from("payload"));
+
+        let req = mirror
+            .mirror(
+                &client,
+                create_request("too large body"),
+                "http://10.0.0.1/",
+            )
+            .await
+            .unwrap();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("too large body"));
+        let unsampled = Mirror {
+            sample_percent: 0.0,
+            ..mirror
+        };
+        unsampled
+            .mirror(&client, create_request("payload"), "http://10.0.0.1/")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_errors_keep_the_request() {
+        assert!(serde_yaml::from_str::<Mirror>("endpoint: /no-host").is_err());
+        assert!(serde_yaml::from_str::<Mirror>(
+            "endpoint: http://shadow\nshadow_header: bad header"
+        )
+        .is_err());
+        let mirror: Mirror = serde_yaml::from_str("endpoint: http://127.0.0.1:1").unwrap();
+        let req = mirror
+            .mirror(&HttpClients::new(), create_request("payload"), "bad path")
+            .await
+            .unwrap();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("payload"));
+    }
+}
@@ -1,7 +1,14 @@
 pub mod allow_deny_ip;
 pub mod authentication;
+pub mod circuit_breaker;
 pub mod cors_config;
+pub mod fault;
+pub mod forward_auth;
 pub mod forward_header;
 pub mod headers;
+pub mod jwt_auth;
+pub mod limiter_store;
 pub mod middlewares;
+pub mod mirror;
 pub mod rate_limit;
+pub mod redis_rate_limit;
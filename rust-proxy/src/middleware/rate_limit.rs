@@ -1,14 +1,23 @@
-use std::collections::HashMap;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use super::limiter_store::LimiterStore;
+use super::redis_rate_limit::RedisLimit;
+use super::redis_rate_limit::RedisRateLimit;
 use crate::constants::common_constants::DEFAULT_FIXEDWINDOW_MAP_SIZE;
 use crate::vojo::app_error::AppError;
+use crate::vojo::runtime_state::RuntimeState;
+use base64::engine::general_purpose;
+use base64::Engine;
 use core::fmt::Debug;
+use dashmap::DashMap;
 use http::HeaderMap;
 use http::HeaderValue;
 use ipnet::Ipv4Net;
 use iprange::IpRange;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::net::Ipv4Addr;
 use std::net::SocketAddr;
 
@@ -19,17 +28,189 @@ pub enum Ratelimit {
     TokenBucket(TokenBucketRateLimit),
     #[serde(rename = "fixed_window")]
     FixedWindow(FixedWindowRateLimit),
+    #[serde(rename = "sliding_window")]
+    SlidingWindow(SlidingWindowRateLimit),
+    #[serde(rename = "gcra")]
+    Gcra(GcraRateLimit),
+}
+/// The quota of a client after a request was counted, which is reported in the RateLimit
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub is_limited: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Milliseconds until the next request is allowed if limited, otherwise until the quota is
+    /// restored.
+    pub reset_millis: u64,
+}
+impl RateLimitStatus {
+    pub fn insert_headers(&self, headers: &mut HeaderMap<HeaderValue>) {
+        let reset_seconds = self.reset_millis.div_ceil(1000);
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(reset_seconds));
+        if self.is_limited {
+            headers.insert(
+                http::header::RETRY_AFTER,
+                HeaderValue::from(reset_seconds.max(1)),
+            );
+        }
+    }
+    /// Keeps the status of the limiter with the least quota left.
+    pub fn most_restrictive(self, other: Option<RateLimitStatus>) -> RateLimitStatus {
+        match other {
+            Some(other) if other.remaining < self.remaining => other,
+            _ => self,
+        }
+    }
+}
+/// The milliseconds it takes to refill the tokens.
+fn refill_millis(tokens: f64, tokens_per_millis: f64) -> u64 {
+    if tokens <= 0.0 || tokens_per_millis <= 0.0 {
+        return 0;
+    }
+    (tokens / tokens_per_millis).ceil() as u64
 }
 impl Ratelimit {
-    pub fn should_limit(
-        &mut self,
+    /// Asks Redis if the limiter has a Redis backend, and falls back to the local state. Returns
+    /// None if the request is not in the scope of the limiter.
+    pub async fn check(
+        &self,
         headers: &HeaderMap<HeaderValue>,
         peer_addr: &SocketAddr,
-    ) -> Result<bool, AppError> {
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        let (redis, limit, scope, key_by) = match self {
+            Ratelimit::TokenBucket(tb) => (&tb.redis, tb.redis_limit(), &tb.scope, &tb.key_by),
+            Ratelimit::FixedWindow(fw) => {
+                (&fw.redis, fw.redis_limit(), &fw.limit_location, &fw.key_by)
+            }
+            _ => (&None, None, &None, &None),
+        };
+        if let (Some(redis), Some(limit)) = (redis, limit) {
+            if !in_scope(scope, headers, peer_addr)? {
+                return Ok(None);
+            }
+            let client_key = key_by
+                .as_ref()
+                .and_then(|key_by| key_by.key.get_value(headers, peer_addr))
+                .unwrap_or_default();
+            if let Some(status) = redis.check(limit, &client_key).await {
+                return Ok(Some(status));
+            }
+        }
+        self.check_locally(headers, peer_addr)
+    }
+    pub fn check_locally(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        peer_addr: &SocketAddr,
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        match self {
+            Ratelimit::TokenBucket(tb) => tb.check(headers, peer_addr),
+            Ratelimit::FixedWindow(fw) => fw.check(headers, peer_addr),
+            Ratelimit::SlidingWindow(sw) => sw.check(headers, peer_addr),
+            Ratelimit::Gcra(gcra) => gcra.check(headers, peer_addr),
+        }
+    }
+}
+/// Identifies the client which a request is counted against.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum LimitKey {
+    #[serde(rename = "client_ip")]
+    ClientIp,
+    #[serde(rename = "header")]
+    Header { name: String },
+    /// A claim of the bearer JWT. The token is not verified here, so the route should also
+    /// authenticate it.
+    #[serde(rename = "jwt_claim")]
+    JwtClaim { name: String },
+    /// The authenticated user, which is the user of Basic credentials or the `sub` claim of a
+    /// bearer JWT.
+    #[serde(rename = "consumer")]
+    Consumer,
+}
+impl LimitKey {
+    pub fn get_value(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        peer_addr: &SocketAddr,
+    ) -> Option<String> {
         match self {
-            Ratelimit::TokenBucket(tb) => tb.should_limit(headers, peer_addr),
-            Ratelimit::FixedWindow(fw) => fw.should_limit(headers, peer_addr),
+            LimitKey::ClientIp => Some(peer_addr.ip().to_string()),
+            LimitKey::Header { name } => headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            LimitKey::JwtClaim { name } => bearer_claim(headers, name),
+            LimitKey::Consumer => basic_user(headers).or_else(|| bearer_claim(headers, "sub")),
+        }
+    }
+}
+fn authorization<'a>(headers: &'a HeaderMap<HeaderValue>, scheme: &str) -> Option<&'a str> {
+    let (request_scheme, credentials) = headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .split_once(' ')?;
+    request_scheme
+        .eq_ignore_ascii_case(scheme)
+        .then_some(credentials.trim())
+}
+fn basic_user(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    let credentials = authorization(headers, "Basic")?;
+    let decoded = general_purpose::STANDARD_NO_PAD
+        .decode(credentials.trim_end_matches('='))
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    decoded.split_once(':').map(|(user, _)| user.to_string())
+}
+fn bearer_claim(headers: &HeaderMap<HeaderValue>, name: &str) -> Option<String> {
+    let payload = authorization(headers, "Bearer")?.split('.').nth(1)?;
+    let payload = general_purpose::URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let claims: serde_json::Map<String, Value> = serde_json::from_slice(&payload).ok()?;
+    match claims.get(name)? {
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
+/// Gives every client its own limit instead of one limit shared by all matched requests.
+/// Requests without the key share one limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBy {
+    #[serde(flatten)]
+    pub key: LimitKey,
+    /// The most clients which are tracked, the least recently seen one is dropped beyond it.
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize,
+    /// Clients which send no request for this long start over with a fresh limit.
+    #[serde(default = "default_idle_timeout_seconds")]
+    pub idle_timeout_seconds: u64,
+}
+fn default_max_keys() -> usize {
+    100_000
+}
+fn default_idle_timeout_seconds() -> u64 {
+    3600
+}
+/// Runs `f` on the state of the client of the request, or on the single shared state without
+/// `key_by`.
+fn update_client_state<T, R>(
+    key_by: &Option<KeyBy>,
+    store: &LimiterStore<T>,
+    headers: &HeaderMap<HeaderValue>,
+    peer_addr: &SocketAddr,
+    init: impl FnOnce() -> T,
+    f: impl FnOnce(&mut T) -> R,
+) -> Result<R, AppError> {
+    match key_by {
+        Some(key_by) => {
+            let key = key_by.key.get_value(headers, peer_addr).unwrap_or_default();
+            let idle_timeout = Duration::from_secs(key_by.idle_timeout_seconds);
+            store.update(&key, key_by.max_keys, idle_timeout, init, f)
         }
+        None => store.update("", 1, Duration::MAX, init, f),
     }
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -100,26 +281,60 @@ pub struct TokenBucketRateLimit {
     pub rate_per_unit: i32,
     pub unit: TimeUnit,
     pub capacity: i32,
-    pub scope: LimitLocation,
+    /// Limits only the requests in the scope, or every request if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<LimitLocation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_by: Option<KeyBy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis: Option<RedisRateLimit>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub state: RuntimeState<TokenBucketState>,
     #[serde(skip_serializing, skip_deserializing)]
-    pub current_count: i32,
-    #[serde(skip_serializing, skip_deserializing, default = "default_time")]
-    pub last_update_time: SystemTime,
+    pub client_state: RuntimeState<LimiterStore<ClientBucket>>,
 }
 impl Default for TokenBucketRateLimit {
     fn default() -> Self {
-        TokenBucketRateLimit {
-            last_update_time: SystemTime::now(),
+        Self {
             rate_per_unit: 0,
             unit: TimeUnit::default(),
             capacity: 0,
-            scope: LimitLocation::default(),
-            current_count: 0,
+            scope: Some(LimitLocation::default()),
+            key_by: None,
+            redis: None,
+            state: Default::default(),
+            client_state: Default::default(),
         }
     }
 }
-fn default_time() -> SystemTime {
-    SystemTime::now()
+/// The bucket of a client, which starts full.
+#[derive(Debug)]
+pub struct ClientBucket {
+    tokens: f64,
+    /// Milliseconds since the unix epoch.
+    last_refill_time: u64,
+}
+#[derive(Debug)]
+pub struct TokenBucketState {
+    pub current_count: AtomicI32,
+    /// Milliseconds since the unix epoch.
+    pub last_update_time: AtomicU64,
+}
+impl TokenBucketState {
+    pub fn new(current_count: i32) -> Self {
+        Self {
+            current_count: AtomicI32::new(current_count),
+            last_update_time: AtomicU64::new(get_current_millis().unwrap_or_default()),
+        }
+    }
+}
+impl Default for TokenBucketState {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+fn get_current_millis() -> Result<u64, AppError> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
 }
 fn get_time_key(time_unit: TimeUnit) -> Result<String, AppError> {
     let current_time = SystemTime::now();
@@ -136,6 +351,16 @@ fn get_time_key(time_unit: TimeUnit) -> Result<String, AppError> {
     Ok(key_u64.to_string())
 }
 
+fn in_scope(
+    scope: &Option<LimitLocation>,
+    headers: &HeaderMap<HeaderValue>,
+    peer_addr: &SocketAddr,
+) -> Result<bool, AppError> {
+    match scope {
+        Some(limit_location) => matched(limit_location.clone(), headers, peer_addr),
+        None => Ok(true),
+    }
+}
 fn matched(
     limit_location: LimitLocation,
     headers: &HeaderMap<HeaderValue>,
@@ -170,66 +395,304 @@ fn matched(
 }
 
 impl TokenBucketRateLimit {
-    fn should_limit(
-        &mut self,
+    fn redis_limit(&self) -> Option<RedisLimit> {
+        let unit_millis = self.unit.get_million_second() as u64;
+        self.redis.as_ref().map(|_| RedisLimit::TokenBucket {
+            tokens_per_millis: self.rate_per_unit as f64 / unit_millis as f64,
+            capacity: self.capacity.max(0) as u64,
+            lease_millis: unit_millis,
+        })
+    }
+    fn check(
+        &self,
         headers: &HeaderMap<HeaderValue>,
         peer_addr: &SocketAddr,
-    ) -> Result<bool, AppError> {
-        if !matched(self.scope.clone(), headers, peer_addr)? {
-            return Ok(false);
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        if !in_scope(&self.scope, headers, peer_addr)? {
+            return Ok(None);
         }
 
-        let now = SystemTime::now();
-        let elapsed = now.duration_since(self.last_update_time)?;
-
-        let elapsed_millis = elapsed.as_millis();
+        let now = get_current_millis()?;
+        let tokens_per_millis = self.rate_per_unit as f64 / self.unit.get_million_second() as f64;
+        let capacity = self.capacity.max(0) as f64;
+        let status = |is_limited: bool, tokens: f64| RateLimitStatus {
+            is_limited,
+            limit: capacity as u64,
+            remaining: tokens.max(0.0) as u64,
+            reset_millis: if is_limited {
+                refill_millis(1.0 - tokens, tokens_per_millis)
+            } else {
+                refill_millis(capacity - tokens, tokens_per_millis)
+            },
+        };
+        if self.key_by.is_some() {
+            let init = || ClientBucket {
+                tokens: capacity,
+                last_refill_time: now,
+            };
+            return update_client_state(
+                &self.key_by,
+                &self.client_state,
+                headers,
+                peer_addr,
+                init,
+                |bucket| {
+                    let elapsed_millis = now.saturating_sub(bucket.last_refill_time) as f64;
+                    bucket.tokens =
+                        (bucket.tokens + elapsed_millis * tokens_per_millis).min(capacity);
+                    bucket.last_refill_time = now;
+                    if bucket.tokens < 1.0 {
+                        return Some(status(true, bucket.tokens));
+                    }
+                    bucket.tokens -= 1.0;
+                    Some(status(false, bucket.tokens))
+                },
+            );
+        }
+        let last_update_time = self.state.last_update_time.load(Ordering::Acquire);
+        let elapsed_millis = now.saturating_sub(last_update_time) as u128;
         let tokens_to_add =
             (elapsed_millis * self.rate_per_unit as u128) / self.unit.get_million_second();
 
-        if tokens_to_add > 0 {
-            self.current_count = (self.current_count + tokens_to_add as i32).min(self.capacity);
-            self.last_update_time = now;
+        // Only the request which moves the update time forward refills the bucket.
+        if tokens_to_add > 0
+            && self
+                .state
+                .last_update_time
+                .compare_exchange(last_update_time, now, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            let tokens_to_add = tokens_to_add.min(i32::MAX as u128) as i32;
+            let _ = self.state.current_count.fetch_update(
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                |count| Some(count.saturating_add(tokens_to_add).min(self.capacity)),
+            );
         }
 
-        if self.current_count > 0 {
-            self.current_count -= 1;
-            Ok(false) // Not limited
-        } else {
-            Ok(true) // Limited
-        }
+        let res =
+            self.state
+                .current_count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                    (count > 0).then_some(count - 1)
+                });
+        // Limited if there was no token left.
+        Ok(Some(match res {
+            Ok(count) => status(false, (count - 1) as f64),
+            Err(_) => status(true, 0.0),
+        }))
     }
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 
 pub struct FixedWindowRateLimit {
-    pub rate_per_unit: u128,
+    pub rate_per_unit: u64,
     pub unit: TimeUnit,
-    pub limit_location: LimitLocation,
+    /// Limits only the requests in the location, or every request if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_location: Option<LimitLocation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_by: Option<KeyBy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis: Option<RedisRateLimit>,
     #[serde(skip_serializing, skip_deserializing)]
-    pub count_map: HashMap<String, i32>,
+    pub count_map: RuntimeState<DashMap<String, i32>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub client_state: RuntimeState<LimiterStore<ClientWindow>>,
+}
+/// The request count of a client in its current window.
+#[derive(Debug, Default)]
+pub struct ClientWindow {
+    window: u64,
+    count: u64,
 }
 impl FixedWindowRateLimit {
-    fn should_limit(
-        &mut self,
+    fn redis_limit(&self) -> Option<RedisLimit> {
+        self.redis.as_ref().map(|_| RedisLimit::FixedWindow {
+            window_millis: self.unit.get_million_second() as u64,
+            limit: self.rate_per_unit,
+        })
+    }
+    fn check(
+        &self,
         headers: &HeaderMap<HeaderValue>,
         peer_addr: &SocketAddr,
-    ) -> Result<bool, AppError> {
-        if !matched(self.limit_location.clone(), headers, peer_addr)? {
-            return Ok(false);
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        if !in_scope(&self.limit_location, headers, peer_addr)? {
+            return Ok(None);
+        }
+        let now = get_current_millis()?;
+        let window_millis = self.unit.get_million_second() as u64;
+        let status = |count: u64| RateLimitStatus {
+            is_limited: count > self.rate_per_unit,
+            limit: self.rate_per_unit,
+            remaining: self.rate_per_unit.saturating_sub(count),
+            reset_millis: window_millis - now % window_millis,
+        };
+        if self.key_by.is_some() {
+            let window = now / window_millis;
+            return update_client_state(
+                &self.key_by,
+                &self.client_state,
+                headers,
+                peer_addr,
+                ClientWindow::default,
+                |client_window| {
+                    if client_window.window != window {
+                        *client_window = ClientWindow { window, count: 0 };
+                    }
+                    client_window.count += 1;
+                    Some(status(client_window.count))
+                },
+            );
         }
 
         let time_unit_key = get_time_key(self.unit.clone())?;
-        let location_key = self.limit_location.get_key();
+        let location_key = self
+            .limit_location
+            .as_ref()
+            .map(|limit_location| limit_location.get_key())
+            .unwrap_or_default();
         let key = format!("{}:{}", location_key, time_unit_key);
 
         if self.count_map.len() >= DEFAULT_FIXEDWINDOW_MAP_SIZE as usize {
-            if let Some(oldest_key) = self.count_map.keys().next().cloned() {
+            let oldest_key = self.count_map.iter().next().map(|item| item.key().clone());
+            if let Some(oldest_key) = oldest_key {
                 self.count_map.remove(&oldest_key);
             }
         }
-        let counter = self.count_map.entry(key).or_insert(0);
+        let mut counter = self.count_map.entry(key).or_insert(0);
         *counter += 1;
-        Ok(*counter > self.rate_per_unit as i32)
+        Ok(Some(status((*counter).max(0) as u64)))
+    }
+}
+/// Allows `rate_per_unit` requests in any window of one `unit`, which avoids the bursts at the
+/// window boundaries of a fixed window. It keeps the time of every counted request, so GCRA is
+/// cheaper for large limits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlidingWindowRateLimit {
+    pub rate_per_unit: u32,
+    pub unit: TimeUnit,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<LimitLocation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_by: Option<KeyBy>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub state: RuntimeState<LimiterStore<VecDeque<u64>>>,
+}
+impl SlidingWindowRateLimit {
+    fn check(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        peer_addr: &SocketAddr,
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        if !in_scope(&self.scope, headers, peer_addr)? {
+            return Ok(None);
+        }
+        let now = get_current_millis()?;
+        let window_millis = self.unit.get_million_second() as u64;
+        let window_start = now.saturating_sub(window_millis);
+        let limit = self.rate_per_unit as u64;
+        update_client_state(
+            &self.key_by,
+            &self.state,
+            headers,
+            peer_addr,
+            VecDeque::new,
+            |request_times| {
+                while request_times
+                    .front()
+                    .is_some_and(|time| *time <= window_start)
+                {
+                    request_times.pop_front();
+                }
+                if request_times.len() >= self.rate_per_unit as usize {
+                    // The oldest request leaves the window first.
+                    let oldest = request_times.front().copied().unwrap_or(now);
+                    return Some(RateLimitStatus {
+                        is_limited: true,
+                        limit,
+                        remaining: 0,
+                        reset_millis: (oldest + window_millis).saturating_sub(now),
+                    });
+                }
+                request_times.push_back(now);
+                Some(RateLimitStatus {
+                    is_limited: false,
+                    limit,
+                    remaining: limit - request_times.len() as u64,
+                    reset_millis: window_millis,
+                })
+            },
+        )
+    }
+}
+/// The generic cell rate algorithm, which spaces the requests evenly at `rate_per_unit` per
+/// `unit` and allows a burst of `burst` requests. It keeps one timestamp per client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GcraRateLimit {
+    pub rate_per_unit: u32,
+    pub unit: TimeUnit,
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<LimitLocation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_by: Option<KeyBy>,
+    /// The theoretical arrival time of the next request in milliseconds since the unix epoch.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub state: RuntimeState<LimiterStore<f64>>,
+}
+fn default_burst() -> u32 {
+    1
+}
+impl GcraRateLimit {
+    fn check(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        peer_addr: &SocketAddr,
+    ) -> Result<Option<RateLimitStatus>, AppError> {
+        if !in_scope(&self.scope, headers, peer_addr)? {
+            return Ok(None);
+        }
+        let limit = self.burst.max(1) as u64;
+        if self.rate_per_unit == 0 {
+            return Ok(Some(RateLimitStatus {
+                is_limited: true,
+                limit,
+                remaining: 0,
+                reset_millis: self.unit.get_million_second() as u64,
+            }));
+        }
+        let now = get_current_millis()? as f64;
+        let interval = self.unit.get_million_second() as f64 / self.rate_per_unit as f64;
+        let burst_tolerance = interval * limit as f64;
+        update_client_state(
+            &self.key_by,
+            &self.state,
+            headers,
+            peer_addr,
+            || now,
+            |arrival_time| {
+                let next_arrival_time = arrival_time.max(now) + interval;
+                if next_arrival_time - now > burst_tolerance {
+                    return Some(RateLimitStatus {
+                        is_limited: true,
+                        limit,
+                        remaining: 0,
+                        reset_millis: (next_arrival_time - now - burst_tolerance).ceil() as u64,
+                    });
+                }
+                *arrival_time = next_arrival_time;
+                let backlog = next_arrival_time - now;
+                Some(RateLimitStatus {
+                    is_limited: false,
+                    limit,
+                    remaining: ((burst_tolerance - backlog) / interval) as u64,
+                    reset_millis: backlog.ceil() as u64,
+                })
+            },
+        )
     }
 }
 #[cfg(test)]
@@ -237,6 +700,10 @@ mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
 
+    fn is_limited(status: Result<Option<RateLimitStatus>, AppError>) -> bool {
+        status.unwrap().is_some_and(|status| status.is_limited)
+    }
+
     #[test]
     fn test_token_bucket_rate_limit() {
         let mut headers = HeaderMap::new();
@@ -244,21 +711,21 @@ mod tests {
 
         let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
-        let mut rate_limit = TokenBucketRateLimit {
+        let rate_limit = TokenBucketRateLimit {
             rate_per_unit: 10,
             unit: TimeUnit::Second,
             capacity: 10,
-            scope: LimitLocation::IP(IPBasedRatelimit {
+            scope: Some(LimitLocation::IP(IPBasedRatelimit {
                 value: "127.0.0.1".to_string(),
-            }),
-            current_count: 5,
-            last_update_time: SystemTime::now(),
+            })),
+            state: TokenBucketState::new(5).into(),
+            ..Default::default()
         };
 
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
 
-        rate_limit.current_count = 0;
-        assert!(rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        rate_limit.state.current_count.store(0, Ordering::Relaxed);
+        assert!(is_limited(rate_limit.check(&headers, &socket_addr)));
     }
 
     #[test]
@@ -268,19 +735,22 @@ mod tests {
 
         let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
-        let mut rate_limit = FixedWindowRateLimit {
+        let rate_limit = FixedWindowRateLimit {
             rate_per_unit: 2,
             unit: TimeUnit::Second,
-            limit_location: LimitLocation::IP(IPBasedRatelimit {
+            limit_location: Some(LimitLocation::IP(IPBasedRatelimit {
                 value: "127.0.0.1".to_string(),
-            }),
-            count_map: HashMap::new(),
+            })),
+            key_by: None,
+            redis: None,
+            count_map: Default::default(),
+            client_state: Default::default(),
         };
 
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
 
-        assert!(rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(is_limited(rate_limit.check(&headers, &socket_addr)));
     }
 
     #[test]
@@ -288,23 +758,23 @@ mod tests {
         let headers = HeaderMap::new();
         let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
 
-        let mut rate_limit = TokenBucketRateLimit {
+        let rate_limit = TokenBucketRateLimit {
             rate_per_unit: 10,
             unit: TimeUnit::Second,
             capacity: 10,
-            scope: LimitLocation::Iprange(IpRangeBasedRatelimit {
+            scope: Some(LimitLocation::Iprange(IpRangeBasedRatelimit {
                 value: "192.168.1.0/24".to_string(),
-            }),
-            current_count: 5,
-            last_update_time: SystemTime::now(),
+            })),
+            state: TokenBucketState::new(5).into(),
+            ..Default::default()
         };
 
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
 
         let socket_addr_outside = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 2, 1)), 8080);
-        assert!(!rate_limit
-            .should_limit(&headers, &socket_addr_outside)
-            .unwrap());
+        assert!(!is_limited(
+            rate_limit.check(&headers, &socket_addr_outside)
+        ));
     }
 
     #[test]
@@ -314,21 +784,165 @@ mod tests {
 
         let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
-        let mut rate_limit = TokenBucketRateLimit {
+        let rate_limit = TokenBucketRateLimit {
             rate_per_unit: 10,
             unit: TimeUnit::Second,
             capacity: 10,
-            scope: LimitLocation::Header(HeaderBasedRatelimit {
+            scope: Some(LimitLocation::Header(HeaderBasedRatelimit {
                 key: "X-API-Key".to_string(),
                 value: "test-key".to_string(),
-            }),
-            current_count: 5,
-            last_update_time: SystemTime::now(),
+            })),
+            state: TokenBucketState::new(5).into(),
+            ..Default::default()
         };
 
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
 
         headers.insert("X-API-Key", "wrong-key".parse().unwrap());
-        assert!(!rate_limit.should_limit(&headers, &socket_addr).unwrap());
+        assert!(!is_limited(rate_limit.check(&headers, &socket_addr)));
+    }
+
+    #[test]
+    fn test_token_bucket_concurrent_requests() {
+        let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let rate_limit = TokenBucketRateLimit {
+            rate_per_unit: 0,
+            unit: TimeUnit::Second,
+            capacity: 100,
+            scope: Some(LimitLocation::IP(IPBasedRatelimit {
+                value: "127.0.0.1".to_string(),
+            })),
+            state: TokenBucketState::new(100).into(),
+            ..Default::default()
+        };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let rate_limit = rate_limit.clone();
+                std::thread::spawn(move || {
+                    (0..50)
+                        .filter(|_| !is_limited(rate_limit.check(&HeaderMap::new(), &socket_addr)))
+                        .count()
+                })
+            })
+            .collect();
+        let allowed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(allowed, 100);
+    }
+
+    fn client(last_octet: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet)), 8080)
+    }
+    fn allowed_count(rate_limit: &Ratelimit, headers: &HeaderMap, peer_addr: &SocketAddr) -> usize {
+        (0..10)
+            .filter(|_| !is_limited(rate_limit.check_locally(headers, peer_addr)))
+            .count()
+    }
+
+    #[test]
+    fn test_limits_are_kept_per_client() {
+        let limiters = [
+            "limiter: token_bucket\nrate_per_unit: 0\nunit:\n  kind: Second\ncapacity: 3",
+            "limiter: fixed_window\nrate_per_unit: 3\nunit:\n  kind: Hour",
+            "limiter: sliding_window\nrate_per_unit: 3\nunit:\n  kind: Hour",
+            "limiter: gcra\nrate_per_unit: 1\nunit:\n  kind: Hour\nburst: 3",
+        ];
+        for limiter in limiters {
+            let rate_limit: Ratelimit =
+                serde_yaml::from_str(&format!("{}\nkey_by:\n  kind: client_ip", limiter)).unwrap();
+            let headers = HeaderMap::new();
+            assert_eq!(
+                allowed_count(&rate_limit, &headers, &client(1)),
+                3,
+                "{}",
+                limiter
+            );
+            assert_eq!(
+                allowed_count(&rate_limit, &headers, &client(2)),
+                3,
+                "{}",
+                limiter
+            );
+            assert_eq!(
+                allowed_count(&rate_limit, &headers, &client(1)),
+                0,
+                "{}",
+                limiter
+            );
+        }
+    }
+
+    #[test]
+    fn test_sliding_window_and_gcra_recover() {
+        let sliding_window: Ratelimit = serde_yaml::from_str(
+            "limiter: sliding_window\nrate_per_unit: 2\nunit:\n  kind: Second",
+        )
+        .unwrap();
+        let gcra: Ratelimit =
+            serde_yaml::from_str("limiter: gcra\nrate_per_unit: 100\nunit:\n  kind: Second")
+                .unwrap();
+        let headers = HeaderMap::new();
+        assert_eq!(allowed_count(&sliding_window, &headers, &client(1)), 2);
+        assert_eq!(allowed_count(&gcra, &headers, &client(1)), 1);
+        std::thread::sleep(Duration::from_millis(1000));
+        assert_eq!(allowed_count(&sliding_window, &headers, &client(1)), 2);
+        assert_eq!(allowed_count(&gcra, &headers, &client(1)), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_falls_back_to_local_limit() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let rate_limit: Ratelimit = serde_yaml::from_str(&format!(
+            "limiter: fixed_window\nrate_per_unit: 2\nunit:\n  kind: Hour\nredis:\n  endpoint: {}\n  name: api",
+            address
+        ))
+        .unwrap();
+        let headers = HeaderMap::new();
+        let mut allowed = 0;
+        for _ in 0..5 {
+            if !is_limited(rate_limit.check(&headers, &client(1)).await) {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 2);
+    }
+
+    #[test]
+    fn test_limit_keys() {
+        let peer_addr = client(7);
+        let claims = general_purpose::URL_SAFE_NO_PAD.encode(r#"{"sub":"alice","tenant":42}"#);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_str(&format!("Bearer e30.{}.signature", claims)).unwrap(),
+        );
+        headers.insert("x-api-key", HeaderValue::from_static("key-1"));
+        let value = |headers: &HeaderMap, key: &str| {
+            serde_yaml::from_str::<LimitKey>(key)
+                .unwrap()
+                .get_value(headers, &peer_addr)
+        };
+        assert_eq!(
+            value(&headers, "kind: client_ip"),
+            Some("10.0.0.7".to_string())
+        );
+        assert_eq!(
+            value(&headers, "kind: header\nname: x-api-key"),
+            Some("key-1".to_string())
+        );
+        assert_eq!(
+            value(&headers, "kind: jwt_claim\nname: tenant"),
+            Some("42".to_string())
+        );
+        assert_eq!(value(&headers, "kind: consumer"), Some("alice".to_string()));
+
+        let basic = general_purpose::STANDARD.encode("bob:secret");
+        headers.insert(
+            "authorization",
+            HeaderValue::from_str(&format!("Basic {}", basic)).unwrap(),
+        );
+        assert_eq!(value(&headers, "kind: consumer"), Some("bob".to_string()));
+        assert_eq!(value(&headers, "kind: jwt_claim\nname: sub"), None);
     }
 }
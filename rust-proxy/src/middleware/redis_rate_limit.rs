@@ -0,0 +1,480 @@
+use super::limiter_store::LimiterStore;
+use super::rate_limit::RateLimitStatus;
+use crate::constants::common_constants::DEFAULT_REDIS_PORT;
+use crate::health_check::health_check_task::get_socket_address;
+use crate::utils::redis_client::RedisConnection;
+use crate::utils::redis_client::RespValue;
+use crate::vojo::app_error::AppError;
+use crate::vojo::runtime_state::RuntimeState;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Refills the bucket by the time of the Redis server and takes up to the requested tokens.
+/// Returns the number of tokens taken, the tokens left and the milliseconds until the bucket is
+/// full again, or until the next token if none was taken.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local tokens_per_millis = tonumber(ARGV[1])
+local capacity = tonumber(ARGV[2])
+local requested = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'time')
+local tokens = tonumber(bucket[1]) or capacity
+local last_time = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - last_time) * tokens_per_millis)
+local granted = math.min(requested, math.floor(tokens))
+local remaining = tokens - granted
+redis.call('HSET', KEYS[1], 'tokens', tostring(remaining), 'time', now)
+local reset = 0
+if tokens_per_millis > 0 then
+  redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / tokens_per_millis) + 1000)
+  if granted == 0 then
+    reset = math.ceil((1 - remaining) / tokens_per_millis)
+  else
+    reset = math.ceil((capacity - remaining) / tokens_per_millis)
+  end
+end
+return {granted, math.floor(remaining), reset}
+"#;
+/// Counts the requested tokens in the current window of the Redis server clock and returns how
+/// many of them are within the limit, the count left and the milliseconds until the window ends.
+const FIXED_WINDOW_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local window_millis = tonumber(ARGV[1])
+local limit = tonumber(ARGV[2])
+local requested = tonumber(ARGV[3])
+local key = KEYS[1] .. ':' .. math.floor(now / window_millis)
+local count = redis.call('INCRBY', key, requested)
+if count == requested then
+  redis.call('PEXPIRE', key, window_millis)
+end
+local granted = math.max(0, math.min(requested, limit - count + requested))
+return {granted, math.max(0, limit - count), window_millis - now % window_millis}
+"#;
+const MAX_IDLE_CONNECTIONS: usize = 8;
+const MAX_LEASES: usize = 100_000;
+
+/// Enforces the limit of the limiter across every Spire instance which uses the same Redis and
+/// `name`. Each instance takes `batch_size` tokens at once and hands them out locally, so an
+/// instance may admit up to `batch_size - 1` requests ahead of the shared limit. If Redis fails,
+/// the limiter falls back to limiting locally for `retry_interval_ms`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedisRateLimit {
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<i64>,
+    /// Names the shared limit, the keys in Redis are `spire:ratelimit:<name>:<client>`.
+    pub name: String,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u32,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub state: RuntimeState<RedisRateLimitState>,
+}
+fn default_batch_size() -> u32 {
+    1
+}
+fn default_timeout_ms() -> u64 {
+    100
+}
+fn default_retry_interval_ms() -> u64 {
+    5000
+}
+/// The limit which the script enforces in Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedisLimit {
+    TokenBucket {
+        tokens_per_millis: f64,
+        capacity: u64,
+        /// How long taken tokens may be handed out locally.
+        lease_millis: u64,
+    },
+    FixedWindow {
+        window_millis: u64,
+        limit: u64,
+    },
+}
+impl RedisLimit {
+    fn limit(&self) -> u64 {
+        match self {
+            RedisLimit::TokenBucket { capacity, .. } => *capacity,
+            RedisLimit::FixedWindow { limit, .. } => *limit,
+        }
+    }
+}
+/// The reply of the rate limit scripts.
+#[derive(Debug, Clone, Copy)]
+struct TakenTokens {
+    granted: u32,
+    remaining: u64,
+    reset_millis: u64,
+}
+/// Tokens which have been taken from Redis and not handed out yet.
+#[derive(Debug)]
+pub struct Lease {
+    tokens: u32,
+    expires_at: Instant,
+    /// The tokens left in Redis when the lease was taken.
+    remaining: u64,
+    reset_at: Instant,
+}
+impl Lease {
+    fn empty(now: Instant) -> Self {
+        Self {
+            tokens: 0,
+            expires_at: now,
+            remaining: 0,
+            reset_at: now,
+        }
+    }
+}
+#[derive(Default)]
+pub struct RedisRateLimitState {
+    connections: Mutex<Vec<RedisConnection>>,
+    /// Milliseconds since the unix epoch until which Redis is not tried.
+    unavailable_until: AtomicU64,
+    leases: LimiterStore<Lease>,
+}
+impl std::fmt::Debug for RedisRateLimitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisRateLimitState")
+            .field("unavailable_until", &self.unavailable_until)
+            .field("leases", &self.leases)
+            .finish()
+    }
+}
+fn get_current_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+impl RedisRateLimit {
+    /// Returns the status of the client after the request, or None if Redis can not decide and
+    /// the local limiter has to.
+    pub async fn check(&self, limit: RedisLimit, client_key: &str) -> Option<RateLimitStatus> {
+        let key = format!("spire:ratelimit:{}:{}", self.name, client_key);
+        let leased_status = self
+            .state
+            .leases
+            .update(
+                &key,
+                MAX_LEASES,
+                Duration::MAX,
+                || Lease::empty(Instant::now()),
+                |lease| {
+                    let now = Instant::now();
+                    if lease.tokens == 0 || lease.expires_at <= now {
+                        return None;
+                    }
+                    lease.tokens -= 1;
+                    Some(RateLimitStatus {
+                        is_limited: false,
+                        limit: limit.limit(),
+                        remaining: lease.remaining + lease.tokens as u64,
+                        reset_millis: lease.reset_at.saturating_duration_since(now).as_millis()
+                            as u64,
+                    })
+                },
+            )
+            .ok()
+            .flatten();
+        if leased_status.is_some() {
+            return leased_status;
+        }
+        if self.state.unavailable_until.load(Ordering::Relaxed) > get_current_millis() {
+            return None;
+        }
+        let timeout = Duration::from_millis(self.timeout_ms);
+        let taken = match tokio::time::timeout(timeout, self.take_tokens(limit, &key)).await {
+            Ok(Ok(taken)) => taken,
+            Ok(Err(e)) => return self.set_unavailable(e),
+            Err(_) => return self.set_unavailable(AppError::from("The redis request timed out")),
+        };
+        let mut status = RateLimitStatus {
+            is_limited: taken.granted == 0,
+            limit: limit.limit(),
+            remaining: taken.remaining,
+            reset_millis: taken.reset_millis,
+        };
+        if taken.granted > 1 {
+            let lease_millis = match limit {
+                RedisLimit::TokenBucket { lease_millis, .. } => lease_millis,
+                RedisLimit::FixedWindow { window_millis, .. } => window_millis,
+            };
+            let now = Instant::now();
+            let lease = Lease {
+                tokens: taken.granted - 1,
+                expires_at: now + Duration::from_millis(lease_millis),
+                remaining: taken.remaining,
+                reset_at: now + Duration::from_millis(taken.reset_millis),
+            };
+            status.remaining += lease.tokens as u64;
+            let _ = self.state.leases.update(
+                &key,
+                MAX_LEASES,
+                Duration::MAX,
+                || Lease::empty(now),
+                |current| *current = lease,
+            );
+        }
+        Some(status)
+    }
+    fn set_unavailable(&self, e: AppError) -> Option<RateLimitStatus> {
+        let until = get_current_millis() + self.retry_interval_ms;
+        let previous = self.state.unavailable_until.swap(until, Ordering::Relaxed);
+        if previous == 0 || previous <= get_current_millis() {
+            warn!(
+                "The redis rate limiter {} falls back to local limiting: {}",
+                self.name, e
+            );
+        }
+        None
+    }
+    async fn connect(&self) -> Result<RedisConnection, AppError> {
+        if let Some(connection) = self.state.connections.lock()?.pop() {
+            return Ok(connection);
+        }
+        let address = get_socket_address(&self.endpoint, Some(DEFAULT_REDIS_PORT))?;
+        let mut connection = RedisConnection::connect(&address).await?;
+        if let Some(password) = &self.password {
+            connection
+                .auth(self.username.as_deref(), password.as_str())
+                .await?;
+        }
+        if let Some(database) = self.database {
+            connection.select(database).await?;
+        }
+        Ok(connection)
+    }
+    async fn take_tokens(&self, limit: RedisLimit, key: &str) -> Result<TakenTokens, AppError> {
+        let requested = self.batch_size.max(1).to_string();
+        let (script, args) = match limit {
+            RedisLimit::TokenBucket {
+                tokens_per_millis,
+                capacity,
+                ..
+            } => (
+                TOKEN_BUCKET_SCRIPT,
+                vec![
+                    tokens_per_millis.to_string(),
+                    capacity.to_string(),
+                    requested,
+                ],
+            ),
+            RedisLimit::FixedWindow {
+                window_millis,
+                limit,
+            } => (
+                FIXED_WINDOW_SCRIPT,
+                vec![window_millis.to_string(), limit.to_string(), requested],
+            ),
+        };
+        let mut connection = self.connect().await?;
+        let res = connection.eval_script(script, &[key], &args).await?;
+        // The connection is only reused after a complete reply, so it never holds a stale one.
+        let mut connections = self.state.connections.lock()?;
+        if connections.len() < MAX_IDLE_CONNECTIONS {
+            connections.push(connection);
+        }
+        match res {
+            RespValue::Array(Some(values)) => match values.as_slice() {
+                [RespValue::Integer(granted), RespValue::Integer(remaining), RespValue::Integer(reset_millis)] => {
+                    Ok(TakenTokens {
+                        granted: (*granted).max(0) as u32,
+                        remaining: (*remaining).max(0) as u64,
+                        reset_millis: (*reset_millis).max(0) as u64,
+                    })
+                }
+                _ => Err(AppError(format!(
+                    "Unexpected redis response {:?} of the rate limit script",
+                    values
+                ))),
+            },
+            other => Err(AppError(format!(
+                "Unexpected redis response {:?} of the rate limit script",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::redis_client::parse_value;
+    use crate::utils::redis_client::script_digest;
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[derive(Default)]
+    struct StandInState {
+        scripts: HashSet<String>,
+        counters: HashMap<String, (f64, u64)>,
+        evals: u64,
+    }
+    /// Emulates the two rate limit scripts, with every fixed window lasting forever.
+    fn run_script(
+        state: &mut StandInState,
+        script: &str,
+        key: &str,
+        args: &[String],
+    ) -> (i64, i64, i64) {
+        state.evals += 1;
+        let now = get_current_millis();
+        let requested: f64 = args[2].parse().unwrap();
+        let (tokens, last_time) = state.counters.entry(key.to_string()).or_insert_with(|| {
+            if script == FIXED_WINDOW_SCRIPT {
+                (0.0, now)
+            } else {
+                (args[1].parse().unwrap(), now)
+            }
+        });
+        if script == FIXED_WINDOW_SCRIPT {
+            let limit: f64 = args[1].parse().unwrap();
+            *tokens += requested;
+            let granted = (limit - *tokens + requested).min(requested).max(0.0);
+            return (granted as i64, (limit - *tokens).max(0.0) as i64, 60_000);
+        }
+        let tokens_per_millis: f64 = args[0].parse().unwrap();
+        let capacity: f64 = args[1].parse().unwrap();
+        *tokens = (*tokens + (now - *last_time) as f64 * tokens_per_millis).min(capacity);
+        *last_time = now;
+        let granted = requested.min(tokens.floor());
+        *tokens -= granted;
+        (granted as i64, tokens.floor() as i64, 0)
+    }
+    /// A stand-in for redis-server which understands EVALSHA and EVAL of the rate limit scripts.
+    async fn start_redis_stand_in() -> (String, Arc<Mutex<StandInState>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(StandInState::default()));
+        let shared_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let state = shared_state.clone();
+                tokio::spawn(async move {
+                    let mut buffer = bytes::BytesMut::new();
+                    loop {
+                        while let Some(RespValue::Array(Some(args))) =
+                            parse_value(&mut buffer).unwrap()
+                        {
+                            let args: Vec<String> = args
+                                .into_iter()
+                                .map(|item| match item {
+                                    RespValue::BulkString(Some(b)) => String::from_utf8(b).unwrap(),
+                                    _ => String::new(),
+                                })
+                                .collect();
+                            let res = {
+                                let mut state = state.lock().unwrap();
+                                let script = match args[0].as_str() {
+                                    "EVAL" => {
+                                        state.scripts.insert(script_digest(&args[1]));
+                                        Some(args[1].clone())
+                                    }
+                                    "EVALSHA" if state.scripts.contains(&args[1]) => {
+                                        [TOKEN_BUCKET_SCRIPT, FIXED_WINDOW_SCRIPT]
+                                            .into_iter()
+                                            .find(|script| script_digest(script) == args[1])
+                                            .map(|script| script.to_string())
+                                    }
+                                    _ => None,
+                                };
+                                match script {
+                                    Some(script) => {
+                                        let (granted, remaining, reset_millis) =
+                                            run_script(&mut state, &script, &args[3], &args[4..]);
+                                        format!(
+                                            "*3\r\n:{}\r\n:{}\r\n:{}\r\n",
+                                            granted, remaining, reset_millis
+                                        )
+                                    }
+                                    None => "-NOSCRIPT No matching script.\r\n".to_string(),
+                                }
+                            };
+                            stream.write_all(res.as_bytes()).await.unwrap();
+                        }
+                        if stream.read_buf(&mut buffer).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (address, state)
+    }
+    fn create_limiter(endpoint: &str, batch_size: u32) -> RedisRateLimit {
+        serde_yaml::from_str(&format!(
+            "endpoint: {}\nname: api\nbatch_size: {}",
+            endpoint, batch_size
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_instances_share_the_limit() {
+        let (address, state) = start_redis_stand_in().await;
+        let limit = RedisLimit::FixedWindow {
+            window_millis: 60_000,
+            limit: 5,
+        };
+        let instances = [create_limiter(&address, 2), create_limiter(&address, 2)];
+        let mut remaining = vec![];
+        for index in 0..10 {
+            let status = instances[index % 2].check(limit, "client").await.unwrap();
+            if !status.is_limited {
+                remaining.push(status.remaining);
+            }
+        }
+        assert_eq!(remaining.len(), 5);
+        assert_eq!(remaining.last(), Some(&0));
+        assert!(state.lock().unwrap().evals < 10);
+
+        let limit = RedisLimit::TokenBucket {
+            tokens_per_millis: 0.0,
+            capacity: 3,
+            lease_millis: 1000,
+        };
+        let is_limited = |status: Option<RateLimitStatus>| status.map(|status| status.is_limited);
+        let mut results = vec![];
+        for instance in instances.iter() {
+            results.push(is_limited(instance.check(limit, "other").await));
+        }
+        results.push(is_limited(instances[0].check(limit, "other").await));
+        results.push(is_limited(instances[1].check(limit, "other").await));
+        assert_eq!(
+            results,
+            vec![Some(false), Some(false), Some(false), Some(true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_falls_back() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let limiter = create_limiter(&address, 1);
+        let limit = RedisLimit::FixedWindow {
+            window_millis: 1000,
+            limit: 1,
+        };
+        assert_eq!(limiter.check(limit, "client").await, None);
+        assert!(limiter.state.unavailable_until.load(Ordering::Relaxed) > get_current_millis());
+        assert_eq!(limiter.check(limit, "client").await, None);
+    }
+}
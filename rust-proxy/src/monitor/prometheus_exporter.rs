@@ -1,6 +1,10 @@
 use lazy_static::lazy_static;
-use prometheus::{labels, opts, register_counter_vec, register_gauge, register_histogram_vec};
-use prometheus::{CounterVec, Gauge, Histogram, HistogramVec};
+use prometheus::{
+    labels, opts, register_counter_vec, register_gauge, register_histogram_vec,
+    register_int_gauge_vec,
+};
+use prometheus::{CounterVec, Gauge, Histogram, HistogramVec, IntGaugeVec};
+use std::time::Duration;
 
 lazy_static! {
     static ref HTTP_COUNTER: CounterVec = register_counter_vec!(
@@ -20,6 +24,68 @@ lazy_static! {
         &["port", "request_path"]
     )
     .unwrap();
+    static ref ROUTE_PANIC_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        opts!(
+            "spire_route_panic_mode",
+            "Whether the route is in panic mode because of too few healthy endpoints.",
+        ),
+        &["route_id"]
+    )
+    .unwrap();
+    static ref CIRCUIT_BREAKER_STATE_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        opts!(
+            "spire_http_circuit_breaker_state",
+            "Whether the circuit of the endpoint is in the state.",
+        ),
+        &["route_id", "endpoint", "state"]
+    )
+    .unwrap();
+    static ref CANARY_COUNTER: CounterVec = register_counter_vec!(
+        opts!(
+            "spire_http_canary_requests_total",
+            "Number of HTTP requests per canary variant.",
+        ),
+        &["route_id", "variant", "status_code"]
+    )
+    .unwrap();
+    static ref CANARY_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "spire_http_canary_request_duration_seconds",
+        "The HTTP request latencies per canary variant in seconds.",
+        &["route_id", "variant"]
+    )
+    .unwrap();
+    static ref CIRCUIT_BREAKER_TRANSITION_COUNTER: CounterVec = register_counter_vec!(
+        opts!(
+            "spire_http_circuit_breaker_transitions_total",
+            "Number of circuit state transitions, labeled by the new state.",
+        ),
+        &["route_id", "endpoint", "state"]
+    )
+    .unwrap();
+}
+const CIRCUIT_BREAKER_STATES: [&str; 3] = ["closed", "open", "half_open"];
+pub fn set_circuit_breaker_state(route_id: &str, endpoint: &str, state: &str) {
+    for item in CIRCUIT_BREAKER_STATES {
+        CIRCUIT_BREAKER_STATE_GAUGE
+            .with_label_values(&[route_id, endpoint, item])
+            .set((item == state) as i64);
+    }
+    CIRCUIT_BREAKER_TRANSITION_COUNTER
+        .with_label_values(&[route_id, endpoint, state])
+        .inc();
+}
+pub fn observe_canary_request(route_id: &str, variant: &str, status_code: u16, duration: Duration) {
+    CANARY_COUNTER
+        .with_label_values(&[route_id, variant, status_code.to_string().as_str()])
+        .inc();
+    CANARY_HISTOGRAM
+        .with_label_values(&[route_id, variant])
+        .observe(duration.as_secs_f64());
+}
+pub fn set_route_panic_mode(route_id: &str, in_panic: bool) {
+    ROUTE_PANIC_GAUGE
+        .with_label_values(&[route_id])
+        .set(in_panic as i64);
 }
 pub fn inc(key: String, path: String, code: u16) {
     HTTP_COUNTER
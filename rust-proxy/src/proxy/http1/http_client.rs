@@ -1,4 +1,6 @@
 use bytes::Bytes;
+use dashmap::DashMap;
+use http::uri::Scheme;
 use http_body_util::combinators::BoxBody;
 
 use hyper::Request;
@@ -6,53 +8,88 @@ use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::ResponseFuture;
 use hyper_util::{client::legacy::Client, rt::TokioExecutor};
 use rustls::RootCertStore;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::timeout;
 use tokio::time::Timeout;
 
+use crate::vojo::app_config::TimeoutConfig;
 use crate::vojo::app_error::AppError;
 
+type ConnectionTimeouts = (Option<Duration>, Option<Duration>);
 #[derive(Clone)]
 pub struct HttpClients {
     pub http_client: Client<HttpConnector, BoxBody<Bytes, AppError>>,
     pub https_client: Client<hyper_rustls::HttpsConnector<HttpConnector>, BoxBody<Bytes, AppError>>,
+    timeout_clients: Arc<DashMap<ConnectionTimeouts, HttpClients>>,
 }
 impl HttpClients {
     pub fn new() -> HttpClients {
-        let http_client = Client::builder(TokioExecutor::new())
-            .http1_title_case_headers(true)
-            .http1_preserve_header_case(true)
-            .build_http();
+        Self::build(None, None)
+    }
+    fn build(connect_timeout: Option<Duration>, idle_timeout: Option<Duration>) -> HttpClients {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(connect_timeout);
+        let mut builder = Client::builder(TokioExecutor::new());
+        if idle_timeout.is_some() {
+            builder.pool_idle_timeout(idle_timeout);
+        }
         let mut root_store = RootCertStore::empty();
         root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
         let tls = rustls::ClientConfig::builder()
             .with_root_certificates(root_store)
             .with_no_client_auth();
+        let mut https_connector = connector.clone();
+        https_connector.enforce_http(false);
         let https = hyper_rustls::HttpsConnectorBuilder::new()
             .with_tls_config(tls)
             .https_or_http()
             .enable_http1()
-            .build();
-        let https_client = Client::builder(TokioExecutor::new()).build(https);
+            .wrap_connector(https_connector);
+        let https_client = builder.build(https);
+        let http_client = builder
+            .http1_title_case_headers(true)
+            .http1_preserve_header_case(true)
+            .build(connector);
         HttpClients {
             http_client,
             https_client,
+            timeout_clients: Arc::new(DashMap::new()),
         }
     }
-    pub fn request_http(
+    /// Returns the clients which apply the connect and idle timeouts of a route, they are
+    /// created once for every distinct pair of timeouts so their connection pools are reused.
+    pub fn with_timeouts(&self, timeout_config: Option<&TimeoutConfig>) -> HttpClients {
+        let timeouts = timeout_config
+            .map(|item| (item.connect_timeout(), item.idle_timeout()))
+            .unwrap_or_default();
+        if timeouts == (None, None) {
+            return self.clone();
+        }
+        self.timeout_clients
+            .entry(timeouts)
+            .or_insert_with(|| Self::build(timeouts.0, timeouts.1))
+            .clone()
+    }
+    /// Sends the request with the https client when the uri has the https scheme.
+    pub fn request(
         &self,
         req: Request<BoxBody<Bytes, AppError>>,
-        time_out: u64,
+        time_out: Duration,
     ) -> Timeout<ResponseFuture> {
-        let request_future = self.http_client.request(req);
-        timeout(Duration::from_secs(time_out), request_future)
+        let request_future = if req.uri().scheme() == Some(&Scheme::HTTPS) {
+            self.https_client.request(req)
+        } else {
+            self.http_client.request(req)
+        };
+        timeout(time_out, request_future)
     }
-    pub fn request_https(
+    pub fn request_http(
         &self,
         req: Request<BoxBody<Bytes, AppError>>,
         time_out: u64,
     ) -> Timeout<ResponseFuture> {
-        let request_future = self.https_client.request(req);
+        let request_future = self.http_client.request(req);
         timeout(Duration::from_secs(time_out), request_future)
     }
 }
@@ -1,27 +1,34 @@
 use crate::constants::common_constants;
-use crate::constants::common_constants::DEFAULT_HTTP_TIMEOUT;
-use crate::monitor::prometheus_exporter::{get_timer_list, inc};
+use crate::health_check::anomaly_detection_task::report_upstream_result;
+use crate::middleware::fault::{FaultAction, ResetConnection};
+use crate::monitor::prometheus_exporter::{get_timer_list, inc, observe_canary_request};
 use crate::proxy::http1::http_client::HttpClients;
 
 use crate::vojo::app_error::AppError;
 use crate::vojo::cli::SharedConfig;
+use crate::vojo::least_request::ActiveRequest;
+use crate::vojo::retry_policy::RetryOn;
+use crate::vojo::sticky_session::endpoint_id;
 use bytes::Bytes;
 use http::{HeaderValue, Uri};
+use hyper::body::Body;
 use hyper::body::Incoming;
 use hyper::header;
-use hyper::header::{CONNECTION, SEC_WEBSOCKET_KEY};
+use hyper::header::{CONNECTION, SEC_WEBSOCKET_KEY, SET_COOKIE};
 use hyper::Method;
 use hyper::StatusCode;
 
 use crate::proxy::http1::websocket_proxy::server_upgrade;
+use crate::proxy::proxy_trait::select_retry_destination;
 use crate::proxy::proxy_trait::{ChainTrait, SpireContext};
-use crate::proxy::proxy_trait::{CommonCheckRequest, RouterDestination};
+use crate::proxy::proxy_trait::{CommonCheckRequest, HandlingResult, RouterDestination};
 use http::uri::PathAndQuery;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
 use hyper_staticfile::Static;
+use hyper_util::client::legacy::Error as ClientError;
 use hyper_util::rt::TokioIo;
 use prometheus::HistogramTimer;
 use rustls_pki_types::CertificateDer;
@@ -30,9 +37,12 @@ use std::io::BufReader;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::SystemTime;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
+use tokio::time::error::Elapsed;
+use tokio::time::Instant;
 use tokio_rustls::TlsAcceptor;
 pub struct HttpProxy {
     pub port: i32,
@@ -166,6 +176,9 @@ async fn proxy_adapter(
     let result =
         proxy_adapter_with_error(port, shared_config, client, req, mapping_key, remote_addr).await;
     match result {
+        Ok(res) if res.extensions().get::<ResetConnection>().is_some() => {
+            Err(AppError::from("The connection is reset by a fault."))
+        }
         Ok(res) => Ok(res),
         Err(err) => {
             error!("The error is {}.", err);
@@ -251,6 +264,7 @@ async fn proxy(
     debug!("req: {:?}", req);
 
     let inbound_headers = req.headers();
+    let method = req.method().clone();
     let uri = req.uri().clone();
     let mut spire_context = SpireContext::new(port, None);
     let handling_result = chain_trait
@@ -258,6 +272,7 @@ async fn proxy(
             shared_config.clone(),
             port,
             mapping_key.clone(),
+            &method,
             inbound_headers,
             uri,
             remote_addr,
@@ -273,6 +288,30 @@ async fn proxy(
         )?);
     }
 
+    if let Some(HandlingResult {
+        router_destination: RouterDestination::Local(local_response),
+        ..
+    }) = &handling_result
+    {
+        let mut res = local_response.to_response()?;
+        if let Some(rate_limit_status) = spire_context.grant.rate_limit_status {
+            rate_limit_status.insert_headers(res.headers_mut());
+        }
+        return Ok(res);
+    }
+    spire_context.grant.apply(req.headers_mut());
+    for fault in spire_context.faults() {
+        match fault.inject(req.headers()).await? {
+            Some(FaultAction::Abort(local_response)) => return local_response.to_response(),
+            Some(FaultAction::Reset) => {
+                let mut response = Response::new(BoxBody::default());
+                response.extensions_mut().insert(ResetConnection);
+                return Ok(response);
+            }
+            None => {}
+        }
+    }
+
     if req.method() == Method::OPTIONS
         && req.headers().contains_key(header::ORIGIN)
         && req
@@ -283,14 +322,12 @@ async fn proxy(
             return chain_trait.handle_preflight(cors_config, "");
         }
     }
-    if inbound_headers.clone().contains_key(CONNECTION)
-        && inbound_headers.contains_key(SEC_WEBSOCKET_KEY)
-    {
+    if req.headers().contains_key(CONNECTION) && req.headers().contains_key(SEC_WEBSOCKET_KEY) {
         debug!(
             "The request has been updated to websocket,the req is {:?}!",
             req
         );
-        return server_upgrade(req, handling_result, client).await;
+        return server_upgrade(req, handling_result, client, spire_context.timeout).await;
     }
 
     if let Some(check_request) = handling_result {
@@ -309,32 +346,18 @@ async fn proxy(
             *req.uri_mut() = Uri::from_parts(parts)?;
             route_file(router_destination, req).await?
         } else {
-            *req.uri_mut() = request_path.parse()?;
-            let host = req
-                .uri()
-                .host()
-                .ok_or("Uri to host cause error")?
-                .to_string();
-            req.headers_mut()
-                .insert(http::header::HOST, HeaderValue::from_str(&host)?);
-
-            let request_future = if request_path.contains("https") {
-                client.request_https(req, DEFAULT_HTTP_TIMEOUT)
-            } else {
-                client.request_http(req, DEFAULT_HTTP_TIMEOUT)
-            };
-            let response_result = match request_future.await {
-                Ok(response) => response.map_err(AppError::from),
-                _ => {
-                    return Err(AppError(format!(
-                        "Request time out,the uri is {}",
-                        request_path
-                    )))
-                }
-            };
-            response_result?
-                .map(|b| b.boxed())
-                .map(|item: BoxBody<Bytes, hyper::Error>| item.map_err(AppError::from).boxed())
+            for mirror in spire_context.mirrors() {
+                req = mirror.mirror(&client, req, request_path).await?;
+            }
+            send_upstream(
+                &shared_config,
+                &client,
+                &spire_context,
+                req,
+                request_path,
+                router_destination.get_endpoint(),
+            )
+            .await?
         };
         if let Some(middlewares) = spire_context.middlewares {
             if !middlewares.is_empty() {
@@ -343,6 +366,9 @@ async fn proxy(
                     .await?;
             }
         }
+        if let Some(rate_limit_status) = spire_context.grant.rate_limit_status {
+            rate_limit_status.insert_headers(res.headers_mut());
+        }
         return Ok(res);
     }
     Ok(Response::builder()
@@ -355,6 +381,230 @@ async fn proxy(
         .unwrap())
 }
 
+type UpstreamResult = Result<Result<Response<Incoming>, ClientError>, Elapsed>;
+
+/// Sends the request to the upstream, a failed try is retried on another endpoint of the route
+/// when the retry policy, the retry budget and the request timeout allow it.
+async fn send_upstream(
+    shared_config: &SharedConfig,
+    client: &HttpClients,
+    spire_context: &SpireContext,
+    req: Request<BoxBody<Bytes, AppError>>,
+    request_path: &str,
+    endpoint: String,
+) -> Result<Response<BoxBody<Bytes, AppError>>, AppError> {
+    let started_at = Instant::now();
+    let sticky_session = spire_context.sticky_session.as_ref();
+    let pinned_endpoint_id = sticky_session.and_then(|s| s.pinned_endpoint_id(req.headers()));
+    let (result, request_path, endpoint) = send_with_retries(
+        shared_config,
+        client,
+        spire_context,
+        req,
+        request_path,
+        endpoint,
+    )
+    .await?;
+    let response = into_response(result, &request_path);
+    if let (Some(route_id), Some(variant)) =
+        (&spire_context.route_id, &spire_context.canary_variant)
+    {
+        let status = response
+            .as_ref()
+            .map_or(StatusCode::INTERNAL_SERVER_ERROR, |response| {
+                response.status()
+            });
+        observe_canary_request(route_id, variant, status.as_u16(), started_at.elapsed());
+    }
+    let mut response = response?;
+    if let Some(sticky_session) = sticky_session {
+        if pinned_endpoint_id != Some(endpoint_id(&endpoint)) {
+            response
+                .headers_mut()
+                .append(SET_COOKIE, sticky_session.set_cookie(&endpoint)?);
+        }
+    }
+    Ok(response)
+}
+/// Sends the request and retries it as the retry policy allows, returns the last result with
+/// the request path and the endpoint it was sent to.
+async fn send_with_retries(
+    shared_config: &SharedConfig,
+    client: &HttpClients,
+    spire_context: &SpireContext,
+    req: Request<BoxBody<Bytes, AppError>>,
+    request_path: &str,
+    endpoint: String,
+) -> Result<(UpstreamResult, String, String), AppError> {
+    let client = client.with_timeouts(spire_context.timeout.as_ref());
+    let request_timeout = spire_context.timeout.unwrap_or_default().request_timeout();
+    let deadline = Instant::now() + request_timeout;
+    // Every request of the route counts for the retry budget, including the ones which can not
+    // be retried.
+    if let Some(retry_policy) = spire_context.retry.as_ref() {
+        retry_policy.record_request();
+    }
+    let retry_policy = spire_context.retry.as_ref().filter(|policy| {
+        policy.attempts > 1
+            && policy.is_method_retriable(req.method())
+            && req
+                .body()
+                .size_hint()
+                .upper()
+                .is_some_and(|size| size <= policy.max_buffered_body_bytes)
+    });
+    let Some(retry_policy) = retry_policy else {
+        let result = try_upstream(
+            shared_config,
+            &client,
+            spire_context,
+            req,
+            request_path,
+            endpoint.clone(),
+            request_timeout,
+        )
+        .await?;
+        return Ok((result, request_path.to_string(), endpoint));
+    };
+    let (parts, body) = req.into_parts();
+    let body = body.collect().await?.to_bytes();
+    let mut tried_endpoints = vec![endpoint];
+    let mut request_path = request_path.to_string();
+    let mut try_count = 0;
+    loop {
+        try_count += 1;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let try_timeout = retry_policy
+            .per_try_timeout()
+            .map_or(remaining, |per_try_timeout| per_try_timeout.min(remaining));
+        let mut req = Request::new(Full::new(body.clone()).map_err(AppError::from).boxed());
+        *req.method_mut() = parts.method.clone();
+        *req.version_mut() = parts.version;
+        *req.headers_mut() = parts.headers.clone();
+        let endpoint = tried_endpoints.last().cloned().unwrap_or_default();
+        let result = try_upstream(
+            shared_config,
+            &client,
+            spire_context,
+            req,
+            &request_path,
+            endpoint.clone(),
+            try_timeout,
+        )
+        .await?;
+        let retry_reason = match &result {
+            Ok(Ok(response)) => retry_policy
+                .should_retry_status(response.status())
+                .then(|| format!("status {}", response.status())),
+            Ok(Err(err)) => {
+                let retry_on = match err.is_connect() {
+                    true => RetryOn::ConnectFailure,
+                    false => RetryOn::Reset,
+                };
+                retry_policy
+                    .should_retry_on(retry_on)
+                    .then(|| err.to_string())
+            }
+            Err(_) => retry_policy
+                .should_retry_on(RetryOn::Timeout)
+                .then(|| String::from("per try timeout")),
+        };
+        let Some(retry_reason) = retry_reason else {
+            return Ok((result, request_path, endpoint));
+        };
+        let delay = retry_policy.backoff.delay(try_count);
+        if try_count >= retry_policy.attempts
+            || Instant::now() + delay >= deadline
+            || !retry_policy.acquire_retry()
+        {
+            return Ok((result, request_path, endpoint));
+        }
+        tokio::time::sleep(delay).await;
+        let Some((endpoint, next_request_path)) = select_retry_destination(
+            shared_config,
+            spire_context,
+            &parts.headers,
+            &tried_endpoints,
+        )?
+        else {
+            return Ok((result, request_path, endpoint));
+        };
+        info!(
+            "Retrying {} on {}, the try {} to {} failed: {}.",
+            parts.uri, endpoint, try_count, request_path, retry_reason
+        );
+        tried_endpoints.push(endpoint);
+        request_path = next_request_path;
+    }
+}
+async fn try_upstream(
+    shared_config: &SharedConfig,
+    client: &HttpClients,
+    spire_context: &SpireContext,
+    mut req: Request<BoxBody<Bytes, AppError>>,
+    request_path: &str,
+    endpoint: String,
+    time_out: Duration,
+) -> Result<UpstreamResult, AppError> {
+    *req.uri_mut() = request_path.parse()?;
+    let host = req
+        .uri()
+        .host()
+        .ok_or("Uri to host cause error")?
+        .to_string();
+    req.headers_mut()
+        .insert(http::header::HOST, HeaderValue::from_str(&host)?);
+    let active_request = spire_context
+        .endpoint_loads
+        .as_ref()
+        .map(|endpoint_loads| ActiveRequest::start(endpoint_loads, &endpoint));
+    let circuit_request = spire_context
+        .circuit_breaker()
+        .map(|circuit_breaker| circuit_breaker.start_request(&endpoint));
+    let started_at = Instant::now();
+    let response_result = client.request(req, time_out).await;
+    let is_failure = match &response_result {
+        Ok(Ok(response)) => response.status().is_server_error(),
+        _ => true,
+    };
+    if let Some(active_request) = active_request {
+        // A failing endpoint answers fast, so it is charged the whole try timeout instead.
+        active_request.finish(match is_failure {
+            true => time_out,
+            false => started_at.elapsed(),
+        });
+    }
+    let Some(route_id) = spire_context.route_id.clone() else {
+        return Ok(response_result);
+    };
+    if let (Some(circuit_breaker), Some(circuit_request)) =
+        (spire_context.circuit_breaker(), circuit_request)
+    {
+        circuit_breaker.record_result(&route_id, circuit_request, is_failure, started_at.elapsed());
+    }
+    if spire_context.anomaly_detection.is_some() {
+        report_upstream_result(shared_config.clone(), route_id, endpoint, is_failure)?;
+    }
+    Ok(response_result)
+}
+fn into_response(
+    result: UpstreamResult,
+    request_path: &str,
+) -> Result<Response<BoxBody<Bytes, AppError>>, AppError> {
+    let response = match result {
+        Ok(response) => response.map_err(AppError::from)?,
+        Err(_) => {
+            return Err(AppError(format!(
+                "Request time out,the uri is {}",
+                request_path
+            )))
+        }
+    };
+    Ok(response
+        .map(|b| b.boxed())
+        .map(|item: BoxBody<Bytes, hyper::Error>| item.map_err(AppError::from).boxed()))
+}
+
 async fn route_file(
     router_destination: RouterDestination,
     req: Request<BoxBody<Bytes, AppError>>,
@@ -378,24 +628,24 @@ mod tests {
     use super::*;
 
     use crate::middleware::authentication::BasicAuth;
+    use crate::middleware::fault::Fault;
     use crate::middleware::middlewares::MiddleWares;
     use crate::proxy::proxy_trait::{HandlingResult, MockChainTrait};
     use crate::vojo::app_config::Matcher;
-    use crate::vojo::app_config::{ApiService, RouteConfig};
-    use crate::vojo::router::{BaseRoute, RandomRoute, Router};
+    use crate::vojo::app_config::{ApiService, RouteConfig, TimeoutConfig};
+    use crate::vojo::retry_policy::RetryPolicy;
+    use crate::vojo::router::{BaseRoute, PollRoute, RandomRoute, Router};
     use crate::{vojo::router::StaticFileRoute, AppConfig};
+    use base64::{engine::general_purpose, Engine as _};
     use http::HeaderMap;
     use std::collections::HashMap;
     use std::net::IpAddr;
     use std::net::Ipv4Addr;
-    use std::sync::Mutex;
 
     #[test]
     fn test_http_proxy_creation() {
         let (_, rx) = mpsc::channel(1);
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig::default())),
-        };
+        let shared_config = SharedConfig::from_app_config(AppConfig::default());
 
         let proxy = HttpProxy {
             port: 8080,
@@ -411,9 +661,7 @@ mod tests {
     #[tokio::test]
     async fn test_proxy_adapter_error_handling() {
         let client = HttpClients::new();
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig::default())),
-        };
+        let shared_config = SharedConfig::from_app_config(AppConfig::default());
         let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
         let req = Request::builder()
@@ -453,32 +701,31 @@ mod tests {
         );
 
         let client = HttpClients::new();
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig {
-                api_service_config: HashMap::from([(
-                    8080,
-                    ApiService {
-                        listen_port: 8080,
-                        route_configs: vec![RouteConfig {
-                            router: Router::Random(RandomRoute {
-                                routes: vec![BaseRoute {
-                                    endpoint: "http://127.0.0.1:9394".to_string(),
-                                    ..Default::default()
-                                }],
-                            }),
-                            matcher: Some(Matcher {
-                                prefix: "/".to_string(),
-                                prefix_rewrite: "/".to_string(),
-                            }),
-
+        let shared_config = SharedConfig::from_app_config(AppConfig {
+            api_service_config: HashMap::from([(
+                8080,
+                ApiService {
+                    listen_port: 8080,
+                    route_configs: vec![RouteConfig {
+                        router: Router::Random(RandomRoute {
+                            routes: vec![BaseRoute {
+                                endpoint: "http://127.0.0.1:9394".to_string(),
+                                ..Default::default()
+                            }],
+                        }),
+                        matcher: Some(Matcher {
+                            prefix: "/".to_string(),
+                            prefix_rewrite: "/".to_string(),
                             ..Default::default()
-                        }],
+                        }),
+
                         ..Default::default()
-                    },
-                )]),
-                ..Default::default()
-            })),
-        };
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
         let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
 
         let mut req = Request::builder()
@@ -527,7 +774,7 @@ mod tests {
         let mut mock_chain_trait = MockChainTrait::new();
         mock_chain_trait
             .expect_get_destination()
-            .returning(|_, _, _, _, _, _, _| Ok(None));
+            .returning(|_, _, _, _, _, _, _, _| Ok(None));
         let result = proxy(
             8080,
             shared_config,
@@ -565,9 +812,8 @@ mod tests {
         req.headers_mut().extend(headers);
 
         let mut mock_chain_trait = MockChainTrait::new();
-        mock_chain_trait
-            .expect_get_destination()
-            .returning(|_, _, _, _, _, _, spire_context| {
+        mock_chain_trait.expect_get_destination().returning(
+            |_, _, _, _, _, _, _, spire_context| {
                 spire_context.middlewares = Some(vec![MiddleWares::Authentication(
                     crate::middleware::authentication::Authentication::Basic(BasicAuth {
                         credentials: "user:pass".to_string(),
@@ -580,7 +826,8 @@ mod tests {
                         doc_root: "./test".to_string(),
                     }),
                 }))
-            });
+            },
+        );
         mock_chain_trait
             .expect_handle_before_request()
             .returning(|_, _, _| Err(AppError("test".to_string())));
@@ -623,7 +870,7 @@ mod tests {
         let mut mock_chain_trait = MockChainTrait::new();
         mock_chain_trait
             .expect_get_destination()
-            .returning(|_, _, _, _, _, _, _| {
+            .returning(|_, _, _, _, _, _, _, _| {
                 Ok(Some(HandlingResult {
                     request_path: "/test".to_string(),
                     router_destination: RouterDestination::File(StaticFileRoute {
@@ -647,6 +894,217 @@ mod tests {
         println!("result is {:?}", result);
         assert!(result.is_ok());
     }
+    async fn start_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|_: Request<Incoming>| async {
+                        Ok::<_, AppError>(Response::new(Full::new(Bytes::from("upstream"))))
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+    async fn unused_endpoint() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+    fn create_retry_config(endpoints: Vec<String>, retry: Option<RetryPolicy>) -> SharedConfig {
+        SharedConfig::from_app_config(AppConfig {
+            api_service_config: HashMap::from([(
+                8080,
+                ApiService {
+                    listen_port: 8080,
+                    route_configs: vec![RouteConfig {
+                        router: Router::Poll(PollRoute {
+                            routes: endpoints
+                                .into_iter()
+                                .map(|endpoint| BaseRoute {
+                                    endpoint,
+                                    ..Default::default()
+                                })
+                                .collect(),
+                            ..Default::default()
+                        }),
+                        matcher: Some(Matcher {
+                            prefix: "/".to_string(),
+                            prefix_rewrite: "/".to_string(),
+                            ..Default::default()
+                        }),
+                        timeout: Some(TimeoutConfig {
+                            connect_ms: Some(500),
+                            request_ms: Some(3000),
+                            ..Default::default()
+                        }),
+                        retry,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        })
+    }
+    #[tokio::test]
+    async fn test_retry_on_another_endpoint() {
+        let endpoints = vec![unused_endpoint().await, start_upstream().await];
+        let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let request = |method: Method| {
+            Request::builder()
+                .method(method)
+                .uri("http://127.0.0.1:8080/test")
+                .body(Full::new(Bytes::from("")).map_err(AppError::from).boxed())
+                .unwrap()
+        };
+
+        let shared_config = create_retry_config(endpoints.clone(), None);
+        let result = proxy(
+            8080,
+            shared_config,
+            HttpClients::new(),
+            request(Method::GET),
+            "test".to_string(),
+            remote_addr,
+            CommonCheckRequest {},
+        )
+        .await;
+        assert!(result.is_err());
+
+        let retry = RetryPolicy {
+            attempts: 2,
+            ..Default::default()
+        };
+        let shared_config = create_retry_config(endpoints.clone(), Some(retry));
+        let response = proxy(
+            8080,
+            shared_config.clone(),
+            HttpClients::new(),
+            request(Method::GET),
+            "test".to_string(),
+            remote_addr,
+            CommonCheckRequest {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, Bytes::from("upstream"));
+
+        // The poll router is back at the unused endpoint and POST is not retried.
+        let result = proxy(
+            8080,
+            shared_config,
+            HttpClients::new(),
+            request(Method::POST),
+            "test".to_string(),
+            remote_addr,
+            CommonCheckRequest {},
+        )
+        .await;
+        assert!(result.is_err());
+    }
+    #[tokio::test]
+    async fn test_fault_injection() {
+        let shared_config = create_retry_config(vec![unused_endpoint().await], None);
+        let fault: Fault = serde_yaml::from_str(
+            "abort:\n  header: x-abort\n  http_status: 503\nreset:\n  header: x-reset",
+        )
+        .unwrap();
+        shared_config
+            .update(|app_config| {
+                let api_service = app_config.api_service_config.get_mut(&8080).unwrap();
+                api_service.route_configs[0].middlewares = Some(vec![MiddleWares::Fault(fault)]);
+                Ok(())
+            })
+            .unwrap();
+        let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let request = |header: &str| {
+            Request::builder()
+                .uri("http://127.0.0.1:8080/test")
+                .header(header, "1")
+                .body(Full::new(Bytes::from("")).map_err(AppError::from).boxed())
+                .unwrap()
+        };
+
+        let response = proxy_adapter(
+            8080,
+            shared_config.clone(),
+            HttpClients::new(),
+            request("x-abort"),
+            "test".to_string(),
+            remote_addr,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let result = proxy_adapter(
+            8080,
+            shared_config,
+            HttpClients::new(),
+            request("x-reset"),
+            "test".to_string(),
+            remote_addr,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+    #[tokio::test]
+    async fn test_denied_requests() {
+        let shared_config = create_retry_config(vec![start_upstream().await], None);
+        let middlewares: Vec<MiddleWares> = serde_yaml::from_str(
+            "- kind: authentication\n  scheme: basic\n  credentials: user:pass\n- kind: rate_limit\n  limiter: fixed_window\n  rate_per_unit: 1\n  unit:\n    kind: Hour",
+        )
+        .unwrap();
+        shared_config
+            .update(|app_config| {
+                let api_service = app_config.api_service_config.get_mut(&8080).unwrap();
+                api_service.route_configs[0].middlewares = Some(middlewares);
+                Ok(())
+            })
+            .unwrap();
+        let remote_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080);
+        let send = |credentials: &str| {
+            let request = Request::builder()
+                .uri("http://127.0.0.1:8080/test")
+                .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+                .body(Full::new(Bytes::from("")).map_err(AppError::from).boxed())
+                .unwrap();
+            proxy(
+                8080,
+                shared_config.clone(),
+                HttpClients::new(),
+                request,
+                "test".to_string(),
+                remote_addr,
+                CommonCheckRequest {},
+            )
+        };
+        let credentials = general_purpose::STANDARD_NO_PAD.encode("user:pass");
+
+        let response = send(&credentials).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-limit"], "1");
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        let response = send(&credentials).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        let response = send("invalid").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[header::WWW_AUTHENTICATE],
+            "Basic realm=\"spire\""
+        );
+    }
     #[tokio::test]
     async fn test_route_file() {
         let router_destination = RouterDestination::File(StaticFileRoute {
@@ -1,8 +1,8 @@
 use hyper::body::Incoming;
 use tokio::io;
 
-use crate::constants::common_constants::DEFAULT_HTTP_TIMEOUT;
 use crate::proxy::http1::http_client::HttpClients;
+use crate::vojo::app_config::TimeoutConfig;
 use crate::vojo::app_error::AppError;
 use base64::{engine::general_purpose, Engine as _};
 use bytes::Bytes;
@@ -48,6 +48,7 @@ pub async fn server_upgrade(
     req: Request<BoxBody<Bytes, AppError>>,
     check_result: Option<HandlingResult>,
     http_client: HttpClients,
+    timeout_config: Option<TimeoutConfig>,
 ) -> Result<Response<BoxBody<Bytes, AppError>>, AppError> {
     debug!("The source request:{:?}.", req);
     let mut res = Response::new(Full::new(Bytes::new()).map_err(AppError::from).boxed());
@@ -76,11 +77,10 @@ pub async fn server_upgrade(
     });
     debug!("The new request is:{:?}", new_request);
 
-    let request_future = if new_request.uri().to_string().contains("https") {
-        http_client.request_https(new_request, DEFAULT_HTTP_TIMEOUT)
-    } else {
-        http_client.request_http(new_request, DEFAULT_HTTP_TIMEOUT)
-    };
+    let request_future = http_client.with_timeouts(timeout_config.as_ref()).request(
+        new_request,
+        timeout_config.unwrap_or_default().request_timeout(),
+    );
     let outbound_res = match request_future.await {
         Ok(response) => response.map_err(AppError::from),
         Err(_) => Err(AppError(format!(
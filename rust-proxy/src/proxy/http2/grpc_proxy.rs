@@ -1,12 +1,16 @@
 use crate::constants::common_constants::GRPC_STATUS_HEADER;
 use crate::constants::common_constants::GRPC_STATUS_OK;
+use crate::middleware::fault::FaultAction;
 use crate::proxy::proxy_trait::ChainTrait;
 use crate::proxy::proxy_trait::CommonCheckRequest;
+use crate::proxy::proxy_trait::LocalResponse;
+use crate::proxy::proxy_trait::RouterDestination;
 use crate::proxy::proxy_trait::SpireContext;
 use crate::vojo::app_error::AppError;
 use h2::client;
 use h2::server;
 use h2::server::SendResponse;
+use h2::Reason;
 use h2::RecvStream;
 use h2::SendStream;
 use http::version::Version;
@@ -229,6 +233,7 @@ async fn request_outbound(
             shared_config,
             port,
             mapping_key.clone(),
+            &inbound_parts.method,
             &inbound_headers,
             uri,
             peer_addr,
@@ -238,8 +243,79 @@ async fn request_outbound(
     if check_result.is_none() {
         return Err(AppError::from("The request has been denied by the proxy!"));
     }
-    let request_path = check_result.ok_or("check_result is none")?.request_path;
+    let handling_result = check_result.ok_or("check_result is none")?;
+    if let RouterDestination::Local(local_response) = handling_result.router_destination {
+        return send_local_response(&mut inbound_respond, local_response);
+    }
+    for fault in spire_context.faults() {
+        match fault.inject(&inbound_headers).await? {
+            Some(FaultAction::Abort(local_response)) => {
+                return send_local_response(&mut inbound_respond, local_response);
+            }
+            Some(FaultAction::Reset) => {
+                inbound_respond.send_reset(Reason::INTERNAL_ERROR);
+                return Ok(());
+            }
+            None => {}
+        }
+    }
+    let request_path = handling_result.request_path;
     let url = Url::parse(&request_path)?;
+    let send_request_poll = connect_outbound(&url).await?;
+
+    debug!("request path is {}", url);
+    let mut send_request = send_request_poll.ready().await?;
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .version(Version::HTTP_2)
+        .uri(url.to_string())
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(())?;
+    spire_context.grant.apply(request.headers_mut());
+    debug!("Our bound request is {:?}", request);
+    let (response, outbound_send_stream) = send_request.send_request(request, false)?;
+    tokio::spawn(async {
+        if let Err(err) = copy_io(outbound_send_stream, inbound_body).await {
+            error!("Copy from inbound to outboud error,the error is {}", err);
+        }
+    });
+
+    let (head, outboud_response_body) = response.await?.into_parts();
+
+    debug!("Received response: {:?}", head);
+
+    let header_map = head.headers.clone();
+    let is_grpc_status_ok = header_map
+        .get(GRPC_STATUS_HEADER)
+        .map(|item| item.to_str().unwrap_or_default() != GRPC_STATUS_OK)
+        .unwrap_or(false);
+    let inbound_response = Response::from_parts(head, ());
+
+    let send_stream = inbound_respond.send_response(inbound_response, is_grpc_status_ok)?;
+
+    tokio::spawn(async {
+        if let Err(err) = copy_io(send_stream, outboud_response_body).await {
+            error!("Copy from outbound to inbound error,the error is {}", err);
+        }
+    });
+    Ok(())
+}
+fn send_local_response(
+    inbound_respond: &mut SendResponse<Bytes>,
+    local_response: LocalResponse,
+) -> Result<(), AppError> {
+    let mut response = Response::builder().status(local_response.status).body(())?;
+    *response.headers_mut() = local_response.headers;
+    let end_of_stream = local_response.body.is_empty();
+    let mut send_stream = inbound_respond.send_response(response, end_of_stream)?;
+    if !end_of_stream {
+        send_stream.send_data(local_response.body, true)?;
+    }
+    Ok(())
+}
+pub async fn connect_outbound(url: &Url) -> Result<client::SendRequest<Bytes>, AppError> {
+    let request_path = url.to_string();
     let cloned_url = url.clone();
     let host = cloned_url
         .host()
@@ -283,46 +359,13 @@ async fn request_outbound(
         let tcpstream = TcpStream::connect(addr).await?;
         let (send_request, connection) = client::handshake(tcpstream).await?;
         tokio::spawn(async move {
-            connection.await.unwrap();
-            debug!("The connection has closed!");
+            if let Err(err) = connection.await {
+                error!("Cause error in grpc http connection,the error is {}.", err);
+            } else {
+                debug!("The connection has closed!");
+            }
         });
         send_request
     };
-
-    debug!("request path is {}", url);
-    let mut send_request = send_request_poll.ready().await?;
-    let request = Request::builder()
-        .method(Method::POST)
-        .version(Version::HTTP_2)
-        .uri(url.to_string())
-        .header("content-type", "application/grpc")
-        .header("te", "trailers")
-        .body(())?;
-    debug!("Our bound request is {:?}", request);
-    let (response, outbound_send_stream) = send_request.send_request(request, false)?;
-    tokio::spawn(async {
-        if let Err(err) = copy_io(outbound_send_stream, inbound_body).await {
-            error!("Copy from inbound to outboud error,the error is {}", err);
-        }
-    });
-
-    let (head, outboud_response_body) = response.await?.into_parts();
-
-    debug!("Received response: {:?}", head);
-
-    let header_map = head.headers.clone();
-    let is_grpc_status_ok = header_map
-        .get(GRPC_STATUS_HEADER)
-        .map(|item| item.to_str().unwrap_or_default() != GRPC_STATUS_OK)
-        .unwrap_or(false);
-    let inbound_response = Response::from_parts(head, ());
-
-    let send_stream = inbound_respond.send_response(inbound_response, is_grpc_status_ok)?;
-
-    tokio::spawn(async {
-        if let Err(err) = copy_io(send_stream, outboud_response_body).await {
-            error!("Copy from outbound to inbound error,the error is {}", err);
-        }
-    });
-    Ok(())
+    Ok(send_request_poll)
 }
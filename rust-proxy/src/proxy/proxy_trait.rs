@@ -1,13 +1,28 @@
+use crate::middleware::circuit_breaker::CircuitBreaker;
 use crate::middleware::cors_config::CorsConfig;
+use crate::middleware::fault::Fault;
+use crate::middleware::middlewares::Admission;
+use crate::middleware::middlewares::Grant;
 use crate::middleware::middlewares::MiddleWares;
+use crate::middleware::mirror::Mirror;
+use crate::vojo::anomaly_detection::AnomalyDetectionType;
+use crate::vojo::app_config::TimeoutConfig;
 use crate::vojo::app_error::AppError;
+use crate::vojo::least_request::EndpointLoads;
+use crate::vojo::retry_policy::RetryPolicy;
+use crate::vojo::route_matcher::RequestInfo;
 use crate::vojo::router::BaseRoute;
+use crate::vojo::router::SelectionContext;
 use crate::vojo::router::StaticFileRoute;
+use crate::vojo::routing_table::RouteMatch;
+use crate::vojo::runtime_state::RuntimeState;
+use crate::vojo::sticky_session::StickySession;
 use crate::SharedConfig;
 use bytes::Bytes;
 use http::header;
 use http::header::HeaderMap;
 use http::HeaderValue;
+use http::Method;
 use http::Request;
 use http::StatusCode;
 use http_body_util::combinators::BoxBody;
@@ -24,10 +39,66 @@ use std::path::Path;
 pub struct SpireContext {
     pub port: i32,
     pub middlewares: Option<Vec<MiddleWares>>,
+    pub route_id: Option<String>,
+    pub anomaly_detection: Option<AnomalyDetectionType>,
+    pub timeout: Option<TimeoutConfig>,
+    pub retry: Option<RetryPolicy>,
+    pub sticky_session: Option<StickySession>,
+    pub canary_variant: Option<String>,
+    #[serde(skip)]
+    pub route_match: Option<RouteMatch>,
+    #[serde(skip)]
+    pub endpoint_loads: Option<RuntimeState<EndpointLoads>>,
+    #[serde(skip)]
+    pub selection_context: SelectionContext,
+    #[serde(skip)]
+    pub grant: Grant,
 }
 impl SpireContext {
     pub fn new(port: i32, middlewares: Option<Vec<MiddleWares>>) -> Self {
-        Self { port, middlewares }
+        Self {
+            port,
+            middlewares,
+            route_id: None,
+            anomaly_detection: None,
+            timeout: None,
+            retry: None,
+            sticky_session: None,
+            canary_variant: None,
+            route_match: None,
+            endpoint_loads: None,
+            selection_context: SelectionContext::default(),
+            grant: Grant::default(),
+        }
+    }
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.middlewares
+            .iter()
+            .flatten()
+            .find_map(|middleware| match middleware {
+                MiddleWares::CircuitBreaker(circuit_breaker) => Some(circuit_breaker),
+                _ => None,
+            })
+    }
+    pub fn mirrors(&self) -> Vec<Mirror> {
+        self.middlewares
+            .iter()
+            .flatten()
+            .filter_map(|middleware| match middleware {
+                MiddleWares::Mirror(mirror) => Some(mirror.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn faults(&self) -> Vec<Fault> {
+        self.middlewares
+            .iter()
+            .flatten()
+            .filter_map(|middleware| match middleware {
+                MiddleWares::Fault(fault) => Some(fault.clone()),
+                _ => None,
+            })
+            .collect()
     }
     pub fn cors_configed(&self) -> Result<Option<CorsConfig>, AppError> {
         if let Some(middlewares) = &self.middlewares {
@@ -48,6 +119,7 @@ pub trait ChainTrait {
         shared_config: SharedConfig,
         port: i32,
         mapping_key: String,
+        method: &Method,
         headers: &HeaderMap,
         uri: Uri,
         peer_addr: SocketAddr,
@@ -83,12 +155,14 @@ pub struct HandlingResult {
 pub enum RouterDestination {
     Http(BaseRoute),
     File(StaticFileRoute),
+    Local(LocalResponse),
 }
 impl RouterDestination {
     pub fn get_endpoint(&self) -> String {
         match self {
             RouterDestination::Http(base_route) => base_route.endpoint.clone(),
             RouterDestination::File(static_file_route) => static_file_route.doc_root.clone(),
+            RouterDestination::Local(_) => String::new(),
         }
     }
 
@@ -96,199 +170,67 @@ impl RouterDestination {
         match self {
             RouterDestination::Http(_) => false,
             RouterDestination::File(_) => true,
+            RouterDestination::Local(_) => false,
         }
     }
 }
-impl ChainTrait for CommonCheckRequest {
-    async fn handle_before_response(
-        &self,
-        middlewares: Vec<MiddleWares>,
-        req_path: &str,
-
-        response: &mut Response<BoxBody<Bytes, AppError>>,
-    ) -> Result<(), AppError> {
-        for item in middlewares.iter() {
-            item.handle_before_response(req_path, response)?;
-        }
-
-        Ok(())
-    }
-    async fn handle_before_request(
-        &self,
-        middlewares: Vec<MiddleWares>,
-
-        peer_addr: SocketAddr,
-        req: &mut Request<BoxBody<Bytes, AppError>>,
-    ) -> Result<(), AppError> {
-        for item in middlewares.iter() {
-            item.handle_before_request(peer_addr, req)?;
-        }
-        Ok(())
-    }
-    async fn get_destination(
-        &self,
-        shared_config: SharedConfig,
-        port: i32,
-        _mapping_key: String,
-        headers: &HeaderMap,
-        uri: Uri,
-        peer_addr: SocketAddr,
-        spire_context: &mut SpireContext,
-    ) -> Result<Option<HandlingResult>, AppError> {
-        let backend_path = uri
-            .path_and_query()
-            .ok_or(AppError::from("Path is empty"))?
-            .as_str();
-        let mut app_config = shared_config.shared_data.lock()?;
-        let api_service = app_config
-            .api_service_config
-            .get_mut(&port)
-            .ok_or(AppError::from(
-                "Can not find config by port from app config.",
-            ))?;
-
-        for item in api_service.route_configs.iter_mut() {
-            let match_result = item.is_matched(backend_path, Some(headers))?;
-            if match_result.is_none() {
-                continue;
-            }
-            let is_allowed = item.is_allowed(&peer_addr, Some(headers))?;
-            if !is_allowed {
-                return Ok(None);
-            }
-            let router_destination = item.router.get_route(headers)?;
-            let rest_path = match_result.ok_or("match_result is none")?;
-
-            match router_destination {
-                RouterDestination::File(file_route) => {
-                    let path = Path::new(&file_route.doc_root);
-                    let request_path = path.join(rest_path);
-                    spire_context.middlewares = item.middlewares.clone();
-                    return Ok(Some(HandlingResult {
-                        request_path: String::from(request_path.to_str().unwrap_or_default()),
-                        router_destination: RouterDestination::File(file_route),
-                    }));
-                }
-                RouterDestination::Http(base_route) => {
-                    let request_path = [base_route.endpoint.as_str(), rest_path.as_str()].join("/");
-                    spire_context.middlewares = item.middlewares.clone();
-                    return Ok(Some(HandlingResult {
-                        request_path,
-                        router_destination: RouterDestination::Http(base_route.clone()),
-                    }));
-                }
-            }
-        }
-        Ok(None)
-    }
-    fn handle_preflight(
-        &self,
-        cors_config: CorsConfig,
-        origin: &str,
-    ) -> Result<Response<BoxBody<Bytes, AppError>>, AppError> {
-        if cors_config.validate_origin("")? {
-            return Ok(Response::builder().status(StatusCode::FORBIDDEN).body(
-                Full::new(Bytes::from("".to_string()))
-                    .map_err(AppError::from)
-                    .boxed(),
-            )?);
-        }
-        let methods_header = cors_config
-            .allowed_methods
-            .iter()
-            .map(|m| m.as_str()) // Convert serde_json::Error to AppError
-            .collect::<Vec<&str>>()
-            .join(", ");
-        let headers_header = cors_config
-            .allowed_headers
-            .iter()
-            .map(|m| m.to_string()) // Convert serde_json::Error to AppError
-            .collect::<Vec<String>>()
-            .join(", ");
-
-        let mut builder = Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods_header)
-            .header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers_header)
-            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
-            .header(header::VARY, "Origin");
-
-        if let Some(s) = cors_config.allow_credentials {
-            if s {
-                builder = builder.header(
-                    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
-                    HeaderValue::from_static("true"),
-                );
-            }
-        }
-        if let Some(s) = cors_config.max_age {
-            builder = builder.header(header::ACCESS_CONTROL_MAX_AGE, s.to_string());
-        }
-
-        Ok(builder.body(
-            Full::new(Bytes::from("".to_string()))
-                .map_err(AppError::from)
-                .boxed(),
-        )?)
-    }
-}
-#[automock]
-pub trait ChainTrait {
-    #[allow(clippy::too_many_arguments)]
-    async fn get_destination(
-        &self,
-        shared_config: SharedConfig,
-        port: i32,
-        mapping_key: String,
-        headers: &HeaderMap,
-        uri: Uri,
-        peer_addr: SocketAddr,
-        spire_context: &mut SpireContext,
-    ) -> Result<Option<HandlingResult>, AppError>;
-    async fn handle_before_response(
-        &self,
-        middlewares: Vec<MiddleWares>,
-        req_path: &str,
-        response: &mut Response<BoxBody<Bytes, AppError>>,
-    ) -> Result<(), AppError>;
-    fn handle_preflight(
-        &self,
-        cors_config: CorsConfig,
-        origin: &str,
-    ) -> Result<Response<BoxBody<Bytes, AppError>>, AppError>;
-    async fn handle_before_request(
-        &self,
-        middlewares: Vec<MiddleWares>,
-        peer_addr: SocketAddr,
-        req: &mut Request<BoxBody<Bytes, AppError>>,
-    ) -> Result<(), AppError>;
-}
-pub struct CommonCheckRequest;
-
-#[derive(Debug, Clone)]
-pub struct HandlingResult {
-    pub request_path: String,
-    pub router_destination: RouterDestination,
-}
+/// A response which is generated by the proxy itself instead of the upstream.
 #[derive(Debug, Clone, PartialEq, Eq)]
-
-pub enum RouterDestination {
-    Http(BaseRoute),
-    File(StaticFileRoute),
+pub struct LocalResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
 }
-impl RouterDestination {
-    pub fn get_endpoint(&self) -> String {
-        match self {
-            RouterDestination::Http(base_route) => base_route.endpoint.clone(),
-            RouterDestination::File(static_file_route) => static_file_route.doc_root.clone(),
+impl LocalResponse {
+    pub fn new(status: StatusCode, body: impl Into<Bytes>) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
         }
     }
-
-    pub fn is_file(&self) -> bool {
-        match self {
-            RouterDestination::Http(_) => false,
-            RouterDestination::File(_) => true,
+    pub fn to_response(&self) -> Result<Response<BoxBody<Bytes, AppError>>, AppError> {
+        let mut response = Response::builder()
+            .status(self.status)
+            .body(Full::new(self.body.clone()).map_err(AppError::from).boxed())?;
+        *response.headers_mut() = self.headers.clone();
+        Ok(response)
+    }
+}
+/// Selects another endpoint of the matched route for a retry, the endpoints which have already
+/// been tried are skipped as long as any other is left. Returns the endpoint and the upstream url.
+pub fn select_retry_destination(
+    shared_config: &SharedConfig,
+    spire_context: &SpireContext,
+    headers: &HeaderMap,
+    tried_endpoints: &[String],
+) -> Result<Option<(String, String)>, AppError> {
+    let route_match = spire_context
+        .route_match
+        .as_ref()
+        .ok_or("The request has not been matched to a route")?;
+    let app_config = shared_config.load();
+    let api_service = app_config
+        .api_service_config
+        .get(&spire_context.port)
+        .ok_or(AppError::from(
+            "Can not find config by port from app config.",
+        ))?;
+    let routing_table = api_service.routing_table.as_ref().map_err(|e| e.clone())?;
+    let route = match api_service.route_configs.get(route_match.route_index) {
+        Some(route) if spire_context.route_id.as_ref() == Some(&route.route_id) => route,
+        _ => return Ok(None),
+    };
+    let context = SelectionContext {
+        excluded_endpoints: tried_endpoints.to_vec(),
+        ..spire_context.selection_context.clone()
+    };
+    match route.get_route(headers, context)? {
+        RouterDestination::Http(base_route) => {
+            let request_path = routing_table.upstream_url(route_match, &base_route.endpoint)?;
+            Ok(Some((base_route.endpoint, request_path)))
         }
+        _ => Ok(None),
     }
 }
 impl ChainTrait for CommonCheckRequest {
@@ -322,56 +264,81 @@ impl ChainTrait for CommonCheckRequest {
         shared_config: SharedConfig,
         port: i32,
         _mapping_key: String,
+        method: &Method,
         headers: &HeaderMap,
         uri: Uri,
         peer_addr: SocketAddr,
         spire_context: &mut SpireContext,
     ) -> Result<Option<HandlingResult>, AppError> {
-        let backend_path = uri
-            .path_and_query()
-            .ok_or(AppError::from("Path is empty"))?
-            .as_str();
-        let mut app_config = shared_config.shared_data.lock()?;
+        let app_config = shared_config.load();
         let api_service = app_config
             .api_service_config
-            .get_mut(&port)
+            .get(&port)
             .ok_or(AppError::from(
                 "Can not find config by port from app config.",
             ))?;
 
-        for item in api_service.route_configs.iter_mut() {
-            let match_result = item.is_matched(backend_path, Some(headers))?;
-            if match_result.is_none() {
-                continue;
+        let routing_table = api_service.routing_table.as_ref().map_err(|e| e.clone())?;
+        let request = RequestInfo::new(method, &uri, headers, &peer_addr);
+        let route_match = match routing_table.match_route(&request)? {
+            Some(route_match) => route_match,
+            None => return Ok(None),
+        };
+        let item = &api_service.route_configs[route_match.route_index];
+        let rest_path = routing_table.rewrite_path(&route_match)?;
+        match item.admit(&peer_addr, Some(&request)).await? {
+            Admission::Denied(denial) => {
+                spire_context.middlewares = item.middlewares.clone();
+                return Ok(Some(HandlingResult {
+                    request_path: rest_path,
+                    router_destination: RouterDestination::Local(item.deny_response(&denial)?),
+                }));
             }
-            let is_allowed = item.is_allowed(&peer_addr, Some(headers))?;
-            if !is_allowed {
-                return Ok(None);
+            Admission::Allowed(grant) => spire_context.grant = grant,
+        }
+        let selection_context = SelectionContext {
+            peer_addr: Some(peer_addr),
+            uri: Some(uri.clone()),
+            ..Default::default()
+        };
+        let router_destination = item.get_route(headers, selection_context.clone())?;
+
+        match router_destination {
+            RouterDestination::Local(local_response) => {
+                spire_context.middlewares = item.middlewares.clone();
+                Ok(Some(HandlingResult {
+                    request_path: rest_path,
+                    router_destination: RouterDestination::Local(local_response),
+                }))
             }
-            let router_destination = item.router.get_route(headers)?;
-            let rest_path = match_result.ok_or("match_result is none")?;
-
-            match router_destination {
-                RouterDestination::File(file_route) => {
-                    let path = Path::new(&file_route.doc_root);
-                    let request_path = path.join(rest_path);
-                    spire_context.middlewares = item.middlewares.clone();
-                    return Ok(Some(HandlingResult {
-                        request_path: String::from(request_path.to_str().unwrap_or_default()),
-                        router_destination: RouterDestination::File(file_route),
-                    }));
-                }
-                RouterDestination::Http(base_route) => {
-                    let request_path = [base_route.endpoint.as_str(), rest_path.as_str()].join("/");
-                    spire_context.middlewares = item.middlewares.clone();
-                    return Ok(Some(HandlingResult {
-                        request_path,
-                        router_destination: RouterDestination::Http(base_route.clone()),
-                    }));
-                }
+            RouterDestination::File(file_route) => {
+                let path = Path::new(&file_route.doc_root);
+                let request_path = path.join(rest_path);
+                spire_context.middlewares = item.middlewares.clone();
+                Ok(Some(HandlingResult {
+                    request_path: String::from(request_path.to_str().unwrap_or_default()),
+                    router_destination: RouterDestination::File(file_route),
+                }))
+            }
+            RouterDestination::Http(base_route) => {
+                let request_path =
+                    routing_table.upstream_url(&route_match, &base_route.endpoint)?;
+                spire_context.middlewares = item.middlewares.clone();
+                spire_context.route_id = Some(item.route_id.clone());
+                spire_context.anomaly_detection = item.anomaly_detection.clone();
+                spire_context.timeout = item.timeout;
+                spire_context.retry = item.retry.clone();
+                spire_context.sticky_session = item.sticky_session.clone();
+                spire_context.canary_variant = item.router.get_canary_variant(&base_route.endpoint);
+                spire_context.route_match = Some(route_match);
+                spire_context.endpoint_loads = item.router.get_endpoint_loads(&base_route.endpoint);
+                spire_context.selection_context = selection_context;
+                Ok(Some(HandlingResult {
+                    request_path,
+                    router_destination: RouterDestination::Http(base_route.clone()),
+                }))
             }
         }
-        Ok(None)
     }
     fn handle_preflight(
         &self,
@@ -433,8 +400,6 @@ mod tests {
     use http::HeaderName;
     use http::HeaderValue;
     use std::collections::HashMap;
-    use std::sync::Arc;
-    use std::sync::Mutex;
     #[tokio::test]
     async fn test_check_http_route() {
         let mut headers = HeaderMap::new();
@@ -451,12 +416,10 @@ mod tests {
         let mut config_map = HashMap::new();
         config_map.insert(8080, api_service);
 
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(crate::vojo::app_config::AppConfig {
-                api_service_config: config_map,
-                ..Default::default()
-            })),
-        };
+        let shared_config = SharedConfig::from_app_config(crate::vojo::app_config::AppConfig {
+            api_service_config: config_map,
+            ..Default::default()
+        });
 
         let checker = CommonCheckRequest {};
         let uri = "/api/test/users".parse().unwrap();
@@ -467,6 +430,7 @@ mod tests {
                 shared_config,
                 8080,
                 "test".into(),
+                &Method::GET,
                 &headers,
                 uri,
                 peer_addr,
@@ -495,12 +459,10 @@ mod tests {
         let mut config_map = HashMap::new();
         config_map.insert(8080, api_service);
 
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig {
-                api_service_config: config_map,
-                ..Default::default()
-            })),
-        };
+        let shared_config = SharedConfig::from_app_config(AppConfig {
+            api_service_config: config_map,
+            ..Default::default()
+        });
 
         let checker = CommonCheckRequest {};
         let uri = "/static/images/test.jpg".parse().unwrap();
@@ -511,6 +473,7 @@ mod tests {
                 shared_config,
                 8080,
                 "test".into(),
+                &Method::GET,
                 &headers,
                 uri,
                 peer_addr,
@@ -526,12 +489,10 @@ mod tests {
         let mut config_map = HashMap::new();
         config_map.insert(8080, ApiService::default());
 
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig {
-                api_service_config: config_map,
-                ..Default::default()
-            })),
-        };
+        let shared_config = SharedConfig::from_app_config(AppConfig {
+            api_service_config: config_map,
+            ..Default::default()
+        });
 
         let checker = CommonCheckRequest {};
         let uri = "/not/exist/path".parse().unwrap();
@@ -542,6 +503,7 @@ mod tests {
                 shared_config,
                 8080,
                 "test".into(),
+                &Method::GET,
                 &headers,
                 uri,
                 peer_addr,
@@ -1,4 +1,6 @@
+use crate::proxy::proxy_trait::RouterDestination;
 use crate::vojo::app_error::AppError;
+use crate::vojo::router::SelectionContext;
 use crate::SharedConfig;
 use futures::FutureExt;
 use http::HeaderMap;
@@ -80,7 +82,7 @@ async fn check(
     _mapping_key: String,
     remote_addr: SocketAddr,
 ) -> Result<bool, AppError> {
-    let app_config = shared_config.shared_data.lock()?.clone();
+    let app_config = shared_config.load();
     let api_service = &app_config
         .api_service_config
         .get(&port)
@@ -95,7 +97,7 @@ async fn check(
         .route_configs
         .first()
         .ok_or("service_config_clone is empty")?;
-    let is_allowed = route.clone().is_allowed(&remote_addr, None)?;
+    let is_allowed = route.is_allowed(&remote_addr, None).await?;
     Ok(is_allowed)
 }
 async fn get_route_cluster(
@@ -103,7 +105,7 @@ async fn get_route_cluster(
     shared_config: SharedConfig,
     port: i32,
 ) -> Result<String, AppError> {
-    let app_config = shared_config.shared_data.lock()?.clone();
+    let app_config = shared_config.load();
     let value = app_config
         .api_service_config
         .get(&port)
@@ -111,17 +113,17 @@ async fn get_route_cluster(
             "Can not get apiservice from mapping_key {}",
             mapping_key
         )))?;
-    let service_config = &value.route_configs.clone();
-    let service_config_clone = service_config.clone();
-    if service_config_clone.is_empty() {
-        return Err(AppError::from("The len of routes is 0"));
-    }
-    let mut route = service_config_clone
+    let route = value
+        .route_configs
         .first()
-        .ok_or("service_config_clone is empty")?
-        .router
-        .clone();
-    route.get_route(&HeaderMap::new()).map(|s| s.get_endpoint())
+        .ok_or("The len of routes is 0")?;
+    match route.get_route(&HeaderMap::new(), SelectionContext::default())? {
+        RouterDestination::Local(local_response) => Err(AppError(format!(
+            "The route can not be proxied, the status is {}",
+            local_response.status
+        ))),
+        router_destination => Ok(router_destination.get_endpoint()),
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -133,8 +135,6 @@ mod tests {
     use std::collections::HashMap;
     use std::net::{IpAddr, Ipv4Addr};
 
-    use std::sync::{Arc, Mutex};
-
     fn create_mock_shared_config(
         port: i32,
         _allowed_ips: Vec<&str>,
@@ -143,10 +143,10 @@ mod tests {
         let header_based = WeightBasedRoute {
             routes: vec![WeightedRouteItem {
                 weight: 1,
-                index: 0,
                 endpoint: "http://www.baidu.com".to_string(),
                 ..Default::default()
             }],
+            ..Default::default()
         };
         let route = RouteConfig {
             route_id: "test_route".to_string(),
@@ -161,12 +161,10 @@ mod tests {
         let mut api_service_config = HashMap::new();
         api_service_config.insert(port, api_service);
 
-        SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig {
-                api_service_config,
-                ..Default::default()
-            })),
-        }
+        SharedConfig::from_app_config(AppConfig {
+            api_service_config,
+            ..Default::default()
+        })
     }
 
     #[tokio::test]
@@ -202,12 +200,10 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_route_cluster_no_service() {
-        let shared_config = SharedConfig {
-            shared_data: Arc::new(Mutex::new(AppConfig {
-                api_service_config: HashMap::new(),
-                ..Default::default()
-            })),
-        };
+        let shared_config = SharedConfig::from_app_config(AppConfig {
+            api_service_config: HashMap::new(),
+            ..Default::default()
+        });
 
         let result = get_route_cluster("test_key".to_string(), shared_config, 8080).await;
         assert!(result.is_err());
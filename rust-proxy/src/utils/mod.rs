@@ -1 +1,3 @@
+pub mod mysql_client;
+pub mod redis_client;
 pub mod uuid;
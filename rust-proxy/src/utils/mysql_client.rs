@@ -0,0 +1,372 @@
+use crate::vojo::app_error::AppError;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const CLIENT_LONG_PASSWORD: u32 = 0x0000_0001;
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_TRANSACTIONS: u32 = 0x0000_2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const COM_PING: u8 = 0x0e;
+const MYSQL_NATIVE_PASSWORD: &str = "mysql_native_password";
+const CACHING_SHA2_PASSWORD: &str = "caching_sha2_password";
+
+/// A minimal MySQL client which only supports the handshake and COM_PING.
+pub struct MysqlConnection {
+    stream: TcpStream,
+    sequence_id: u8,
+    /// False when the server asked for the caching_sha2_password full authentication, which
+    /// needs TLS or RSA. The server is alive then, but the connection can not be used.
+    is_authenticated: bool,
+}
+impl MysqlConnection {
+    pub async fn connect(
+        address: &str,
+        user: &str,
+        password: &str,
+        database: Option<&str>,
+    ) -> Result<Self, AppError> {
+        let stream = TcpStream::connect(address).await?;
+        let mut connection = Self {
+            stream,
+            sequence_id: 0,
+            is_authenticated: true,
+        };
+        connection.handshake(user, password, database).await?;
+        Ok(connection)
+    }
+    pub fn is_authenticated(&self) -> bool {
+        self.is_authenticated
+    }
+    pub async fn ping(&mut self) -> Result<(), AppError> {
+        self.sequence_id = 0;
+        self.write_packet(&[COM_PING]).await?;
+        let packet = self.read_packet().await?;
+        check_ok_packet(&packet)
+    }
+    async fn handshake(
+        &mut self,
+        user: &str,
+        password: &str,
+        database: Option<&str>,
+    ) -> Result<(), AppError> {
+        let packet = self.read_packet().await?;
+        let handshake = parse_handshake(&packet)?;
+        let mut plugin_name = handshake.auth_plugin_name.clone();
+        if plugin_name != CACHING_SHA2_PASSWORD {
+            plugin_name = MYSQL_NATIVE_PASSWORD.to_string();
+        }
+        let auth_response = scramble_password(&plugin_name, password, &handshake.scramble);
+
+        let mut capability = CLIENT_LONG_PASSWORD
+            | CLIENT_PROTOCOL_41
+            | CLIENT_TRANSACTIONS
+            | CLIENT_SECURE_CONNECTION
+            | CLIENT_PLUGIN_AUTH;
+        if database.is_some() {
+            capability |= CLIENT_CONNECT_WITH_DB;
+        }
+        let mut payload = vec![];
+        payload.extend_from_slice(&capability.to_le_bytes());
+        payload.extend_from_slice(&(16 * 1024 * 1024u32).to_le_bytes());
+        payload.push(45);
+        payload.extend_from_slice(&[0; 23]);
+        payload.extend_from_slice(user.as_bytes());
+        payload.push(0);
+        payload.push(auth_response.len() as u8);
+        payload.extend_from_slice(&auth_response);
+        if let Some(database) = database {
+            payload.extend_from_slice(database.as_bytes());
+            payload.push(0);
+        }
+        payload.extend_from_slice(plugin_name.as_bytes());
+        payload.push(0);
+        self.write_packet(&payload).await?;
+
+        loop {
+            let packet = self.read_packet().await?;
+            match packet.first() {
+                Some(0x00) => return Ok(()),
+                Some(0xfe) => {
+                    let (plugin_name, scramble) = parse_auth_switch(&packet)?;
+                    let auth_response = scramble_password(&plugin_name, password, &scramble);
+                    self.write_packet(&auth_response).await?;
+                }
+                Some(0x01) => match packet.get(1) {
+                    Some(0x03) => continue,
+                    Some(0x04) => {
+                        self.is_authenticated = false;
+                        return Ok(());
+                    }
+                    _ => return Err(AppError::from("Unexpected mysql auth more data packet")),
+                },
+                _ => return check_ok_packet(&packet),
+            }
+        }
+    }
+    async fn read_packet(&mut self) -> Result<Vec<u8>, AppError> {
+        let mut header = [0u8; 4];
+        self.stream.read_exact(&mut header).await?;
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        self.sequence_id = header[3].wrapping_add(1);
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+    async fn write_packet(&mut self, payload: &[u8]) -> Result<(), AppError> {
+        let len = (payload.len() as u32).to_le_bytes();
+        let mut packet = vec![len[0], len[1], len[2], self.sequence_id];
+        packet.extend_from_slice(payload);
+        self.stream.write_all(&packet).await?;
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+        Ok(())
+    }
+}
+#[derive(Debug, PartialEq, Eq)]
+struct Handshake {
+    scramble: Vec<u8>,
+    auth_plugin_name: String,
+}
+fn read_null_terminated(payload: &[u8], start: usize) -> Result<(&[u8], usize), AppError> {
+    let rest = payload
+        .get(start..)
+        .ok_or("The mysql packet is too short")?;
+    let pos = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+    Ok((&rest[..pos], start + pos + 1))
+}
+fn check_ok_packet(packet: &[u8]) -> Result<(), AppError> {
+    match packet.first() {
+        Some(0x00) => Ok(()),
+        Some(0xff) => {
+            let code = packet
+                .get(1..3)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .unwrap_or_default();
+            let message = packet
+                .get(3..)
+                .map(|b| {
+                    let b = b
+                        .strip_prefix(b"#")
+                        .map(|b| b.get(5..).unwrap_or(b))
+                        .unwrap_or(b);
+                    String::from_utf8_lossy(b).to_string()
+                })
+                .unwrap_or_default();
+            Err(AppError(format!("Mysql error {}: {}", code, message)))
+        }
+        _ => Err(AppError::from("Unexpected mysql packet")),
+    }
+}
+fn parse_handshake(packet: &[u8]) -> Result<Handshake, AppError> {
+    check_protocol_version(packet)?;
+    let (_, cursor) = read_null_terminated(packet, 1)?;
+    // connection id
+    let cursor = cursor + 4;
+    let mut scramble = packet
+        .get(cursor..cursor + 8)
+        .ok_or("The mysql handshake packet is too short")?
+        .to_vec();
+    // filler, capability flags, character set, status flags, upper capability flags
+    let cursor = cursor + 8 + 1 + 2 + 1 + 2 + 2;
+    let auth_data_len = *packet
+        .get(cursor)
+        .ok_or("The mysql handshake packet is too short")? as usize;
+    let cursor = cursor + 1 + 10;
+    let part2_len = std::cmp::max(13, auth_data_len.saturating_sub(8));
+    let part2 = packet
+        .get(cursor..cursor + part2_len)
+        .ok_or("The mysql handshake packet is too short")?;
+    scramble.extend_from_slice(part2.strip_suffix(&[0]).unwrap_or(part2));
+    let auth_plugin_name = match packet.get(cursor + part2_len..) {
+        Some(rest) if !rest.is_empty() => {
+            let (name, _) = read_null_terminated(rest, 0)?;
+            String::from_utf8_lossy(name).to_string()
+        }
+        _ => MYSQL_NATIVE_PASSWORD.to_string(),
+    };
+    Ok(Handshake {
+        scramble,
+        auth_plugin_name,
+    })
+}
+fn check_protocol_version(packet: &[u8]) -> Result<(), AppError> {
+    match packet.first() {
+        Some(10) => Ok(()),
+        Some(0xff) => check_ok_packet(packet),
+        _ => Err(AppError::from("Unsupported mysql protocol version")),
+    }
+}
+fn parse_auth_switch(packet: &[u8]) -> Result<(String, Vec<u8>), AppError> {
+    let (name, cursor) = read_null_terminated(packet, 1)?;
+    let data = packet.get(cursor..).unwrap_or_default();
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    Ok((String::from_utf8_lossy(name).to_string(), data.to_vec()))
+}
+fn scramble_password(plugin_name: &str, password: &str, scramble: &[u8]) -> Vec<u8> {
+    if password.is_empty() {
+        return vec![];
+    }
+    if plugin_name == CACHING_SHA2_PASSWORD {
+        let stage1 = Sha256::digest(password.as_bytes());
+        let stage2 = Sha256::digest(stage1);
+        let stage3 = Sha256::new()
+            .chain_update(stage2)
+            .chain_update(scramble)
+            .finalize();
+        return stage1
+            .iter()
+            .zip(stage3.iter())
+            .map(|(a, b)| a ^ b)
+            .collect();
+    }
+    let stage1 = Sha1::digest(password.as_bytes());
+    let stage2 = Sha1::digest(stage1);
+    let stage3 = Sha1::new()
+        .chain_update(scramble)
+        .chain_update(stage2)
+        .finalize();
+    stage1
+        .iter()
+        .zip(stage3.iter())
+        .map(|(a, b)| a ^ b)
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    pub fn build_handshake_packet(scramble: &[u8; 20], plugin_name: &str) -> Vec<u8> {
+        let mut payload = vec![10];
+        payload.extend_from_slice(b"8.0.0-test\0");
+        payload.extend_from_slice(&1u32.to_le_bytes());
+        payload.extend_from_slice(&scramble[..8]);
+        payload.push(0);
+        payload.extend_from_slice(&0xffffu16.to_le_bytes());
+        payload.push(45);
+        payload.extend_from_slice(&2u16.to_le_bytes());
+        payload.extend_from_slice(&0x000fu16.to_le_bytes());
+        payload.push(21);
+        payload.extend_from_slice(&[0; 10]);
+        payload.extend_from_slice(&scramble[8..]);
+        payload.push(0);
+        payload.extend_from_slice(plugin_name.as_bytes());
+        payload.push(0);
+        payload
+    }
+    async fn write_packet(stream: &mut TcpStream, sequence_id: u8, payload: &[u8]) {
+        let len = (payload.len() as u32).to_le_bytes();
+        let mut packet = vec![len[0], len[1], len[2], sequence_id];
+        packet.extend_from_slice(payload);
+        stream.write_all(&packet).await.unwrap();
+    }
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        (header[3], payload)
+    }
+    /// Starts a stand-in mysql server which accepts the given password with mysql_native_password.
+    pub async fn start_mock_mysql_server(password: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let scramble = *b"abcdefghijklmnopqrst";
+                    write_packet(
+                        &mut stream,
+                        0,
+                        &build_handshake_packet(&scramble, MYSQL_NATIVE_PASSWORD),
+                    )
+                    .await;
+                    let (sequence_id, payload) = read_packet(&mut stream).await;
+                    let expected = scramble_password(MYSQL_NATIVE_PASSWORD, password, &scramble);
+                    let user_end = 32 + payload[32..].iter().position(|b| *b == 0).unwrap();
+                    let auth_len = payload[user_end + 1] as usize;
+                    let auth = &payload[user_end + 2..user_end + 2 + auth_len];
+                    if auth != expected.as_slice() {
+                        let mut err = vec![0xff, 0x15, 0x04];
+                        err.extend_from_slice(b"#28000Access denied");
+                        write_packet(&mut stream, sequence_id + 1, &err).await;
+                        return;
+                    }
+                    write_packet(&mut stream, sequence_id + 1, &[0, 0, 0, 2, 0, 0, 0]).await;
+                    let (_, payload) = read_packet(&mut stream).await;
+                    if payload == [COM_PING] {
+                        write_packet(&mut stream, 1, &[0, 0, 0, 2, 0, 0, 0]).await;
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_parse_handshake() {
+        let scramble = *b"abcdefghijklmnopqrst";
+        let handshake =
+            parse_handshake(&build_handshake_packet(&scramble, CACHING_SHA2_PASSWORD)).unwrap();
+        assert_eq!(handshake.scramble, scramble.to_vec());
+        assert_eq!(handshake.auth_plugin_name, CACHING_SHA2_PASSWORD);
+    }
+
+    #[test]
+    fn test_scramble_password_empty() {
+        assert!(scramble_password(MYSQL_NATIVE_PASSWORD, "", b"abc").is_empty());
+        assert_eq!(
+            scramble_password(MYSQL_NATIVE_PASSWORD, "root", b"abcdefghijklmnopqrst").len(),
+            20
+        );
+        assert_eq!(
+            scramble_password(CACHING_SHA2_PASSWORD, "root", b"abcdefghijklmnopqrst").len(),
+            32
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_ping() {
+        let address = start_mock_mysql_server("secret").await;
+        let mut connection = MysqlConnection::connect(&address, "root", "secret", Some("test"))
+            .await
+            .unwrap();
+        assert!(connection.ping().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_full_authentication() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let scramble = *b"abcdefghijklmnopqrst";
+            write_packet(
+                &mut stream,
+                0,
+                &build_handshake_packet(&scramble, CACHING_SHA2_PASSWORD),
+            )
+            .await;
+            let (sequence_id, _) = read_packet(&mut stream).await;
+            write_packet(&mut stream, sequence_id + 1, &[0x01, 0x04]).await;
+            let _ = read_packet(&mut stream).await;
+        });
+        let connection = MysqlConnection::connect(&address, "root", "secret", None)
+            .await
+            .unwrap();
+        assert!(!connection.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_wrong_password() {
+        let address = start_mock_mysql_server("secret").await;
+        let res = MysqlConnection::connect(&address, "root", "wrong", None).await;
+        assert!(res.is_err());
+        assert!(res.err().unwrap().0.contains("Access denied"));
+    }
+}
@@ -0,0 +1,236 @@
+use crate::vojo::app_error::AppError;
+use bytes::{Buf, BytesMut};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespValue {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Option<Vec<u8>>),
+    Array(Option<Vec<RespValue>>),
+}
+impl RespValue {
+    pub fn into_result(self) -> Result<RespValue, AppError> {
+        match self {
+            RespValue::Error(e) => Err(AppError(format!("Redis error: {}", e))),
+            other => Ok(other),
+        }
+    }
+}
+
+/// A minimal RESP2 client which is enough for health checks and script evaluation.
+pub struct RedisConnection {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+impl RedisConnection {
+    pub async fn connect(address: &str) -> Result<Self, AppError> {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Self {
+            stream,
+            buffer: BytesMut::with_capacity(512),
+        })
+    }
+    pub async fn command(&mut self, args: &[&[u8]]) -> Result<RespValue, AppError> {
+        self.stream.write_all(&encode_command(args)).await?;
+        loop {
+            if let Some(value) = parse_value(&mut self.buffer)? {
+                return Ok(value);
+            }
+            let read_size = self.stream.read_buf(&mut self.buffer).await?;
+            if read_size == 0 {
+                return Err(AppError::from("The redis connection has been closed"));
+            }
+        }
+    }
+    pub async fn auth(&mut self, username: Option<&str>, password: &str) -> Result<(), AppError> {
+        let res = match username {
+            Some(username) => {
+                self.command(&[b"AUTH", username.as_bytes(), password.as_bytes()])
+                    .await?
+            }
+            None => self.command(&[b"AUTH", password.as_bytes()]).await?,
+        };
+        expect_simple_string(res, "OK")
+    }
+    pub async fn select(&mut self, database: i64) -> Result<(), AppError> {
+        let res = self
+            .command(&[b"SELECT", database.to_string().as_bytes()])
+            .await?;
+        expect_simple_string(res, "OK")
+    }
+    pub async fn ping(&mut self) -> Result<(), AppError> {
+        let res = self.command(&[b"PING"]).await?;
+        expect_simple_string(res, "PONG")
+    }
+    /// Runs the script by its digest, and sends the whole script only if the server does not
+    /// have it cached yet.
+    pub async fn eval_script(
+        &mut self,
+        script: &str,
+        keys: &[&str],
+        args: &[String],
+    ) -> Result<RespValue, AppError> {
+        let digest = script_digest(script);
+        let key_count = keys.len().to_string();
+        let mut command: Vec<&[u8]> = vec![b"EVALSHA", digest.as_bytes(), key_count.as_bytes()];
+        command.extend(keys.iter().map(|key| key.as_bytes()));
+        command.extend(args.iter().map(|arg| arg.as_bytes()));
+        match self.command(&command).await? {
+            RespValue::Error(e) if e.starts_with("NOSCRIPT") => {
+                command[0] = b"EVAL";
+                command[1] = script.as_bytes();
+                self.command(&command).await?.into_result()
+            }
+            other => other.into_result(),
+        }
+    }
+}
+pub fn script_digest(script: &str) -> String {
+    Sha1::digest(script.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+fn expect_simple_string(res: RespValue, expected: &str) -> Result<(), AppError> {
+    match res.into_result()? {
+        RespValue::SimpleString(s) if s == expected => Ok(()),
+        other => Err(AppError(format!(
+            "Unexpected redis response {:?}, expected {}",
+            other, expected
+        ))),
+    }
+}
+pub fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+/// Parses one value from the buffer, returns None if the buffer does not hold a complete value yet.
+pub fn parse_value(buffer: &mut BytesMut) -> Result<Option<RespValue>, AppError> {
+    match parse_at(buffer, 0)? {
+        Some((value, consumed)) => {
+            buffer.advance(consumed);
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+fn read_line(buffer: &[u8], start: usize) -> Option<(&[u8], usize)> {
+    let rest = buffer.get(start..)?;
+    let pos = rest.windows(2).position(|w| w == b"\r\n")?;
+    Some((&rest[..pos], start + pos + 2))
+}
+fn parse_number(line: &[u8]) -> Result<i64, AppError> {
+    std::str::from_utf8(line)
+        .map_err(|e| AppError(e.to_string()))?
+        .parse::<i64>()
+        .map_err(|e| AppError(e.to_string()))
+}
+fn parse_at(buffer: &[u8], start: usize) -> Result<Option<(RespValue, usize)>, AppError> {
+    let prefix = match buffer.get(start) {
+        Some(prefix) => *prefix,
+        None => return Ok(None),
+    };
+    let (line, next) = match read_line(buffer, start + 1) {
+        Some(res) => res,
+        None => return Ok(None),
+    };
+    let value = match prefix {
+        b'+' => (
+            RespValue::SimpleString(String::from_utf8_lossy(line).to_string()),
+            next,
+        ),
+        b'-' => (
+            RespValue::Error(String::from_utf8_lossy(line).to_string()),
+            next,
+        ),
+        b':' => (RespValue::Integer(parse_number(line)?), next),
+        b'$' => {
+            let len = parse_number(line)?;
+            if len < 0 {
+                (RespValue::BulkString(None), next)
+            } else {
+                let end = next + len as usize;
+                if buffer.len() < end + 2 {
+                    return Ok(None);
+                }
+                (
+                    RespValue::BulkString(Some(buffer[next..end].to_vec())),
+                    end + 2,
+                )
+            }
+        }
+        b'*' => {
+            let len = parse_number(line)?;
+            if len < 0 {
+                (RespValue::Array(None), next)
+            } else {
+                let mut items = Vec::with_capacity(len as usize);
+                let mut cursor = next;
+                for _ in 0..len {
+                    match parse_at(buffer, cursor)? {
+                        Some((item, item_end)) => {
+                            items.push(item);
+                            cursor = item_end;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                (RespValue::Array(Some(items)), cursor)
+            }
+        }
+        other => {
+            return Err(AppError(format!(
+                "Unknown redis response prefix {}",
+                other as char
+            )))
+        }
+    };
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_command() {
+        let res = encode_command(&[b"GET", b"key"]);
+        assert_eq!(res, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        let mut buffer = BytesMut::from(&b"*3\r\n+OK\r\n:10\r\n$-1\r\n-ERR"[..]);
+        let value = parse_value(&mut buffer).unwrap();
+        assert_eq!(
+            value,
+            Some(RespValue::Array(Some(vec![
+                RespValue::SimpleString("OK".to_string()),
+                RespValue::Integer(10),
+                RespValue::BulkString(None),
+            ])))
+        );
+        assert_eq!(&buffer[..], b"-ERR");
+        assert_eq!(parse_value(&mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_incomplete_bulk_string() {
+        let mut buffer = BytesMut::from(&b"$5\r\nhel"[..]);
+        assert_eq!(parse_value(&mut buffer).unwrap(), None);
+        buffer.extend_from_slice(b"lo\r\n");
+        assert_eq!(
+            parse_value(&mut buffer).unwrap(),
+            Some(RespValue::BulkString(Some(b"hello".to_vec())))
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+#[allow(dead_code)]
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct BaseHealthCheckParam {
     pub timeout: i32,
@@ -18,3 +19,17 @@ pub struct HttpAnomalyDetectionParam {
 pub enum AnomalyDetectionType {
     Http(HttpAnomalyDetectionParam),
 }
+impl AnomalyDetectionType {
+    pub fn get_consecutive_5xx(&self) -> i32 {
+        match self {
+            AnomalyDetectionType::Http(http_param) => http_param.consecutive_5xx,
+        }
+    }
+    pub fn get_ejection_second(&self) -> u64 {
+        match self {
+            AnomalyDetectionType::Http(http_param) => {
+                http_param.base_anomaly_detection_param.ejection_second
+            }
+        }
+    }
+}
@@ -1,19 +1,45 @@
+use crate::constants::common_constants::ALL_CIRCUITS_OPEN;
+use crate::constants::common_constants::DEFAULT_HTTP_TIMEOUT;
+use crate::constants::common_constants::NO_HEALTHY_UPSTREAM;
+use crate::middleware::circuit_breaker::CircuitBreaker;
+use crate::middleware::middlewares::Admission;
+use crate::middleware::middlewares::Denial;
+use crate::middleware::middlewares::Grant;
 use crate::middleware::middlewares::MiddleWares;
+use crate::monitor::prometheus_exporter::set_route_panic_mode;
+use crate::proxy::proxy_trait::LocalResponse;
+use crate::proxy::proxy_trait::RouterDestination;
 use crate::utils::uuid::get_uuid;
 use crate::vojo::anomaly_detection::AnomalyDetectionType;
 use crate::vojo::app_error::AppError;
+use crate::vojo::deny_response::DenyResponses;
+use crate::vojo::health_check::HealthCheckStatus;
 use crate::vojo::health_check::HealthCheckType;
+use crate::vojo::retry_policy::RetryPolicy;
+use crate::vojo::rewrite::Rewrite;
+use crate::vojo::route_matcher::Condition;
+use crate::vojo::route_matcher::RequestInfo;
+use crate::vojo::route_rule::Rule;
 use crate::vojo::router::deserialize_router;
+use crate::vojo::router::AnomalyDetectionStatus;
 use crate::vojo::router::Router;
+use crate::vojo::router::SelectionContext;
+use crate::vojo::routing_table::RoutingTable;
+use crate::vojo::runtime_state::RuntimeState;
+use crate::vojo::sticky_session::StickySession;
 use crate::DEFAULT_ADMIN_PORT;
+use dashmap::DashMap;
 use http::HeaderMap;
 use http::HeaderValue;
-use regex::Regex;
+use http::StatusCode;
 use serde::Deserializer;
 use serde::Serializer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tracing_subscriber::filter::LevelFilter;
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -63,16 +89,67 @@ where
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct Matcher {
+    #[serde(default)]
     pub prefix: String,
+    #[serde(default)]
     pub prefix_rewrite: String,
+    /// Matches the whole path instead of the prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Matches the path by a regex, `prefix_rewrite` replaces the matched part and can refer to the
+    /// capture groups as `$1` or `${name}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+    /// Conditions which must all hold besides the path.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+}
+/// Upstream timeouts of a route in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub struct TimeoutConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// Bounds the whole request, including every retry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_ms: Option<u64>,
+    /// How long an unused upstream connection is kept in the pool.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+}
+impl TimeoutConfig {
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_ms.map(Duration::from_millis)
+    }
+    pub fn request_timeout(&self) -> Duration {
+        self.request_ms
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(DEFAULT_HTTP_TIMEOUT))
+    }
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_ms.map(Duration::from_millis)
+    }
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct LivenessConfig {
     pub min_liveness_count: i32,
+    #[serde(default)]
+    pub panic_mode: PanicMode,
 }
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+/// What a route does when fewer than `min_liveness_count` endpoints are healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum PanicMode {
+    #[default]
+    #[serde(rename = "all_endpoints")]
+    AllEndpoints,
+    #[serde(rename = "fail_fast")]
+    FailFast,
+}
+#[derive(Debug, Default)]
 pub struct LivenessStatus {
-    pub current_liveness_count: i32,
+    pub current_liveness_count: AtomicI32,
+    pub in_panic: AtomicBool,
 }
 fn is_empty(value: &str) -> bool {
     value.is_empty()
@@ -85,21 +162,41 @@ pub struct RouteConfig {
     pub host_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub matcher: Option<Matcher>,
+    /// A Traefik style rule which replaces the matcher.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<Rule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<Rewrite>,
+    /// Overrides the longest prefix ordering, the matching route with the highest priority wins.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<TimeoutConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sticky_session: Option<StickySession>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub anomaly_detection: Option<AnomalyDetectionType>,
     #[serde(skip_deserializing, skip_serializing)]
-    pub liveness_status: LivenessStatus,
+    pub anomaly_detection_status: RuntimeState<DashMap<String, AnomalyDetectionStatus>>,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub liveness_status: RuntimeState<LivenessStatus>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rewrite_headers: Option<HashMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub liveness_config: Option<LivenessConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub health_check: Option<HealthCheckType>,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub health_check_status: RuntimeState<DashMap<String, HealthCheckStatus>>,
     #[serde(deserialize_with = "deserialize_router", rename = "forward_to")]
     pub router: Router,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub middlewares: Option<Vec<MiddleWares>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny_responses: Option<DenyResponses>,
 }
 
 fn default_route_id() -> String {
@@ -107,60 +204,205 @@ fn default_route_id() -> String {
 }
 
 impl RouteConfig {
-    pub fn is_matched(
-        &mut self,
-        path: &str,
-        headers_option: Option<&HeaderMap<HeaderValue>>,
-    ) -> Result<Option<String>, AppError> {
-        let matcher = self
-            .matcher
-            .as_mut()
-            .ok_or("The matcher counld not be none for http")?;
-
-        let match_res = path.strip_prefix(matcher.prefix.as_str());
-        if match_res.is_none() {
-            return Ok(None);
+    pub fn get_route(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        mut context: SelectionContext,
+    ) -> Result<RouterDestination, AppError> {
+        context.unhealthy_endpoints = self.ejected_endpoints();
+        let in_panic = self.update_liveness_status(&context);
+        let panic_mode = self
+            .liveness_config
+            .as_ref()
+            .map(|item| item.panic_mode)
+            .unwrap_or_default();
+        if in_panic && panic_mode == PanicMode::FailFast {
+            return Ok(RouterDestination::Local(LocalResponse::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                NO_HEALTHY_UPSTREAM,
+            )));
         }
-        let final_path = [
-            matcher.prefix_rewrite.as_str(),
-            match_res.ok_or("match_res is none")?,
-        ]
-        .join("");
-        // info!("final_path:{}", final_path);
-        if let Some(real_host_name) = &self.host_name {
-            if headers_option.is_none() {
-                return Ok(None);
+        context.in_panic = in_panic;
+        let circuit_breaker = self.get_circuit_breaker();
+        if let Some(circuit_breaker) = circuit_breaker {
+            let endpoints = self.router.get_endpoints();
+            context.unavailable_endpoints =
+                circuit_breaker.unavailable_endpoints(&self.route_id, &endpoints);
+            if !endpoints.is_empty()
+                && endpoints
+                    .iter()
+                    .all(|endpoint| context.unavailable_endpoints.contains(endpoint))
+            {
+                return Ok(RouterDestination::Local(LocalResponse::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    ALL_CIRCUITS_OPEN,
+                )));
             }
-            let header_map = headers_option.ok_or("headers_option is none")?;
-            let host_option = header_map.get("Host");
-            if host_option.is_none() {
-                return Ok(None);
+        }
+        let sticky_route = self.sticky_session.as_ref().and_then(|sticky_session| {
+            sticky_session.select(headers, &self.router.get_base_routes(), &context)
+        });
+        match sticky_route {
+            Some(base_route) => Ok(RouterDestination::Http(base_route)),
+            None => self.router.get_route(headers, &context),
+        }
+    }
+    /// Returns whether the routing table compiles the other route the same way.
+    pub fn has_same_routing(&self, other: &RouteConfig) -> bool {
+        self.route_id == other.route_id
+            && self.host_name == other.host_name
+            && self.matcher == other.matcher
+            && self.rule == other.rule
+            && self.rewrite == other.rewrite
+            && self.priority == other.priority
+    }
+    /// Sticky sessions pin clients to any endpoint of the router, so they would bypass the
+    /// group which a header, failover or canary router picks.
+    pub fn check_sticky_session(&self) -> Result<(), AppError> {
+        if self.sticky_session.is_some() && self.router.has_endpoint_groups() {
+            return Err(AppError(format!(
+                "The route {} can not use a sticky session with a header, failover or canary router.",
+                self.route_id
+            )));
+        }
+        Ok(())
+    }
+    pub fn get_circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.middlewares
+            .iter()
+            .flatten()
+            .find_map(|middleware| match middleware {
+                MiddleWares::CircuitBreaker(circuit_breaker) => Some(circuit_breaker),
+                _ => None,
+            })
+    }
+    /// Refreshes the liveness status and returns whether the route is in panic mode, which is
+    /// the case when fewer than `min_liveness_count` (at least one) endpoints are healthy.
+    fn update_liveness_status(&self, context: &SelectionContext) -> bool {
+        let healthy_count = match self.router.get_healthy_count(context) {
+            Some(healthy_count) => healthy_count as i32,
+            None => return false,
+        };
+        let min_liveness_count = self
+            .liveness_config
+            .as_ref()
+            .map(|item| item.min_liveness_count)
+            .unwrap_or_default()
+            .max(1);
+        let in_panic = healthy_count < min_liveness_count;
+        self.liveness_status
+            .current_liveness_count
+            .store(healthy_count, Ordering::Relaxed);
+        if in_panic
+            != self
+                .liveness_status
+                .in_panic
+                .swap(in_panic, Ordering::Relaxed)
+        {
+            if in_panic {
+                warn!(
+                    "The route {} enters panic mode, healthy endpoints: {}, min_liveness_count: {}.",
+                    self.route_id, healthy_count, min_liveness_count
+                );
+            } else {
+                info!(
+                    "The route {} leaves panic mode, healthy endpoints: {}.",
+                    self.route_id, healthy_count
+                );
             }
-            let host_result = host_option.ok_or("host_option is none")?.to_str();
-            if host_result.is_err() {
-                return Ok(None);
+            set_route_panic_mode(&self.route_id, in_panic);
+        }
+        in_panic
+    }
+    /// Counts consecutive upstream failures of the endpoint and returns true when the
+    /// endpoint has just crossed the `consecutive_5xx` threshold and should be ejected.
+    pub fn record_upstream_result(&self, endpoint: &str, is_failure: bool) -> bool {
+        let consecutive_5xx = match &self.anomaly_detection {
+            Some(anomaly_detection) => anomaly_detection.get_consecutive_5xx(),
+            None => return false,
+        };
+        let mut status = self
+            .anomaly_detection_status
+            .entry(endpoint.to_string())
+            .or_default();
+        if !is_failure {
+            status.consecutive_5xx = 0;
+            return false;
+        }
+        status.consecutive_5xx += 1;
+        if status.is_ejected || status.consecutive_5xx < consecutive_5xx {
+            return false;
+        }
+        status.is_ejected = true;
+        true
+    }
+    /// Counts consecutive probe results of the endpoint and returns the liveness only when
+    /// reaching the healthy or unhealthy threshold changes it.
+    pub fn record_health_check_result(
+        &self,
+        endpoint: &str,
+        is_healthy: bool,
+        healthy_threshold: u32,
+        unhealthy_threshold: u32,
+    ) -> Option<bool> {
+        let mut status = self
+            .health_check_status
+            .entry(endpoint.to_string())
+            .or_default();
+        let is_alive = if is_healthy {
+            status.consecutive_failures = 0;
+            status.consecutive_successes = status.consecutive_successes.saturating_add(1);
+            (status.consecutive_successes >= healthy_threshold).then_some(true)
+        } else {
+            status.consecutive_successes = 0;
+            status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+            (status.consecutive_failures >= unhealthy_threshold).then_some(false)
+        };
+        if is_alive.is_none() || is_alive == status.is_alive {
+            return None;
+        }
+        status.is_alive = is_alive;
+        is_alive
+    }
+    pub fn ejected_endpoints(&self) -> Vec<String> {
+        self.anomaly_detection_status
+            .iter()
+            .filter(|item| item.is_ejected)
+            .map(|item| item.key().clone())
+            .collect()
+    }
+    pub fn readmit_endpoint(&self, endpoint: &str) {
+        if let Some(mut status) = self.anomaly_detection_status.get_mut(endpoint) {
+            status.consecutive_5xx = 0;
+            status.is_ejected = false;
+        }
+    }
+    pub async fn admit(
+        &self,
+        peer_addr: &SocketAddr,
+        request_option: Option<&RequestInfo<'_>>,
+    ) -> Result<Admission, AppError> {
+        let mut grant = Grant::default();
+        for middleware in self.middlewares.iter().flatten() {
+            match middleware.admit(peer_addr, request_option).await? {
+                Admission::Denied(denial) => return Ok(Admission::Denied(denial)),
+                Admission::Allowed(middleware_grant) => grant.merge(middleware_grant),
             }
-            let host_name_regex = Regex::new(real_host_name.as_str())?;
-            return host_name_regex
-                .captures(host_result?)
-                .map_or(Ok(None), |_| Ok(Some(final_path)));
         }
-        Ok(Some(final_path))
+        Ok(Admission::Allowed(grant))
     }
-    pub fn is_allowed(
-        &mut self,
+    pub async fn is_allowed(
+        &self,
         peer_addr: &SocketAddr,
-        headers_option: Option<&HeaderMap<HeaderValue>>,
+        request_option: Option<&RequestInfo<'_>>,
     ) -> Result<bool, AppError> {
-        if let Some(middlewares) = &mut self.middlewares {
-            for middleware in middlewares.iter_mut() {
-                let is_allowed = middleware.is_allowed(peer_addr, headers_option)?;
-                if !is_allowed {
-                    return Ok(is_allowed);
-                }
-            }
+        Ok(self.admit(peer_addr, request_option).await?.is_allowed())
+    }
+    pub fn deny_response(&self, denial: &Denial) -> Result<LocalResponse, AppError> {
+        match &self.deny_responses {
+            Some(deny_responses) => deny_responses.get_response(denial),
+            None => DenyResponses::default().get_response(denial),
         }
-        Ok(true)
     }
 }
 
@@ -193,6 +435,57 @@ pub struct ApiService {
     pub route_configs: Vec<RouteConfig>,
     #[serde(skip_deserializing, skip_serializing)]
     pub sender: mpsc::Sender<()>,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub routing_table: Result<Arc<RoutingTable>, AppError>,
+}
+impl ApiService {
+    pub fn compile_routing_table(&mut self) {
+        self.routing_table = RoutingTable::new(&self.route_configs).map(Arc::new);
+    }
+    /// Takes over the routing table of the previous config of the listener when the routes are
+    /// matched the same, so that changes of endpoint state do not recompile it.
+    pub fn update_routing_table(&mut self, previous: Option<&ApiService>) {
+        if let Some(previous) = previous {
+            if self.route_configs.len() == previous.route_configs.len()
+                && self
+                    .route_configs
+                    .iter()
+                    .zip(previous.route_configs.iter())
+                    .all(|(route, previous_route)| route.has_same_routing(previous_route))
+            {
+                self.routing_table = previous.routing_table.clone();
+                return;
+            }
+        }
+        self.compile_routing_table();
+    }
+    /// Reports invalid routes and routes which can never be reached.
+    pub fn check_routes(&self) -> Result<(), AppError> {
+        if self.server_type == ServiceType::Tcp {
+            return Ok(());
+        }
+        self.route_configs
+            .iter()
+            .try_for_each(|route| route.check_sticky_session())?;
+        let shadowed_routes = RoutingTable::new(&self.route_configs)?.find_shadowed_routes();
+        if shadowed_routes.is_empty() {
+            return Ok(());
+        }
+        let message = shadowed_routes
+            .iter()
+            .map(|(shadowed, by)| {
+                format!(
+                    "the route {} is shadowed by the route {}",
+                    self.route_configs[*shadowed].route_id, self.route_configs[*by].route_id
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        Err(AppError(format!(
+            "The routes of port {} are unreachable: {}.",
+            self.listen_port, message
+        )))
+    }
 }
 impl<'de> Deserialize<'de> for ApiService {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -214,16 +507,24 @@ impl<'de> Deserialize<'de> for ApiService {
         }
 
         let api_service_without_sender = ApiServiceWithoutSender::deserialize(deserializer)?;
+        for route in api_service_without_sender.route_configs.iter() {
+            route
+                .check_sticky_session()
+                .map_err(|e| serde::de::Error::custom(e.0))?;
+        }
         let (sender, _) = mpsc::channel(1); // Create a new channel for the deserialized instance
 
-        Ok(ApiService {
+        let mut api_service = ApiService {
             listen_port: api_service_without_sender.port,
             server_type: api_service_without_sender.server_type,
             cert_str: api_service_without_sender.cert_str,
             key_str: api_service_without_sender.key_str,
             route_configs: api_service_without_sender.route_configs,
             sender,
-        })
+            routing_table: Ok(Arc::default()),
+        };
+        api_service.compile_routing_table();
+        Ok(api_service)
     }
 }
 impl PartialEq for ApiService {
@@ -247,11 +548,28 @@ impl Default for ApiService {
             route_configs: Default::default(),
 
             sender,
+            routing_table: Ok(Arc::default()),
         }
     }
 }
 
 impl AppConfig {
+    pub fn compile_routing_tables(&mut self) {
+        for api_service in self.api_service_config.values_mut() {
+            api_service.compile_routing_table();
+        }
+    }
+    pub fn update_routing_tables(&mut self, previous: &AppConfig) {
+        for (port, api_service) in self.api_service_config.iter_mut() {
+            api_service.update_routing_table(previous.api_service_config.get(port));
+        }
+    }
+    pub fn check_routes(&self) -> Result<(), AppError> {
+        for api_service in self.api_service_config.values() {
+            api_service.check_routes()?;
+        }
+        Ok(())
+    }
     pub fn get_log_level(&self) -> LevelFilter {
         match self.log_level {
             Some(LogLevel::Debug) => LevelFilter::DEBUG,
@@ -277,16 +595,16 @@ pub enum LogLevel {
 #[cfg(test)]
 mod tests {
 
-    use std::time::SystemTime;
-
     use super::*;
     use crate::middleware::authentication::ApiKeyAuth;
+    use crate::vojo::route_matcher::RequestInfo;
 
     use crate::middleware::cors_config::CorsAllowedOrigins;
 
     use crate::middleware::rate_limit::IPBasedRatelimit;
     use crate::middleware::rate_limit::TimeUnit;
     use crate::middleware::rate_limit::TokenBucketRateLimit;
+    use crate::middleware::rate_limit::TokenBucketState;
     use crate::vojo::health_check::BaseHealthCheckParam;
     use crate::vojo::health_check::HttpHealthCheckParam;
     use crate::vojo::router::BaseRoute;
@@ -318,23 +636,21 @@ mod tests {
             routes: vec![
                 WeightedRouteItem {
                     weight: 1,
-                    index: 0,
                     endpoint: "http://127.0.0.1:9394".to_string(),
                     ..Default::default()
                 },
                 WeightedRouteItem {
                     weight: 2,
-                    index: 0,
                     endpoint: "http://127.0.0.1:9396".to_string(),
                     ..Default::default()
                 },
                 WeightedRouteItem {
                     weight: 3,
-                    index: 0,
                     endpoint: "http://127.0.0.1:9395".to_string(),
                     ..Default::default()
                 },
             ],
+            ..Default::default()
         };
         let route = RouteConfig {
             route_id: "test_route".to_string(),
@@ -366,7 +682,7 @@ mod tests {
                     ..Default::default()
                 },
             ],
-            current_index: 0,
+            current_index: Default::default(),
         };
         let route = RouteConfig {
             route_id: "test_route".to_string(),
@@ -462,16 +778,17 @@ mod tests {
         let header_based = WeightBasedRoute {
             routes: vec![WeightedRouteItem {
                 weight: 1,
-                index: 0,
                 endpoint: "http://127.0.0.1:9393".to_string(),
                 ..Default::default()
             }],
+            ..Default::default()
         };
         let route = RouteConfig {
             route_id: "test_route".to_string(),
             matcher: Some(Matcher {
                 prefix: "/".to_string(),
                 prefix_rewrite: "/".to_string(),
+                ..Default::default()
             }),
             router: Router::WeightBased(header_based),
 
@@ -481,10 +798,12 @@ mod tests {
                     interval: 5,
                     timeout: 5,
                 },
+                ..Default::default()
             })),
 
             liveness_config: Some(LivenessConfig {
                 min_liveness_count: 1,
+                ..Default::default()
             }),
             middlewares: Some(vec![
                 MiddleWares::Authentication(Authentication::ApiKey(ApiKeyAuth {
@@ -494,12 +813,12 @@ mod tests {
                 MiddleWares::RateLimit(Ratelimit::TokenBucket(TokenBucketRateLimit {
                     capacity: 10,
                     rate_per_unit: 10,
-                    scope: LimitLocation::IP(IPBasedRatelimit {
+                    scope: Some(LimitLocation::IP(IPBasedRatelimit {
                         value: "192.168.0.1".to_string(),
-                    }),
+                    })),
                     unit: TimeUnit::Second,
-                    current_count: 10,
-                    last_update_time: SystemTime::now(),
+                    state: TokenBucketState::new(10).into(),
+                    ..Default::default()
                 })),
                 MiddleWares::AllowDenyList(AllowDenyIp {
                     rules: vec![AllowDenyItem {
@@ -554,40 +873,58 @@ mod tests {
 
     #[test]
     fn test_route_matching() {
-        let mut route = RouteConfig {
+        let route = RouteConfig {
             matcher: Some(Matcher {
                 prefix: "/api".to_string(),
                 prefix_rewrite: "/v1".to_string(),
+                ..Default::default()
             }),
             ..Default::default()
         };
 
-        let result = route.is_matched("/api/test", None).unwrap();
-        assert_eq!(result, Some("/v1/test".to_string()));
+        let routing_table = RoutingTable::new(&[route]).unwrap();
+        let headers = HeaderMap::new();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let uri = "/api/test?id=1".parse().unwrap();
+        let request = RequestInfo::new(&http::Method::GET, &uri, &headers, &peer_addr);
+        let route_match = routing_table.match_route(&request).unwrap().unwrap();
+        let result = routing_table.rewrite_path(&route_match).unwrap();
+        assert_eq!(result, "/v1/test?id=1");
 
-        let result = route.is_matched("/other/test", None).unwrap();
+        let uri = "/other/test".parse().unwrap();
+        let request = RequestInfo::new(&http::Method::GET, &uri, &headers, &peer_addr);
+        let result = routing_table.match_route(&request).unwrap();
         assert_eq!(result, None);
     }
 
     #[test]
     fn test_route_host_matching() {
-        let mut route = RouteConfig {
+        let route = RouteConfig {
             host_name: Some("example.com".to_string()),
             matcher: Some(Matcher {
                 prefix: "/api".to_string(),
                 prefix_rewrite: "/v1".to_string(),
+                ..Default::default()
             }),
             ..Default::default()
         };
 
+        let routing_table = RoutingTable::new(&[route]).unwrap();
         let mut headers = HeaderMap::new();
         headers.insert("Host", HeaderValue::from_static("example.com"));
 
-        let result = route.is_matched("/api/test", Some(&headers)).unwrap();
-        assert_eq!(result, Some("/v1/test".to_string()));
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let uri = "/api/test".parse().unwrap();
+        let request = RequestInfo::new(&http::Method::GET, &uri, &headers, &peer_addr);
+        let result = routing_table
+            .match_route(&request)
+            .unwrap()
+            .map(|item| item.route_index);
+        assert_eq!(result, Some(0));
 
         headers.insert("Host", HeaderValue::from_static("wrong.com"));
-        let result = route.is_matched("/api/test", Some(&headers)).unwrap();
+        let request = RequestInfo::new(&http::Method::GET, &uri, &headers, &peer_addr);
+        let result = routing_table.match_route(&request).unwrap();
         assert_eq!(result, None);
     }
 
@@ -612,10 +949,10 @@ mod tests {
             router: Router::WeightBased(WeightBasedRoute {
                 routes: vec![WeightedRouteItem {
                     weight: 1,
-                    index: 0,
                     endpoint: "http://example.com".to_string(),
                     ..Default::default()
                 }],
+                ..Default::default()
             }),
             ..Default::default()
         };
@@ -650,4 +987,226 @@ response_object:
           targets: []"#;
         let _: BaseResponse<AppConfig> = serde_yaml::from_str(src).unwrap();
     }
+
+    fn create_panic_route(panic_mode: PanicMode) -> RouteConfig {
+        RouteConfig {
+            route_id: "route1".to_string(),
+            router: Router::Poll(PollRoute {
+                current_index: Default::default(),
+                routes: vec![
+                    BaseRoute {
+                        endpoint: "http://127.0.0.1:9001".to_string(),
+                        is_alive: Some(true),
+                    },
+                    BaseRoute {
+                        endpoint: "http://127.0.0.1:9002".to_string(),
+                        is_alive: Some(false),
+                    },
+                    BaseRoute {
+                        endpoint: "http://127.0.0.1:9003".to_string(),
+                        is_alive: Some(false),
+                    },
+                ],
+            }),
+            liveness_config: Some(LivenessConfig {
+                min_liveness_count: 2,
+                panic_mode,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_panic_mode_all_endpoints() {
+        let mut route = create_panic_route(PanicMode::AllEndpoints);
+        let endpoints: Vec<String> = (0..3)
+            .map(|_| {
+                route
+                    .get_route(&HeaderMap::new(), SelectionContext::default())
+                    .unwrap()
+                    .get_endpoint()
+            })
+            .collect();
+        assert!(route.liveness_status.in_panic.load(Ordering::Relaxed));
+        assert_eq!(
+            route
+                .liveness_status
+                .current_liveness_count
+                .load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(
+            endpoints,
+            vec![
+                "http://127.0.0.1:9001",
+                "http://127.0.0.1:9002",
+                "http://127.0.0.1:9003"
+            ]
+        );
+
+        route
+            .router
+            .update_route_alive(
+                BaseRoute {
+                    endpoint: "http://127.0.0.1:9002".to_string(),
+                    is_alive: None,
+                },
+                true,
+            )
+            .unwrap();
+        for _ in 0..4 {
+            let endpoint = route
+                .get_route(&HeaderMap::new(), SelectionContext::default())
+                .unwrap()
+                .get_endpoint();
+            assert_ne!(endpoint, "http://127.0.0.1:9003");
+        }
+        assert!(!route.liveness_status.in_panic.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_panic_mode_fail_fast() {
+        let route = create_panic_route(PanicMode::FailFast);
+        match route
+            .get_route(&HeaderMap::new(), SelectionContext::default())
+            .unwrap()
+        {
+            RouterDestination::Local(local_response) => {
+                assert_eq!(local_response.status, StatusCode::SERVICE_UNAVAILABLE);
+            }
+            other => panic!("unexpected destination {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_open_circuits_are_skipped() {
+        let circuit_breaker = CircuitBreaker {
+            consecutive_failures: Some(1),
+            ..Default::default()
+        };
+        let route = RouteConfig {
+            middlewares: Some(vec![MiddleWares::CircuitBreaker(circuit_breaker.clone())]),
+            ..create_panic_route(PanicMode::AllEndpoints)
+        };
+        let latency = Duration::from_millis(1);
+        let request = circuit_breaker.start_request("http://127.0.0.1:9001");
+        circuit_breaker.record_result("route1", request, true, latency);
+        for _ in 0..4 {
+            let endpoint = route
+                .get_route(&HeaderMap::new(), SelectionContext::default())
+                .unwrap()
+                .get_endpoint();
+            assert_ne!(endpoint, "http://127.0.0.1:9001");
+        }
+
+        let request = circuit_breaker.start_request("http://127.0.0.1:9002");
+        circuit_breaker.record_result("route1", request, true, latency);
+        let request = circuit_breaker.start_request("http://127.0.0.1:9003");
+        circuit_breaker.record_result("route1", request, true, latency);
+        match route
+            .get_route(&HeaderMap::new(), SelectionContext::default())
+            .unwrap()
+        {
+            RouterDestination::Local(local_response) => {
+                assert_eq!(local_response.status, StatusCode::SERVICE_UNAVAILABLE);
+            }
+            other => panic!("unexpected destination {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sticky_session_pins_endpoint() {
+        let circuit_breaker = CircuitBreaker {
+            consecutive_failures: Some(1),
+            ..Default::default()
+        };
+        let sticky_session: StickySession = serde_yaml::from_str("key: secret").unwrap();
+        let set_cookie = sticky_session.set_cookie("http://127.0.0.1:9001").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "cookie",
+            HeaderValue::from_str(set_cookie.to_str().unwrap().split(';').next().unwrap()).unwrap(),
+        );
+        let route = RouteConfig {
+            sticky_session: Some(sticky_session),
+            middlewares: Some(vec![MiddleWares::CircuitBreaker(circuit_breaker.clone())]),
+            ..create_panic_route(PanicMode::AllEndpoints)
+        };
+        for _ in 0..10 {
+            let endpoint = route
+                .get_route(&headers, SelectionContext::default())
+                .unwrap()
+                .get_endpoint();
+            assert_eq!(endpoint, "http://127.0.0.1:9001");
+        }
+
+        let latency = Duration::from_millis(1);
+        let request = circuit_breaker.start_request("http://127.0.0.1:9001");
+        circuit_breaker.record_result("route1", request, true, latency);
+        let endpoint = route
+            .get_route(&headers, SelectionContext::default())
+            .unwrap()
+            .get_endpoint();
+        assert_ne!(endpoint, "http://127.0.0.1:9001");
+    }
+
+    #[test]
+    fn test_liveness_config_default_panic_mode() {
+        let liveness_config: LivenessConfig =
+            serde_yaml::from_str("min_liveness_count: 2").unwrap();
+        assert_eq!(liveness_config.panic_mode, PanicMode::AllEndpoints);
+        let liveness_config: LivenessConfig =
+            serde_yaml::from_str("min_liveness_count: 2\npanic_mode: fail_fast").unwrap();
+        assert_eq!(liveness_config.panic_mode, PanicMode::FailFast);
+    }
+
+    #[test]
+    fn test_check_routes_reports_shadowed_route() {
+        let create_route = |route_id: &str, prefix: &str| RouteConfig {
+            route_id: route_id.to_string(),
+            matcher: Some(Matcher {
+                prefix: prefix.to_string(),
+                prefix_rewrite: "/".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut api_service = ApiService {
+            listen_port: 8080,
+            route_configs: vec![create_route("root", "/"), create_route("api", "/api")],
+            ..Default::default()
+        };
+        assert!(api_service.check_routes().is_ok());
+
+        api_service.route_configs.push(create_route("api2", "/api"));
+        let err = api_service.check_routes().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("the route api2 is shadowed by the route api"));
+
+        api_service.server_type = ServiceType::Tcp;
+        assert!(api_service.check_routes().is_ok());
+    }
+
+    #[test]
+    fn test_sticky_session_is_rejected_on_grouped_routers() {
+        let create_service = |forward_to: &str| {
+            format!(
+                "listen: 8080\nprotocol: http\nroutes:\n  - route_id: api\n    matcher:\n      prefix: /\n      prefix_rewrite: /\n    sticky_session:\n      key: secret\n    forward_to:\n{}",
+                forward_to
+            )
+        };
+        let poll = "      kind: poll\n      targets:\n        - endpoint: http://a\n        - endpoint: http://b";
+        let api_service: ApiService = serde_yaml::from_str(&create_service(poll)).unwrap();
+        assert!(api_service.check_routes().is_ok());
+
+        let failover = "      kind: failover\n      targets:\n        - forward_to: http://a\n        - forward_to: http://b";
+        assert!(serde_yaml::from_str::<ApiService>(&create_service(failover)).is_err());
+        let mut api_service = api_service;
+        api_service.route_configs[0].router = serde_yaml::from_str(
+            "kind: failover\ntargets:\n  - forward_to: http://a\n  - forward_to: http://b",
+        )
+        .unwrap();
+        assert!(api_service.check_routes().is_err());
+    }
 }
@@ -1,4 +1,3 @@
-use crate::vojo::app_config::AppConfig;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use http::header::InvalidHeaderValue;
@@ -170,8 +169,8 @@ impl From<http::Error> for AppError {
         AppError(format!("HTTP error: {}", error))
     }
 }
-impl From<PoisonError<std::sync::MutexGuard<'_, AppConfig>>> for AppError {
-    fn from(error: PoisonError<std::sync::MutexGuard<'_, AppConfig>>) -> Self {
+impl<T> From<PoisonError<T>> for AppError {
+    fn from(error: PoisonError<T>) -> Self {
         AppError(format!("Mutex error: {}", error))
     }
 }
@@ -183,6 +182,7 @@ impl From<serde_json::Error> for AppError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vojo::app_config::AppConfig;
 
     use std::io::ErrorKind;
     use std::sync::Arc;
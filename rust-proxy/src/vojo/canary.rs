@@ -0,0 +1,286 @@
+use super::app_error::AppError;
+use super::consistent_hash::hash;
+use super::consistent_hash::HashKey;
+use super::router::deserialize_router;
+use super::router::BaseRoute;
+use super::router::Router;
+use super::router::SelectionContext;
+use crate::proxy::proxy_trait::RouterDestination;
+use http::HeaderMap;
+use http::HeaderValue;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The resolution of the split, a hash key falls into one of these buckets.
+const CANARY_BUCKETS: u64 = 10_000;
+
+/// Splits the traffic between variants by weight. A request is assigned by the hash of its
+/// `hash_on` value, so a user keeps its variant, and requests without the value are assigned
+/// randomly. The variants take consecutive bucket ranges in the configured order, so when only
+/// the weight of the last variant grows, users only move towards later variants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanaryRoute {
+    pub hash_on: HashKey,
+    #[serde(rename = "targets")]
+    pub variants: Vec<CanaryVariant>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanaryVariant {
+    pub name: String,
+    pub weight: u32,
+    #[serde(deserialize_with = "deserialize_router", rename = "forward_to")]
+    pub router: Router,
+}
+impl CanaryRoute {
+    fn assign(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Option<usize> {
+        let total: u64 = self.variants.iter().map(|v| v.weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let bucket = match self.hash_on.get_value(headers, context) {
+            Some(value) => hash(value.as_bytes()) % CANARY_BUCKETS,
+            None => rand::rng().random_range(0..CANARY_BUCKETS),
+        };
+        let mut cumulative = 0;
+        self.variants.iter().position(|variant| {
+            cumulative += variant.weight as u64;
+            bucket < cumulative * CANARY_BUCKETS / total
+        })
+    }
+    /// Returns whether the variant has an endpoint which is healthy and available, or answers
+    /// locally.
+    fn has_healthy_endpoint(&self, index: usize, context: &SelectionContext) -> bool {
+        let router = &self.variants[index].router;
+        !router.has_endpoints()
+            || router.get_base_routes().iter().any(|route| {
+                context.is_healthy(&route.endpoint, route.is_alive)
+                    && !context.unavailable_endpoints.contains(&route.endpoint)
+            })
+    }
+    /// Routes to the assigned variant, or to the other weighted variants in order if it has no
+    /// endpoint left. Variants with a healthy endpoint are tried first, so a variant which is
+    /// down hands its share over instead of sending it to its unhealthy endpoints.
+    pub fn get_route(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<RouterDestination, AppError> {
+        let assigned = self
+            .assign(headers, context)
+            .ok_or("No canary variant has weight")?;
+        let fallbacks = (0..self.variants.len())
+            .filter(|&index| index != assigned && self.variants[index].weight > 0);
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) = std::iter::once(assigned)
+            .chain(fallbacks)
+            .partition(|&index| context.in_panic || self.has_healthy_endpoint(index, context));
+        let mut last_error = None;
+        for index in healthy.into_iter().chain(unhealthy) {
+            match self.variants[index].router.get_route(headers, context) {
+                Ok(router_destination) => return Ok(router_destination),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or(AppError::from("No routes available")))
+    }
+    pub fn get_base_routes(&self) -> Vec<BaseRoute> {
+        self.variants
+            .iter()
+            .flat_map(|variant| variant.router.get_base_routes())
+            .collect()
+    }
+    /// Returns the variant whose endpoints include the endpoint.
+    pub fn find_variant(&self, endpoint: &str) -> Option<&CanaryVariant> {
+        self.variants.iter().find(|variant| {
+            variant
+                .router
+                .get_base_routes()
+                .iter()
+                .any(|r| r.endpoint == endpoint)
+        })
+    }
+    /// Changes the weights of the named variants, the others keep their weight.
+    pub fn set_weights(&mut self, weights: &HashMap<String, u32>) -> Result<(), AppError> {
+        if let Some(name) = weights
+            .keys()
+            .find(|name| !self.variants.iter().any(|v| &v.name == *name))
+        {
+            return Err(AppError(format!(
+                "Can not find the canary variant {}!",
+                name
+            )));
+        }
+        let total: u64 = self
+            .variants
+            .iter()
+            .map(|v| *weights.get(&v.name).unwrap_or(&v.weight) as u64)
+            .sum();
+        if total == 0 {
+            return Err(AppError::from("At least one canary variant needs weight!"));
+        }
+        for variant in self.variants.iter_mut() {
+            if let Some(weight) = weights.get(&variant.name) {
+                variant.weight = *weight;
+            }
+        }
+        Ok(())
+    }
+    pub fn update_route_alive(
+        &mut self,
+        base_route: BaseRoute,
+        is_alive: bool,
+    ) -> Result<(), AppError> {
+        for variant in self.variants.iter_mut() {
+            if variant.router.has_endpoints() {
+                variant
+                    .router
+                    .update_route_alive(base_route.clone(), is_alive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_canary_route(canary_weight: u32) -> CanaryRoute {
+        let mut route: CanaryRoute = serde_yaml::from_str(
+            r#"
+hash_on:
+  kind: header
+  name: x-user
+targets:
+  - name: stable
+    weight: 100
+    forward_to:
+      kind: poll
+      targets:
+        - endpoint: http://stable-1
+        - endpoint: http://stable-2
+  - name: v2
+    weight: 0
+    forward_to: http://v2
+"#,
+        )
+        .unwrap();
+        route
+            .set_weights(&HashMap::from([
+                ("stable".to_string(), 100 - canary_weight),
+                ("v2".to_string(), canary_weight),
+            ]))
+            .unwrap();
+        route
+    }
+    fn select(route: &CanaryRoute, user: &str) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user", HeaderValue::from_str(user).unwrap());
+        route
+            .get_route(&headers, &SelectionContext::default())
+            .unwrap()
+            .get_endpoint()
+    }
+
+    #[test]
+    fn test_users_are_assigned_consistently() {
+        let users: Vec<String> = (0..2000).map(|index| format!("user-{}", index)).collect();
+        let route = create_canary_route(5);
+        let canary_users: Vec<&String> = users
+            .iter()
+            .filter(|user| select(&route, user) == "http://v2")
+            .collect();
+        assert!(
+            (50..150).contains(&canary_users.len()),
+            "{} canary users",
+            canary_users.len()
+        );
+        assert!(canary_users
+            .iter()
+            .all(|user| (0..5).all(|_| select(&route, user) == "http://v2")));
+
+        let route = create_canary_route(20);
+        assert!(canary_users
+            .iter()
+            .all(|user| select(&route, user) == "http://v2"));
+        let count = users
+            .iter()
+            .filter(|user| select(&route, user) == "http://v2")
+            .count();
+        assert!((300..500).contains(&count), "{} canary users", count);
+    }
+
+    #[test]
+    fn test_unavailable_variant_falls_back() {
+        let route = create_canary_route(90);
+        let context = SelectionContext {
+            unavailable_endpoints: vec!["http://v2".to_string()],
+            ..Default::default()
+        };
+        let endpoint = route
+            .get_route(&HeaderMap::new(), &context)
+            .unwrap()
+            .get_endpoint();
+        assert!(endpoint.starts_with("http://stable"));
+        assert_eq!(
+            route.find_variant("http://stable-2").unwrap().name,
+            "stable"
+        );
+        assert_eq!(route.get_base_routes().len(), 3);
+    }
+
+    #[test]
+    fn test_variant_without_healthy_endpoint_falls_back() {
+        let mut route = create_canary_route(90);
+        let down = BaseRoute {
+            endpoint: "http://v2".to_string(),
+            is_alive: None,
+        };
+        route.update_route_alive(down, false).unwrap();
+        let users: Vec<String> = (0..50).map(|index| format!("user-{}", index)).collect();
+        assert!(users
+            .iter()
+            .all(|user| select(&route, user).starts_with("http://stable")));
+
+        for endpoint in ["http://stable-1", "http://stable-2"] {
+            let base_route = BaseRoute {
+                endpoint: endpoint.to_string(),
+                is_alive: None,
+            };
+            route.update_route_alive(base_route, false).unwrap();
+        }
+        assert!(users.iter().any(|user| select(&route, user) == "http://v2"));
+    }
+
+    #[test]
+    fn test_zero_weight_variant_is_no_fallback() {
+        let route = create_canary_route(0);
+        let context = SelectionContext {
+            unavailable_endpoints: vec![
+                "http://stable-1".to_string(),
+                "http://stable-2".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(route.get_route(&HeaderMap::new(), &context).is_err());
+    }
+
+    #[test]
+    fn test_set_weights() {
+        let mut route = create_canary_route(5);
+        let unknown = HashMap::from([("v3".to_string(), 10)]);
+        assert!(route.set_weights(&unknown).is_err());
+        let zero = HashMap::from([("stable".to_string(), 0), ("v2".to_string(), 0)]);
+        assert!(route.set_weights(&zero).is_err());
+        assert_eq!(route.variants[1].weight, 5);
+        route
+            .set_weights(&HashMap::from([("v2".to_string(), 10)]))
+            .unwrap();
+        assert_eq!(route.variants[0].weight, 95);
+        assert_eq!(route.variants[1].weight, 10);
+    }
+}
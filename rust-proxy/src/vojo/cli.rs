@@ -1,6 +1,8 @@
 use clap::Parser;
 
 use super::app_config::AppConfig;
+use super::app_error::AppError;
+use arc_swap::ArcSwap;
 use std::sync::Arc;
 use std::sync::Mutex;
 #[derive(Parser, Debug, Clone)]
@@ -9,14 +11,127 @@ pub struct Cli {
     #[arg(short = 'f', long, default_value = "config.yaml")]
     pub config_path: String,
 }
+/// The config is published as an immutable snapshot which the request path loads without
+/// locking. Writers are serialized, build a new config from the latest snapshot and swap it in.
 #[derive(Clone)]
 pub struct SharedConfig {
-    pub shared_data: Arc<Mutex<AppConfig>>,
+    shared_data: Arc<ArcSwap<AppConfig>>,
+    write_lock: Arc<Mutex<()>>,
 }
 impl SharedConfig {
-    pub fn from_app_config(app_config: AppConfig) -> Self {
+    pub fn from_app_config(mut app_config: AppConfig) -> Self {
+        app_config.compile_routing_tables();
         Self {
-            shared_data: Arc::new(Mutex::new(app_config)),
+            shared_data: Arc::new(ArcSwap::from_pointee(app_config)),
+            write_lock: Arc::new(Mutex::new(())),
         }
     }
+    pub fn load(&self) -> Arc<AppConfig> {
+        self.shared_data.load_full()
+    }
+    /// Applies the change to a copy of the latest snapshot and publishes it if the change succeeds.
+    pub fn update<T>(
+        &self,
+        change: impl FnOnce(&mut AppConfig) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let _write_guard = self.write_lock.lock()?;
+        let previous = self.shared_data.load_full();
+        let mut app_config = AppConfig::clone(&previous);
+        let res = change(&mut app_config)?;
+        app_config.update_routing_tables(&previous);
+        self.shared_data.store(Arc::new(app_config));
+        Ok(res)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vojo::app_config::ApiService;
+    use crate::vojo::app_config::Matcher;
+    use crate::vojo::app_config::RouteConfig;
+
+    #[test]
+    fn test_update_publishes_new_snapshot() {
+        let shared_config = SharedConfig::from_app_config(AppConfig::default());
+        let old_snapshot = shared_config.load();
+        shared_config
+            .update(|app_config| {
+                app_config
+                    .api_service_config
+                    .insert(8080, ApiService::default());
+                Ok(())
+            })
+            .unwrap();
+        assert!(old_snapshot.api_service_config.is_empty());
+        assert!(shared_config.load().api_service_config.contains_key(&8080));
+    }
+
+    #[test]
+    fn test_update_keeps_routing_table_of_unchanged_routes() {
+        let mut api_service = ApiService::default();
+        api_service.route_configs.push(RouteConfig {
+            route_id: "route1".to_string(),
+            matcher: Some(Matcher {
+                prefix: "/".to_string(),
+                prefix_rewrite: "/".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let mut app_config = AppConfig::default();
+        app_config.api_service_config.insert(8080, api_service);
+        let shared_config = SharedConfig::from_app_config(app_config);
+        let routing_table = |shared_config: &SharedConfig| {
+            shared_config.load().api_service_config[&8080]
+                .routing_table
+                .clone()
+                .unwrap()
+        };
+        let old_routing_table = routing_table(&shared_config);
+        shared_config
+            .update(|app_config| {
+                let route = &mut app_config
+                    .api_service_config
+                    .get_mut(&8080)
+                    .unwrap()
+                    .route_configs[0];
+                route.readmit_endpoint("http://127.0.0.1:9001");
+                route.health_check = None;
+                Ok(())
+            })
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            &old_routing_table,
+            &routing_table(&shared_config)
+        ));
+
+        shared_config
+            .update(|app_config| {
+                app_config
+                    .api_service_config
+                    .get_mut(&8080)
+                    .unwrap()
+                    .route_configs[0]
+                    .priority = Some(1);
+                Ok(())
+            })
+            .unwrap();
+        assert!(!Arc::ptr_eq(
+            &old_routing_table,
+            &routing_table(&shared_config)
+        ));
+    }
+
+    #[test]
+    fn test_failed_update_keeps_snapshot() {
+        let shared_config = SharedConfig::from_app_config(AppConfig::default());
+        let res = shared_config.update(|app_config| -> Result<(), AppError> {
+            app_config
+                .api_service_config
+                .insert(8080, ApiService::default());
+            Err(AppError::from("invalid config"))
+        });
+        assert!(res.is_err());
+        assert!(shared_config.load().api_service_config.is_empty());
+    }
 }
@@ -0,0 +1,295 @@
+use super::app_error::AppError;
+use super::route_matcher::cookie_values;
+use super::route_matcher::query_values;
+use super::router::candidate_indices;
+use super::router::BaseRoute;
+use super::router::SelectionContext;
+use http::HeaderMap;
+use http::HeaderValue;
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The part of the request which decides the endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum HashKey {
+    #[serde(rename = "client_ip")]
+    ClientIp,
+    #[serde(rename = "header")]
+    Header { name: String },
+    #[serde(rename = "cookie")]
+    Cookie { name: String },
+    #[serde(rename = "query")]
+    Query { name: String },
+    #[serde(rename = "path")]
+    Path,
+}
+impl HashKey {
+    pub fn get_value(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Option<String> {
+        match self {
+            HashKey::ClientIp => context.peer_addr.map(|addr| addr.ip().to_string()),
+            HashKey::Header { name } => headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            HashKey::Cookie { name } => cookie_values(headers, name)
+                .next()
+                .map(|value| value.to_string()),
+            HashKey::Query { name } => {
+                let query = context.uri.as_ref().and_then(|uri| uri.query());
+                query_values(query, name).into_iter().next()
+            }
+            HashKey::Path => context.uri.as_ref().map(|uri| uri.path().to_string()),
+        }
+    }
+}
+
+/// Maps the hash key to an endpoint on a hash ring, so adding or removing an endpoint only
+/// remaps the keys next to its points. Requests without the key go to a random endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConsistentHashRoute {
+    pub hash_on: HashKey,
+    /// The number of points every endpoint has on the ring.
+    pub virtual_nodes: u32,
+    #[serde(rename = "targets")]
+    pub routes: Vec<BaseRoute>,
+    /// Built once from the endpoints, which never change within a config snapshot.
+    #[serde(skip_serializing)]
+    pub ring: Arc<HashRing>,
+}
+fn default_virtual_nodes() -> u32 {
+    160
+}
+impl<'de> Deserialize<'de> for ConsistentHashRoute {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Inner {
+            hash_on: HashKey,
+            #[serde(default = "default_virtual_nodes")]
+            virtual_nodes: u32,
+            #[serde(rename = "targets")]
+            routes: Vec<BaseRoute>,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+        Ok(ConsistentHashRoute::new(
+            inner.hash_on,
+            inner.virtual_nodes,
+            inner.routes,
+        ))
+    }
+}
+/// The points of the endpoints, sorted by hash.
+#[derive(Debug, Default, PartialEq)]
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+}
+impl HashRing {
+    fn new(routes: &[BaseRoute], virtual_nodes: u32) -> Self {
+        let mut points: Vec<(u64, usize)> = routes
+            .iter()
+            .map(|route| &route.endpoint)
+            .enumerate()
+            .flat_map(|(index, endpoint)| {
+                (0..virtual_nodes.max(1))
+                    .map(move |node| (hash(format!("{}#{}", endpoint, node).as_bytes()), index))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+    /// Walks the ring clockwise from the key and returns the first allowed endpoint, so a
+    /// skipped endpoint always hands its keys to the same neighbour.
+    fn lookup(&self, key: u64, allowed: &HashSet<usize>) -> Option<usize> {
+        let start = self.points.partition_point(|(point, _)| *point < key);
+        (0..self.points.len())
+            .map(|offset| self.points[(start + offset) % self.points.len()].1)
+            .find(|index| allowed.contains(index))
+    }
+}
+/// FNV-1a followed by the splitmix64 finalizer, which is stable across processes and releases
+/// unlike the std hasher.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+impl ConsistentHashRoute {
+    pub fn new(hash_on: HashKey, virtual_nodes: u32, routes: Vec<BaseRoute>) -> Self {
+        let ring = Arc::new(HashRing::new(&routes, virtual_nodes));
+        Self {
+            hash_on,
+            virtual_nodes,
+            routes,
+            ring,
+        }
+    }
+    pub fn get_route(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        if candidates.is_empty() {
+            return Err(AppError::from("No routes available"));
+        }
+        let index = match self.hash_on.get_value(headers, context) {
+            Some(value) => {
+                let allowed = candidates.iter().copied().collect();
+                self.ring
+                    .lookup(hash(value.as_bytes()), &allowed)
+                    .ok_or("No routes available")?
+            }
+            None => candidates[rand::rng().random_range(0..candidates.len())],
+        };
+        Ok(self.routes[index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Uri;
+    use std::net::SocketAddr;
+
+    fn create_route(hash_on: HashKey, count: usize) -> ConsistentHashRoute {
+        ConsistentHashRoute::new(
+            hash_on,
+            default_virtual_nodes(),
+            (0..count)
+                .map(|index| BaseRoute {
+                    endpoint: format!("http://10.0.0.{}", index),
+                    is_alive: None,
+                })
+                .collect(),
+        )
+    }
+    fn select(route: &ConsistentHashRoute, key: &str) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user", HeaderValue::from_str(key).unwrap());
+        route
+            .get_route(&headers, &SelectionContext::default())
+            .unwrap()
+            .endpoint
+    }
+
+    #[test]
+    fn test_adding_an_endpoint_remaps_few_keys() {
+        let hash_on = HashKey::Header {
+            name: "x-user".to_string(),
+        };
+        let route = create_route(hash_on.clone(), 10);
+        let keys: Vec<String> = (0..2000).map(|index| format!("user-{}", index)).collect();
+        let before: Vec<String> = keys.iter().map(|key| select(&route, key)).collect();
+        assert_eq!(
+            before,
+            keys.iter()
+                .map(|key| select(&route, key))
+                .collect::<Vec<_>>()
+        );
+        let distinct: HashSet<&String> = before.iter().collect();
+        assert_eq!(distinct.len(), 10);
+
+        let route = create_route(hash_on, 11);
+        let moved = keys
+            .iter()
+            .zip(before.iter())
+            .filter(|(key, endpoint)| select(&route, key) != **endpoint)
+            .count();
+        assert!(moved > 0 && moved < 400, "moved {} keys", moved);
+    }
+
+    #[test]
+    fn test_ring_is_built_on_load() {
+        let route: ConsistentHashRoute = serde_yaml::from_str(
+            "hash_on:\n  kind: path\nvirtual_nodes: 4\ntargets:\n  - endpoint: http://a\n  - endpoint: http://b",
+        )
+        .unwrap();
+        assert_eq!(route.ring.points.len(), 8);
+        assert!(Arc::ptr_eq(&route.clone().ring, &route.ring));
+    }
+
+    #[test]
+    fn test_unhealthy_endpoints_are_skipped_deterministically() {
+        let mut route = create_route(
+            HashKey::Header {
+                name: "x-user".to_string(),
+            },
+            5,
+        );
+        let keys: Vec<String> = (0..200).map(|index| format!("user-{}", index)).collect();
+        let before: Vec<String> = keys.iter().map(|key| select(&route, key)).collect();
+        route.routes[2].is_alive = Some(false);
+        let after: Vec<String> = keys.iter().map(|key| select(&route, key)).collect();
+        for (old, new) in before.iter().zip(after.iter()) {
+            assert_ne!(new, "http://10.0.0.2");
+            if old != "http://10.0.0.2" {
+                assert_eq!(old, new);
+            }
+        }
+        assert_eq!(
+            after,
+            keys.iter()
+                .map(|key| select(&route, key))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_hash_keys() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_static("a=1; session=abc"));
+        let context = SelectionContext {
+            peer_addr: Some(SocketAddr::from(([192, 168, 1, 7], 4000))),
+            uri: Some(Uri::from_static("http://example.com/cache/item?shard=9")),
+            ..Default::default()
+        };
+        let cases = vec![
+            (HashKey::ClientIp, Some("192.168.1.7")),
+            (
+                HashKey::Cookie {
+                    name: "session".to_string(),
+                },
+                Some("abc"),
+            ),
+            (
+                HashKey::Query {
+                    name: "shard".to_string(),
+                },
+                Some("9"),
+            ),
+            (HashKey::Path, Some("/cache/item")),
+            (
+                HashKey::Header {
+                    name: "x-missing".to_string(),
+                },
+                None,
+            ),
+        ];
+        for (hash_key, expected) in cases {
+            assert_eq!(
+                hash_key.get_value(&headers, &context).as_deref(),
+                expected,
+                "{:?}",
+                hash_key
+            );
+        }
+        let route: ConsistentHashRoute = serde_yaml::from_str(
+            "hash_on:\n  kind: cookie\n  name: session\ntargets:\n  - endpoint: http://a",
+        )
+        .unwrap();
+        assert_eq!(route.virtual_nodes, 160);
+    }
+}
@@ -0,0 +1,223 @@
+use super::app_error::AppError;
+use crate::constants::common_constants::DENY_RESPONSE;
+use crate::constants::common_constants::FORBIDDEN_RESPONSE;
+use crate::constants::common_constants::RATE_LIMITED_RESPONSE;
+use crate::constants::common_constants::UNAUTHENTICATED_RESPONSE;
+use crate::middleware::middlewares::Denial;
+use crate::proxy::proxy_trait::LocalResponse;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::StatusCode;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Replaces the status or the body of the response to a kind of denial. The headers are added to
+/// the ones Spire sets, such as Retry-After and WWW-Authenticate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct DenyResponse {
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_status",
+        deserialize_with = "deserialize_status"
+    )]
+    pub status: Option<StatusCode>,
+    #[serde(
+        default,
+        skip_serializing_if = "HeaderMap::is_empty",
+        serialize_with = "serialize_headers",
+        deserialize_with = "deserialize_headers"
+    )]
+    pub headers: HeaderMap<HeaderValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+fn serialize_status<S>(status: &Option<StatusCode>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match status {
+        Some(status) => serializer.serialize_u16(status.as_u16()),
+        None => serializer.serialize_none(),
+    }
+}
+fn deserialize_status<'de, D>(deserializer: D) -> Result<Option<StatusCode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let status = u16::deserialize(deserializer)?;
+    StatusCode::from_u16(status).map(Some).map_err(|_| {
+        serde::de::Error::custom(format!("the status {} is no valid status code", status))
+    })
+}
+fn serialize_headers<S>(headers: &HeaderMap<HeaderValue>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(headers.len()))?;
+    for (name, value) in headers.iter() {
+        let value = value.to_str().map_err(serde::ser::Error::custom)?;
+        map.serialize_entry(name.as_str(), value)?;
+    }
+    map.end()
+}
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<HeaderMap<HeaderValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut headers = HeaderMap::new();
+    for (name, value) in HashMap::<String, String>::deserialize(deserializer)? {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => {
+                return Err(serde::de::Error::custom(format!(
+                    "the header {}: {} is no valid header",
+                    name, value
+                )))
+            }
+        }
+    }
+    Ok(headers)
+}
+/// The responses to the requests which the middlewares of a route deny. Rate limited requests
+/// get a 429, unauthenticated ones a 401, and forbidden ones and denied client IPs a 403 by
+/// default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct DenyResponses {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limited: Option<DenyResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unauthenticated: Option<DenyResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forbidden: Option<DenyResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_denied: Option<DenyResponse>,
+}
+impl DenyResponses {
+    pub fn get_response(&self, denial: &Denial) -> Result<LocalResponse, AppError> {
+        let (status, body, deny_response) = match denial {
+            Denial::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                RATE_LIMITED_RESPONSE,
+                &self.rate_limited,
+            ),
+            Denial::Unauthenticated { .. } => (
+                StatusCode::UNAUTHORIZED,
+                UNAUTHENTICATED_RESPONSE,
+                &self.unauthenticated,
+            ),
+            Denial::Forbidden { .. } => {
+                (StatusCode::FORBIDDEN, FORBIDDEN_RESPONSE, &self.forbidden)
+            }
+            Denial::IpDenied => (StatusCode::FORBIDDEN, DENY_RESPONSE, &self.ip_denied),
+            Denial::ForwardAuth(local_response) => return Ok(local_response.clone()),
+        };
+        let status = deny_response
+            .as_ref()
+            .and_then(|deny_response| deny_response.status)
+            .unwrap_or(status);
+        let body = deny_response
+            .as_ref()
+            .and_then(|deny_response| deny_response.body.clone())
+            .unwrap_or(body.to_string());
+        let mut local_response = LocalResponse::new(status, body);
+        match denial {
+            Denial::RateLimited(rate_limit_status) => {
+                rate_limit_status.insert_headers(&mut local_response.headers)
+            }
+            Denial::Unauthenticated {
+                www_authenticate: Some(challenge),
+            }
+            | Denial::Forbidden {
+                www_authenticate: Some(challenge),
+            } => {
+                local_response.headers.insert(
+                    http::header::WWW_AUTHENTICATE,
+                    HeaderValue::from_str(challenge)?,
+                );
+            }
+            _ => {}
+        }
+        if let Some(deny_response) = deny_response {
+            for (name, value) in deny_response.headers.iter() {
+                local_response.headers.insert(name, value.clone());
+            }
+        }
+        Ok(local_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit::RateLimitStatus;
+
+    #[test]
+    fn test_deny_responses() {
+        let rate_limited = Denial::RateLimited(RateLimitStatus {
+            is_limited: true,
+            limit: 10,
+            remaining: 0,
+            reset_millis: 1500,
+        });
+        let local_response = DenyResponses::default()
+            .get_response(&rate_limited)
+            .unwrap();
+        assert_eq!(local_response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(local_response.headers["retry-after"], "2");
+        assert_eq!(local_response.headers["ratelimit-limit"], "10");
+        assert_eq!(local_response.headers["ratelimit-remaining"], "0");
+        assert_eq!(local_response.headers["ratelimit-reset"], "2");
+
+        let unauthenticated = Denial::Unauthenticated {
+            www_authenticate: Some("Basic realm=\"spire\"".to_string()),
+        };
+        let local_response = DenyResponses::default()
+            .get_response(&unauthenticated)
+            .unwrap();
+        assert_eq!(local_response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            local_response.headers["www-authenticate"],
+            "Basic realm=\"spire\""
+        );
+        let forbidden = Denial::Forbidden {
+            www_authenticate: Some("Bearer error=\"insufficient_scope\"".to_string()),
+        };
+        let local_response = DenyResponses::default().get_response(&forbidden).unwrap();
+        assert_eq!(local_response.status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            local_response.headers["www-authenticate"],
+            "Bearer error=\"insufficient_scope\""
+        );
+
+        let deny_responses: DenyResponses = serde_yaml::from_str(
+            "ip_denied:\n  status: 404\n  body: not here\nrate_limited:\n  headers:\n    content-type: text/plain",
+        )
+        .unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<DenyResponses>(&serde_yaml::to_string(&deny_responses).unwrap())
+                .unwrap(),
+            deny_responses
+        );
+        for invalid in [
+            "ip_denied:\n  status: 1000",
+            "rate_limited:\n  headers:\n    bad header: value",
+        ] {
+            assert!(serde_yaml::from_str::<DenyResponses>(invalid).is_err());
+        }
+        let local_response = deny_responses.get_response(&Denial::IpDenied).unwrap();
+        assert_eq!(local_response.status, StatusCode::NOT_FOUND);
+        assert_eq!(local_response.body, "not here");
+        let local_response = deny_responses.get_response(&rate_limited).unwrap();
+        assert_eq!(local_response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(local_response.headers["content-type"], "text/plain");
+        assert_eq!(local_response.headers["retry-after"], "2");
+    }
+}
@@ -0,0 +1,323 @@
+use super::app_error::AppError;
+use super::router::SelectionContext;
+use crate::proxy::proxy_trait::LocalResponse;
+use bytes::Bytes;
+use http::HeaderMap;
+use http::HeaderName;
+use http::HeaderValue;
+use http::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// Answers every request with a fixed response, such as a maintenance page.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DirectResponseRoute {
+    pub status: u16,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Serves the content of the file as the body instead. The file is read when the config
+    /// loads, so changes need a config reload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_file: Option<String>,
+    #[serde(skip_serializing)]
+    pub file_body: Option<Bytes>,
+}
+fn default_status() -> u16 {
+    200
+}
+impl<'de> Deserialize<'de> for DirectResponseRoute {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Inner {
+            #[serde(default = "default_status")]
+            status: u16,
+            #[serde(default)]
+            headers: HashMap<String, String>,
+            #[serde(default)]
+            body: Option<String>,
+            #[serde(default)]
+            body_file: Option<String>,
+        }
+
+        let inner = Inner::deserialize(deserializer)?;
+        StatusCode::from_u16(inner.status).map_err(|_| {
+            serde::de::Error::custom(format!(
+                "the status {} is no valid status code",
+                inner.status
+            ))
+        })?;
+        for (name, value) in inner.headers.iter() {
+            if HeaderName::from_bytes(name.as_bytes()).is_err()
+                || HeaderValue::from_str(value).is_err()
+            {
+                return Err(serde::de::Error::custom(format!(
+                    "the header {}: {} is no valid header",
+                    name, value
+                )));
+            }
+        }
+        let file_body = match (&inner.body, &inner.body_file) {
+            (Some(_), Some(_)) => {
+                return Err(serde::de::Error::custom(
+                    "only one of body and body_file can be set",
+                ))
+            }
+            (None, Some(body_file)) => {
+                Some(Bytes::from(std::fs::read(body_file).map_err(|e| {
+                    serde::de::Error::custom(format!(
+                        "can not read the body file {}: {}",
+                        body_file, e
+                    ))
+                })?))
+            }
+            _ => None,
+        };
+        Ok(DirectResponseRoute {
+            status: inner.status,
+            headers: inner.headers,
+            body: inner.body,
+            body_file: inner.body_file,
+            file_body,
+        })
+    }
+}
+impl DirectResponseRoute {
+    fn body(&self) -> Bytes {
+        match &self.file_body {
+            Some(file_body) => file_body.clone(),
+            None => Bytes::from(self.body.clone().unwrap_or_default()),
+        }
+    }
+    pub fn get_response(&self) -> Result<LocalResponse, AppError> {
+        let status = StatusCode::from_u16(self.status).map_err(|e| AppError(e.to_string()))?;
+        let mut local_response = LocalResponse::new(status, self.body());
+        for (name, value) in self.headers.iter() {
+            local_response.headers.insert(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| AppError(e.to_string()))?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        Ok(local_response)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefixReplacement {
+    pub from: String,
+    pub to: String,
+}
+/// Redirects the request to the same url with the configured parts replaced. Without a
+/// `scheme` the Location is scheme-relative, so the client keeps the scheme it used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectRoute {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Replaces the port. Without it the port of the request is dropped, as it rarely fits a new
+    /// scheme, while a configured `host` is kept as it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Replaces the whole path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_prefix: Option<PrefixReplacement>,
+    #[serde(default)]
+    pub strip_query: bool,
+    #[serde(
+        default = "default_status_code",
+        deserialize_with = "deserialize_status_code"
+    )]
+    pub status_code: u16,
+}
+fn default_status_code() -> u16 {
+    301
+}
+fn deserialize_status_code<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let status_code = u16::deserialize(deserializer)?;
+    if ![301, 302, 307, 308].contains(&status_code) {
+        return Err(serde::de::Error::custom(format!(
+            "the redirect status code must be 301, 302, 307 or 308, not {}",
+            status_code
+        )));
+    }
+    Ok(status_code)
+}
+impl RedirectRoute {
+    fn authority(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<Option<String>, AppError> {
+        if self.scheme.is_none() && self.host.is_none() && self.port.is_none() {
+            return Ok(None);
+        }
+        let host = match &self.host {
+            Some(host) => host.clone(),
+            None => match context.uri.as_ref().and_then(|uri| uri.authority()) {
+                Some(authority) => authority.as_str().to_string(),
+                None => headers
+                    .get(http::header::HOST)
+                    .ok_or("The request has no host to redirect to")?
+                    .to_str()?
+                    .to_string(),
+            },
+        };
+        let authority = match self.port {
+            Some(port) => format!("{}:{}", strip_port(&host), port),
+            None if self.host.is_none() => strip_port(&host).to_string(),
+            None => host,
+        };
+        Ok(Some(authority))
+    }
+    fn path_and_query(&self, context: &SelectionContext) -> String {
+        let uri = context.uri.as_ref();
+        let request_path = uri.map(|uri| uri.path()).unwrap_or("/");
+        let mut path = match (&self.path, &self.replace_prefix) {
+            (Some(path), _) => path.clone(),
+            (None, Some(replacement)) => match request_path.strip_prefix(&replacement.from) {
+                Some(rest) => format!("{}{}", replacement.to, rest),
+                None => request_path.to_string(),
+            },
+            (None, None) => request_path.to_string(),
+        };
+        if let Some(query) = uri
+            .and_then(|uri| uri.query())
+            .filter(|_| !self.strip_query)
+        {
+            path.push('?');
+            path.push_str(query);
+        }
+        path
+    }
+    pub fn get_response(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<LocalResponse, AppError> {
+        let path_and_query = self.path_and_query(context);
+        let location = match (self.authority(headers, context)?, &self.scheme) {
+            (Some(authority), Some(scheme)) => {
+                format!("{}://{}{}", scheme, authority, path_and_query)
+            }
+            (Some(authority), None) => format!("//{}{}", authority, path_and_query),
+            (None, _) => path_and_query,
+        };
+        let status = StatusCode::from_u16(self.status_code).map_err(|e| AppError(e.to_string()))?;
+        let mut local_response = LocalResponse::new(status, "");
+        local_response
+            .headers
+            .insert(http::header::LOCATION, HeaderValue::from_str(&location)?);
+        Ok(local_response)
+    }
+}
+/// Removes the port of a host, keeping the brackets of an IPv6 address.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => &host[..index],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Uri;
+
+    fn redirect(route: &str, uri: &str, host: &str) -> (StatusCode, String) {
+        let route: RedirectRoute = serde_yaml::from_str(route).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("host", HeaderValue::from_str(host).unwrap());
+        let context = SelectionContext {
+            uri: Some(uri.parse::<Uri>().unwrap()),
+            ..Default::default()
+        };
+        let local_response = route.get_response(&headers, &context).unwrap();
+        let location = local_response.headers["location"].to_str().unwrap();
+        (local_response.status, location.to_string())
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        assert_eq!(
+            redirect("scheme: https", "/a/b?c=1", "example.com:8080"),
+            (
+                StatusCode::MOVED_PERMANENTLY,
+                "https://example.com/a/b?c=1".to_string()
+            )
+        );
+        assert_eq!(
+            redirect(
+                "host: new.example.com\nstatus_code: 308",
+                "/a?c=1",
+                "old.example.com"
+            ),
+            (
+                StatusCode::PERMANENT_REDIRECT,
+                "//new.example.com/a?c=1".to_string()
+            )
+        );
+        assert_eq!(
+            redirect(
+                "scheme: https\nport: 8443\nreplace_prefix:\n  from: /old\n  to: /new",
+                "/old/items",
+                "[::1]:8080"
+            )
+            .1,
+            "https://[::1]:8443/new/items"
+        );
+        assert_eq!(
+            redirect(
+                "path: /maintenance\nstrip_query: true\nstatus_code: 302",
+                "/a?c=1",
+                "example.com"
+            ),
+            (StatusCode::FOUND, "/maintenance".to_string())
+        );
+        let invalid: Result<RedirectRoute, _> = serde_yaml::from_str("status_code: 200");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_direct_response() {
+        let route: DirectResponseRoute = serde_yaml::from_str(
+            "status: 503\nheaders:\n  content-type: text/plain\n  retry-after: '120'\nbody: down",
+        )
+        .unwrap();
+        let local_response = route.get_response().unwrap();
+        assert_eq!(local_response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(local_response.headers["content-type"], "text/plain");
+        assert_eq!(local_response.headers["retry-after"], "120");
+        assert_eq!(local_response.body, Bytes::from("down"));
+
+        let path = std::env::temp_dir().join("spire_direct_response_test.html");
+        std::fs::write(&path, "<h1>maintenance</h1>").unwrap();
+        let route: DirectResponseRoute =
+            serde_yaml::from_str(&format!("body_file: {}", path.display())).unwrap();
+        let local_response = route.get_response().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(local_response.status, StatusCode::OK);
+        assert_eq!(local_response.body, Bytes::from("<h1>maintenance</h1>"));
+        assert_eq!(
+            route.get_response().unwrap().body,
+            Bytes::from("<h1>maintenance</h1>")
+        );
+        for invalid in [
+            "body_file: /nonexistent/spire/body.html",
+            "body: down\nbody_file: /etc/hostname",
+            "headers:\n  bad header: value",
+            "status: 1000",
+        ] {
+            assert!(serde_yaml::from_str::<DirectResponseRoute>(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use super::app_error::AppError;
+use super::router::deserialize_router;
+use super::router::BaseRoute;
+use super::router::Router;
+use super::router::SelectionContext;
+use crate::proxy::proxy_trait::RouterDestination;
+use http::HeaderMap;
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+
+/// Sends the traffic to the first group, in the configured order, which has at least
+/// `min_healthy` healthy endpoints. If no group has enough, the group with the most healthy
+/// endpoints is used, the earlier one on a tie.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailoverRoute {
+    #[serde(rename = "targets")]
+    pub groups: Vec<PriorityGroup>,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriorityGroup {
+    #[serde(default = "default_min_healthy")]
+    pub min_healthy: usize,
+    /// The strategy which balances the endpoints of the group.
+    #[serde(deserialize_with = "deserialize_router", rename = "forward_to")]
+    pub router: Router,
+}
+fn default_min_healthy() -> usize {
+    1
+}
+impl PriorityGroup {
+    /// Counts the endpoints which are healthy and whose circuit is not open.
+    /// A group which answers locally, such as a maintenance page, is always healthy.
+    fn healthy_count(&self, context: &SelectionContext) -> usize {
+        if !self.router.has_endpoints() {
+            return usize::MAX;
+        }
+        self.router
+            .get_base_routes()
+            .iter()
+            .filter(|r| context.is_healthy(&r.endpoint, r.is_alive))
+            .filter(|r| !context.unavailable_endpoints.contains(&r.endpoint))
+            .count()
+    }
+}
+impl FailoverRoute {
+    fn select_group(&self, context: &SelectionContext) -> Option<&PriorityGroup> {
+        let healthy_counts: Vec<usize> = self
+            .groups
+            .iter()
+            .map(|group| group.healthy_count(context))
+            .collect();
+        let index = self
+            .groups
+            .iter()
+            .zip(healthy_counts.iter())
+            .position(|(group, count)| *count >= group.min_healthy.max(1))
+            .or_else(|| {
+                (0..self.groups.len())
+                    .rev()
+                    .max_by_key(|&index| healthy_counts[index])
+            })?;
+        self.groups.get(index)
+    }
+    pub fn get_route(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<RouterDestination, AppError> {
+        self.select_group(context)
+            .ok_or("No routes available")?
+            .router
+            .get_route(headers, context)
+    }
+    pub fn get_base_routes(&self) -> Vec<BaseRoute> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.router.get_base_routes())
+            .collect()
+    }
+    /// Returns the group whose endpoints include the endpoint.
+    pub fn find_group(&self, endpoint: &str) -> Option<&PriorityGroup> {
+        self.groups.iter().find(|group| {
+            group
+                .router
+                .get_base_routes()
+                .iter()
+                .any(|r| r.endpoint == endpoint)
+        })
+    }
+    pub fn update_route_alive(
+        &mut self,
+        base_route: BaseRoute,
+        is_alive: bool,
+    ) -> Result<(), AppError> {
+        for group in self.groups.iter_mut() {
+            if group.router.has_endpoints() {
+                group
+                    .router
+                    .update_route_alive(base_route.clone(), is_alive)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_failover_route() -> FailoverRoute {
+        serde_yaml::from_str(
+            r#"
+targets:
+  - min_healthy: 2
+    forward_to:
+      kind: poll
+      targets:
+        - endpoint: http://primary-1
+        - endpoint: http://primary-2
+  - forward_to:
+      kind: least_request
+      targets:
+        - endpoint: http://backup-1
+  - forward_to: http://last-resort
+"#,
+        )
+        .unwrap()
+    }
+    fn select(route: &FailoverRoute, context: &SelectionContext) -> String {
+        route
+            .get_route(&HeaderMap::new(), context)
+            .unwrap()
+            .get_endpoint()
+    }
+    fn set_alive(route: &mut FailoverRoute, endpoint: &str, is_alive: bool) {
+        let base_route = BaseRoute {
+            endpoint: endpoint.to_string(),
+            is_alive: None,
+        };
+        route.update_route_alive(base_route, is_alive).unwrap();
+    }
+
+    #[test]
+    fn test_traffic_spills_over_by_priority() {
+        let mut route = create_failover_route();
+        let context = SelectionContext::default();
+        for _ in 0..4 {
+            assert!(select(&route, &context).starts_with("http://primary"));
+        }
+
+        set_alive(&mut route, "http://primary-2", false);
+        assert_eq!(select(&route, &context), "http://backup-1");
+
+        set_alive(&mut route, "http://backup-1", false);
+        assert_eq!(select(&route, &context), "http://last-resort");
+
+        set_alive(&mut route, "http://backup-1", true);
+        let context = SelectionContext {
+            unavailable_endpoints: vec!["http://backup-1".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(select(&route, &context), "http://last-resort");
+
+        set_alive(&mut route, "http://primary-2", true);
+        assert!(select(&route, &context).starts_with("http://primary"));
+    }
+
+    #[test]
+    fn test_group_with_most_healthy_endpoints_without_enough() {
+        let mut route = create_failover_route();
+        route.groups[1].min_healthy = 2;
+        route.groups.pop();
+        set_alive(&mut route, "http://primary-1", false);
+        let context = SelectionContext::default();
+        assert_eq!(select(&route, &context), "http://primary-2");
+
+        set_alive(&mut route, "http://primary-2", false);
+        assert_eq!(select(&route, &context), "http://backup-1");
+        assert_eq!(route.get_base_routes().len(), 3);
+        assert_eq!(route.find_group("http://backup-1"), Some(&route.groups[1]));
+    }
+
+    #[test]
+    fn test_local_group_answers_when_upstreams_are_down() {
+        let mut route: FailoverRoute = serde_yaml::from_str(
+            r#"
+targets:
+  - forward_to: http://primary
+  - forward_to:
+      kind: direct_response
+      status: 503
+      body: maintenance
+"#,
+        )
+        .unwrap();
+        let context = SelectionContext::default();
+        assert_eq!(select(&route, &context), "http://primary");
+        set_alive(&mut route, "http://primary", false);
+        match route.get_route(&HeaderMap::new(), &context).unwrap() {
+            RouterDestination::Local(local_response) => {
+                assert_eq!(local_response.body, bytes::Bytes::from("maintenance"));
+            }
+            other => panic!("unexpected destination {:?}", other),
+        }
+    }
+}
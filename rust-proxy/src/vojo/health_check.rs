@@ -1,5 +1,10 @@
-use serde::{Deserialize, Serialize};
-#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+use http::Method;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize, Default)]
 pub struct BaseHealthCheckParam {
     pub timeout: i32,
     pub interval: i32,
@@ -8,6 +13,207 @@ pub struct BaseHealthCheckParam {
 pub struct HttpHealthCheckParam {
     pub base_health_check_param: BaseHealthCheckParam,
     pub path: String,
+    #[serde(
+        default = "default_http_method",
+        serialize_with = "serialize_http_method",
+        deserialize_with = "deserialize_http_method"
+    )]
+    pub method: Method,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default = "default_threshold")]
+    pub healthy_threshold: u32,
+    #[serde(default = "default_threshold")]
+    pub unhealthy_threshold: u32,
+    #[serde(default = "default_expected_statuses")]
+    pub expected_statuses: Vec<StatusCodeRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<BodyRegex>,
+}
+impl Default for HttpHealthCheckParam {
+    fn default() -> Self {
+        Self {
+            base_health_check_param: BaseHealthCheckParam::default(),
+            path: String::from("/"),
+            method: default_http_method(),
+            headers: BTreeMap::new(),
+            healthy_threshold: default_threshold(),
+            unhealthy_threshold: default_threshold(),
+            expected_statuses: default_expected_statuses(),
+            body_contains: None,
+            body_regex: None,
+        }
+    }
+}
+impl HttpHealthCheckParam {
+    pub fn is_expected_status(&self, status: u16) -> bool {
+        self.expected_statuses
+            .iter()
+            .any(|item| item.contains(status))
+    }
+}
+fn default_http_method() -> Method {
+    Method::GET
+}
+fn serialize_http_method<S>(method: &Method, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(method.as_str())
+}
+fn deserialize_http_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let method = String::deserialize(deserializer)?;
+    Method::from_bytes(method.as_bytes())
+        .map_err(|_| serde::de::Error::custom(format!("Invalid http method {}", method)))
+}
+/// A regex which the body of a healthy response has to match. It is compiled when the config
+/// loads and compares by its pattern.
+#[derive(Debug, Clone)]
+pub struct BodyRegex(pub Regex);
+impl PartialEq for BodyRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+impl Eq for BodyRegex {}
+impl Hash for BodyRegex {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+impl Serialize for BodyRegex {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+impl<'de> Deserialize<'de> for BodyRegex {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern)
+            .map(BodyRegex)
+            .map_err(|e| serde::de::Error::custom(format!("Invalid body regex {}: {}", pattern, e)))
+    }
+}
+fn default_threshold() -> u32 {
+    1
+}
+fn default_expected_statuses() -> Vec<StatusCodeRange> {
+    vec![StatusCodeRange {
+        start: 200,
+        end: 299,
+    }]
+}
+/// An inclusive range of status codes, configured as `204` or `"200-399"`.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct StatusCodeRange {
+    pub start: u16,
+    pub end: u16,
+}
+impl StatusCodeRange {
+    pub fn contains(&self, status: u16) -> bool {
+        self.start <= status && status <= self.end
+    }
+}
+impl Serialize for StatusCodeRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.start == self.end {
+            serializer.serialize_u16(self.start)
+        } else {
+            serializer.serialize_str(&format!("{}-{}", self.start, self.end))
+        }
+    }
+}
+impl<'de> Deserialize<'de> for StatusCodeRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum StatusCodeRangeValue {
+            Code(u16),
+            Range(String),
+        }
+        let (start, end) = match StatusCodeRangeValue::deserialize(deserializer)? {
+            StatusCodeRangeValue::Code(code) => (code, code),
+            StatusCodeRangeValue::Range(range) => {
+                let parse = |value: &str| {
+                    value.trim().parse::<u16>().map_err(|_| {
+                        serde::de::Error::custom(format!("Invalid status code range {}", range))
+                    })
+                };
+                match range.split_once('-') {
+                    Some((start, end)) => (parse(start)?, parse(end)?),
+                    None => {
+                        let code = parse(&range)?;
+                        (code, code)
+                    }
+                }
+            }
+        };
+        if start > end || !(100..=599).contains(&start) || !(100..=599).contains(&end) {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid status code range {}-{}",
+                start, end
+            )));
+        }
+        Ok(StatusCodeRange { start, end })
+    }
+}
+/// The consecutive probe results of one endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HealthCheckStatus {
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+    /// The liveness which has last been published to the config snapshot.
+    pub is_alive: Option<bool>,
+}
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RedisHealthCheckParam {
+    pub base_health_check_param: BaseHealthCheckParam,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<i64>,
+}
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MysqlHealthCheckParam {
+    pub base_health_check_param: BaseHealthCheckParam,
+    #[serde(default = "default_mysql_user")]
+    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+fn default_mysql_user() -> String {
+    String::from("root")
+}
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TcpHealthCheckParam {
+    pub base_health_check_param: BaseHealthCheckParam,
+}
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GrpcHealthCheckParam {
+    pub base_health_check_param: BaseHealthCheckParam,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
 }
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "kind")]
@@ -15,16 +221,114 @@ pub enum HealthCheckType {
     #[serde(rename = "http_get")]
     HttpGet(HttpHealthCheckParam),
     #[serde(rename = "redis")]
-    Redis(BaseHealthCheckParam),
+    Redis(RedisHealthCheckParam),
     #[serde(rename = "mysql")]
-    Mysql(BaseHealthCheckParam),
+    Mysql(MysqlHealthCheckParam),
+    #[serde(rename = "tcp")]
+    Tcp(TcpHealthCheckParam),
+    #[serde(rename = "grpc")]
+    Grpc(GrpcHealthCheckParam),
 }
 impl HealthCheckType {
     pub fn get_base_param(&self) -> BaseHealthCheckParam {
         match self {
             HealthCheckType::HttpGet(http_param) => http_param.base_health_check_param.clone(),
-            HealthCheckType::Mysql(base_pram) => base_pram.clone(),
-            HealthCheckType::Redis(base_pram) => base_pram.clone(),
+            HealthCheckType::Mysql(mysql_param) => mysql_param.base_health_check_param.clone(),
+            HealthCheckType::Redis(redis_param) => redis_param.base_health_check_param.clone(),
+            HealthCheckType::Tcp(tcp_param) => tcp_param.base_health_check_param.clone(),
+            HealthCheckType::Grpc(grpc_param) => grpc_param.base_health_check_param.clone(),
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_code_range_serde() {
+        let ranges: Vec<StatusCodeRange> = serde_yaml::from_str(r#"[204, "200-399"]"#).unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                StatusCodeRange {
+                    start: 204,
+                    end: 204
+                },
+                StatusCodeRange {
+                    start: 200,
+                    end: 399
+                }
+            ]
+        );
+        assert!(ranges[1].contains(302));
+        assert!(!ranges[1].contains(404));
+        let yaml = serde_yaml::to_string(&ranges).unwrap();
+        assert_eq!(yaml, "- 204\n- 200-399\n");
+    }
+
+    #[test]
+    fn test_status_code_range_invalid() {
+        assert!(serde_yaml::from_str::<StatusCodeRange>(r#""300-200""#).is_err());
+        assert!(serde_yaml::from_str::<StatusCodeRange>(r#""abc""#).is_err());
+        assert!(serde_yaml::from_str::<StatusCodeRange>("700").is_err());
+    }
+
+    #[test]
+    fn test_http_health_check_param_defaults() {
+        let param: HttpHealthCheckParam = serde_yaml::from_str(
+            r#"
+base_health_check_param:
+  timeout: 5
+  interval: 5
+path: /health
+"#,
+        )
+        .unwrap();
+        assert_eq!(param.method, Method::GET);
+        assert_eq!(param.healthy_threshold, 1);
+        assert_eq!(param.unhealthy_threshold, 1);
+        assert!(param.is_expected_status(204));
+        assert!(!param.is_expected_status(301));
+
+        let base = "base_health_check_param:\n  timeout: 5\n  interval: 5\npath: /health\n";
+        let param: HttpHealthCheckParam =
+            serde_yaml::from_str(&format!("{}method: HEAD\nbody_regex: ok$", base)).unwrap();
+        assert_eq!(param.method, Method::HEAD);
+        assert!(param.body_regex.unwrap().0.is_match("all ok"));
+        assert!(
+            serde_yaml::from_str::<HttpHealthCheckParam>(&format!("{}method: \"GE T\"", base))
+                .is_err()
+        );
+        assert!(serde_yaml::from_str::<HttpHealthCheckParam>(&format!(
+            "{}body_regex: \"(\"",
+            base
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_redis_and_mysql_params_nest_the_base_param() {
+        let health_check: HealthCheckType = serde_yaml::from_str(
+            "kind: redis\nbase_health_check_param:\n  timeout: 3\n  interval: 5\ndatabase: 1",
+        )
+        .unwrap();
+        let HealthCheckType::Redis(param) = health_check else {
+            panic!("{:?}", health_check);
+        };
+        assert_eq!(param.base_health_check_param.timeout, 3);
+        assert_eq!(param.base_health_check_param.interval, 5);
+        assert_eq!(param.database, Some(1));
+
+        let health_check: HealthCheckType = serde_yaml::from_str(
+            "kind: mysql\nbase_health_check_param:\n  timeout: 3\n  interval: 5",
+        )
+        .unwrap();
+        let HealthCheckType::Mysql(param) = health_check else {
+            panic!("{:?}", health_check);
+        };
+        assert_eq!(param.base_health_check_param.interval, 5);
+        assert_eq!(param.user, "root");
+        let yaml = serde_yaml::to_string(&HealthCheckType::Mysql(param)).unwrap();
+        assert!(yaml.contains("\n  timeout: 3\n"), "{}", yaml);
+    }
+}
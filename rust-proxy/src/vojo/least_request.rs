@@ -0,0 +1,224 @@
+use super::app_error::AppError;
+use super::router::candidate_indices;
+use super::router::BaseRoute;
+use super::router::SelectionContext;
+use super::runtime_state::RuntimeState;
+use dashmap::DashMap;
+use http::HeaderMap;
+use http::HeaderValue;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The weight of a new latency sample in the moving average.
+const EWMA_SMOOTHING: f64 = 0.3;
+
+/// The load of an endpoint observed by the proxy.
+#[derive(Debug, Default)]
+pub struct EndpointStats {
+    active_requests: AtomicU64,
+    /// The f64 bits of the latency moving average in microseconds, zero until the first sample.
+    ewma_latency_micros: AtomicU64,
+}
+impl EndpointStats {
+    pub fn active_requests(&self) -> u64 {
+        self.active_requests.load(Ordering::Relaxed)
+    }
+    pub fn ewma_latency(&self) -> f64 {
+        f64::from_bits(self.ewma_latency_micros.load(Ordering::Relaxed))
+    }
+    fn observe_latency(&self, latency: Duration) {
+        let sample = latency.as_micros() as f64;
+        let _ =
+            self.ewma_latency_micros
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    let ewma = match f64::from_bits(bits) {
+                        0.0 => sample,
+                        ewma => ewma + (sample - ewma) * EWMA_SMOOTHING,
+                    };
+                    Some(ewma.to_bits())
+                });
+    }
+}
+pub type EndpointLoads = DashMap<String, EndpointStats>;
+
+/// Counts a request as active on the endpoint until it is finished or dropped.
+pub struct ActiveRequest {
+    endpoint_loads: RuntimeState<EndpointLoads>,
+    endpoint: String,
+}
+impl ActiveRequest {
+    pub fn start(endpoint_loads: &RuntimeState<EndpointLoads>, endpoint: &str) -> Self {
+        endpoint_loads
+            .entry(endpoint.to_string())
+            .or_default()
+            .active_requests
+            .fetch_add(1, Ordering::Relaxed);
+        Self {
+            endpoint_loads: endpoint_loads.clone(),
+            endpoint: endpoint.to_string(),
+        }
+    }
+    pub fn finish(self, latency: Duration) {
+        if let Some(stats) = self.endpoint_loads.get(&self.endpoint) {
+            stats.observe_latency(latency);
+        }
+    }
+}
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        if let Some(stats) = self.endpoint_loads.get(&self.endpoint) {
+            stats.active_requests.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Picks the endpoint with the fewest active requests, ties are broken randomly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct LeastRequestRoute {
+    #[serde(rename = "targets")]
+    pub routes: Vec<BaseRoute>,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub endpoint_loads: RuntimeState<EndpointLoads>,
+}
+impl LeastRequestRoute {
+    fn active_requests(&self, endpoint: &str) -> u64 {
+        self.endpoint_loads
+            .get(endpoint)
+            .map(|stats| stats.active_requests())
+            .unwrap_or_default()
+    }
+    pub fn get_route(
+        &self,
+        _headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        let least_active = candidates
+            .iter()
+            .map(|&i| self.active_requests(&self.routes[i].endpoint))
+            .min()
+            .ok_or("No routes available")?;
+        let least_loaded: Vec<usize> = candidates
+            .into_iter()
+            .filter(|&i| self.active_requests(&self.routes[i].endpoint) == least_active)
+            .collect();
+        let index = least_loaded[rand::rng().random_range(0..least_loaded.len())];
+        Ok(self.routes[index].clone())
+    }
+}
+
+/// Picks two random endpoints and sends the request to the one whose latency moving average,
+/// scaled by its active requests, is lower. Endpoints without samples are preferred.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct P2cEwmaRoute {
+    #[serde(rename = "targets")]
+    pub routes: Vec<BaseRoute>,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub endpoint_loads: RuntimeState<EndpointLoads>,
+}
+impl P2cEwmaRoute {
+    fn score(&self, endpoint: &str) -> f64 {
+        self.endpoint_loads
+            .get(endpoint)
+            .map(|stats| stats.ewma_latency() * (stats.active_requests() + 1) as f64)
+            .unwrap_or_default()
+    }
+    pub fn get_route(
+        &self,
+        _headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        if candidates.is_empty() {
+            return Err(AppError::from("No routes available"));
+        }
+        let mut rng = rand::rng();
+        let first = rng.random_range(0..candidates.len());
+        if candidates.len() == 1 {
+            return Ok(self.routes[candidates[first]].clone());
+        }
+        let second = (first + rng.random_range(1..candidates.len())) % candidates.len();
+        let (first, second) = (candidates[first], candidates[second]);
+        let index = match self.score(&self.routes[first].endpoint)
+            <= self.score(&self.routes[second].endpoint)
+        {
+            true => first,
+            false => second,
+        };
+        Ok(self.routes[index].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_routes(endpoints: &[&str]) -> Vec<BaseRoute> {
+        endpoints
+            .iter()
+            .map(|endpoint| BaseRoute {
+                endpoint: endpoint.to_string(),
+                is_alive: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_least_request_route() {
+        let mut route = LeastRequestRoute {
+            routes: create_routes(&["a", "b", "c"]),
+            ..Default::default()
+        };
+        let context = SelectionContext::default();
+        let _a = ActiveRequest::start(&route.endpoint_loads, "a");
+        let _c = ActiveRequest::start(&route.endpoint_loads, "c");
+        for _ in 0..10 {
+            let selected = route.get_route(&HeaderMap::new(), &context).unwrap();
+            assert_eq!(selected.endpoint, "b");
+        }
+
+        route.routes[1].is_alive = Some(false);
+        drop(ActiveRequest::start(&route.endpoint_loads, "a"));
+        assert_eq!(route.active_requests("a"), 1);
+        for _ in 0..10 {
+            let selected = route.get_route(&HeaderMap::new(), &context).unwrap();
+            assert_ne!(selected.endpoint, "b");
+        }
+    }
+
+    #[test]
+    fn test_p2c_ewma_route() {
+        let route = P2cEwmaRoute {
+            routes: create_routes(&["fast", "slow"]),
+            ..Default::default()
+        };
+        ActiveRequest::start(&route.endpoint_loads, "fast").finish(Duration::from_millis(5));
+        for _ in 0..5 {
+            ActiveRequest::start(&route.endpoint_loads, "slow").finish(Duration::from_millis(500));
+        }
+        let fast = route.endpoint_loads.get("fast").unwrap().ewma_latency();
+        assert_eq!(fast, 5000.0);
+        let context = SelectionContext::default();
+        for _ in 0..10 {
+            let selected = route.get_route(&HeaderMap::new(), &context).unwrap();
+            assert_eq!(selected.endpoint, "fast");
+        }
+
+        let context = SelectionContext {
+            unavailable_endpoints: vec!["fast".to_string()],
+            ..Default::default()
+        };
+        let selected = route.get_route(&HeaderMap::new(), &context).unwrap();
+        assert_eq!(selected.endpoint, "slow");
+    }
+
+    #[test]
+    fn test_ewma_latency() {
+        let stats = EndpointStats::default();
+        stats.observe_latency(Duration::from_micros(100));
+        stats.observe_latency(Duration::from_micros(200));
+        assert!((stats.ewma_latency() - 130.0).abs() < f64::EPSILON);
+    }
+}
@@ -3,7 +3,20 @@ pub mod app_config;
 pub mod anomaly_detection;
 pub mod app_error;
 pub mod base_response;
+pub mod canary;
 pub mod cli;
+pub mod consistent_hash;
+pub mod deny_response;
+pub mod direct_response;
+pub mod failover;
 pub mod health_check;
+pub mod least_request;
 pub mod lets_encrypt;
+pub mod retry_policy;
+pub mod rewrite;
+pub mod route_matcher;
+pub mod route_rule;
 pub mod router;
+pub mod routing_table;
+pub mod runtime_state;
+pub mod sticky_session;
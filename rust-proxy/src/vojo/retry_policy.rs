@@ -0,0 +1,258 @@
+use super::runtime_state::RuntimeState;
+use http::Method;
+use http::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const RETRY_BUDGET_WINDOW: Duration = Duration::from_secs(10);
+
+/// The upstream failures which can be retried besides the configured statuses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RetryOn {
+    /// The connection to the endpoint could not be established.
+    #[serde(rename = "connect_failure")]
+    ConnectFailure,
+    /// The connection was reset or closed before a response was received.
+    #[serde(rename = "reset")]
+    Reset,
+    /// The try exceeded `per_try_timeout_ms`.
+    #[serde(rename = "timeout")]
+    Timeout,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// The total number of tries, including the first one.
+    pub attempts: u32,
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<RetryOn>,
+    #[serde(default = "default_retry_statuses")]
+    pub statuses: Vec<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_try_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub backoff: Backoff,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<RetryBudget>,
+    /// Also retries methods which are not idempotent, such as POST and PATCH.
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
+    /// Requests whose body may be larger than this are never retried, as the body has to be
+    /// buffered to be sent again.
+    #[serde(default = "default_max_buffered_body_bytes")]
+    pub max_buffered_body_bytes: u64,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub budget_state: RuntimeState<RetryBudgetState>,
+}
+fn default_retry_on() -> Vec<RetryOn> {
+    vec![RetryOn::ConnectFailure, RetryOn::Reset]
+}
+fn default_retry_statuses() -> Vec<u16> {
+    vec![502, 503, 504]
+}
+fn default_max_buffered_body_bytes() -> u64 {
+    64 * 1024
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            retry_on: default_retry_on(),
+            statuses: default_retry_statuses(),
+            per_try_timeout_ms: None,
+            backoff: Backoff::default(),
+            budget: None,
+            retry_non_idempotent: false,
+            max_buffered_body_bytes: default_max_buffered_body_bytes(),
+            budget_state: RuntimeState::default(),
+        }
+    }
+}
+impl RetryPolicy {
+    pub fn is_method_retriable(&self, method: &Method) -> bool {
+        self.retry_non_idempotent || method.is_idempotent()
+    }
+    pub fn per_try_timeout(&self) -> Option<Duration> {
+        self.per_try_timeout_ms.map(Duration::from_millis)
+    }
+    pub fn should_retry_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status.as_u16())
+    }
+    pub fn should_retry_on(&self, retry_on: RetryOn) -> bool {
+        self.retry_on.contains(&retry_on)
+    }
+    /// Counts a request towards the retry budget.
+    pub fn record_request(&self) {
+        if self.budget.is_some() {
+            self.budget_state.record_request();
+        }
+    }
+    /// Returns whether the retry budget allows one more retry and takes it if so.
+    pub fn acquire_retry(&self) -> bool {
+        match &self.budget {
+            Some(budget) => self.budget_state.acquire_retry(budget),
+            None => true,
+        }
+    }
+}
+/// Exponential backoff with full jitter, the delay before the nth retry is picked uniformly
+/// from `[0, min(max_interval_ms, base_interval_ms * 2^(n-1))]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backoff {
+    #[serde(default = "default_base_interval_ms")]
+    pub base_interval_ms: u64,
+    #[serde(default = "default_max_interval_ms")]
+    pub max_interval_ms: u64,
+}
+fn default_base_interval_ms() -> u64 {
+    25
+}
+fn default_max_interval_ms() -> u64 {
+    250
+}
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base_interval_ms: default_base_interval_ms(),
+            max_interval_ms: default_max_interval_ms(),
+        }
+    }
+}
+impl Backoff {
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(32);
+        let ceiling = self
+            .base_interval_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_interval_ms);
+        Duration::from_millis(rand::rng().random_range(0..=ceiling))
+    }
+}
+/// Caps the retries of a route to a percentage of its requests, so retries can not multiply
+/// the load of an endpoint which is already struggling.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryBudget {
+    pub percent: f64,
+    /// Retries which are always allowed in a window, so routes with little traffic can retry.
+    #[serde(default = "default_min_retries")]
+    pub min_retries: u64,
+}
+fn default_min_retries() -> u64 {
+    3
+}
+#[derive(Debug)]
+struct RetryBudgetWindow {
+    started_at: Instant,
+    requests: u64,
+    retries: u64,
+}
+impl RetryBudgetWindow {
+    fn refresh(&mut self) {
+        if self.started_at.elapsed() >= RETRY_BUDGET_WINDOW {
+            *self = Self::default();
+        }
+    }
+}
+impl Default for RetryBudgetWindow {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            requests: 0,
+            retries: 0,
+        }
+    }
+}
+#[derive(Debug, Default)]
+pub struct RetryBudgetState {
+    window: Mutex<RetryBudgetWindow>,
+}
+impl RetryBudgetState {
+    fn record_request(&self) {
+        if let Ok(mut window) = self.window.lock() {
+            window.refresh();
+            window.requests += 1;
+        }
+    }
+    fn acquire_retry(&self, budget: &RetryBudget) -> bool {
+        let Ok(mut window) = self.window.lock() else {
+            return false;
+        };
+        window.refresh();
+        let allowed =
+            ((window.requests as f64 * budget.percent / 100.0) as u64).max(budget.min_retries);
+        if window.retries >= allowed {
+            return false;
+        }
+        window.retries += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_retry_policy() {
+        let policy: RetryPolicy = serde_yaml::from_str(
+            r#"
+attempts: 3
+retry_on: [connect_failure, timeout]
+per_try_timeout_ms: 500
+budget:
+  percent: 20
+"#,
+        )
+        .unwrap();
+        assert_eq!(policy.attempts, 3);
+        assert!(policy.should_retry_on(RetryOn::Timeout));
+        assert!(!policy.should_retry_on(RetryOn::Reset));
+        assert!(policy.should_retry_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.should_retry_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(policy.per_try_timeout(), Some(Duration::from_millis(500)));
+        assert_eq!(policy.budget.unwrap().min_retries, 3);
+        assert!(policy.is_method_retriable(&Method::PUT));
+        assert!(!policy.is_method_retriable(&Method::POST));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let backoff = Backoff {
+            base_interval_ms: 10,
+            max_interval_ms: 50,
+        };
+        for retry in 1..40 {
+            let ceiling = (10u64 << (retry - 1).min(20)).min(50);
+            assert!(backoff.delay(retry) <= Duration::from_millis(ceiling));
+        }
+    }
+
+    #[test]
+    fn test_retry_budget() {
+        let policy = RetryPolicy {
+            attempts: 2,
+            budget: Some(RetryBudget {
+                percent: 10.0,
+                min_retries: 2,
+            }),
+            ..Default::default()
+        };
+        assert!(policy.acquire_retry());
+        assert!(policy.acquire_retry());
+        assert!(!policy.acquire_retry());
+        for _ in 0..50 {
+            policy.record_request();
+        }
+        assert!(policy.acquire_retry());
+        assert_eq!(policy.budget_state.window.lock().unwrap().retries, 3);
+        for _ in 0..2 {
+            assert!(policy.acquire_retry());
+        }
+        assert!(!policy.acquire_retry());
+
+        let unlimited = RetryPolicy::default();
+        assert!((0..100).all(|_| unlimited.acquire_retry()));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::vojo::app_error::AppError;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Rewrites the request after the matcher, in the order of regex replace, query and url template.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct Rewrite {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex_replace: Option<RegexReplace>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<QueryRewrite>,
+    /// The upstream url such as `http://svc/{1}/items?id={query.id}`, a template starting with `/`
+    /// is joined to the endpoint. `{1}` and `{name}` refer to the capture groups of the path
+    /// regex, `{query.name}` to a query parameter of the request, `{path}` and `{query}` to the
+    /// rewritten path and query.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_template: Option<String>,
+}
+/// Replaces every match of the regex in the path, the replacement can refer to the capture groups
+/// as `$1` or `${name}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct RegexReplace {
+    pub regex: String,
+    pub replacement: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct QueryRewrite {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub rename: HashMap<String, String>,
+    /// Sets the parameters, replacing the values the request already has.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub add: HashMap<String, String>,
+}
+
+/// The path, query and captures of a matched request, which the rewrite stage works on.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RewriteInput {
+    pub path: String,
+    pub query: Option<String>,
+    /// The capture groups of the path regex by index and by name.
+    pub captures: HashMap<String, String>,
+    /// The query of the inbound request.
+    pub request_query: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Capture(String),
+    QueryParam(String),
+    Path,
+    Query,
+}
+
+#[derive(Debug, Default)]
+pub struct CompiledRewrite {
+    regex_replace: Option<(Regex, String)>,
+    remove: Vec<String>,
+    rename: Vec<(String, String)>,
+    add: Vec<(String, String)>,
+    url_template: Option<Vec<TemplatePart>>,
+}
+impl Rewrite {
+    /// Compiles the rewrite, `path_regex` is the regex of the matcher whose capture groups the url
+    /// template can refer to.
+    pub fn compile(&self, path_regex: Option<&Regex>) -> Result<CompiledRewrite, AppError> {
+        let regex_replace = match &self.regex_replace {
+            Some(regex_replace) => {
+                let regex = Regex::new(&regex_replace.regex).map_err(|e| {
+                    AppError(format!(
+                        "The regex {} of regex_replace is invalid: {}",
+                        regex_replace.regex, e
+                    ))
+                })?;
+                Some((regex, regex_replace.replacement.clone()))
+            }
+            None => None,
+        };
+        let query_rewrite = self.query.clone().unwrap_or_default();
+        let mut rename = query_rewrite.rename.into_iter().collect::<Vec<_>>();
+        rename.sort();
+        let mut add = query_rewrite.add.into_iter().collect::<Vec<_>>();
+        add.sort();
+        let url_template = match &self.url_template {
+            Some(url_template) => Some(parse_template(url_template, path_regex)?),
+            None => None,
+        };
+        Ok(CompiledRewrite {
+            regex_replace,
+            remove: query_rewrite.remove,
+            rename,
+            add,
+            url_template,
+        })
+    }
+}
+
+fn parse_template(
+    url_template: &str,
+    path_regex: Option<&Regex>,
+) -> Result<Vec<TemplatePart>, AppError> {
+    let invalid_template = |message: String| {
+        AppError(format!(
+            "The url_template {} is invalid: {}",
+            url_template, message
+        ))
+    };
+    let mut parts = vec![];
+    let mut rest = url_template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(invalid_template(format!(
+                "unmatched }} at {}",
+                url_template.len() - rest.len() + start
+            )));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| invalid_template("unclosed {".to_string()))?
+            + start;
+        if start > 0 {
+            parts.push(TemplatePart::Literal(rest[..start].to_string()));
+        }
+        let name = &rest[start + 1..end];
+        let part = match name {
+            "path" => TemplatePart::Path,
+            "query" => TemplatePart::Query,
+            _ => match name.strip_prefix("query.") {
+                Some(param) => TemplatePart::QueryParam(param.to_string()),
+                None => {
+                    let path_regex = path_regex.ok_or_else(|| {
+                        invalid_template(format!("{{{}}} needs a path regex", name))
+                    })?;
+                    let is_capture = match name.parse::<usize>() {
+                        Ok(index) => index < path_regex.captures_len(),
+                        Err(_) => path_regex
+                            .capture_names()
+                            .flatten()
+                            .any(|item| item == name),
+                    };
+                    if !is_capture {
+                        return Err(invalid_template(format!(
+                            "the path regex {} has no capture group {}",
+                            path_regex, name
+                        )));
+                    }
+                    TemplatePart::Capture(name.to_string())
+                }
+            },
+        };
+        parts.push(part);
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+/// Returns the raw value of the first query parameter with the name.
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .map(|item| item.split_once('=').unwrap_or((item, "")))
+        .find(|(key, _)| decode(key) == name)
+        .map(|(_, value)| value)
+}
+fn decode(value: &str) -> String {
+    url::form_urlencoded::parse(value.as_bytes())
+        .next()
+        .map(|(key, _)| key.into_owned())
+        .unwrap_or_default()
+}
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// Joins the endpoint and the path without doubling or dropping the slash between them.
+pub fn join_url(endpoint: &str, path_and_query: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if path_and_query.is_empty() || path_and_query.starts_with('?') {
+        return format!("{}/{}", endpoint, path_and_query);
+    }
+    format!("{}/{}", endpoint, path_and_query.trim_start_matches('/'))
+}
+
+impl CompiledRewrite {
+    /// Returns the rewritten path and query.
+    pub fn rewrite_path(&self, input: &RewriteInput) -> (String, Option<String>) {
+        let path = match &self.regex_replace {
+            Some((regex, replacement)) => regex.replace_all(&input.path, replacement).into_owned(),
+            None => input.path.clone(),
+        };
+        if self.remove.is_empty() && self.rename.is_empty() && self.add.is_empty() {
+            return (path, input.query.clone());
+        }
+        let mut params = input
+            .query
+            .as_deref()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                let (key, value) = item.split_once('=').unwrap_or((item, ""));
+                (decode(key), item.to_string(), value.to_string())
+            })
+            .filter(|(key, _, _)| !self.remove.contains(key))
+            .collect::<Vec<(String, String, String)>>();
+        for (key, raw, value) in params.iter_mut() {
+            if let Some((_, new_key)) = self.rename.iter().find(|(old_key, _)| old_key == key) {
+                *raw = format!("{}={}", encode(new_key), value);
+                *key = new_key.clone();
+            }
+        }
+        params.retain(|(key, _, _)| !self.add.iter().any(|(name, _)| name == key));
+        let mut query = params
+            .into_iter()
+            .map(|(_, raw, _)| raw)
+            .collect::<Vec<String>>();
+        query.extend(
+            self.add
+                .iter()
+                .map(|(name, value)| format!("{}={}", encode(name), encode(value))),
+        );
+        match query.is_empty() {
+            true => (path, None),
+            false => (path, Some(query.join("&"))),
+        }
+    }
+    /// Returns the rewritten path with the query.
+    pub fn rewrite_path_and_query(&self, input: &RewriteInput) -> String {
+        match self.rewrite_path(input) {
+            (path, Some(query)) => format!("{}?{}", path, query),
+            (path, None) => path,
+        }
+    }
+    /// Returns the url of the upstream request.
+    pub fn upstream_url(&self, input: &RewriteInput, endpoint: &str) -> String {
+        let url_template = match &self.url_template {
+            Some(url_template) => url_template,
+            None => return join_url(endpoint, &self.rewrite_path_and_query(input)),
+        };
+        let (path, query) = self.rewrite_path(input);
+        let url = url_template
+            .iter()
+            .map(|item| match item {
+                TemplatePart::Literal(literal) => literal.as_str(),
+                TemplatePart::Capture(name) => input
+                    .captures
+                    .get(name)
+                    .map(|item| item.as_str())
+                    .unwrap_or_default(),
+                TemplatePart::QueryParam(name) => {
+                    query_param(input.request_query.as_deref(), name).unwrap_or_default()
+                }
+                TemplatePart::Path => path.as_str(),
+                TemplatePart::Query => query.as_deref().unwrap_or_default(),
+            })
+            .collect::<String>();
+        match url.starts_with('/') {
+            true => join_url(endpoint, &url),
+            false => url,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_input(path: &str, query: Option<&str>) -> RewriteInput {
+        RewriteInput {
+            path: path.to_string(),
+            query: query.map(|item| item.to_string()),
+            request_query: query.map(|item| item.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_join_url() {
+        assert_eq!(join_url("http://svc", "/a"), "http://svc/a");
+        assert_eq!(join_url("http://svc/", "/a"), "http://svc/a");
+        assert_eq!(
+            join_url("http://svc/base/", "a?b=1"),
+            "http://svc/base/a?b=1"
+        );
+        assert_eq!(join_url("http://svc", "?b=1"), "http://svc/?b=1");
+        assert_eq!(join_url("http://svc", ""), "http://svc/");
+    }
+
+    #[test]
+    fn test_regex_replace_and_query_rewrite() {
+        let rewrite = Rewrite {
+            regex_replace: Some(RegexReplace {
+                regex: "^/api/(v[0-9]+)/(.*)$".to_string(),
+                replacement: "/$2/$1".to_string(),
+            }),
+            query: Some(QueryRewrite {
+                remove: vec!["debug".to_string()],
+                rename: HashMap::from([("uid".to_string(), "user_id".to_string())]),
+                add: HashMap::from([
+                    ("source".to_string(), "spire gw".to_string()),
+                    ("page".to_string(), "1".to_string()),
+                ]),
+            }),
+            ..Default::default()
+        };
+        let compiled_rewrite = rewrite.compile(None).unwrap();
+        let input = create_input("/api/v2/users", Some("uid=7&debug&page=3&sort=name"));
+        assert_eq!(
+            compiled_rewrite.rewrite_path_and_query(&input),
+            "/users/v2?user_id=7&sort=name&page=1&source=spire+gw"
+        );
+        assert_eq!(
+            compiled_rewrite.upstream_url(&input, "http://svc/"),
+            "http://svc/users/v2?user_id=7&sort=name&page=1&source=spire+gw"
+        );
+        let input = create_input("/other", Some("debug=1"));
+        assert_eq!(
+            compiled_rewrite.rewrite_path_and_query(&input),
+            "/other?page=1&source=spire+gw"
+        );
+    }
+
+    #[test]
+    fn test_url_template() {
+        let path_regex = Regex::new("^/users/(?P<id>[0-9]+)/(.*)$").unwrap();
+        let rewrite = Rewrite {
+            url_template: Some("http://legacy/{2}/items?id={query.id}&user={id}".to_string()),
+            ..Default::default()
+        };
+        let compiled_rewrite = rewrite.compile(Some(&path_regex)).unwrap();
+        let input = RewriteInput {
+            path: "/users/7/orders".to_string(),
+            query: Some("id=42".to_string()),
+            captures: HashMap::from([
+                ("1".to_string(), "7".to_string()),
+                ("id".to_string(), "7".to_string()),
+                ("2".to_string(), "orders".to_string()),
+            ]),
+            request_query: Some("id=42".to_string()),
+        };
+        assert_eq!(
+            compiled_rewrite.upstream_url(&input, "http://svc"),
+            "http://legacy/orders/items?id=42&user=7"
+        );
+
+        let rewrite = Rewrite {
+            url_template: Some("/v2{path}?{query}".to_string()),
+            ..Default::default()
+        };
+        let compiled_rewrite = rewrite.compile(None).unwrap();
+        assert_eq!(
+            compiled_rewrite.upstream_url(&input, "http://svc/"),
+            "http://svc/v2/users/7/orders?id=42"
+        );
+    }
+
+    #[test]
+    fn test_invalid_url_template() {
+        let path_regex = Regex::new("^/users/([0-9]+)$").unwrap();
+        let cases = vec![
+            ("http://svc/{1", Some(&path_regex), "unclosed {"),
+            ("http://svc/1}", Some(&path_regex), "unmatched }"),
+            ("http://svc/{2}", Some(&path_regex), "no capture group 2"),
+            ("http://svc/{id}", Some(&path_regex), "no capture group id"),
+            ("http://svc/{1}", None, "needs a path regex"),
+        ];
+        for (url_template, path_regex, expected) in cases {
+            let rewrite = Rewrite {
+                url_template: Some(url_template.to_string()),
+                ..Default::default()
+            };
+            let err = rewrite.compile(path_regex).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::vojo::app_error::AppError;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::Method;
+use http::Uri;
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+/// A predicate on the request besides its host and path, `all`, `any` and `not` combine them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Condition {
+    #[serde(rename = "host")]
+    Host { host: String },
+    #[serde(rename = "host_regex")]
+    HostRegex { regex: String },
+    #[serde(rename = "path")]
+    Path { path: String },
+    #[serde(rename = "path_prefix")]
+    PathPrefix { prefix: String },
+    #[serde(rename = "path_regex")]
+    PathRegex { regex: String },
+    /// Matches the peer address by an ip or a cidr.
+    #[serde(rename = "client_ip")]
+    ClientIp { ip: String },
+    #[serde(rename = "method")]
+    Method { methods: Vec<String> },
+    #[serde(rename = "header")]
+    Header(ValueCondition),
+    #[serde(rename = "query")]
+    Query(ValueCondition),
+    #[serde(rename = "cookie")]
+    Cookie(ValueCondition),
+    #[serde(rename = "all")]
+    All { conditions: Vec<Condition> },
+    #[serde(rename = "any")]
+    Any { conditions: Vec<Condition> },
+    #[serde(rename = "not")]
+    Not { condition: Box<Condition> },
+}
+/// Matches the named header, query parameter or cookie. Without `value` and `regex` it only has to
+/// be present.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ValueCondition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// The parts of the request which the conditions are evaluated against.
+pub struct RequestInfo<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    /// The host without the port, from the Host header or the uri.
+    pub host: Option<&'a str>,
+    pub headers: &'a HeaderMap<HeaderValue>,
+    pub peer_addr: &'a SocketAddr,
+}
+impl<'a> RequestInfo<'a> {
+    pub fn new(
+        method: &'a Method,
+        uri: &'a Uri,
+        headers: &'a HeaderMap<HeaderValue>,
+        peer_addr: &'a SocketAddr,
+    ) -> Self {
+        let host = headers
+            .get(http::header::HOST)
+            .and_then(|item| item.to_str().ok())
+            .or(uri.host())
+            .map(strip_port);
+        Self {
+            method,
+            path: uri.path(),
+            query: uri.query(),
+            host,
+            headers,
+            peer_addr,
+        }
+    }
+}
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split_inclusive(']').next().unwrap_or(host);
+    }
+    match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    }
+}
+
+#[derive(Debug)]
+pub enum CompiledCondition {
+    Host(String),
+    HostRegex(Regex),
+    Path(String),
+    PathPrefix(String),
+    PathRegex(Regex),
+    ClientIp(IpNet),
+    Method(Vec<Method>),
+    Header(CompiledValue),
+    Query(CompiledValue),
+    Cookie(CompiledValue),
+    All(Vec<CompiledCondition>),
+    Any(Vec<CompiledCondition>),
+    Not(Box<CompiledCondition>),
+}
+#[derive(Debug)]
+pub struct CompiledValue {
+    name: String,
+    value: Option<String>,
+    regex: Option<Regex>,
+}
+impl CompiledValue {
+    fn is_matched<'a>(&self, mut values: impl Iterator<Item = &'a str>) -> bool {
+        values.any(|item| {
+            self.value.as_ref().is_none_or(|value| value == item)
+                && self.regex.as_ref().is_none_or(|regex| regex.is_match(item))
+        })
+    }
+}
+
+/// Returns the decoded values of the query parameter.
+pub fn query_values(query: Option<&str>, name: &str) -> Vec<String> {
+    url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .filter(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .collect()
+}
+/// Returns the values of the cookie from every Cookie header.
+pub fn cookie_values<'a>(headers: &'a HeaderMap, name: &'a str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|item| item.to_str().ok())
+        .flat_map(|item| item.split(';'))
+        .filter_map(|item| item.trim().split_once('='))
+        .filter(move |(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+pub fn parse_methods(methods: &[String]) -> Result<Vec<Method>, AppError> {
+    methods
+        .iter()
+        .map(|item| {
+            Method::from_bytes(item.to_uppercase().as_bytes())
+                .map_err(|_| AppError(format!("The method {} is invalid", item)))
+        })
+        .collect()
+}
+
+fn compile_regex(regex: &str) -> Result<Regex, AppError> {
+    Regex::new(regex).map_err(|e| AppError(format!("The regex {} is invalid: {}", regex, e)))
+}
+
+impl Condition {
+    pub fn compile(&self) -> Result<CompiledCondition, AppError> {
+        let compiled_condition = match self {
+            Condition::Host { host } => CompiledCondition::Host(host.to_lowercase()),
+            Condition::HostRegex { regex } => CompiledCondition::HostRegex(compile_regex(regex)?),
+            Condition::Path { path } => CompiledCondition::Path(path.clone()),
+            Condition::PathPrefix { prefix } => CompiledCondition::PathPrefix(prefix.clone()),
+            Condition::PathRegex { regex } => CompiledCondition::PathRegex(compile_regex(regex)?),
+            Condition::ClientIp { ip } => {
+                let ip_net = match ip.parse::<IpAddr>() {
+                    Ok(ip_addr) => IpNet::from(ip_addr),
+                    Err(_) => ip
+                        .parse::<IpNet>()
+                        .map_err(|e| AppError(format!("The client ip {} is invalid: {}", ip, e)))?,
+                };
+                CompiledCondition::ClientIp(ip_net)
+            }
+            Condition::Method { methods } => CompiledCondition::Method(parse_methods(methods)?),
+            Condition::Header(value_condition) => {
+                CompiledCondition::Header(value_condition.compile()?)
+            }
+            Condition::Query(value_condition) => {
+                CompiledCondition::Query(value_condition.compile()?)
+            }
+            Condition::Cookie(value_condition) => {
+                CompiledCondition::Cookie(value_condition.compile()?)
+            }
+            Condition::All { conditions } => CompiledCondition::All(
+                conditions
+                    .iter()
+                    .map(|item| item.compile())
+                    .collect::<Result<Vec<_>, AppError>>()?,
+            ),
+            Condition::Any { conditions } => CompiledCondition::Any(
+                conditions
+                    .iter()
+                    .map(|item| item.compile())
+                    .collect::<Result<Vec<_>, AppError>>()?,
+            ),
+            Condition::Not { condition } => CompiledCondition::Not(Box::new(condition.compile()?)),
+        };
+        Ok(compiled_condition)
+    }
+}
+impl ValueCondition {
+    fn compile(&self) -> Result<CompiledValue, AppError> {
+        let regex = match &self.regex {
+            Some(regex) => Some(Regex::new(regex).map_err(|e| {
+                AppError(format!(
+                    "The regex {} of condition {} is invalid: {}",
+                    regex, self.name, e
+                ))
+            })?),
+            None => None,
+        };
+        Ok(CompiledValue {
+            name: self.name.clone(),
+            value: self.value.clone(),
+            regex,
+        })
+    }
+}
+impl CompiledCondition {
+    pub fn is_matched(&self, request: &RequestInfo) -> bool {
+        match self {
+            CompiledCondition::Host(host) => request
+                .host
+                .is_some_and(|item| item.eq_ignore_ascii_case(host)),
+            CompiledCondition::HostRegex(regex) => {
+                request.host.is_some_and(|item| regex.is_match(item))
+            }
+            CompiledCondition::Path(path) => request.path == path,
+            CompiledCondition::PathPrefix(prefix) => request.path.starts_with(prefix.as_str()),
+            CompiledCondition::PathRegex(regex) => regex.is_match(request.path),
+            CompiledCondition::ClientIp(ip_net) => ip_net.contains(&request.peer_addr.ip()),
+            CompiledCondition::Method(methods) => methods.contains(request.method),
+            CompiledCondition::Header(compiled_value) => compiled_value.is_matched(
+                request
+                    .headers
+                    .get_all(compiled_value.name.as_str())
+                    .iter()
+                    .filter_map(|item| item.to_str().ok()),
+            ),
+            CompiledCondition::Query(compiled_value) => {
+                let values = query_values(request.query, &compiled_value.name);
+                compiled_value.is_matched(values.iter().map(|item| item.as_str()))
+            }
+            CompiledCondition::Cookie(compiled_value) => {
+                compiled_value.is_matched(cookie_values(request.headers, &compiled_value.name))
+            }
+            CompiledCondition::All(conditions) => {
+                conditions.iter().all(|item| item.is_matched(request))
+            }
+            CompiledCondition::Any(conditions) => {
+                conditions.iter().any(|item| item.is_matched(request))
+            }
+            CompiledCondition::Not(condition) => !condition.is_matched(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_condition(name: &str, value: Option<&str>, regex: Option<&str>) -> ValueCondition {
+        ValueCondition {
+            name: name.to_string(),
+            value: value.map(|item| item.to_string()),
+            regex: regex.map(|item| item.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_value_conditions() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Version", HeaderValue::from_static("v2"));
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_static("session=abc; beta=true"),
+        );
+        let uri = "/?id=42&debug".parse::<Uri>().unwrap();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+        let cases = vec![
+            (
+                Condition::Header(value_condition("x-version", Some("v2"), None)),
+                true,
+            ),
+            (
+                Condition::Header(value_condition("x-version", Some("v1"), None)),
+                false,
+            ),
+            (
+                Condition::Header(value_condition("x-other", None, None)),
+                false,
+            ),
+            (Condition::Query(value_condition("debug", None, None)), true),
+            (
+                Condition::Query(value_condition("id", None, Some("^[0-9]+$"))),
+                true,
+            ),
+            (
+                Condition::Query(value_condition("id", Some("43"), None)),
+                false,
+            ),
+            (
+                Condition::Cookie(value_condition("beta", Some("true"), None)),
+                true,
+            ),
+            (
+                Condition::Cookie(value_condition("session", None, Some("^x"))),
+                false,
+            ),
+        ];
+        for (condition, expected) in cases {
+            assert_eq!(
+                condition.compile().unwrap().is_matched(&request),
+                expected,
+                "{:?}",
+                condition
+            );
+        }
+    }
+
+    #[test]
+    fn test_combined_conditions() {
+        let yaml = r#"
+kind: any
+conditions:
+  - kind: all
+    conditions:
+      - kind: method
+        methods: [get, head]
+      - kind: not
+        condition:
+          kind: header
+          name: x-internal
+  - kind: query
+    name: debug
+"#;
+        let condition: Condition = serde_yaml::from_str(yaml).unwrap();
+        let compiled_condition = condition.compile().unwrap();
+
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let is_matched = |method: Method, uri: &str, headers: &HeaderMap| {
+            let uri = uri.parse::<Uri>().unwrap();
+            let request = RequestInfo::new(&method, &uri, headers, &peer_addr);
+            compiled_condition.is_matched(&request)
+        };
+        let mut headers = HeaderMap::new();
+        assert!(is_matched(Method::GET, "/", &headers));
+        assert!(!is_matched(Method::POST, "/", &headers));
+        assert!(is_matched(Method::POST, "/?debug=1", &headers));
+
+        headers.insert("x-internal", HeaderValue::from_static("1"));
+        assert!(!is_matched(Method::GET, "/", &headers));
+    }
+
+    #[test]
+    fn test_host_path_and_client_ip_conditions() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::HOST,
+            HeaderValue::from_static("API.example.com:8080"),
+        );
+        let uri = "/v1/users/7".parse::<Uri>().unwrap();
+        let peer_addr = "10.1.2.3:4000".parse().unwrap();
+        let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+        let cases = vec![
+            (
+                Condition::Host {
+                    host: "api.example.com".to_string(),
+                },
+                true,
+            ),
+            (
+                Condition::HostRegex {
+                    regex: "^web\\.".to_string(),
+                },
+                false,
+            ),
+            (
+                Condition::Path {
+                    path: "/v1/users".to_string(),
+                },
+                false,
+            ),
+            (
+                Condition::PathPrefix {
+                    prefix: "/v1/".to_string(),
+                },
+                true,
+            ),
+            (
+                Condition::PathRegex {
+                    regex: "^/v1/users/[0-9]+$".to_string(),
+                },
+                true,
+            ),
+            (
+                Condition::ClientIp {
+                    ip: "10.0.0.0/8".to_string(),
+                },
+                true,
+            ),
+            (
+                Condition::ClientIp {
+                    ip: "10.1.2.4".to_string(),
+                },
+                false,
+            ),
+        ];
+        for (condition, expected) in cases {
+            assert_eq!(
+                condition.compile().unwrap().is_matched(&request),
+                expected,
+                "{:?}",
+                condition
+            );
+        }
+        let uri = "http://[::1]:8080/".parse::<Uri>().unwrap();
+        let headers = HeaderMap::new();
+        let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+        assert_eq!(request.host, Some("[::1]"));
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        let condition = Condition::Method {
+            methods: vec!["GE T".to_string()],
+        };
+        assert!(condition.compile().is_err());
+        let condition = Condition::Header(value_condition("x", None, Some("(a")));
+        assert!(condition.compile().is_err());
+        let condition = Condition::ClientIp {
+            ip: "10.0.0.0/33".to_string(),
+        };
+        assert!(condition.compile().is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::vojo::app_error::AppError;
+use crate::vojo::route_matcher::Condition;
+use crate::vojo::route_matcher::ValueCondition;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::str::FromStr;
+
+/// A Traefik style rule such as ``Host(`example.com`) && (PathPrefix(`/v1`) || Method(`GET`))``,
+/// it is parsed into a condition when the config is loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub expression: String,
+    pub condition: Condition,
+}
+impl FromStr for Rule {
+    type Err = AppError;
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            expression,
+            tokens,
+            position: 0,
+        };
+        let condition = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(parser.error_at(token.position, "expected && or ||"));
+        }
+        Ok(Rule {
+            expression: expression.to_string(),
+            condition,
+        })
+    }
+}
+impl Serialize for Rule {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.expression)
+    }
+}
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let expression = String::deserialize(deserializer)?;
+        Rule::from_str(&expression).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Literal(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    And,
+    Or,
+    Not,
+}
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn error_at(expression: &str, position: usize, message: &str) -> AppError {
+    AppError(format!(
+        "The rule is invalid at column {}: {}\n{}\n{}^",
+        position + 1,
+        message,
+        expression,
+        " ".repeat(position)
+    ))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, AppError> {
+    let chars = expression.chars().collect::<Vec<char>>();
+    let mut tokens = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        let position = index;
+        let kind = match chars[index] {
+            c if c.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            ',' => TokenKind::Comma,
+            '!' => TokenKind::Not,
+            '&' | '|' => {
+                let c = chars[index];
+                if chars.get(index + 1) != Some(&c) {
+                    return Err(error_at(expression, index, &format!("expected {}{}", c, c)));
+                }
+                index += 1;
+                match c {
+                    '&' => TokenKind::And,
+                    _ => TokenKind::Or,
+                }
+            }
+            quote @ ('`' | '"') => {
+                let end = chars[index + 1..]
+                    .iter()
+                    .position(|c| *c == quote)
+                    .ok_or_else(|| error_at(expression, index, "unterminated string"))?;
+                let literal = chars[index + 1..index + 1 + end].iter().collect();
+                index += end + 1;
+                TokenKind::Literal(literal)
+            }
+            c if c.is_ascii_alphabetic() => {
+                let end = chars[index..]
+                    .iter()
+                    .position(|c| !c.is_ascii_alphanumeric())
+                    .unwrap_or(chars.len() - index);
+                let ident = chars[index..index + end].iter().collect();
+                index += end - 1;
+                TokenKind::Ident(ident)
+            }
+            c => {
+                return Err(error_at(
+                    expression,
+                    index,
+                    &format!("unexpected character {}", c),
+                ))
+            }
+        };
+        index += 1;
+        tokens.push(Token { kind, position });
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+    fn end_position(&self) -> usize {
+        self.expression.chars().count()
+    }
+    fn error_at(&self, position: usize, message: &str) -> AppError {
+        error_at(self.expression, position, message)
+    }
+    fn expect(&mut self, kind: TokenKind, message: &str) -> Result<(), AppError> {
+        match self.next() {
+            Some(token) if token.kind == kind => Ok(()),
+            Some(token) => Err(self.error_at(token.position, message)),
+            None => Err(self.error_at(self.end_position(), message)),
+        }
+    }
+    fn parse_or(&mut self) -> Result<Condition, AppError> {
+        let mut conditions = vec![self.parse_and()?];
+        while self.peek().is_some_and(|token| token.kind == TokenKind::Or) {
+            self.position += 1;
+            conditions.push(self.parse_and()?);
+        }
+        Ok(match conditions.len() {
+            1 => conditions.remove(0),
+            _ => Condition::Any { conditions },
+        })
+    }
+    fn parse_and(&mut self) -> Result<Condition, AppError> {
+        let mut conditions = vec![self.parse_unary()?];
+        while self
+            .peek()
+            .is_some_and(|token| token.kind == TokenKind::And)
+        {
+            self.position += 1;
+            conditions.push(self.parse_unary()?);
+        }
+        Ok(match conditions.len() {
+            1 => conditions.remove(0),
+            _ => Condition::All { conditions },
+        })
+    }
+    fn parse_unary(&mut self) -> Result<Condition, AppError> {
+        let token = self
+            .next()
+            .ok_or_else(|| self.error_at(self.end_position(), "expected a matcher"))?;
+        match token.kind {
+            TokenKind::Not => Ok(Condition::Not {
+                condition: Box::new(self.parse_unary()?),
+            }),
+            TokenKind::LeftParen => {
+                let condition = self.parse_or()?;
+                self.expect(TokenKind::RightParen, "expected )")?;
+                Ok(condition)
+            }
+            TokenKind::Ident(name) => {
+                self.expect(TokenKind::LeftParen, "expected (")?;
+                let mut args = vec![];
+                loop {
+                    match self.next() {
+                        Some(Token {
+                            kind: TokenKind::Literal(literal),
+                            ..
+                        }) => args.push(literal),
+                        Some(token) => {
+                            return Err(self.error_at(token.position, "expected a string"))
+                        }
+                        None => return Err(self.error_at(self.end_position(), "expected a string")),
+                    }
+                    match self.next() {
+                        Some(Token {
+                            kind: TokenKind::Comma,
+                            ..
+                        }) => continue,
+                        Some(Token {
+                            kind: TokenKind::RightParen,
+                            ..
+                        }) => break,
+                        Some(token) => return Err(self.error_at(token.position, "expected , or )")),
+                        None => return Err(self.error_at(self.end_position(), "expected , or )")),
+                    }
+                }
+                self.build_matcher(&name, token.position, args)
+            }
+            _ => Err(self.error_at(token.position, "expected a matcher")),
+        }
+    }
+    fn build_matcher(
+        &self,
+        name: &str,
+        position: usize,
+        mut args: Vec<String>,
+    ) -> Result<Condition, AppError> {
+        let arg_count = match name {
+            "Header" | "HeaderRegexp" | "QueryRegexp" => 2..=2,
+            "Query" => 1..=2,
+            _ => 1..=1,
+        };
+        if !arg_count.contains(&args.len()) {
+            let expected = match arg_count.start() == arg_count.end() {
+                true => arg_count.start().to_string(),
+                false => format!("{} or {}", arg_count.start(), arg_count.end()),
+            };
+            return Err(self.error_at(
+                position,
+                &format!(
+                    "{} expects {} arguments, found {}",
+                    name,
+                    expected,
+                    args.len()
+                ),
+            ));
+        }
+        let value = args.pop().unwrap_or_default();
+        let key = args.pop();
+        let value_condition = |regex: bool| match key.clone() {
+            Some(key) if regex => ValueCondition {
+                name: key,
+                regex: Some(value.clone()),
+                ..Default::default()
+            },
+            Some(key) => ValueCondition {
+                name: key,
+                value: Some(value.clone()),
+                ..Default::default()
+            },
+            None => ValueCondition {
+                name: value.clone(),
+                ..Default::default()
+            },
+        };
+        let condition = match name {
+            "Host" => Condition::Host {
+                host: value.clone(),
+            },
+            "HostRegexp" => Condition::HostRegex {
+                regex: value.clone(),
+            },
+            "Path" => Condition::Path {
+                path: value.clone(),
+            },
+            "PathPrefix" => Condition::PathPrefix {
+                prefix: value.clone(),
+            },
+            "PathRegexp" => Condition::PathRegex {
+                regex: value.clone(),
+            },
+            "Method" => Condition::Method {
+                methods: vec![value.clone()],
+            },
+            "ClientIP" => Condition::ClientIp { ip: value.clone() },
+            "Header" | "Query" => match name {
+                "Header" => Condition::Header(value_condition(false)),
+                _ => Condition::Query(value_condition(false)),
+            },
+            "HeaderRegexp" => Condition::Header(value_condition(true)),
+            "QueryRegexp" => Condition::Query(value_condition(true)),
+            _ => {
+                return Err(self.error_at(position, &format!("unknown matcher {}", name)));
+            }
+        };
+        Ok(condition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        let rule = Rule::from_str(
+            "Host(`api.example.com`) && (PathPrefix(`/v1`) || Header(`X-Canary`, `1`)) && !ClientIP(`10.0.0.0/8`)",
+        )
+        .unwrap();
+        assert_eq!(
+            rule.condition,
+            Condition::All {
+                conditions: vec![
+                    Condition::Host {
+                        host: "api.example.com".to_string()
+                    },
+                    Condition::Any {
+                        conditions: vec![
+                            Condition::PathPrefix {
+                                prefix: "/v1".to_string()
+                            },
+                            Condition::Header(ValueCondition {
+                                name: "X-Canary".to_string(),
+                                value: Some("1".to_string()),
+                                ..Default::default()
+                            }),
+                        ]
+                    },
+                    Condition::Not {
+                        condition: Box::new(Condition::ClientIp {
+                            ip: "10.0.0.0/8".to_string()
+                        })
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let rule =
+            Rule::from_str(r#"Method("GET") || Query(`debug`) && QueryRegexp(`id`, `^[0-9]+$`)"#)
+                .unwrap();
+        assert_eq!(
+            rule.condition,
+            Condition::Any {
+                conditions: vec![
+                    Condition::Method {
+                        methods: vec!["GET".to_string()]
+                    },
+                    Condition::All {
+                        conditions: vec![
+                            Condition::Query(ValueCondition {
+                                name: "debug".to_string(),
+                                ..Default::default()
+                            }),
+                            Condition::Query(ValueCondition {
+                                name: "id".to_string(),
+                                regex: Some("^[0-9]+$".to_string()),
+                                ..Default::default()
+                            }),
+                        ]
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_rule_errors() {
+        let cases = vec![
+            ("Host(`a`) & Path(`/`)", "column 11: expected &&"),
+            ("Host(`a`) && Foo(`/`)", "column 14: unknown matcher Foo"),
+            ("Host(`a`", "column 9: expected , or )"),
+            ("Host(`a) && Path(x)", "column 6: unterminated string"),
+            (
+                "Header(`a`)",
+                "column 1: Header expects 2 arguments, found 1",
+            ),
+            ("(Host(`a`) || Path(`/`)", "column 24: expected )"),
+            ("Host(`a`) Path(`/`)", "column 11: expected && or ||"),
+            ("Host(`a`) && ", "column 14: expected a matcher"),
+            ("Path(/)", "column 6: unexpected character /"),
+        ];
+        for (expression, expected) in cases {
+            let err = Rule::from_str(expression).unwrap_err();
+            assert!(
+                err.to_string().contains(expected),
+                "{}: {}",
+                expression,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_deserialize_rule() {
+        let rule: Rule = serde_yaml::from_str("\"Path(`/health`)\"").unwrap();
+        assert_eq!(
+            serde_yaml::to_string(&rule).unwrap().trim(),
+            "Path(`/health`)"
+        );
+        let res = serde_yaml::from_str::<Rule>("\"Path(`/health`\"");
+        assert!(res.unwrap_err().to_string().contains("column 15"));
+    }
+}
@@ -2,9 +2,19 @@ use crate::proxy::proxy_trait::RouterDestination;
 use serde::Serializer;
 
 use super::app_error::AppError;
+use super::canary::CanaryRoute;
+use super::consistent_hash::ConsistentHashRoute;
+use super::direct_response::DirectResponseRoute;
+use super::direct_response::RedirectRoute;
+use super::failover::FailoverRoute;
+use super::least_request::EndpointLoads;
+use super::least_request::LeastRequestRoute;
+use super::least_request::P2cEwmaRoute;
+use super::runtime_state::RuntimeState;
 use core::fmt::Debug;
 use http::HeaderMap;
 use http::HeaderValue;
+use http::Uri;
 use rand::prelude::*;
 use regex::Regex;
 use serde::de;
@@ -15,8 +25,11 @@ use serde::Deserializer;
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "kind")]
 pub enum Router {
@@ -30,6 +43,20 @@ pub enum Router {
     Random(RandomRoute),
     #[serde(rename = "weight")]
     WeightBased(WeightBasedRoute),
+    #[serde(rename = "least_request")]
+    LeastRequest(LeastRequestRoute),
+    #[serde(rename = "p2c_ewma")]
+    P2cEwma(P2cEwmaRoute),
+    #[serde(rename = "consistent_hash")]
+    ConsistentHash(ConsistentHashRoute),
+    #[serde(rename = "failover")]
+    Failover(FailoverRoute),
+    #[serde(rename = "canary")]
+    Canary(CanaryRoute),
+    #[serde(rename = "direct_response")]
+    DirectResponse(DirectResponseRoute),
+    #[serde(rename = "redirect")]
+    Redirect(RedirectRoute),
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Default, Eq)]
 
@@ -94,39 +121,235 @@ impl Default for Router {
         Self::Poll(PollRoute::default())
     }
 }
+/// The state of the route and of the request which the endpoint selection depends on.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SelectionContext {
+    pub peer_addr: Option<SocketAddr>,
+    pub uri: Option<Uri>,
+    pub in_panic: bool,
+    /// Endpoints which the request has already tried, they are only picked again when no other
+    /// candidate is left.
+    pub excluded_endpoints: Vec<String>,
+    /// Endpoints which must never be picked, such as the ones with an open circuit.
+    pub unavailable_endpoints: Vec<String>,
+    /// Endpoints which are ejected for consecutive failures. They count as unhealthy, so they are
+    /// only picked in panic mode.
+    pub unhealthy_endpoints: Vec<String>,
+}
+impl SelectionContext {
+    pub fn is_healthy(&self, endpoint: &str, is_alive: Option<bool>) -> bool {
+        is_alive != Some(false)
+            && !self
+                .unhealthy_endpoints
+                .iter()
+                .any(|unhealthy| unhealthy == endpoint)
+    }
+}
+/// Returns the indices of the endpoints which can take traffic. Endpoints which have not been
+/// health checked yet count as healthy. In panic mode, or when no endpoint is healthy, every
+/// available endpoint is a candidate.
+pub fn candidate_indices<T>(
+    routes: &[T],
+    endpoint: impl Fn(&T) -> &str,
+    is_alive: impl Fn(&T) -> Option<bool>,
+    context: &SelectionContext,
+) -> Vec<usize> {
+    let available_indices: Vec<usize> = (0..routes.len())
+        .filter(|&i| {
+            !context
+                .unavailable_endpoints
+                .iter()
+                .any(|unavailable| unavailable == endpoint(&routes[i]))
+        })
+        .collect();
+    let healthy_indices: Vec<usize> = available_indices
+        .iter()
+        .copied()
+        .filter(|&i| context.is_healthy(endpoint(&routes[i]), is_alive(&routes[i])))
+        .collect();
+    let candidates: Vec<usize> = if context.in_panic || healthy_indices.is_empty() {
+        if !available_indices.is_empty() {
+            debug!("Not enough healthy routes, selecting from all routes");
+        }
+        available_indices
+    } else {
+        healthy_indices
+    };
+    let untried: Vec<usize> = candidates
+        .iter()
+        .copied()
+        .filter(|&i| {
+            !context
+                .excluded_endpoints
+                .iter()
+                .any(|excluded| excluded == endpoint(&routes[i]))
+        })
+        .collect();
+    if untried.is_empty() {
+        return candidates;
+    }
+    untried
+}
 impl Router {
     pub fn get_route(
-        &mut self,
+        &self,
         headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
     ) -> Result<RouterDestination, AppError> {
         match self {
             Router::StaticFile(s) => Ok(RouterDestination::File(s.clone())),
-            Router::Poll(poll_route) => Ok(RouterDestination::Http(poll_route.get_route(headers)?)),
-
-            Router::HeaderBased(poll_route) => {
-                Ok(RouterDestination::Http(poll_route.get_route(headers)?))
-            }
-
-            Router::Random(poll_route) => {
-                Ok(RouterDestination::Http(poll_route.get_route(headers)?))
-            }
-
-            Router::WeightBased(poll_route) => {
-                Ok(RouterDestination::Http(poll_route.get_route(headers)?))
+            Router::Poll(poll_route) => Ok(RouterDestination::Http(
+                poll_route.get_route(headers, context)?,
+            )),
+
+            Router::HeaderBased(poll_route) => Ok(RouterDestination::Http(
+                poll_route.get_route(headers, context)?,
+            )),
+
+            Router::Random(poll_route) => Ok(RouterDestination::Http(
+                poll_route.get_route(headers, context)?,
+            )),
+
+            Router::WeightBased(poll_route) => Ok(RouterDestination::Http(
+                poll_route.get_route(headers, context)?,
+            )),
+            Router::LeastRequest(least_request_route) => Ok(RouterDestination::Http(
+                least_request_route.get_route(headers, context)?,
+            )),
+            Router::P2cEwma(p2c_ewma_route) => Ok(RouterDestination::Http(
+                p2c_ewma_route.get_route(headers, context)?,
+            )),
+            Router::ConsistentHash(consistent_hash_route) => Ok(RouterDestination::Http(
+                consistent_hash_route.get_route(headers, context)?,
+            )),
+            Router::Failover(failover_route) => failover_route.get_route(headers, context),
+            Router::Canary(canary_route) => canary_route.get_route(headers, context),
+            Router::DirectResponse(direct_response_route) => Ok(RouterDestination::Local(
+                direct_response_route.get_response()?,
+            )),
+            Router::Redirect(redirect_route) => Ok(RouterDestination::Local(
+                redirect_route.get_response(headers, context)?,
+            )),
+        }
+    }
+    /// Returns the load which the router balances the endpoint on, for the proxy to keep it up
+    /// to date.
+    pub fn get_endpoint_loads(&self, endpoint: &str) -> Option<RuntimeState<EndpointLoads>> {
+        match self {
+            Router::LeastRequest(least_request_route) => {
+                Some(least_request_route.endpoint_loads.clone())
             }
+            Router::P2cEwma(p2c_ewma_route) => Some(p2c_ewma_route.endpoint_loads.clone()),
+            Router::Failover(failover_route) => failover_route
+                .find_group(endpoint)
+                .and_then(|group| group.router.get_endpoint_loads(endpoint)),
+            Router::Canary(canary_route) => canary_route
+                .find_variant(endpoint)
+                .and_then(|variant| variant.router.get_endpoint_loads(endpoint)),
+            _ => None,
+        }
+    }
+    /// Returns the name of the canary variant which the endpoint belongs to.
+    pub fn get_canary_variant(&self, endpoint: &str) -> Option<String> {
+        match self {
+            Router::Canary(canary_route) => canary_route
+                .find_variant(endpoint)
+                .map(|variant| variant.name.clone()),
+            Router::Failover(failover_route) => failover_route
+                .find_group(endpoint)
+                .and_then(|group| group.router.get_canary_variant(endpoint)),
+            _ => None,
+        }
+    }
+    pub fn set_canary_weights(&mut self, weights: &HashMap<String, u32>) -> Result<(), AppError> {
+        match self {
+            Router::Canary(canary_route) => canary_route.set_weights(weights),
+            _ => Err(AppError::from("The route is not a canary route!")),
+        }
+    }
+    /// Returns the upstream endpoints with their health, which is empty for routers which do not
+    /// proxy.
+    /// Returns whether the router splits its endpoints into groups which it picks between, so
+    /// not every endpoint may serve every request.
+    pub fn has_endpoint_groups(&self) -> bool {
+        matches!(
+            self,
+            Router::HeaderBased(_) | Router::Failover(_) | Router::Canary(_)
+        )
+    }
+    pub fn get_base_routes(&self) -> Vec<BaseRoute> {
+        match self {
+            Router::StaticFile(_) | Router::DirectResponse(_) | Router::Redirect(_) => vec![],
+            Router::Poll(poll_route) => poll_route.routes.clone(),
+            Router::HeaderBased(header_route) => header_route
+                .routes
+                .iter()
+                .map(|r| r.get_base_route())
+                .collect(),
+            Router::Random(random_route) => random_route.routes.clone(),
+            Router::WeightBased(weight_route) => weight_route
+                .routes
+                .iter()
+                .map(|r| r.get_base_route())
+                .collect(),
+            Router::LeastRequest(least_request_route) => least_request_route.routes.clone(),
+            Router::P2cEwma(p2c_ewma_route) => p2c_ewma_route.routes.clone(),
+            Router::ConsistentHash(consistent_hash_route) => consistent_hash_route.routes.clone(),
+            Router::Failover(failover_route) => failover_route.get_base_routes(),
+            Router::Canary(canary_route) => canary_route.get_base_routes(),
         }
     }
+    pub fn get_endpoints(&self) -> Vec<String> {
+        self.get_base_routes()
+            .into_iter()
+            .map(|r| r.endpoint)
+            .collect()
+    }
+    /// Returns the number of healthy endpoints, or None if the router has no endpoints to check.
+    pub fn get_healthy_count(&self, context: &SelectionContext) -> Option<usize> {
+        if !self.has_endpoints() {
+            return None;
+        }
+        Some(
+            self.get_base_routes()
+                .iter()
+                .filter(|r| context.is_healthy(&r.endpoint, r.is_alive))
+                .count(),
+        )
+    }
+    /// Returns whether the router proxies to upstream endpoints instead of answering itself.
+    pub fn has_endpoints(&self) -> bool {
+        !matches!(
+            self,
+            Router::StaticFile(_) | Router::DirectResponse(_) | Router::Redirect(_)
+        )
+    }
     pub async fn get_all_route(&mut self) -> Result<Vec<BaseRoute>, AppError> {
         match self {
             Router::StaticFile(_) => {
                 Err(AppError("StaticFile router can not get route".to_string()))
             }
+            Router::DirectResponse(_) | Router::Redirect(_) => Err(AppError::from(
+                "A router which answers locally has no route",
+            )),
             Router::Poll(poll_route) => poll_route.get_all_route().await,
             Router::HeaderBased(poll_route) => poll_route.get_all_route().await,
 
             Router::Random(poll_route) => poll_route.get_all_route().await,
 
             Router::WeightBased(poll_route) => poll_route.get_all_route().await,
+
+            Router::LeastRequest(least_request_route) => Ok(least_request_route.routes.clone()),
+
+            Router::P2cEwma(p2c_ewma_route) => Ok(p2c_ewma_route.routes.clone()),
+
+            Router::ConsistentHash(consistent_hash_route) => {
+                Ok(consistent_hash_route.routes.clone())
+            }
+
+            Router::Failover(failover_route) => Ok(failover_route.get_base_routes()),
+
+            Router::Canary(canary_route) => Ok(canary_route.get_base_routes()),
         }
     }
     pub fn update_route_alive(
@@ -138,15 +361,44 @@ impl Router {
             Router::StaticFile(_) => {
                 Err(AppError("StaticFile router can not get route".to_string()))
             }
+            Router::DirectResponse(_) | Router::Redirect(_) => Err(AppError::from(
+                "A router which answers locally has no route",
+            )),
             Router::Poll(poll_route) => poll_route.update_route_alive(base_route, is_alive),
             Router::HeaderBased(poll_route) => poll_route.update_route_alive(base_route, is_alive),
 
             Router::Random(poll_route) => poll_route.update_route_alive(base_route, is_alive),
 
             Router::WeightBased(poll_route) => poll_route.update_route_alive(base_route, is_alive),
+
+            Router::LeastRequest(least_request_route) => {
+                update_base_route_alive(&mut least_request_route.routes, base_route, is_alive)
+            }
+            Router::P2cEwma(p2c_ewma_route) => {
+                update_base_route_alive(&mut p2c_ewma_route.routes, base_route, is_alive)
+            }
+            Router::ConsistentHash(consistent_hash_route) => {
+                update_base_route_alive(&mut consistent_hash_route.routes, base_route, is_alive)
+            }
+            Router::Failover(failover_route) => {
+                failover_route.update_route_alive(base_route, is_alive)
+            }
+            Router::Canary(canary_route) => canary_route.update_route_alive(base_route, is_alive),
         }
     }
 }
+fn update_base_route_alive(
+    routes: &mut [BaseRoute],
+    base_route: BaseRoute,
+    is_alive: bool,
+) -> Result<(), AppError> {
+    for item in routes.iter_mut() {
+        if item.endpoint == base_route.endpoint {
+            item.is_alive = Some(is_alive);
+        }
+    }
+    Ok(())
+}
 pub fn deserialize_router<'de, D>(deserializer: D) -> Result<Router, D::Error>
 where
     D: Deserializer<'de>,
@@ -196,6 +448,7 @@ where
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct AnomalyDetectionStatus {
     pub consecutive_5xx: i32,
+    pub is_ejected: bool,
 }
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default, Eq)]
 pub struct BaseRoute {
@@ -211,16 +464,19 @@ pub struct SplitSegment {
     #[serde(rename = "matches")]
     pub split_list: Vec<String>,
 }
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SplitItem {
     pub header_key: String,
     pub header_value: String,
 }
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 
 pub struct RegexMatch {
     pub value: String,
 }
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TextMatch {
     pub value: String,
@@ -392,18 +648,16 @@ impl HeaderBasedRoute {
             .collect::<Vec<BaseRoute>>())
     }
 
-    fn get_route(&mut self, headers: &HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
-        let has_unconfigured = self.routes.iter().any(|r| r.is_alive.is_none());
-        debug!("has_unconfigured:{}", has_unconfigured);
-        let routes = if has_unconfigured {
-            self.routes.clone()
-        } else {
-            self.routes
-                .iter()
-                .filter(|r| r.is_alive == Some(true))
-                .cloned()
-                .collect()
-        };
+    fn get_route(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let routes: Vec<HeaderRoutingRule> =
+            candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context)
+                .into_iter()
+                .map(|i| self.routes[i].clone())
+                .collect();
         for item in routes.iter() {
             let headers_contais_key = headers.contains_key(item.header_key.clone());
             if !headers_contais_key {
@@ -451,8 +705,7 @@ impl HeaderBasedRoute {
         }
         error!("Can not find the route!And Spire has selected the first route!");
 
-        let first = self
-            .routes
+        let first = routes
             .first()
             .ok_or("The first item not found.")?
             .get_base_route()
@@ -495,32 +748,18 @@ impl RandomRoute {
         Ok(self.routes.to_vec())
     }
 
-    fn get_route(&mut self, _headers: &HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
-        let has_unconfigured = self.routes.iter().any(|r| r.is_alive.is_none());
-
-        if has_unconfigured {
-            let mut rng = rand::rng();
-            let random_index = rng.random_range(0..self.routes.len());
-            Ok(self.routes[random_index].clone())
-        } else {
-            let alive_indices: Vec<usize> = self
-                .routes
-                .iter()
-                .enumerate()
-                .filter(|(_, r)| r.is_alive == Some(true))
-                .map(|(i, _)| i)
-                .collect();
-            if alive_indices.is_empty() {
-                debug!("All routes are dead, selecting a random route");
-                let mut rng = rand::rng();
-                let random_index = rng.random_range(0..self.routes.len());
-                Ok(self.routes[random_index].clone())
-            } else {
-                let mut rng = rand::rng();
-                let random_index = rng.random_range(0..alive_indices.len());
-                Ok(self.routes[alive_indices[random_index]].clone())
-            }
+    fn get_route(
+        &self,
+        _headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        if candidates.is_empty() {
+            return Err(AppError::from("No routes available"));
         }
+        let mut rng = rand::rng();
+        let random_index = rng.random_range(0..candidates.len());
+        Ok(self.routes[candidates[random_index]].clone())
     }
     fn update_route_alive(
         &mut self,
@@ -539,7 +778,7 @@ impl RandomRoute {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct PollRoute {
     #[serde(skip_deserializing, skip_serializing)]
-    pub current_index: i128,
+    pub current_index: RuntimeState<AtomicUsize>,
     #[serde(rename = "targets")]
     pub routes: Vec<BaseRoute>,
 }
@@ -549,43 +788,22 @@ impl PollRoute {
         Ok(self.routes.clone())
     }
 
-    fn get_route(&mut self, _headers: &HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
-        let has_unconfigured = self.routes.iter().any(|r| r.is_alive.is_none());
-        if has_unconfigured {
-            self.current_index += 1;
-            if self.current_index >= self.routes.len() as i128 {
-                self.current_index = 0;
-            }
-            debug!("current_index:{}", self.current_index);
-            let route = self.routes[self.current_index as usize].clone();
-            Ok(route)
-        } else {
-            let alive_indices: Vec<usize> = self
-                .routes
-                .iter()
-                .enumerate()
-                .filter(|(_, r)| r.is_alive == Some(true))
-                .map(|(i, _)| i)
-                .collect();
-            if alive_indices.is_empty() {
-                debug!("All routes are dead, selecting a random route");
-                let mut rng = rand::rng();
-                let random_index = rng.random_range(0..self.routes.len());
-                Ok(self.routes[random_index].clone())
-            } else {
-                self.current_index += 1;
-                if self.current_index >= alive_indices.len() as i128 {
-                    self.current_index = 0;
-                }
-                let selected_index = alive_indices[self.current_index as usize];
-                debug!(
-                    "current_index:{} (alive index), selected_index: {}",
-                    self.current_index, selected_index
-                );
-                let route = self.routes[selected_index].clone();
-                Ok(route)
-            }
+    fn get_route(
+        &self,
+        _headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        if candidates.is_empty() {
+            return Err(AppError::from("No routes available"));
         }
+        let current_index = self.current_index.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        let selected_index = candidates[current_index];
+        debug!(
+            "current_index:{}, selected_index: {}",
+            current_index, selected_index
+        );
+        Ok(self.routes[selected_index].clone())
     }
     fn update_route_alive(
         &mut self,
@@ -600,8 +818,10 @@ impl PollRoute {
         Ok(())
     }
 }
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct WeightBasedRoute {
+    #[serde(skip_deserializing, skip_serializing)]
+    pub current_index: RuntimeState<AtomicUsize>,
     #[serde(rename = "targets")]
     pub routes: Vec<WeightedRouteItem>,
 }
@@ -611,8 +831,6 @@ pub struct WeightedRouteItem {
     #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     pub is_alive: Option<bool>,
     pub weight: i32,
-    #[serde(skip_deserializing, skip_serializing, default)]
-    pub index: i32,
 }
 impl WeightedRouteItem {
     fn get_base_route(&self) -> BaseRoute {
@@ -631,57 +849,31 @@ impl WeightBasedRoute {
             .collect::<Vec<BaseRoute>>())
     }
 
-    fn get_route(&mut self, _headers: &HeaderMap<HeaderValue>) -> Result<BaseRoute, AppError> {
+    fn get_route(
+        &self,
+        _headers: &HeaderMap<HeaderValue>,
+        context: &SelectionContext,
+    ) -> Result<BaseRoute, AppError> {
         if self.routes.is_empty() {
             return Err(AppError::from("No routes available"));
         }
-
-        let has_unconfigured = self.routes.iter().any(|r| r.is_alive.is_none());
-        if has_unconfigured {
-            let all_reached = self.routes.iter().all(|r| r.index >= r.weight);
-            if all_reached {
-                for route in &mut self.routes {
-                    route.index = 0;
-                }
-            }
-            if let Some(route) = self.routes.iter_mut().find(|r| r.index < r.weight) {
-                route.index += 1;
-                Ok(route.get_base_route().clone())
-            } else {
-                Err(AppError::from("WeightRoute get route error"))
-            }
-        } else {
-            let alive_indices: Vec<usize> = self
-                .routes
-                .iter()
-                .enumerate()
-                .filter(|(_, r)| r.is_alive == Some(true))
-                .map(|(i, _)| i)
-                .collect();
-
-            if !alive_indices.is_empty() {
-                let all_reached = alive_indices
-                    .iter()
-                    .all(|&i| self.routes[i].index >= self.routes[i].weight);
-                if all_reached {
-                    for &i in &alive_indices {
-                        self.routes[i].index = 0;
-                    }
-                }
-                for &i in &alive_indices {
-                    if self.routes[i].index < self.routes[i].weight {
-                        self.routes[i].index += 1;
-                        return Ok(self.routes[i].get_base_route().clone());
-                    }
-                }
-                Err(AppError::from("WeightRoute get route error"))
-            } else {
-                let mut rng = rand::rng();
-                let idx = rng.random_range(0..self.routes.len());
-                self.routes[idx].index += 1;
-                Ok(self.routes[idx].get_base_route().clone())
+        let candidates = candidate_indices(&self.routes, |r| &r.endpoint, |r| r.is_alive, context);
+        let total_weight: usize = candidates
+            .iter()
+            .map(|&i| self.routes[i].weight.max(0) as usize)
+            .sum();
+        if total_weight == 0 {
+            return Err(AppError::from("WeightRoute get route error"));
+        }
+        let mut current_index = self.current_index.fetch_add(1, Ordering::Relaxed) % total_weight;
+        for &i in &candidates {
+            let weight = self.routes[i].weight.max(0) as usize;
+            if current_index < weight {
+                return Ok(self.routes[i].get_base_route());
             }
+            current_index -= weight;
         }
+        Err(AppError::from("WeightRoute get route error"))
     }
     fn update_route_alive(
         &mut self,
@@ -793,7 +985,7 @@ mod tests {
     #[test]
     fn test_poll_route_logic() {
         let mut poll_route = PollRoute {
-            current_index: -1,
+            current_index: Default::default(),
             routes: vec![
                 BaseRoute {
                     endpoint: "s1".to_string(),
@@ -811,19 +1003,31 @@ mod tests {
         };
 
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s2"
         );
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s3"
         );
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
 
@@ -855,20 +1059,60 @@ mod tests {
             )
             .unwrap();
 
-        poll_route.current_index = -1;
+        poll_route.current_index = Default::default();
 
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s3"
         );
         assert_eq!(
-            poll_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
+            "s1"
+        );
+    }
+    #[test]
+    fn test_poll_route_index_shared_by_clones() {
+        let poll_route = PollRoute {
+            current_index: Default::default(),
+            routes: vec![
+                BaseRoute {
+                    endpoint: "s1".to_string(),
+                    is_alive: None,
+                },
+                BaseRoute {
+                    endpoint: "s2".to_string(),
+                    is_alive: None,
+                },
+            ],
+        };
+        let cloned_route = poll_route.clone();
+        assert_eq!(
+            poll_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
+        assert_eq!(
+            cloned_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
+            "s2"
+        );
     }
     #[test]
     fn test_random_route_logic() {
@@ -885,7 +1129,9 @@ mod tests {
             ],
         };
 
-        let route = random_route.get_route(&HeaderMap::new()).unwrap();
+        let route = random_route
+            .get_route(&HeaderMap::new(), &SelectionContext::default())
+            .unwrap();
         assert!(route.endpoint == "s1" || route.endpoint == "s2");
 
         random_route
@@ -909,7 +1155,10 @@ mod tests {
 
         for _ in 0..10 {
             assert_eq!(
-                random_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+                random_route
+                    .get_route(&HeaderMap::new(), &SelectionContext::default())
+                    .unwrap()
+                    .endpoint,
                 "s2"
             );
         }
@@ -924,7 +1173,9 @@ mod tests {
             )
             .unwrap();
 
-        let route = random_route.get_route(&HeaderMap::new()).unwrap();
+        let route = random_route
+            .get_route(&HeaderMap::new(), &SelectionContext::default())
+            .unwrap();
         assert!(route.endpoint == "s1" || route.endpoint == "s2");
     }
     #[test]
@@ -935,35 +1186,49 @@ mod tests {
                     endpoint: "s1".to_string(),
                     is_alive: None,
                     weight: 2,
-                    index: 0,
                 },
                 WeightedRouteItem {
                     endpoint: "s2".to_string(),
                     is_alive: None,
                     weight: 1,
-                    index: 0,
                 },
             ],
+            ..Default::default()
         };
 
         assert_eq!(
-            weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            weight_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
         assert_eq!(
-            weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            weight_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
         assert_eq!(
-            weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            weight_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s2"
         );
         assert_eq!(
-            weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            weight_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
         assert_eq!(
-            weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+            weight_route
+                .get_route(&HeaderMap::new(), &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "s1"
         );
 
@@ -986,13 +1251,14 @@ mod tests {
             )
             .unwrap();
 
-        for r in &mut weight_route.routes {
-            r.index = 0;
-        }
+        weight_route.current_index = Default::default();
 
         for _ in 0..5 {
             assert_eq!(
-                weight_route.get_route(&HeaderMap::new()).unwrap().endpoint,
+                weight_route
+                    .get_route(&HeaderMap::new(), &SelectionContext::default())
+                    .unwrap()
+                    .endpoint,
                 "s2"
             );
         }
@@ -1030,28 +1296,40 @@ mod tests {
         let mut headers = HeaderMap::new();
         headers.insert("x-request-id", HeaderValue::from_static("user-12345"));
         assert_eq!(
-            header_route.get_route(&headers).unwrap().endpoint,
+            header_route
+                .get_route(&headers, &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "user-service"
         );
 
         headers.clear();
         headers.insert("x-user-role", HeaderValue::from_static("admin"));
         assert_eq!(
-            header_route.get_route(&headers).unwrap().endpoint,
+            header_route
+                .get_route(&headers, &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "admin-service"
         );
 
         headers.clear();
         headers.insert("x-flags", HeaderValue::from_static("canary,new-ui,beta"));
         assert_eq!(
-            header_route.get_route(&headers).unwrap().endpoint,
+            header_route
+                .get_route(&headers, &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "feature-service"
         );
 
         headers.clear();
         headers.insert("x-some-other-header", HeaderValue::from_static("value"));
         assert_eq!(
-            header_route.get_route(&headers).unwrap().endpoint,
+            header_route
+                .get_route(&headers, &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "user-service"
         );
 
@@ -1067,17 +1345,22 @@ mod tests {
         headers.clear();
         headers.insert("x-user-role", HeaderValue::from_static("admin"));
         assert_eq!(
-            header_route.get_route(&headers).unwrap().endpoint,
+            header_route
+                .get_route(&headers, &SelectionContext::default())
+                .unwrap()
+                .endpoint,
             "user-service"
         );
     }
     #[test]
     fn test_router_enum_dispatch() {
-        let mut static_file_router = Router::StaticFile(StaticFileRoute {
+        let static_file_router = Router::StaticFile(StaticFileRoute {
             doc_root: "".to_string(),
         });
-        static_file_router.get_route(&HeaderMap::new()).unwrap();
-        let mut header_based_router = Router::HeaderBased(HeaderBasedRoute {
+        static_file_router
+            .get_route(&HeaderMap::new(), &SelectionContext::default())
+            .unwrap();
+        let header_based_router = Router::HeaderBased(HeaderBasedRoute {
             routes: vec![HeaderRoutingRule {
                 header_key: "a".to_string(),
                 header_value_mapping_type: HeaderValueMappingType::Text("b".to_string()),
@@ -1085,16 +1368,20 @@ mod tests {
                 is_alive: None,
             }],
         });
-        header_based_router.get_route(&HeaderMap::new()).unwrap();
+        header_based_router
+            .get_route(&HeaderMap::new(), &SelectionContext::default())
+            .unwrap();
         let mut router = Router::Poll(PollRoute {
-            current_index: -1,
+            current_index: Default::default(),
             routes: vec![BaseRoute {
                 endpoint: "s1".to_string(),
                 is_alive: None,
             }],
         });
 
-        let dest = router.get_route(&HeaderMap::new()).unwrap();
+        let dest = router
+            .get_route(&HeaderMap::new(), &SelectionContext::default())
+            .unwrap();
         assert_eq!(
             dest,
             RouterDestination::Http(BaseRoute {
@@ -1130,7 +1417,10 @@ mod tests {
             },
             false,
         );
-        let mut weight_based_router = Router::WeightBased(WeightBasedRoute { routes: vec![] });
+        let mut weight_based_router = Router::WeightBased(WeightBasedRoute {
+            routes: vec![],
+            ..Default::default()
+        });
         let _ = weight_based_router.get_all_route().await;
         let _ = weight_based_router.update_route_alive(
             BaseRoute {
@@ -1179,4 +1469,43 @@ mod tests {
             AppError("StaticFile router can not get route".to_string())
         );
     }
+
+    #[test]
+    fn test_excluded_endpoints_are_skipped() {
+        let poll_route = PollRoute {
+            routes: ["a", "b", "c"]
+                .iter()
+                .map(|endpoint| BaseRoute {
+                    endpoint: endpoint.to_string(),
+                    is_alive: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let context = SelectionContext {
+            excluded_endpoints: vec!["a".to_string(), "c".to_string()],
+            ..Default::default()
+        };
+        for _ in 0..4 {
+            let route = poll_route.get_route(&HeaderMap::new(), &context).unwrap();
+            assert_eq!(route.endpoint, "b");
+        }
+
+        let context = SelectionContext {
+            excluded_endpoints: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            ..Default::default()
+        };
+        assert!(poll_route.get_route(&HeaderMap::new(), &context).is_ok());
+
+        let context = SelectionContext {
+            in_panic: true,
+            unavailable_endpoints: vec!["a".to_string(), "b".to_string()],
+            excluded_endpoints: vec!["c".to_string()],
+            ..Default::default()
+        };
+        for _ in 0..4 {
+            let route = poll_route.get_route(&HeaderMap::new(), &context).unwrap();
+            assert_eq!(route.endpoint, "c");
+        }
+    }
 }
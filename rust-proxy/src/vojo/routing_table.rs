@@ -0,0 +1,907 @@
+use crate::vojo::app_config::Matcher;
+use crate::vojo::app_config::RouteConfig;
+use crate::vojo::app_error::AppError;
+use crate::vojo::rewrite::CompiledRewrite;
+use crate::vojo::rewrite::RewriteInput;
+use crate::vojo::route_matcher::parse_methods;
+use crate::vojo::route_matcher::CompiledCondition;
+use crate::vojo::route_matcher::Condition;
+use crate::vojo::route_matcher::RequestInfo;
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// A radix tree over the bytes of the path prefixes, every node keeps the routes whose prefix
+/// ends at it.
+#[derive(Debug, Default)]
+struct RadixNode {
+    children: Vec<(Vec<u8>, RadixNode)>,
+    routes: Vec<usize>,
+}
+impl RadixNode {
+    fn insert(&mut self, key: &[u8], route_index: usize) {
+        if key.is_empty() {
+            self.routes.push(route_index);
+            return;
+        }
+        for (label, child) in self.children.iter_mut() {
+            let common = label
+                .iter()
+                .zip(key.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common == 0 {
+                continue;
+            }
+            if common < label.len() {
+                let rest = label.split_off(common);
+                let old_child = std::mem::take(child);
+                child.children.push((rest, old_child));
+            }
+            child.insert(&key[common..], route_index);
+            return;
+        }
+        let mut child = RadixNode::default();
+        child.routes.push(route_index);
+        self.children.push((key.to_vec(), child));
+    }
+    /// Collects the routes of every prefix of the path.
+    fn collect(&self, path: &[u8], res: &mut Vec<usize>) {
+        res.extend_from_slice(&self.routes);
+        for (label, child) in self.children.iter() {
+            if path.starts_with(label) {
+                child.collect(&path[label.len()..], res);
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostRoutes {
+    host_name: Option<String>,
+    host_regex: Option<Regex>,
+    tree: RadixNode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PathKind {
+    Prefix,
+    Regex,
+    Exact,
+}
+#[derive(Debug)]
+enum PathMatcher {
+    Prefix(String),
+    Exact(String),
+    Regex(Regex),
+}
+impl PathMatcher {
+    fn new(route: &RouteConfig, matcher: &Matcher) -> Result<Self, AppError> {
+        let path_matcher = match (&matcher.path, &matcher.path_regex) {
+            (None, None) => PathMatcher::Prefix(matcher.prefix.clone()),
+            (Some(path), None) if matcher.prefix.is_empty() => PathMatcher::Exact(path.clone()),
+            (None, Some(path_regex)) if matcher.prefix.is_empty() => {
+                PathMatcher::Regex(Regex::new(path_regex).map_err(|e| {
+                    AppError(format!(
+                        "The path_regex {} of route {} is invalid: {}",
+                        path_regex, route.route_id, e
+                    ))
+                })?)
+            }
+            _ => {
+                return Err(AppError(format!(
+                    "Only one of prefix, path and path_regex can be set in the matcher of route {}",
+                    route.route_id
+                )))
+            }
+        };
+        Ok(path_matcher)
+    }
+    fn radix_key(&self) -> &str {
+        match self {
+            PathMatcher::Prefix(prefix) => prefix,
+            PathMatcher::Exact(path) => path,
+            PathMatcher::Regex(_) => "",
+        }
+    }
+    fn kind(&self) -> PathKind {
+        match self {
+            PathMatcher::Prefix(_) => PathKind::Prefix,
+            PathMatcher::Exact(_) => PathKind::Exact,
+            PathMatcher::Regex(_) => PathKind::Regex,
+        }
+    }
+    /// Returns the rewritten path and the capture groups if the path matches. The prefix is always
+    /// replaced by the rewrite, the exact path and the regex match only when the rewrite is not
+    /// empty.
+    fn match_path(
+        &self,
+        path: &str,
+        rewrite: Option<&str>,
+    ) -> Option<(String, HashMap<String, String>)> {
+        match self {
+            PathMatcher::Prefix(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                match rewrite {
+                    Some(rewrite) => Some(([rewrite, rest].join(""), HashMap::new())),
+                    None => Some((path.to_string(), HashMap::new())),
+                }
+            }
+            PathMatcher::Exact(exact_path) if exact_path == path => match rewrite {
+                Some(rewrite) if !rewrite.is_empty() => Some((rewrite.to_string(), HashMap::new())),
+                _ => Some((path.to_string(), HashMap::new())),
+            },
+            PathMatcher::Exact(_) => None,
+            PathMatcher::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let mut capture_map = HashMap::new();
+                for (index, name) in regex.capture_names().enumerate() {
+                    if let Some(value) = captures.get(index) {
+                        capture_map.insert(index.to_string(), value.as_str().to_string());
+                        if let Some(name) = name {
+                            capture_map.insert(name.to_string(), value.as_str().to_string());
+                        }
+                    }
+                }
+                let rewrite = match rewrite {
+                    Some(rewrite) if !rewrite.is_empty() => rewrite,
+                    _ => return Some((path.to_string(), capture_map)),
+                };
+                let whole_match = captures.get(0)?;
+                let mut rewritten_path = path[..whole_match.start()].to_string();
+                captures.expand(rewrite, &mut rewritten_path);
+                rewritten_path.push_str(&path[whole_match.end()..]);
+                Some((rewritten_path, capture_map))
+            }
+        }
+    }
+    /// Whether this path matcher matches every path the other one matches.
+    fn covers(&self, other: &PathMatcher) -> bool {
+        match (self, other) {
+            (PathMatcher::Prefix(prefix), PathMatcher::Prefix(other_path))
+            | (PathMatcher::Prefix(prefix), PathMatcher::Exact(other_path)) => {
+                other_path.starts_with(prefix.as_str())
+            }
+            (PathMatcher::Prefix(prefix), PathMatcher::Regex(_)) => prefix.is_empty(),
+            (PathMatcher::Exact(path), PathMatcher::Exact(other_path)) => path == other_path,
+            (PathMatcher::Regex(regex), PathMatcher::Regex(other_regex)) => {
+                regex.as_str() == other_regex.as_str()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The order in which matching routes win: the explicit priority first, then routes bound to a
+/// host over catch-all routes, then exact paths over regex paths over the longest prefix, then
+/// the routes with more conditions and finally the declaration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct RouteRank {
+    priority: i32,
+    has_host: bool,
+    path_kind: PathKind,
+    path_len: usize,
+    condition_count: usize,
+    order: Reverse<usize>,
+}
+
+#[derive(Debug)]
+struct CompiledRoute {
+    rank: RouteRank,
+    host_name: Option<String>,
+    path_matcher: PathMatcher,
+    /// Replaces the matched part of the path. Rule routes have none and keep the path, like the
+    /// rules of Traefik, so only `rewrite` changes it.
+    path_rewrite: Option<String>,
+    conditions: Vec<CompiledCondition>,
+    rewrite: CompiledRewrite,
+}
+impl CompiledRoute {
+    /// Compiles the matcher or the rule of the route, returns none if the route has neither.
+    fn new(index: usize, route: &RouteConfig) -> Result<Option<Self>, AppError> {
+        let invalid_route =
+            |e: AppError| AppError(format!("The route {} is invalid: {}", route.route_id, e));
+        let (path_matcher, path_rewrite, conditions, has_host) = match (&route.matcher, &route.rule)
+        {
+            (Some(_), Some(_)) => {
+                return Err(AppError(format!(
+                    "Only one of matcher and rule can be set in route {}",
+                    route.route_id
+                )))
+            }
+            (None, None) => return Ok(None),
+            (Some(matcher), None) => {
+                let path_matcher = PathMatcher::new(route, matcher)?;
+                let mut conditions = vec![];
+                if !matcher.methods.is_empty() {
+                    conditions.push(CompiledCondition::Method(
+                        parse_methods(&matcher.methods).map_err(invalid_route)?,
+                    ));
+                }
+                for condition in matcher.conditions.iter() {
+                    conditions.push(condition.compile().map_err(invalid_route)?);
+                }
+                (
+                    path_matcher,
+                    Some(matcher.prefix_rewrite.clone()),
+                    conditions,
+                    false,
+                )
+            }
+            (None, Some(rule)) => {
+                // The conjuncts at the top of the rule narrow down the radix lookup and the rank.
+                let mut conjuncts = match &rule.condition {
+                    Condition::All { conditions } => conditions.iter().collect(),
+                    condition => vec![condition],
+                };
+                let has_host = conjuncts.iter().any(|item| {
+                    matches!(item, Condition::Host { .. } | Condition::HostRegex { .. })
+                });
+                let path_position = conjuncts.iter().position(|item| {
+                    matches!(
+                        item,
+                        Condition::Path { .. }
+                            | Condition::PathPrefix { .. }
+                            | Condition::PathRegex { .. }
+                    )
+                });
+                let path_matcher = match path_position.map(|item| conjuncts.remove(item)) {
+                    Some(Condition::Path { path }) => PathMatcher::Exact(path.clone()),
+                    Some(Condition::PathRegex { regex }) => {
+                        PathMatcher::Regex(Regex::new(regex).map_err(|e| {
+                            AppError(format!(
+                                "The route {} is invalid: the regex {} is invalid: {}",
+                                route.route_id, regex, e
+                            ))
+                        })?)
+                    }
+                    Some(Condition::PathPrefix { prefix }) => PathMatcher::Prefix(prefix.clone()),
+                    _ => PathMatcher::Prefix(String::new()),
+                };
+                let conditions = conjuncts
+                    .into_iter()
+                    .map(|item| item.compile().map_err(invalid_route))
+                    .collect::<Result<Vec<CompiledCondition>, AppError>>()?;
+                (path_matcher, None, conditions, has_host)
+            }
+        };
+        let path_regex = match &path_matcher {
+            PathMatcher::Regex(regex) => Some(regex),
+            _ => None,
+        };
+        let rewrite = route
+            .rewrite
+            .clone()
+            .unwrap_or_default()
+            .compile(path_regex)
+            .map_err(invalid_route)?;
+        let rank = RouteRank {
+            priority: route.priority.unwrap_or_default(),
+            has_host: has_host || route.host_name.is_some(),
+            path_kind: path_matcher.kind(),
+            path_len: path_matcher.radix_key().len(),
+            condition_count: conditions.len(),
+            order: Reverse(index),
+        };
+        Ok(Some(Self {
+            rank,
+            host_name: route.host_name.clone(),
+            path_matcher,
+            path_rewrite,
+            conditions,
+            rewrite,
+        }))
+    }
+    /// Whether this route matches every request the other one matches.
+    fn covers(&self, other: &CompiledRoute) -> bool {
+        self.conditions.is_empty()
+            && (self.host_name.is_none() || self.host_name == other.host_name)
+            && self.path_matcher.covers(&other.path_matcher)
+    }
+}
+
+/// The route which serves the request and the input of its rewrite stage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteMatch {
+    pub route_index: usize,
+    pub rewrite_input: RewriteInput,
+}
+
+/// The routes of one server compiled into a host to radix tree lookup.
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    hosts: Vec<HostRoutes>,
+    routes: Vec<Option<CompiledRoute>>,
+    missing_matcher: bool,
+}
+impl RoutingTable {
+    pub fn new(routes: &[RouteConfig]) -> Result<Self, AppError> {
+        let mut routing_table = RoutingTable::default();
+        for (index, route) in routes.iter().enumerate() {
+            let compiled_route = match CompiledRoute::new(index, route)? {
+                Some(compiled_route) => compiled_route,
+                None => {
+                    routing_table.missing_matcher = true;
+                    routing_table.routes.push(None);
+                    continue;
+                }
+            };
+            let position = routing_table
+                .hosts
+                .iter()
+                .position(|item| item.host_name == route.host_name);
+            let host_routes = match position {
+                Some(position) => &mut routing_table.hosts[position],
+                None => {
+                    let host_regex = match &route.host_name {
+                        Some(host_name) => Some(Regex::new(host_name).map_err(|e| {
+                            AppError(format!(
+                                "The host_name {} of route {} is invalid: {}",
+                                host_name, route.route_id, e
+                            ))
+                        })?),
+                        None => None,
+                    };
+                    routing_table.hosts.push(HostRoutes {
+                        host_name: route.host_name.clone(),
+                        host_regex,
+                        tree: RadixNode::default(),
+                    });
+                    routing_table
+                        .hosts
+                        .last_mut()
+                        .ok_or("The host routes is empty")?
+                }
+            };
+            host_routes
+                .tree
+                .insert(compiled_route.path_matcher.radix_key().as_bytes(), index);
+            routing_table.routes.push(Some(compiled_route));
+        }
+        Ok(routing_table)
+    }
+    /// Returns the route which serves the request.
+    pub fn match_route(&self, request: &RequestInfo) -> Result<Option<RouteMatch>, AppError> {
+        let path = request.path;
+        let host = request
+            .headers
+            .get("Host")
+            .and_then(|item| item.to_str().ok());
+        let mut candidates = vec![];
+        for host_routes in self.hosts.iter() {
+            if let Some(host_regex) = &host_routes.host_regex {
+                match host {
+                    Some(host) if host_regex.is_match(host) => {}
+                    _ => continue,
+                }
+            }
+            host_routes.tree.collect(path.as_bytes(), &mut candidates);
+        }
+        let mut candidates = candidates
+            .into_iter()
+            .filter_map(|index| self.routes[index].as_ref().map(|route| (index, route)))
+            .collect::<Vec<(usize, &CompiledRoute)>>();
+        candidates.sort_by_key(|(_, route)| Reverse(route.rank));
+        for (route_index, route) in candidates {
+            let (rewritten_path, captures) = match route
+                .path_matcher
+                .match_path(path, route.path_rewrite.as_deref())
+            {
+                Some(res) => res,
+                None => continue,
+            };
+            if !route.conditions.iter().all(|item| item.is_matched(request)) {
+                continue;
+            }
+            // The rewrite of the matcher may add query parameters in front of the request ones.
+            let (path, query) = match rewritten_path.split_once('?') {
+                Some((path, query)) => match request.query {
+                    Some(request_query) => (path, Some(format!("{}&{}", query, request_query))),
+                    None => (path, Some(query.to_string())),
+                },
+                None => (
+                    rewritten_path.as_str(),
+                    request.query.map(|item| item.to_string()),
+                ),
+            };
+            return Ok(Some(RouteMatch {
+                route_index,
+                rewrite_input: RewriteInput {
+                    path: path.to_string(),
+                    query,
+                    captures,
+                    request_query: request.query.map(|item| item.to_string()),
+                },
+            }));
+        }
+        if self.missing_matcher {
+            return Err(AppError::from("The matcher counld not be none for http"));
+        }
+        Ok(None)
+    }
+    /// Returns the rewritten path and query of the request.
+    pub fn rewrite_path(&self, route_match: &RouteMatch) -> Result<String, AppError> {
+        Ok(self
+            .compiled_route(route_match)?
+            .rewrite
+            .rewrite_path_and_query(&route_match.rewrite_input))
+    }
+    /// Returns the url of the upstream request to the endpoint.
+    pub fn upstream_url(
+        &self,
+        route_match: &RouteMatch,
+        endpoint: &str,
+    ) -> Result<String, AppError> {
+        Ok(self
+            .compiled_route(route_match)?
+            .rewrite
+            .upstream_url(&route_match.rewrite_input, endpoint))
+    }
+    fn compiled_route(&self, route_match: &RouteMatch) -> Result<&CompiledRoute, AppError> {
+        self.routes
+            .get(route_match.route_index)
+            .and_then(|item| item.as_ref())
+            .ok_or(AppError::from(
+                "The matched route is not in the routing table",
+            ))
+    }
+    /// Returns the pairs of (shadowed route, shadowing route). A route is shadowed when another
+    /// route matches every request it matches and always wins over it.
+    pub fn find_shadowed_routes(&self) -> Vec<(usize, usize)> {
+        let mut res = vec![];
+        for (index, route) in self.routes.iter().enumerate() {
+            let route = match route {
+                Some(route) => route,
+                None => continue,
+            };
+            let shadowing_route = self.routes.iter().enumerate().find(|(_, other)| {
+                other
+                    .as_ref()
+                    .is_some_and(|other| other.covers(route) && other.rank > route.rank)
+            });
+            if let Some((other_index, _)) = shadowing_route {
+                res.push((index, other_index));
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vojo::rewrite::Rewrite;
+    use crate::vojo::route_matcher::ValueCondition;
+    use crate::vojo::route_rule::Rule;
+    use http::HeaderMap;
+    use http::HeaderValue;
+    use http::Method;
+    use http::Uri;
+    use std::str::FromStr;
+
+    fn create_route(
+        route_id: &str,
+        prefix: &str,
+        host_name: Option<&str>,
+        priority: Option<i32>,
+    ) -> RouteConfig {
+        RouteConfig {
+            route_id: route_id.to_string(),
+            host_name: host_name.map(|item| item.to_string()),
+            matcher: Some(Matcher {
+                prefix: prefix.to_string(),
+                prefix_rewrite: "/".to_string(),
+                ..Default::default()
+            }),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    fn create_matcher_route(route_id: &str, matcher: Matcher) -> RouteConfig {
+        RouteConfig {
+            route_id: route_id.to_string(),
+            matcher: Some(matcher),
+            ..Default::default()
+        }
+    }
+
+    fn host_headers(host: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Host", HeaderValue::from_str(host).unwrap());
+        headers
+    }
+
+    fn match_index(
+        routing_table: &RoutingTable,
+        method: Method,
+        uri: &str,
+        headers: &HeaderMap,
+    ) -> Result<Option<usize>, AppError> {
+        let uri = uri.parse::<Uri>().unwrap();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let request = RequestInfo::new(&method, &uri, headers, &peer_addr);
+        routing_table
+            .match_route(&request)
+            .map(|item| item.map(|item| item.route_index))
+    }
+
+    fn match_get(routing_table: &RoutingTable, uri: &str) -> Result<Option<usize>, AppError> {
+        match_index(routing_table, Method::GET, uri, &HeaderMap::new())
+    }
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let routes = vec![
+            create_route("root", "/", None, None),
+            create_route("api", "/api", None, None),
+            create_route("api_users", "/api/users", None, None),
+            create_route("apple", "/apple", None, None),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert_eq!(match_get(&routing_table, "/index.html"), Ok(Some(0)));
+        assert_eq!(match_get(&routing_table, "/api/orders"), Ok(Some(1)));
+        assert_eq!(match_get(&routing_table, "/api/users/1"), Ok(Some(2)));
+        assert_eq!(match_get(&routing_table, "/apple/1"), Ok(Some(3)));
+        assert_eq!(match_get(&routing_table, "/ap"), Ok(Some(0)));
+        assert_eq!(match_get(&routing_table, "*"), Ok(None));
+    }
+
+    #[test]
+    fn test_priority_overrides_prefix_length() {
+        let routes = vec![
+            create_route("api", "/api", None, None),
+            create_route("root", "/", None, Some(10)),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert_eq!(match_get(&routing_table, "/api/orders"), Ok(Some(1)));
+    }
+
+    #[test]
+    fn test_host_routes() {
+        let routes = vec![
+            create_route("default", "/", None, None),
+            create_route("example", "/", Some("example.com"), None),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert_eq!(
+            match_index(
+                &routing_table,
+                Method::GET,
+                "/a",
+                &host_headers("example.com")
+            ),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            match_index(
+                &routing_table,
+                Method::GET,
+                "/a",
+                &host_headers("other.com")
+            ),
+            Ok(Some(0))
+        );
+        assert_eq!(match_get(&routing_table, "/a"), Ok(Some(0)));
+    }
+
+    #[test]
+    fn test_invalid_host_regex() {
+        let routes = vec![create_route("bad", "/", Some("(example"), None)];
+        assert!(RoutingTable::new(&routes).is_err());
+    }
+
+    #[test]
+    fn test_missing_matcher() {
+        let routes = vec![
+            create_route("api", "/api", None, None),
+            RouteConfig::default(),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert_eq!(match_get(&routing_table, "/api"), Ok(Some(0)));
+        assert!(match_get(&routing_table, "/other").is_err());
+    }
+
+    #[test]
+    fn test_find_shadowed_routes() {
+        let routes = vec![
+            create_route("api", "/api", None, None),
+            create_route("api_copy", "/api", None, None),
+            create_route("root", "/", None, Some(5)),
+            create_route("users", "/users", Some("example.com"), Some(1)),
+            create_route("orders", "/orders", Some("example.com"), Some(5)),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert_eq!(
+            routing_table.find_shadowed_routes(),
+            vec![(0, 2), (1, 0), (3, 2)]
+        );
+
+        let routes = vec![
+            create_route("root", "/", None, None),
+            create_route("api", "/api", None, None),
+            create_route("example", "/", Some("example.com"), None),
+            create_matcher_route(
+                "get_api",
+                Matcher {
+                    prefix: "/api".to_string(),
+                    methods: vec!["GET".to_string()],
+                    ..Default::default()
+                },
+            ),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        assert!(routing_table.find_shadowed_routes().is_empty());
+    }
+
+    #[test]
+    fn test_method_and_condition_routes() {
+        let routes = vec![
+            create_matcher_route(
+                "get_items",
+                Matcher {
+                    prefix: "/items".to_string(),
+                    methods: vec!["GET".to_string()],
+                    ..Default::default()
+                },
+            ),
+            create_matcher_route(
+                "post_items",
+                Matcher {
+                    prefix: "/items".to_string(),
+                    methods: vec!["POST".to_string()],
+                    ..Default::default()
+                },
+            ),
+            create_matcher_route(
+                "beta_items",
+                Matcher {
+                    prefix: "/items".to_string(),
+                    conditions: vec![Condition::Any {
+                        conditions: vec![
+                            Condition::Cookie(ValueCondition {
+                                name: "beta".to_string(),
+                                ..Default::default()
+                            }),
+                            Condition::Query(ValueCondition {
+                                name: "beta".to_string(),
+                                value: Some("1".to_string()),
+                                ..Default::default()
+                            }),
+                        ],
+                    }],
+                    ..Default::default()
+                },
+            ),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        let headers = HeaderMap::new();
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/items", &headers),
+            Ok(Some(0))
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::POST, "/items/1", &headers),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::DELETE, "/items/1", &headers),
+            Ok(None)
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::DELETE, "/items?beta=1", &headers),
+            Ok(Some(2))
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::COOKIE, HeaderValue::from_static("beta=on"));
+        assert_eq!(
+            match_index(&routing_table, Method::PUT, "/items", &headers),
+            Ok(Some(2))
+        );
+    }
+
+    #[test]
+    fn test_exact_and_regex_paths() {
+        let routes = vec![
+            create_route("root", "/", None, None),
+            create_matcher_route(
+                "exact",
+                Matcher {
+                    path: Some("/users".to_string()),
+                    ..Default::default()
+                },
+            ),
+            create_matcher_route(
+                "regex",
+                Matcher {
+                    path_regex: Some("^/users/(?P<id>[0-9]+)/orders/([0-9]+)$".to_string()),
+                    prefix_rewrite: "/orders/$2?user=${id}".to_string(),
+                    ..Default::default()
+                },
+            ),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        let headers = HeaderMap::new();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let route_match = |uri: &str| {
+            let uri = uri.parse::<Uri>().unwrap();
+            let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+            let route_match = routing_table.match_route(&request).unwrap().unwrap();
+            let rewritten_path = routing_table.rewrite_path(&route_match).unwrap();
+            (route_match, rewritten_path)
+        };
+        let (res, rewritten_path) = route_match("/users?page=2");
+        assert_eq!(res.route_index, 1);
+        assert_eq!(rewritten_path, "/users?page=2");
+        assert_eq!(route_match("/users/").0.route_index, 0);
+        let (res, rewritten_path) = route_match("/users/7/orders/9?page=2");
+        assert_eq!(res.route_index, 2);
+        assert_eq!(rewritten_path, "/orders/9?user=7&page=2");
+        assert_eq!(res.rewrite_input.captures.get("id"), Some(&"7".to_string()));
+        assert_eq!(res.rewrite_input.captures.get("2"), Some(&"9".to_string()));
+        assert_eq!(route_match("/users/7/orders/x").0.route_index, 0);
+    }
+
+    #[test]
+    fn test_invalid_matchers() {
+        let routes = vec![create_matcher_route(
+            "both",
+            Matcher {
+                prefix: "/api".to_string(),
+                path: Some("/api".to_string()),
+                ..Default::default()
+            },
+        )];
+        assert!(RoutingTable::new(&routes).is_err());
+        let routes = vec![create_matcher_route(
+            "bad_regex",
+            Matcher {
+                path_regex: Some("(".to_string()),
+                ..Default::default()
+            },
+        )];
+        assert!(RoutingTable::new(&routes).is_err());
+        let routes = vec![create_matcher_route(
+            "bad_method",
+            Matcher {
+                methods: vec!["G T".to_string()],
+                ..Default::default()
+            },
+        )];
+        assert!(RoutingTable::new(&routes).is_err());
+    }
+
+    fn create_rule_route(route_id: &str, rule: &str) -> RouteConfig {
+        RouteConfig {
+            route_id: route_id.to_string(),
+            rule: Some(Rule::from_str(rule).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rule_routes() {
+        let routes = vec![
+            create_route("root", "/", None, None),
+            create_rule_route(
+                "v1",
+                "Host(`api.example.com`) && (PathPrefix(`/v1`) || Header(`X-Canary`, `1`)) && !ClientIP(`10.0.0.0/8`)",
+            ),
+            create_rule_route("health", "Path(`/health`) && Method(`GET`)"),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        let mut headers = host_headers("api.example.com");
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/v1/users", &headers),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/v2/users", &headers),
+            Ok(Some(0))
+        );
+        headers.insert("X-Canary", HeaderValue::from_static("1"));
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/v2/users", &headers),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/health", &headers),
+            Ok(Some(1))
+        );
+        let headers = HeaderMap::new();
+        assert_eq!(
+            match_index(&routing_table, Method::GET, "/health", &headers),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            match_index(&routing_table, Method::POST, "/health", &headers),
+            Ok(Some(0))
+        );
+
+        let uri = "/v1/users".parse::<Uri>().unwrap();
+        let headers = host_headers("api.example.com");
+        let peer_addr = "10.0.0.1:8080".parse().unwrap();
+        let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+        assert_eq!(
+            routing_table
+                .match_route(&request)
+                .unwrap()
+                .map(|item| item.route_index),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_rule_routes_keep_the_path() {
+        let routes = vec![
+            create_rule_route("top", "PathPrefix(`/v1`)"),
+            create_rule_route("nested", "PathPrefix(`/v2`) || Header(`X-Canary`, `1`)"),
+            create_rule_route("regex", "PathRegexp(`^/v3/(?P<id>[0-9]+)$`)"),
+        ];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let headers = HeaderMap::new();
+        for (uri, route_index) in [("/v1/users?page=2", 0), ("/v2/users?page=2", 1)] {
+            let uri = uri.parse::<Uri>().unwrap();
+            let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+            let route_match = routing_table.match_route(&request).unwrap().unwrap();
+            assert_eq!(route_match.route_index, route_index);
+            let rest_path = routing_table.rewrite_path(&route_match).unwrap();
+            assert_eq!(rest_path, format!("{}", uri));
+        }
+        let uri = "/v3/42".parse::<Uri>().unwrap();
+        let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+        let route_match = routing_table.match_route(&request).unwrap().unwrap();
+        assert_eq!(routing_table.rewrite_path(&route_match).unwrap(), "/v3/42");
+    }
+
+    #[test]
+    fn test_rule_and_matcher_are_exclusive() {
+        let mut route = create_rule_route("both", "PathPrefix(`/`)");
+        route.matcher = Some(Matcher::default());
+        assert!(RoutingTable::new(&[route]).is_err());
+    }
+
+    #[test]
+    fn test_upstream_url() {
+        let mut route = create_matcher_route(
+            "legacy",
+            Matcher {
+                path_regex: Some("^/api/users/(?P<id>[0-9]+)$".to_string()),
+                ..Default::default()
+            },
+        );
+        route.rewrite = Some(Rewrite {
+            url_template: Some("/legacy/user.php?uid={id}&lang={query.lang}".to_string()),
+            ..Default::default()
+        });
+        let routes = vec![create_route("root", "/", None, None), route];
+        let routing_table = RoutingTable::new(&routes).unwrap();
+        let headers = HeaderMap::new();
+        let peer_addr = "127.0.0.1:8080".parse().unwrap();
+        let upstream_url = |uri: &str| {
+            let uri = uri.parse::<Uri>().unwrap();
+            let request = RequestInfo::new(&Method::GET, &uri, &headers, &peer_addr);
+            let route_match = routing_table.match_route(&request).unwrap().unwrap();
+            routing_table
+                .upstream_url(&route_match, "http://backend/")
+                .unwrap()
+        };
+        assert_eq!(
+            upstream_url("/api/users/7?lang=en"),
+            "http://backend/legacy/user.php?uid=7&lang=en"
+        );
+        assert_eq!(
+            upstream_url("/index.html?a=1"),
+            "http://backend/index.html?a=1"
+        );
+
+        let mut route = create_route("invalid", "/", None, None);
+        route.rewrite = Some(Rewrite {
+            url_template: Some("/{1}".to_string()),
+            ..Default::default()
+        });
+        assert!(RoutingTable::new(&[route]).is_err());
+    }
+}
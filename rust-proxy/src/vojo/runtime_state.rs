@@ -0,0 +1,55 @@
+use core::fmt::Debug;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Mutable state which lives next to the config but is shared by every snapshot of it, so the
+/// request path can update it through `&self`. It is never serialized and is ignored by equality.
+#[derive(Default)]
+pub struct RuntimeState<T>(Arc<T>);
+impl<T> RuntimeState<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(value))
+    }
+}
+impl<T> Clone for RuntimeState<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<T> PartialEq for RuntimeState<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+impl<T> Eq for RuntimeState<T> {}
+impl<T: Debug> Debug for RuntimeState<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl<T> Deref for RuntimeState<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> From<T> for RuntimeState<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_clone_shares_state() {
+        let state = RuntimeState::new(AtomicUsize::new(0));
+        let cloned = state.clone();
+        cloned.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(state.load(Ordering::Relaxed), 1);
+        assert_eq!(state, RuntimeState::new(AtomicUsize::new(5)));
+    }
+}
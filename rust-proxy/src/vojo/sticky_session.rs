@@ -0,0 +1,242 @@
+use super::app_error::AppError;
+use super::route_matcher::cookie_values;
+use super::router::candidate_indices;
+use super::router::BaseRoute;
+use super::router::SelectionContext;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::Hmac;
+use hmac::Mac;
+use http::HeaderMap;
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use sha2::Sha256;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SameSite {
+    #[serde(rename = "strict")]
+    Strict,
+    #[default]
+    #[serde(rename = "lax")]
+    Lax,
+    #[serde(rename = "none")]
+    None,
+}
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Pins a client to the endpoint which served its first request with a signed cookie. The
+/// cookie is honoured while the endpoint can be picked, otherwise the router balances as usual
+/// and the cookie is replaced. It is rejected on header, failover and canary routers, whose
+/// endpoints may not all serve a request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StickySession {
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    /// The lifetime of the cookie, which lasts for the browser session if unset. The cookie is
+    /// not renewed, so a client is rebalanced once it expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(default)]
+    pub same_site: SameSite,
+    /// Adds the Secure attribute, which is always added with `same_site: none`.
+    #[serde(default)]
+    pub secure: bool,
+    /// The HMAC-SHA256 key which signs the cookie, so clients can not pick an endpoint.
+    pub key: String,
+}
+fn default_cookie_name() -> String {
+    String::from("spire_sticky")
+}
+fn default_path() -> String {
+    String::from("/")
+}
+/// An opaque id of the endpoint, so the cookie does not reveal the upstream address.
+pub fn endpoint_id(endpoint: &str) -> String {
+    Sha256::digest(endpoint.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+impl StickySession {
+    fn mac(&self, payload: &str) -> Result<Hmac<Sha256>, AppError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_bytes())
+            .map_err(|e| AppError(e.to_string()))?;
+        mac.update(payload.as_bytes());
+        Ok(mac)
+    }
+    /// Returns the endpoint id of the first cookie whose signature is valid and which has not
+    /// expired. The cookie value is `<endpoint id>.<expiry in unix seconds, 0 if none>.<mac>`.
+    pub fn pinned_endpoint_id(&self, headers: &HeaderMap<HeaderValue>) -> Option<String> {
+        cookie_values(headers, &self.cookie_name).find_map(|value| {
+            let (payload, signature) = value.rsplit_once('.')?;
+            let (id, expires) = payload.split_once('.')?;
+            let expires: u64 = expires.parse().ok()?;
+            if expires != 0 && expires <= now_seconds() {
+                return None;
+            }
+            let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+            self.mac(payload).ok()?.verify_slice(&signature).ok()?;
+            Some(id.to_string())
+        })
+    }
+    /// Returns the pinned endpoint if it is among the endpoints the router may pick.
+    pub fn select(
+        &self,
+        headers: &HeaderMap<HeaderValue>,
+        routes: &[BaseRoute],
+        context: &SelectionContext,
+    ) -> Option<BaseRoute> {
+        let id = self.pinned_endpoint_id(headers)?;
+        candidate_indices(routes, |r| &r.endpoint, |r| r.is_alive, context)
+            .into_iter()
+            .map(|index| &routes[index])
+            .find(|route| endpoint_id(&route.endpoint) == id)
+            .cloned()
+    }
+    pub fn set_cookie(&self, endpoint: &str) -> Result<HeaderValue, AppError> {
+        let expires = self
+            .ttl_seconds
+            .map_or(0, |ttl_seconds| now_seconds() + ttl_seconds);
+        let payload = format!("{}.{}", endpoint_id(endpoint), expires);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload)?.finalize().into_bytes());
+        let mut cookie = format!(
+            "{}={}.{}; Path={}; HttpOnly; SameSite={}",
+            self.cookie_name,
+            payload,
+            signature,
+            self.path,
+            self.same_site.as_str()
+        );
+        if let Some(ttl_seconds) = self.ttl_seconds {
+            cookie.push_str(&format!("; Max-Age={}", ttl_seconds));
+        }
+        if self.secure || self.same_site == SameSite::None {
+            cookie.push_str("; Secure");
+        }
+        Ok(HeaderValue::from_str(&cookie)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_sticky_session() -> StickySession {
+        serde_yaml::from_str("key: secret\nttl_seconds: 60").unwrap()
+    }
+    fn create_routes() -> Vec<BaseRoute> {
+        ["http://a", "http://b", "http://c"]
+            .iter()
+            .map(|endpoint| BaseRoute {
+                endpoint: endpoint.to_string(),
+                is_alive: None,
+            })
+            .collect()
+    }
+    fn cookie_headers(set_cookie: &HeaderValue) -> HeaderMap<HeaderValue> {
+        let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "cookie",
+            HeaderValue::from_str(&format!("theme=dark; {}", cookie)).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_pinned_endpoint_is_selected_while_available() {
+        let sticky_session = create_sticky_session();
+        let mut routes = create_routes();
+        let headers = cookie_headers(&sticky_session.set_cookie("http://b").unwrap());
+        let context = SelectionContext::default();
+        assert_eq!(
+            sticky_session.pinned_endpoint_id(&headers),
+            Some(endpoint_id("http://b"))
+        );
+        let selected = sticky_session.select(&headers, &routes, &context);
+        assert_eq!(selected.unwrap().endpoint, "http://b");
+
+        let context = SelectionContext {
+            unavailable_endpoints: vec!["http://b".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(sticky_session.select(&headers, &routes, &context), None);
+        routes[1].is_alive = Some(false);
+        let context = SelectionContext::default();
+        assert_eq!(sticky_session.select(&headers, &routes, &context), None);
+        assert_eq!(
+            sticky_session.select(&HeaderMap::new(), &routes, &context),
+            None
+        );
+    }
+
+    #[test]
+    fn test_forged_and_expired_cookies_are_ignored() {
+        let sticky_session = create_sticky_session();
+        let set_cookie = sticky_session.set_cookie("http://a").unwrap();
+        let other_key = StickySession {
+            key: "other".to_string(),
+            ..create_sticky_session()
+        };
+        assert_eq!(
+            other_key.pinned_endpoint_id(&cookie_headers(&set_cookie)),
+            None
+        );
+
+        let forged = set_cookie
+            .to_str()
+            .unwrap()
+            .replace(&endpoint_id("http://a"), &endpoint_id("http://c"));
+        let forged = cookie_headers(&HeaderValue::from_str(&forged).unwrap());
+        assert_eq!(sticky_session.pinned_endpoint_id(&forged), None);
+
+        let payload = format!("{}.{}", endpoint_id("http://a"), now_seconds() - 1);
+        let signature = URL_SAFE_NO_PAD.encode(
+            sticky_session
+                .mac(&payload)
+                .unwrap()
+                .finalize()
+                .into_bytes(),
+        );
+        let expired = HeaderValue::from_str(&format!("spire_sticky={}.{}", payload, signature));
+        assert_eq!(
+            sticky_session.pinned_endpoint_id(&cookie_headers(&expired.unwrap())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_cookie_attributes() {
+        let sticky_session: StickySession =
+            serde_yaml::from_str("cookie_name: backend\nkey: secret\npath: /api\nsame_site: none")
+                .unwrap();
+        let set_cookie = sticky_session.set_cookie("http://a").unwrap();
+        let set_cookie = set_cookie.to_str().unwrap();
+        assert!(set_cookie.starts_with(&format!("backend={}.0.", endpoint_id("http://a"))));
+        assert!(set_cookie.ends_with("; Path=/api; HttpOnly; SameSite=None; Secure"));
+
+        let set_cookie = create_sticky_session().set_cookie("http://a").unwrap();
+        let set_cookie = set_cookie.to_str().unwrap();
+        assert!(set_cookie.starts_with("spire_sticky="));
+        assert!(set_cookie.ends_with("; Path=/; HttpOnly; SameSite=Lax; Max-Age=60"));
+    }
+}